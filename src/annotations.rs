@@ -0,0 +1,58 @@
+// Bookx - annotations.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::storage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnotationKind {
+    Highlight,
+    Bookmark,
+    // a highlight with a note written on it
+    Note,
+}
+
+// Something marked in a text book. Places are a chapter and characters
+// into its text, the same as the reading position.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub kind: AnnotationKind,
+    pub chapter: usize,
+    pub start: usize,
+    // the same as `start` for bookmarks
+    pub end: usize,
+    // text marked, or the start of the line a bookmark is on
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    // seconds since the epoch
+    pub created: i64,
+}
+
+fn file_path() -> PathBuf {
+    storage::data_dir().join("annotations.json")
+}
+
+// Annotations of every book, keyed on the path of the book, each in the
+// order they were made.
+pub fn load_all() -> BTreeMap<String, Vec<Annotation>> {
+    storage::load_json(&file_path())
+}
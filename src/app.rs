@@ -13,10 +13,11 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
+use gettextrs::gettext;
 use gtk::prelude::*;
 use relm4::prelude::*;
 use relm4::{
-    actions::{ActionGroupName, RelmAction, RelmActionGroup},
+    actions::{AccelsPlus, ActionGroupName, RelmAction, RelmActionGroup},
     gtk, main_application, Component, ComponentController, ComponentParts, ComponentSender,
     Controller, SimpleComponent,
 };
@@ -24,7 +25,10 @@ use relm4::{
 use gtk::prelude::{ApplicationExt, ApplicationWindowExt, GtkWindowExt, SettingsExt, WidgetExt};
 use gtk::{gio, glib};
 
-use crate::components::{AboutDialog, BookxMainContainer, BookxPreferences};
+use std::cell::RefCell;
+use std::path::PathBuf;
+
+use crate::components::{AboutDialog, BookxMainContainer, BookxPreferences, MainContainerInput};
 use crate::config::{APP_ID, PROFILE};
 
 pub(super) struct App {
@@ -36,13 +40,67 @@ pub(super) struct App {
 #[derive(Debug)]
 pub(super) enum Event {
     OpenPreferences,
+    OpenFiles(Vec<PathBuf>),
+    // asked for over D-Bus
+    Remote(MainContainerInput),
     Quit,
 }
 
+thread_local! {
+    // files opened before the window is there to show them
+    static PENDING_FILES: RefCell<Vec<PathBuf>> = RefCell::new(Vec::new());
+    // requests made over D-Bus before the window is there to take them
+    static PENDING_REMOTE: RefCell<Vec<MainContainerInput>> = RefCell::new(Vec::new());
+    static APP_SENDER: RefCell<Option<relm4::Sender<Event>>> = RefCell::new(None);
+}
+
+// Opens books handed to the application, by the file manager or on the
+// command line.
+pub(super) fn open_files(files: Vec<PathBuf>) {
+    APP_SENDER.with(|sender| match sender.borrow().as_ref() {
+        Some(sender) => sender.send(Event::OpenFiles(files)).unwrap(),
+        None => PENDING_FILES.with(|pending| pending.borrow_mut().extend(files)),
+    });
+}
+
+// Hands a request made over D-Bus to the window, returns false when there
+// is no window to take it.
+pub(super) fn remote(message: MainContainerInput) -> bool {
+    APP_SENDER.with(|sender| match sender.borrow().as_ref() {
+        Some(sender) => {
+            sender.send(Event::Remote(message)).unwrap();
+            true
+        }
+        None => false,
+    })
+}
+
+// Like `remote`, but a request made while there is no window is kept for
+// the window to come. Returns false when it was kept.
+pub(super) fn remote_queued(message: MainContainerInput) -> bool {
+    APP_SENDER.with(|sender| match sender.borrow().as_ref() {
+        Some(sender) => {
+            sender.send(Event::Remote(message)).unwrap();
+            true
+        }
+        None => {
+            PENDING_REMOTE.with(|pending| pending.borrow_mut().push(message));
+            false
+        }
+    })
+}
+
 relm4::new_action_group!(pub(super) WindowActionGroup, "win");
 relm4::new_stateless_action!(PreferencesAction, WindowActionGroup, "preferences");
 relm4::new_stateless_action!(pub(super) ShortcutsAction, WindowActionGroup, "show-help-overlay");
 relm4::new_stateless_action!(AboutAction, WindowActionGroup, "about");
+relm4::new_stateless_action!(SearchLibraryAction, WindowActionGroup, "search-library");
+relm4::new_stateless_action!(RescanLibraryAction, WindowActionGroup, "rescan-library");
+relm4::new_stateless_action!(NewBookAction, WindowActionGroup, "new-book");
+relm4::new_stateless_action!(SelectBooksAction, WindowActionGroup, "select-books");
+relm4::new_stateless_action!(FindDuplicatesAction, WindowActionGroup, "find-duplicates");
+relm4::new_stateless_action!(StatisticsAction, WindowActionGroup, "statistics");
+relm4::new_stateless_action!(NewWindowAction, WindowActionGroup, "new-window");
 
 #[relm4::component(pub)]
 impl SimpleComponent for App {
@@ -57,6 +115,13 @@ impl SimpleComponent for App {
 
     menu! {
         primary_menu: {
+            section! {
+                "New _Window" => NewWindowAction,
+                "_New Book…" => NewBookAction,
+                "_Rescan Library" => RescanLibraryAction,
+                "Find _Duplicates…" => FindDuplicatesAction,
+                "Reading _Statistics" => StatisticsAction,
+            },
             section! {
                 "_Preferences" => PreferencesAction,
                 "_Keyboard" => ShortcutsAction,
@@ -93,7 +158,17 @@ impl SimpleComponent for App {
                 pack_end = &gtk::MenuButton {
                     set_icon_name: "open-menu-symbolic",
                     set_menu_model: Some(&primary_menu),
-                }
+                },
+                pack_end = &gtk::Button {
+                    set_icon_name: "system-search-symbolic",
+                    set_tooltip_text: Some(&gettext("Search All Books")),
+                    set_action_name: Some("win.search-library"),
+                },
+                pack_end = &gtk::Button {
+                    set_icon_name: "selection-mode-symbolic",
+                    set_tooltip_text: Some(&gettext("Select Books")),
+                    set_action_name: Some("win.select-books"),
+                },
             },
 
             gtk::Box {
@@ -150,9 +225,75 @@ impl SimpleComponent for App {
             })
         };
 
+        let search_library_action = {
+            let sender = model.bookx_main_container.sender().clone();
+            RelmAction::<SearchLibraryAction>::new_stateless(move |_| {
+                sender
+                    .send(MainContainerInput::ToggleLibrarySearch)
+                    .unwrap();
+            })
+        };
+
+        let rescan_library_action = {
+            let sender = model.bookx_main_container.sender().clone();
+            RelmAction::<RescanLibraryAction>::new_stateless(move |_| {
+                sender.send(MainContainerInput::RescanLibrary).unwrap();
+            })
+        };
+
+        let new_book_action = {
+            let sender = model.bookx_main_container.sender().clone();
+            RelmAction::<NewBookAction>::new_stateless(move |_| {
+                sender.send(MainContainerInput::NewBook).unwrap();
+            })
+        };
+
+        let select_books_action = {
+            let sender = model.bookx_main_container.sender().clone();
+            RelmAction::<SelectBooksAction>::new_stateless(move |_| {
+                sender
+                    .send(MainContainerInput::ToggleSelectionMode)
+                    .unwrap();
+            })
+        };
+
+        let find_duplicates_action = {
+            let sender = model.bookx_main_container.sender().clone();
+            RelmAction::<FindDuplicatesAction>::new_stateless(move |_| {
+                sender.send(MainContainerInput::FindDuplicates).unwrap();
+            })
+        };
+
+        let statistics_action = {
+            let sender = model.bookx_main_container.sender().clone();
+            RelmAction::<StatisticsAction>::new_stateless(move |_| {
+                sender.send(MainContainerInput::ShowStatistics).unwrap();
+            })
+        };
+
+        let new_window_action = {
+            let sender = model.bookx_main_container.sender().clone();
+            RelmAction::<NewWindowAction>::new_stateless(move |_| {
+                sender.send(MainContainerInput::NewWindow).unwrap();
+            })
+        };
+
         actions.add_action(shortcuts_action);
         actions.add_action(about_action);
         actions.add_action(preferences_action);
+        actions.add_action(search_library_action);
+        actions.add_action(rescan_library_action);
+        actions.add_action(new_book_action);
+        actions.add_action(select_books_action);
+        actions.add_action(find_duplicates_action);
+        actions.add_action(statistics_action);
+        actions.add_action(new_window_action);
+
+        let app = main_application();
+        app.set_accelerators_for_action::<SearchLibraryAction>(&["<Control><Shift>f"]);
+        app.set_accelerators_for_action::<RescanLibraryAction>(&["<Control>r"]);
+        app.set_accelerators_for_action::<NewBookAction>(&["<Control>n"]);
+        app.set_accelerators_for_action::<NewWindowAction>(&["<Control><Shift>n"]);
 
         widgets
             .main_window
@@ -160,6 +301,16 @@ impl SimpleComponent for App {
 
         widgets.load_window_size();
 
+        APP_SENDER
+            .with(|app_sender| *app_sender.borrow_mut() = Some(sender.input_sender().clone()));
+        let files = PENDING_FILES.with(|pending| pending.take());
+        if !files.is_empty() {
+            sender.input(Event::OpenFiles(files));
+        }
+        for message in PENDING_REMOTE.with(|pending| pending.take()) {
+            sender.input(Event::Remote(message));
+        }
+
         ComponentParts { model, widgets }
     }
 
@@ -167,6 +318,14 @@ impl SimpleComponent for App {
         match message {
             Event::Quit => main_application().quit(),
             Event::OpenPreferences => self.bookx_preferences.widget().present(),
+            Event::Remote(message) => self.bookx_main_container.emit(message),
+            Event::OpenFiles(files) => {
+                self.bookx_main_container
+                    .emit(MainContainerInput::OpenFiles(files));
+                if let Some(window) = main_application().active_window() {
+                    window.present();
+                }
+            }
         }
     }
 
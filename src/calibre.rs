@@ -0,0 +1,298 @@
+// Bookx - calibre.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use rusqlite::{types::Value, Connection, OpenFlags};
+use tracing::{info, warn};
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::library_db::{CustomField, FieldKind, FieldValue, ImportedMetadata, LibraryDb};
+
+// What Bookx takes from a calibre library: the metadata of every book and
+// its custom columns. The files stay where calibre keeps them.
+#[derive(Debug, Default)]
+pub struct CalibreLibrary {
+    pub books: Vec<CalibreBook>,
+    pub columns: Vec<CustomField>,
+}
+
+#[derive(Debug, Default)]
+pub struct CalibreBook {
+    // a file for each format calibre has of the book
+    pub files: Vec<PathBuf>,
+    pub metadata: ImportedMetadata,
+    pub tags: Vec<String>,
+    // values by column label
+    pub fields: BTreeMap<String, FieldValue>,
+}
+
+// calibre keeps its database in the root of the library
+pub fn is_library(folder: &Path) -> bool {
+    folder.join("metadata.db").is_file()
+}
+
+// Reads `metadata.db` of the calibre library at `folder`. The database is
+// opened read-only, so calibre can keep running.
+pub fn read(folder: &Path) -> rusqlite::Result<CalibreLibrary> {
+    let db = Connection::open_with_flags(
+        folder.join("metadata.db"),
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+
+    let mut books: BTreeMap<i64, CalibreBook> = BTreeMap::new();
+    let mut statement = db.prepare("SELECT id, title, path, has_cover, series_index FROM books")?;
+    let mut rows = statement.query([])?;
+    // folders of the books, their files and cover are in there
+    let mut book_folders: BTreeMap<i64, PathBuf> = BTreeMap::new();
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        let book_folder = folder.join(row.get::<_, String>(2)?);
+        let has_cover: bool = row.get(3)?;
+        let metadata = ImportedMetadata {
+            title: row.get(1)?,
+            series_index: row.get(4)?,
+            cover: Some(book_folder.join("cover.jpg")).filter(|_| has_cover),
+            ..Default::default()
+        };
+        books.insert(
+            id,
+            CalibreBook {
+                metadata,
+                ..Default::default()
+            },
+        );
+        book_folders.insert(id, book_folder);
+    }
+    drop(rows);
+
+    let mut statement = db.prepare("SELECT book, format, name FROM data")?;
+    let files = statement.query_map([], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+        ))
+    })?;
+    for file in files {
+        let (id, format, name) = file?;
+        if let (Some(book), Some(book_folder)) = (books.get_mut(&id), book_folders.get(&id)) {
+            book.files
+                .push(book_folder.join(format!("{}.{}", name, format.to_lowercase())));
+        }
+    }
+    for (id, author) in query::<String>(
+        &db,
+        "SELECT link.book, authors.name FROM books_authors_link AS link
+         JOIN authors ON authors.id = link.author ORDER BY link.id",
+    )? {
+        if let Some(book) = books.get_mut(&id) {
+            book.metadata.authors.push(author.replace('|', ","));
+        }
+    }
+    for (id, tag) in query::<String>(
+        &db,
+        "SELECT link.book, tags.name FROM books_tags_link AS link
+         JOIN tags ON tags.id = link.tag",
+    )? {
+        if let Some(book) = books.get_mut(&id) {
+            book.tags.push(tag);
+        }
+    }
+    for (id, series) in query::<String>(
+        &db,
+        "SELECT link.book, series.name FROM books_series_link AS link
+         JOIN series ON series.id = link.series",
+    )? {
+        if let Some(book) = books.get_mut(&id) {
+            book.metadata.series = Some(series);
+        }
+    }
+    // ratings are kept as half stars
+    for (id, rating) in query::<Option<i64>>(
+        &db,
+        "SELECT link.book, ratings.rating FROM books_ratings_link AS link
+         JOIN ratings ON ratings.id = link.rating",
+    )? {
+        if let Some(book) = books.get_mut(&id) {
+            book.metadata.rating = rating
+                .filter(|rating| *rating > 0)
+                .map(|rating| rating as f64 / 2.0);
+        }
+    }
+
+    let columns = read_columns(&db, &mut books)?;
+    let books: Vec<CalibreBook> = books.into_values().collect();
+    info!(
+        "Read {} books and {} custom columns from the calibre library at {:?}",
+        books.len(),
+        columns.len(),
+        folder
+    );
+    Ok(CalibreLibrary { books, columns })
+}
+
+// Custom columns become custom fields, computed columns are left out.
+fn read_columns(
+    db: &Connection,
+    books: &mut BTreeMap<i64, CalibreBook>,
+) -> rusqlite::Result<Vec<CustomField>> {
+    let mut statement = db.prepare(
+        "SELECT id, label, name, datatype, normalized, display FROM custom_columns
+         WHERE mark_for_delete = 0",
+    )?;
+    let columns: Vec<(i64, String, String, String, bool, String)> = statement
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let mut fields = Vec::new();
+    for (id, label, name, datatype, normalized, display) in columns {
+        let kind = match datatype.as_str() {
+            "text" | "comments" | "series" => FieldKind::Text,
+            "enumeration" => FieldKind::Enum,
+            "int" | "float" | "rating" => FieldKind::Number,
+            "datetime" => FieldKind::Date,
+            "bool" => FieldKind::Boolean,
+            _ => {
+                info!("Skipping calibre column #{} of type {}", label, datatype);
+                continue;
+            }
+        };
+        let values = match kind {
+            FieldKind::Enum => serde_json::from_str::<serde_json::Value>(&display)
+                .ok()
+                .and_then(|display| {
+                    serde_json::from_value::<Vec<String>>(display["enum_values"].clone()).ok()
+                })
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        let sql = if normalized {
+            format!(
+                "SELECT link.book, value.value FROM books_custom_column_{id}_link AS link
+                 JOIN custom_column_{id} AS value ON value.id = link.value"
+            )
+        } else {
+            format!("SELECT book, value FROM custom_column_{id}")
+        };
+
+        // columns with many values per book, like tags, are joined
+        let mut column_values: BTreeMap<i64, Vec<String>> = BTreeMap::new();
+        for (book, value) in query::<Value>(db, &sql)? {
+            let text = match (value, datatype.as_str()) {
+                (Value::Null, _) => continue,
+                (Value::Integer(value), "bool") => (value != 0).to_string(),
+                (Value::Integer(value), "rating") => (value as f64 / 2.0).to_string(),
+                (Value::Integer(value), _) => value.to_string(),
+                (Value::Real(value), _) => value.to_string(),
+                // dates are kept as `2023-04-01 00:00:00+00:00`
+                (Value::Text(value), "datetime") => value.chars().take(10).collect(),
+                (Value::Text(value), _) => value,
+                (Value::Blob(_), _) => continue,
+            };
+            column_values.entry(book).or_default().push(text);
+        }
+        let field = CustomField {
+            name: label.to_lowercase(),
+            label: name,
+            kind,
+            values,
+        };
+        for (book, texts) in column_values {
+            let value = FieldValue::parse(&field, &texts.join(", "));
+            if let (Some(book), Some(value)) = (books.get_mut(&book), value) {
+                book.fields.insert(field.name.clone(), value);
+            }
+        }
+        fields.push(field);
+    }
+    Ok(fields)
+}
+
+// rows of a book id and one value
+fn query<T: rusqlite::types::FromSql>(
+    db: &Connection,
+    sql: &str,
+) -> rusqlite::Result<Vec<(i64, T)>> {
+    let mut statement = db.prepare(sql)?;
+    let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+impl CalibreLibrary {
+    // Brings the books into the library database, returns how many were
+    // brought in. Values set in Bookx are replaced by calibre's, tags are
+    // added to the ones already there.
+    pub fn merge_into(self, db: &mut LibraryDb) -> usize {
+        let mut fields = db.fields.clone();
+        let mut skipped = Vec::new();
+        for column in self.columns {
+            match fields.iter_mut().find(|field| field.name == column.name) {
+                Some(field) if field.kind != column.kind => {
+                    warn!(
+                        "Field {} is a {:?} in Bookx and a {:?} in calibre, keeping the one of Bookx",
+                        field.name, field.kind, column.kind
+                    );
+                    skipped.push(column.name);
+                }
+                Some(field) => {
+                    for value in column.values {
+                        if !field.values.contains(&value) {
+                            field.values.push(value);
+                        }
+                    }
+                }
+                None => fields.push(column),
+            }
+        }
+        db.set_fields(fields);
+
+        let mut count = 0;
+        for book in self.books {
+            for file in &book.files {
+                // files calibre lists but that aren't there are left out
+                if !file.is_file() {
+                    continue;
+                }
+                let path = file.display().to_string();
+                let mut record = db.books.get(&path).cloned().unwrap_or_default();
+                for tag in &book.tags {
+                    if !record.tags.contains(tag) {
+                        record.tags.push(tag.clone());
+                    }
+                }
+                for (name, value) in &book.fields {
+                    if !skipped.contains(name) {
+                        record.fields.insert(name.clone(), value.clone());
+                    }
+                }
+                record.imported = Some(book.metadata.clone());
+                db.set_record(&path, record);
+                count += 1;
+            }
+        }
+        count
+    }
+}
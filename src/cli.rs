@@ -0,0 +1,680 @@
+// Bookx - cli.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use relm4::gtk::{
+    gio::{self, prelude::*},
+    glib,
+};
+use serde_json::{json, Value};
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::annotations;
+use crate::calibre;
+use crate::components::utils;
+use crate::config::APP_ID;
+use crate::formats::epub_check::{self, Severity};
+use crate::formats::epub_package::{EpubPackage, MetadataChange};
+use crate::formats::{self, epub_writer, BookMetadata};
+use crate::library_db::{FieldValue, Filter, LibraryDb, SortKey};
+use crate::search::SharedIndex;
+
+const USAGE: &str = "Usage: bookx [COMMAND] [OPTION…]
+
+Without a command the library window is opened, with files they are opened
+in the reader.
+
+Commands:
+  scan [FOLDER] [--calibre]      Find the books in the library and index them
+                                 for search, --calibre imports the metadata of
+                                 a calibre library
+  list [FOLDER] [--filter TEXT] [--sort title|file|FIELD] [--json]
+                                 List the books of the library
+  info FILE [--json]             Show the metadata of a book
+  set-meta FILE [--title TITLE] [--author NAME]… [--language CODE]
+           [--series NAME] [--series-index NUMBER] [--tag TAG]…
+           [--field NAME=VALUE]…
+                                 Change the metadata of a book, tags and
+                                 fields are the ones kept in the library
+  validate FILE…                 Check EPUBs against the specification
+  convert FILE… [--output FOLDER]
+                                 Convert books to EPUB
+  export-annotations [FILE…] [--output FILE]
+                                 Write the highlights, bookmarks and notes
+                                 of the books, or of every book, as JSON
+  help                           Show this help
+
+FOLDER defaults to the books folder chosen in the preferences.";
+
+const COMMANDS: [&str; 8] = [
+    "scan",
+    "list",
+    "info",
+    "set-meta",
+    "validate",
+    "convert",
+    "export-annotations",
+    "help",
+];
+
+enum CliError {
+    // the command line is wrong, the usage is shown with it
+    Usage(String),
+    Failed(String),
+}
+
+// whether the arguments are for the command line rather than the window
+pub fn is_command(arg: &str) -> bool {
+    COMMANDS.contains(&arg) || arg == "--help"
+}
+
+// Runs the command in `args`, returns the exit status.
+pub fn run(args: Vec<String>) -> i32 {
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), args),
+        None => ("help", &[][..]),
+    };
+    let result = match command {
+        "scan" => scan(args),
+        "list" => list(args),
+        "info" => info(args),
+        "set-meta" => set_meta(args),
+        "validate" => validate(args),
+        "convert" => convert(args),
+        "export-annotations" => export_annotations(args),
+        _ => {
+            println!("{}", USAGE);
+            Ok(0)
+        }
+    };
+    match result {
+        Ok(status) => status,
+        Err(CliError::Usage(message)) => {
+            eprintln!("bookx {}: {}", command, message);
+            eprintln!("Run “bookx help” to see the commands and their options.");
+            2
+        }
+        Err(CliError::Failed(message)) => {
+            eprintln!("bookx {}: {}", command, message);
+            1
+        }
+    }
+}
+
+// Arguments split into the ones on their own and the values of options,
+// `--name value` and `--name=value` are both taken.
+struct Options {
+    positional: Vec<String>,
+    values: Vec<(String, String)>,
+    flags: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: &[String], with_value: &[&str], flags: &[&str]) -> Result<Self, CliError> {
+        let mut options = Options {
+            positional: Vec::new(),
+            values: Vec::new(),
+            flags: Vec::new(),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let name = match arg.strip_prefix("--") {
+                Some(name) => name,
+                None => {
+                    options.positional.push(arg.clone());
+                    continue;
+                }
+            };
+            let (name, value) = match name.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (name, None),
+            };
+            if flags.contains(&name) && value.is_none() {
+                options.flags.push(name.to_string());
+            } else if with_value.contains(&name) {
+                let value = match value.or_else(|| args.next().cloned()) {
+                    Some(value) => value,
+                    None => return Err(CliError::Usage(format!("--{} needs a value", name))),
+                };
+                options.values.push((name.to_string(), value));
+            } else {
+                return Err(CliError::Usage(format!("unknown option --{}", name)));
+            }
+        }
+        Ok(options)
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+
+    // the last value given for the option
+    fn value(&self, name: &str) -> Option<&str> {
+        self.all(name).last().copied()
+    }
+
+    fn all(&self, name: &str) -> Vec<&str> {
+        self.values
+            .iter()
+            .filter(|(option, _)| option == name)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    // the folder given, or the books folder of the preferences
+    fn folder(&self) -> Result<PathBuf, CliError> {
+        let folder = match self.positional.as_slice() {
+            [] => books_folder(),
+            [folder] => PathBuf::from(folder),
+            _ => {
+                return Err(CliError::Usage(String::from(
+                    "only one folder can be given",
+                )))
+            }
+        };
+        if folder.is_dir() {
+            Ok(folder)
+        } else {
+            Err(CliError::Failed(format!(
+                "{} is not a folder",
+                folder.display()
+            )))
+        }
+    }
+
+    fn files(&self) -> Result<Vec<PathBuf>, CliError> {
+        if self.positional.is_empty() {
+            return Err(CliError::Usage(String::from("no book given")));
+        }
+        self.positional
+            .iter()
+            .map(|file| {
+                let file = PathBuf::from(file);
+                if file.is_file() {
+                    Ok(file)
+                } else {
+                    Err(CliError::Failed(format!(
+                        "{} is not a file",
+                        file.display()
+                    )))
+                }
+            })
+            .collect()
+    }
+}
+
+// Folder chosen in the preferences, when the settings are installed, or
+// the documents folder like in the window.
+fn books_folder() -> PathBuf {
+    let chosen = gio::SettingsSchemaSource::default()
+        .and_then(|source| source.lookup(APP_ID, true))
+        .map(|_| gio::Settings::new(APP_ID).string("books-dir").to_string())
+        .filter(|folder| !folder.is_empty());
+    match chosen {
+        Some(folder) => PathBuf::from(folder),
+        None => {
+            glib::user_special_dir(glib::UserDirectory::Documents).unwrap_or_else(glib::home_dir)
+        }
+    }
+}
+
+fn find_books(folder: &Path) -> Vec<String> {
+    let documents = gio::SettingsSchemaSource::default()
+        .and_then(|source| source.lookup(APP_ID, true))
+        .map(|_| gio::Settings::new(APP_ID).boolean("library-documents"))
+        .unwrap_or(false);
+    utils::load_files_from_folder(&gio::File::for_path(folder), true, documents)
+        .iter()
+        .filter_map(|(file, _)| file.path())
+        .map(|path| path.display().to_string())
+        .collect()
+}
+
+fn read_metadata(path: &Path) -> Result<BookMetadata, CliError> {
+    formats::open(path)
+        .map(|source| source.metadata())
+        .map_err(|e| CliError::Failed(format!("unable to read {}: {}", path.display(), e)))
+}
+
+fn scan(args: &[String]) -> Result<i32, CliError> {
+    let options = Options::parse(args, &[], &["calibre"])?;
+    let folder = options.folder()?;
+    let books = find_books(&folder);
+
+    let mut formats: BTreeMap<&str, usize> = BTreeMap::new();
+    for book in &books {
+        if let Some(format) = formats::detect(Path::new(book)) {
+            *formats.entry(format.name).or_default() += 1;
+        }
+    }
+    println!("{} books in {}", books.len(), folder.display());
+    for (format, count) in formats {
+        println!("  {}: {}", format, count);
+    }
+
+    if options.flag("calibre") {
+        if !calibre::is_library(&folder) {
+            return Err(CliError::Failed(format!(
+                "{} is not a calibre library",
+                folder.display()
+            )));
+        }
+        let library = calibre::read(&folder)
+            .map_err(|e| CliError::Failed(format!("unable to read the calibre library: {}", e)))?;
+        let mut db = LibraryDb::load();
+        let count = library.merge_into(&mut db);
+        db.save();
+        println!("{} books imported from calibre", count);
+    }
+
+    SharedIndex::default().update(&books);
+    println!("Search index updated");
+    Ok(0)
+}
+
+fn list(args: &[String]) -> Result<i32, CliError> {
+    let options = Options::parse(args, &["filter", "sort"], &["json"])?;
+    let folder = options.folder()?;
+    let db = LibraryDb::load();
+    let filter = Filter::parse(options.value("filter").unwrap_or_default());
+    let sort = match options.value("sort").unwrap_or("title") {
+        "title" => SortKey::Title,
+        "file" => SortKey::FileName,
+        name if db.field(name).is_some() => SortKey::Field(name.to_string()),
+        name => {
+            return Err(CliError::Usage(format!(
+                "no field named {} to sort by",
+                name
+            )))
+        }
+    };
+
+    let mut books: Vec<(String, String, BookMetadata)> = find_books(&folder)
+        .into_iter()
+        .filter_map(|path| {
+            let metadata = read_metadata(Path::new(&path)).ok()?;
+            // calibre's title wins over the book's own, as in the library
+            let title = db
+                .books
+                .get(&path)
+                .and_then(|record| record.imported.as_ref())
+                .map(|imported| imported.title.clone())
+                .or_else(|| metadata.title.clone())
+                .unwrap_or_default();
+            Some((path, title, metadata))
+        })
+        .filter(|(path, title, _)| filter.matches(&db, path, title))
+        .collect();
+    books.sort_by(|a, b| db.compare(&sort, (&a.0, &a.1), (&b.0, &b.1)));
+
+    if options.flag("json") {
+        let books: Vec<Value> = books
+            .iter()
+            .map(|(path, title, metadata)| book_json(&db, path, title, metadata))
+            .collect();
+        println!("{}", Value::Array(books));
+    } else {
+        for (path, title, metadata) in &books {
+            println!("{}\t{}\t{}", path, title, metadata.authors.join(", "));
+        }
+    }
+    Ok(0)
+}
+
+fn info(args: &[String]) -> Result<i32, CliError> {
+    let options = Options::parse(args, &[], &["json"])?;
+    let file = match options.files()?.as_slice() {
+        [file] => file.clone(),
+        _ => return Err(CliError::Usage(String::from("one book at a time"))),
+    };
+    let metadata = read_metadata(&file)?;
+    // the library keeps books by their full path
+    let path = file
+        .canonicalize()
+        .unwrap_or_else(|_| file.clone())
+        .display()
+        .to_string();
+    let db = LibraryDb::load();
+    let title = metadata.title.clone().unwrap_or_default();
+
+    if options.flag("json") {
+        println!("{}", book_json(&db, &path, &title, &metadata));
+        return Ok(0);
+    }
+    let format = formats::detect(&file)
+        .map(|format| format.name)
+        .unwrap_or_default();
+    let mut rows = vec![
+        ("Title", title),
+        ("Authors", metadata.authors.join(", ")),
+        ("Format", format.to_string()),
+        ("Language", metadata.language.unwrap_or_default()),
+        ("Publisher", metadata.publisher.unwrap_or_default()),
+        ("Date", metadata.date.unwrap_or_default()),
+        ("Identifier", metadata.identifier.unwrap_or_default()),
+        ("Subjects", metadata.subjects.join(", ")),
+    ];
+    let record = db.books.get(&path).cloned().unwrap_or_default();
+    rows.push(("Tags", record.tags.join(", ")));
+    if let Some(progress) = &record.progress {
+        rows.push(("Progress", format!("{}%", progress.percent())));
+    }
+    for (name, value) in rows.into_iter().filter(|(_, value)| !value.is_empty()) {
+        println!("{}: {}", name, value);
+    }
+    for field in &db.fields {
+        if let Some(value) = record.fields.get(&field.name) {
+            println!("{}: {}", field.label, value.to_text());
+        }
+    }
+    Ok(0)
+}
+
+fn set_meta(args: &[String]) -> Result<i32, CliError> {
+    let options = Options::parse(
+        args,
+        &[
+            "title",
+            "author",
+            "language",
+            "series",
+            "series-index",
+            "tag",
+            "field",
+        ],
+        &[],
+    )?;
+    let file = match options.files()?.as_slice() {
+        [file] => file.clone(),
+        _ => return Err(CliError::Usage(String::from("one book at a time"))),
+    };
+    let path = file
+        .canonicalize()
+        .unwrap_or_else(|_| file.clone())
+        .display()
+        .to_string();
+
+    let mut changes = Vec::new();
+    if let Some(title) = options.value("title") {
+        changes.push(MetadataChange::Title(title.to_string()));
+    }
+    let authors = options.all("author");
+    if !authors.is_empty() {
+        changes.push(MetadataChange::Authors(
+            authors.into_iter().map(str::to_string).collect(),
+        ));
+    }
+    if let Some(language) = options.value("language") {
+        changes.push(MetadataChange::Language(language.to_string()));
+    }
+    let series_index =
+        match options.value("series-index") {
+            Some(index) => Some(index.parse::<u32>().map_err(|_| {
+                CliError::Usage(format!("{} is not a position in a series", index))
+            })?),
+            None => None,
+        };
+    if options.value("series").is_some() || series_index.is_some() {
+        changes.push(MetadataChange::Series(
+            options.value("series").map(str::to_string),
+            series_index,
+        ));
+    }
+
+    // tags and fields live in the library, and in the book when the
+    // library writes them there
+    let mut db = LibraryDb::load();
+    let mut record = db.books.get(&path).cloned().unwrap_or_default();
+    let tags = options.all("tag");
+    let fields = options.all("field");
+    if !tags.is_empty() {
+        record.tags = tags
+            .iter()
+            .flat_map(|tags| tags.split(','))
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+    }
+    for field in &fields {
+        let (name, text) = match field.split_once('=') {
+            Some(field) => field,
+            None => return Err(CliError::Usage(format!("{} is not NAME=VALUE", field))),
+        };
+        let definition = db.field(name).ok_or_else(|| {
+            let names: Vec<&str> = db.fields.iter().map(|field| field.name.as_str()).collect();
+            if names.is_empty() {
+                CliError::Failed(format!(
+                    "no field named {}, the library has no custom fields",
+                    name
+                ))
+            } else {
+                CliError::Failed(format!(
+                    "no field named {}, the library has: {}",
+                    name,
+                    names.join(", ")
+                ))
+            }
+        })?;
+        if text.trim().is_empty() {
+            record.fields.remove(name);
+            continue;
+        }
+        let value = FieldValue::parse(definition, text).ok_or_else(|| {
+            CliError::Failed(format!(
+                "{} is not a {} value",
+                text,
+                definition.kind.label().to_lowercase()
+            ))
+        })?;
+        record.fields.insert(name.to_string(), value);
+    }
+    let library_changed = !tags.is_empty() || !fields.is_empty();
+    if library_changed {
+        db.set_record(&path, record.clone());
+        db.save();
+    }
+
+    let editable = formats::detect(&file)
+        .map(|format| format.editable)
+        .unwrap_or(false);
+    if db.write_to_books && library_changed && editable {
+        changes.push(MetadataChange::Tags(record.tags));
+        changes.push(MetadataChange::CustomFields(
+            db.calibre_user_metadata(&path),
+        ));
+    }
+    if changes.is_empty() {
+        if !library_changed {
+            return Err(CliError::Usage(String::from("nothing to change")));
+        }
+        return Ok(0);
+    }
+    if !editable {
+        return Err(CliError::Failed(String::from(
+            "only the metadata of EPUBs can be changed",
+        )));
+    }
+    let failed = |e| CliError::Failed(format!("unable to change {}: {}", file.display(), e));
+    let mut package = EpubPackage::open(&file).map_err(failed)?;
+    for change in &changes {
+        package.change_metadata(change).map_err(failed)?;
+    }
+    package.save().map_err(failed)?;
+    Ok(0)
+}
+
+fn validate(args: &[String]) -> Result<i32, CliError> {
+    let options = Options::parse(args, &[], &[])?;
+    let mut status = 0;
+    for file in options.files()? {
+        let report = match epub_check::check(&file) {
+            Ok(report) => report,
+            Err(e) => {
+                eprintln!("{}: unable to check: {}", file.display(), e);
+                status = 1;
+                continue;
+            }
+        };
+        let counts: Vec<String> = [
+            Severity::Fatal,
+            Severity::Error,
+            Severity::Warning,
+            Severity::Usage,
+        ]
+        .iter()
+        .map(|severity| (severity, report.count(*severity)))
+        .filter(|(_, count)| *count > 0)
+        .map(|(severity, count)| format!("{} {}", count, severity.label().to_lowercase()))
+        .collect();
+        let verdict = if report.is_valid() {
+            "valid"
+        } else {
+            "invalid"
+        };
+        if counts.is_empty() {
+            println!("{}: {}", file.display(), verdict);
+        } else {
+            println!("{}: {} ({})", file.display(), verdict, counts.join(", "));
+        }
+        for message in &report.messages {
+            match message.location() {
+                Some(location) => println!(
+                    "  {} {}: {}",
+                    message.severity.label(),
+                    location,
+                    message.text
+                ),
+                None => println!("  {}: {}", message.severity.label(), message.text),
+            }
+        }
+        if !report.is_valid() {
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+fn convert(args: &[String]) -> Result<i32, CliError> {
+    let options = Options::parse(args, &["output"], &[])?;
+    let output = options.value("output").map(PathBuf::from);
+    if let Some(output) = &output {
+        if !output.is_dir() {
+            return Err(CliError::Failed(format!(
+                "{} is not a folder",
+                output.display()
+            )));
+        }
+    }
+    let mut status = 0;
+    for file in options.files()? {
+        let converted = match &output {
+            Some(output) => epub_writer::convert_to(&file, output),
+            None => epub_writer::convert(&file),
+        };
+        match converted {
+            Ok(target) => println!("{} → {}", file.display(), target.display()),
+            Err(e) => {
+                eprintln!("{}: unable to convert: {}", file.display(), e);
+                status = 1;
+            }
+        }
+    }
+    Ok(status)
+}
+
+fn export_annotations(args: &[String]) -> Result<i32, CliError> {
+    let options = Options::parse(args, &["output"], &[])?;
+    let mut books = annotations::load_all();
+    if !options.positional.is_empty() {
+        // annotations are kept by the full path of the book
+        let paths: Vec<String> = options
+            .files()?
+            .iter()
+            .map(|file| {
+                file.canonicalize()
+                    .unwrap_or_else(|_| file.clone())
+                    .display()
+                    .to_string()
+            })
+            .collect();
+        books.retain(|path, _| paths.contains(path));
+    }
+    // titles of the library, the book is only read when it isn't in it
+    let catalog = LibraryDb::load().catalog;
+    let books: Vec<Value> = books
+        .iter()
+        .map(|(path, annotations)| {
+            let title = match catalog.get(path) {
+                Some(entry) => entry.title.clone(),
+                None => read_metadata(Path::new(path))
+                    .ok()
+                    .and_then(|metadata| metadata.title)
+                    .unwrap_or_default(),
+            };
+            json!({
+                "path": path,
+                "title": title,
+                "annotations": annotations,
+            })
+        })
+        .collect();
+    let text = serde_json::to_string_pretty(&books).unwrap_or_default();
+    match options.value("output") {
+        Some(output) => {
+            if let Err(e) = std::fs::write(output, text + "\n") {
+                return Err(CliError::Failed(format!(
+                    "unable to write {}: {}",
+                    output, e
+                )));
+            }
+        }
+        None => println!("{}", text),
+    }
+    Ok(0)
+}
+
+// everything known about a book, from the file and the library
+fn book_json(db: &LibraryDb, path: &str, title: &str, metadata: &BookMetadata) -> Value {
+    let record = db.books.get(path).cloned().unwrap_or_default();
+    let fields: serde_json::Map<String, Value> = record
+        .fields
+        .iter()
+        .map(|(name, value)| {
+            let value = match value {
+                FieldValue::Number(number) => json!(number),
+                FieldValue::Boolean(boolean) => json!(boolean),
+                value => json!(value.to_text()),
+            };
+            (name.clone(), value)
+        })
+        .collect();
+    json!({
+        "path": path,
+        "format": formats::detect(Path::new(path)).map(|format| format.name),
+        "title": title,
+        "authors": metadata.authors,
+        "language": metadata.language,
+        "publisher": metadata.publisher,
+        "date": metadata.date,
+        "identifier": metadata.identifier,
+        "subjects": metadata.subjects,
+        "tags": record.tags,
+        "fields": fields,
+        "progress": record.progress.as_ref().map(|progress| progress.percent()),
+        "series": record.imported.as_ref().and_then(|imported| imported.series.clone()),
+    })
+}
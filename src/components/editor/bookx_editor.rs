@@ -0,0 +1,720 @@
+// Bookx - bookx_editor.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::gettext;
+use relm4::{
+    adw,
+    gtk::{self, glib, pango, prelude::*},
+    Component, ComponentParts, ComponentSender, Controller, RelmWidgetExt,
+};
+use tracing::{error, info};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::components::editor::cover;
+use crate::components::editor::highlight::{self, Syntax};
+use crate::components::ValidationReport;
+use crate::formats::epub_package::EpubPackage;
+use crate::formats::epub_writer::media_type;
+
+// columns of the tree store
+const COLUMN_LABEL: u32 = 0;
+const COLUMN_ID: u32 = 1;
+// position in the spine, -1 for rows that aren't spine items
+const COLUMN_SPINE: u32 = 2;
+
+// Editing workspace for an EPUB: the manifest and spine on the side and
+// the selected file as source on the right.
+pub struct BookxEditor {
+    package: EpubPackage,
+    title: String,
+    store: gtk::TreeStore,
+    buffer: gtk::TextBuffer,
+    // id of the selected manifest item, and where it is in the spine if
+    // it was selected there
+    selected: Option<String>,
+    selected_spine: Option<usize>,
+    syntax: Syntax,
+    editable: bool,
+    modified: bool,
+    // kept alive while the dialog is shown
+    file_chooser: Option<gtk::FileChooserNative>,
+    report: Option<Controller<ValidationReport>>,
+}
+
+#[derive(Debug)]
+pub enum EditorInput {
+    Select(Option<String>, Option<usize>),
+    BufferChanged,
+    AddFile,
+    FileChosen(PathBuf),
+    CoverFromFile,
+    CoverChosen(PathBuf),
+    CoverFromClipboard,
+    GenerateCover,
+    // image data of a new cover, in any format GdkPixbuf reads
+    SetCover(Vec<u8>),
+    RemoveFile,
+    MoveUp,
+    MoveDown,
+    Check,
+    Save,
+    Close,
+    SaveAndClose,
+    Discard,
+}
+
+#[derive(Debug)]
+pub enum EditorOutput {
+    // the book changed on disk, its library tile needs refreshing
+    Saved,
+    Close,
+}
+
+#[relm4_macros::component(pub)]
+impl Component for BookxEditor {
+    type Init = EpubPackage;
+    type Input = EditorInput;
+    type Output = EditorOutput;
+    type CommandOutput = ();
+
+    view! {
+        #[name = "editor"]
+        gtk::Box {
+            set_orientation: gtk::Orientation::Vertical,
+
+            gtk::CenterBox {
+                set_margin_all: 6,
+
+                #[wrap(Some)]
+                set_start_widget = &gtk::Button {
+                    set_icon_name: "go-previous-symbolic",
+                    set_tooltip_text: Some(&gettext("Back to Library")),
+                    connect_clicked[sender] => move |_| {
+                        sender.input(EditorInput::Close);
+                    },
+                },
+                #[wrap(Some)]
+                set_center_widget = &gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    gtk::Label {
+                        set_label: &model.title,
+                        set_ellipsize: pango::EllipsizeMode::End,
+                        add_css_class: "heading",
+                    },
+                    gtk::Label {
+                        #[watch]
+                        set_label: &model.selected_href(),
+                        set_ellipsize: pango::EllipsizeMode::Middle,
+                        add_css_class: "dim-label",
+                    },
+                },
+                #[wrap(Some)]
+                set_end_widget = &gtk::Box {
+                    set_spacing: 6,
+                    gtk::Button {
+                        set_icon_name: "list-add-symbolic",
+                        set_tooltip_text: Some(&gettext("Add File")),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(EditorInput::AddFile);
+                        },
+                    },
+                    gtk::Button {
+                        set_icon_name: "list-remove-symbolic",
+                        set_tooltip_text: Some(&gettext("Remove File")),
+                        #[watch]
+                        set_sensitive: model.selected.is_some(),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(EditorInput::RemoveFile);
+                        },
+                    },
+                    gtk::Button {
+                        set_icon_name: "go-up-symbolic",
+                        set_tooltip_text: Some(&gettext("Move Up in Reading Order")),
+                        #[watch]
+                        set_sensitive: matches!(model.selected_spine, Some(index) if index > 0),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(EditorInput::MoveUp);
+                        },
+                    },
+                    gtk::Button {
+                        set_icon_name: "go-down-symbolic",
+                        set_tooltip_text: Some(&gettext("Move Down in Reading Order")),
+                        #[watch]
+                        set_sensitive: matches!(
+                            model.selected_spine,
+                            Some(index) if index + 1 < model.package.spine().len()
+                        ),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(EditorInput::MoveDown);
+                        },
+                    },
+                    gtk::MenuButton {
+                        set_icon_name: "image-x-generic-symbolic",
+                        set_tooltip_text: Some(&gettext("Set Cover")),
+
+                        #[wrap(Some)]
+                        #[name = "cover_menu"]
+                        set_popover = &gtk::Popover {
+                            gtk::Box {
+                                set_orientation: gtk::Orientation::Vertical,
+                                gtk::Button {
+                                    set_label: &gettext("Cover from File…"),
+                                    add_css_class: "flat",
+                                    connect_clicked[sender] => move |_| {
+                                        sender.input(EditorInput::CoverFromFile);
+                                    },
+                                },
+                                gtk::Button {
+                                    set_label: &gettext("Paste Cover"),
+                                    add_css_class: "flat",
+                                    connect_clicked[sender] => move |_| {
+                                        sender.input(EditorInput::CoverFromClipboard);
+                                    },
+                                },
+                                gtk::Button {
+                                    set_label: &gettext("Generate Cover"),
+                                    add_css_class: "flat",
+                                    connect_clicked[sender] => move |_| {
+                                        sender.input(EditorInput::GenerateCover);
+                                    },
+                                },
+                            }
+                        },
+                    },
+                    gtk::Button {
+                        set_label: &gettext("Check"),
+                        set_tooltip_text: Some(&gettext("Check the Book for Problems")),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(EditorInput::Check);
+                        },
+                    },
+                    gtk::Button {
+                        set_label: &gettext("Save"),
+                        add_css_class: "suggested-action",
+                        #[watch]
+                        set_sensitive: model.modified,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(EditorInput::Save);
+                        },
+                    },
+                },
+            },
+
+            gtk::Paned {
+                set_vexpand: true,
+                set_position: 280,
+
+                #[wrap(Some)]
+                set_start_child = &gtk::ScrolledWindow {
+                    set_hscrollbar_policy: gtk::PolicyType::Never,
+
+                    #[name = "tree"]
+                    gtk::TreeView {
+                        set_model: Some(&model.store),
+                        set_headers_visible: false,
+                        set_enable_search: false,
+                    }
+                },
+                #[wrap(Some)]
+                set_end_child = &gtk::ScrolledWindow {
+                    set_hexpand: true,
+
+                    gtk::TextView {
+                        set_buffer: Some(&model.buffer),
+                        set_monospace: true,
+                        #[watch]
+                        set_editable: model.editable,
+                        #[watch]
+                        set_cursor_visible: model.editable,
+                        set_wrap_mode: gtk::WrapMode::WordChar,
+                        set_left_margin: 12,
+                        set_right_margin: 12,
+                        set_top_margin: 12,
+                        set_bottom_margin: 12,
+                    }
+                }
+            }
+        }
+    }
+
+    fn init(
+        package: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let title = package
+            .path()
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let store = gtk::TreeStore::new(&[
+            String::static_type(),
+            String::static_type(),
+            i32::static_type(),
+        ]);
+        let buffer = gtk::TextBuffer::new(None);
+        highlight::create_tags(&buffer);
+        buffer.connect_changed(glib::clone!(@strong sender => move |_| {
+            sender.input(EditorInput::BufferChanged);
+        }));
+
+        let model = BookxEditor {
+            package,
+            title,
+            store,
+            buffer,
+            selected: None,
+            selected_spine: None,
+            syntax: Syntax::Plain,
+            editable: false,
+            modified: false,
+            file_chooser: None,
+            report: None,
+        };
+        let widgets = view_output!();
+
+        let cell = gtk::CellRendererText::new();
+        cell.set_ellipsize(pango::EllipsizeMode::Middle);
+        let column = gtk::TreeViewColumn::new();
+        column.pack_start(&cell, true);
+        column.add_attribute(&cell, "text", COLUMN_LABEL as i32);
+        widgets.tree.append_column(&column);
+        widgets
+            .tree
+            .selection()
+            .connect_changed(glib::clone!(@strong sender => move |selection| {
+                let (id, spine) = match selection.selected() {
+                    Some((store, iter)) => (
+                        store.get::<String>(&iter, COLUMN_ID as i32),
+                        store.get::<i32>(&iter, COLUMN_SPINE as i32),
+                    ),
+                    None => (String::new(), -1),
+                };
+                let id = Some(id).filter(|id| !id.is_empty());
+                sender.input(EditorInput::Select(id, usize::try_from(spine).ok()));
+            }));
+        model.fill_tree(&widgets.tree);
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        message: Self::Input,
+        sender: ComponentSender<Self>,
+        root: &Self::Root,
+    ) {
+        match message {
+            EditorInput::Select(id, spine) => {
+                // rebuilding the tree selects the same item again
+                if id != self.selected {
+                    self.commit_buffer();
+                    self.selected = id;
+                    self.load_selected();
+                }
+                self.selected_spine = spine;
+            }
+            EditorInput::BufferChanged => highlight::highlight(&self.buffer, self.syntax),
+            EditorInput::AddFile => {
+                self.choose_file(
+                    root,
+                    &gettext("Add File"),
+                    &gettext("_Add"),
+                    None,
+                    EditorInput::FileChosen,
+                    &sender,
+                );
+            }
+            EditorInput::FileChosen(path) => {
+                self.file_chooser = None;
+                let data = match fs::read(&path) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        error!("Unable to read {:?}: {}", path, e);
+                        return;
+                    }
+                };
+                let href = self.new_href(&path);
+                // documents are added at the end of the reading order
+                let in_spine = media_type(&href) == "application/xhtml+xml";
+                match self.package.add_item(&href, data, in_spine) {
+                    Ok(id) => {
+                        self.commit_buffer();
+                        self.selected_spine = in_spine.then(|| self.package.spine().len() - 1);
+                        self.selected = Some(id);
+                        self.load_selected();
+                        self.fill_tree(&widgets.tree);
+                    }
+                    Err(e) => error!("Unable to add {:?} to the book: {}", path, e),
+                }
+            }
+            EditorInput::CoverFromFile => {
+                widgets.cover_menu.popdown();
+                let filter = gtk::FileFilter::new();
+                filter.set_name(Some(&gettext("Images")));
+                filter.add_pixbuf_formats();
+                self.choose_file(
+                    root,
+                    &gettext("Choose Cover"),
+                    &gettext("_Open"),
+                    Some(filter),
+                    EditorInput::CoverChosen,
+                    &sender,
+                );
+            }
+            EditorInput::CoverChosen(path) => {
+                self.file_chooser = None;
+                match fs::read(&path) {
+                    Ok(data) => sender.input(EditorInput::SetCover(data)),
+                    Err(e) => error!("Unable to read {:?}: {}", path, e),
+                }
+            }
+            EditorInput::CoverFromClipboard => {
+                widgets.cover_menu.popdown();
+                root.clipboard().read_texture_async(
+                    None::<&gtk::gio::Cancellable>,
+                    glib::clone!(@strong sender => move |result| match result {
+                        Ok(Some(texture)) => {
+                            sender.input(EditorInput::SetCover(texture.save_to_png_bytes().to_vec()));
+                        }
+                        Ok(None) => info!("The clipboard has no image"),
+                        Err(e) => error!("Unable to paste the cover: {}", e),
+                    }),
+                );
+            }
+            EditorInput::GenerateCover => {
+                widgets.cover_menu.popdown();
+                let metadata = self.package.metadata();
+                let title = metadata.title.unwrap_or_else(|| self.title.clone());
+                match cover::generate(&title, &metadata.authors) {
+                    Some(data) => sender.input(EditorInput::SetCover(data)),
+                    None => error!("Unable to generate a cover for {:?}", title),
+                }
+            }
+            EditorInput::SetCover(data) => {
+                // new covers keep the type of the one they replace
+                let media_type = self
+                    .package
+                    .cover()
+                    .map(|item| item.media_type.clone())
+                    .unwrap_or_default();
+                match cover::encode(data, &media_type).and_then(|data| self.package.set_cover(data))
+                {
+                    Ok(id) => {
+                        self.commit_buffer();
+                        self.selected = Some(id);
+                        self.selected_spine = None;
+                        self.load_selected();
+                        self.fill_tree(&widgets.tree);
+                    }
+                    Err(e) => error!("Unable to set the cover: {}", e),
+                }
+            }
+            EditorInput::RemoveFile => {
+                if let Some(id) = self.selected.take() {
+                    self.package.remove_item(&id);
+                    self.selected_spine = None;
+                    self.load_selected();
+                    self.fill_tree(&widgets.tree);
+                }
+            }
+            EditorInput::MoveUp | EditorInput::MoveDown => {
+                if let Some(index) = self.selected_spine {
+                    let to = match message {
+                        EditorInput::MoveUp => index.saturating_sub(1),
+                        _ => index + 1,
+                    };
+                    self.package.move_spine_item(index, to);
+                    self.selected_spine = Some(to.min(self.package.spine().len() - 1));
+                    self.fill_tree(&widgets.tree);
+                }
+            }
+            EditorInput::Check => {
+                // the book is checked as edited, saved or not
+                self.commit_buffer();
+                let report = self.package.check();
+                self.report = Some(
+                    ValidationReport::builder()
+                        .transient_for(root)
+                        .launch((self.title.clone(), report))
+                        .detach(),
+                );
+            }
+            EditorInput::Save => {
+                if self.save() {
+                    sender.output(EditorOutput::Saved).unwrap();
+                }
+            }
+            EditorInput::Close => {
+                self.commit_buffer();
+                if !self.package.is_modified() {
+                    sender.output(EditorOutput::Close).unwrap();
+                    return;
+                }
+                let window = root
+                    .root()
+                    .and_then(|root| root.downcast::<gtk::Window>().ok());
+                let dialog = adw::MessageDialog::new(
+                    window.as_ref(),
+                    Some(&gettext("Save Changes?")),
+                    Some(&gettext(
+                        "The book has unsaved changes. Changes which are not saved will be permanently lost.",
+                    )),
+                );
+                dialog.add_responses(&[
+                    ("cancel", &gettext("_Cancel")),
+                    ("discard", &gettext("_Discard")),
+                    ("save", &gettext("_Save")),
+                ]);
+                dialog.set_response_appearance("discard", adw::ResponseAppearance::Destructive);
+                dialog.set_response_appearance("save", adw::ResponseAppearance::Suggested);
+                dialog.set_default_response(Some("save"));
+                dialog.set_close_response("cancel");
+                dialog.connect_response(
+                    None,
+                    glib::clone!(@strong sender => move |_, response| {
+                        match response {
+                            "save" => sender.input(EditorInput::SaveAndClose),
+                            "discard" => sender.input(EditorInput::Discard),
+                            _ => {}
+                        }
+                    }),
+                );
+                dialog.present();
+            }
+            EditorInput::SaveAndClose => {
+                if self.save() {
+                    sender.output(EditorOutput::Saved).unwrap();
+                    sender.output(EditorOutput::Close).unwrap();
+                }
+            }
+            EditorInput::Discard => sender.output(EditorOutput::Close).unwrap(),
+        }
+        self.modified = self.package.is_modified() || self.buffer.is_modified();
+        self.update_view(widgets, sender);
+    }
+}
+
+impl BookxEditor {
+    fn selected_href(&self) -> String {
+        self.selected
+            .as_deref()
+            .and_then(|id| self.package.item(id))
+            .map(|item| item.href.clone())
+            .unwrap_or_default()
+    }
+
+    // Shows the manifest and spine, the spine first as it's the order the
+    // book is read in.
+    fn fill_tree(&self, tree: &gtk::TreeView) {
+        self.store.clear();
+        let mut selected = None;
+
+        let spine = self.store.insert_with_values(
+            None,
+            None,
+            &[
+                (COLUMN_LABEL, &gettext("Reading Order")),
+                (COLUMN_ID, &""),
+                (COLUMN_SPINE, &-1),
+            ],
+        );
+        for (index, item) in self.package.spine().iter().enumerate() {
+            let label = self
+                .package
+                .item(&item.idref)
+                .map(|manifest_item| manifest_item.href.clone())
+                .unwrap_or_else(|| item.idref.clone());
+            let iter = self.store.insert_with_values(
+                Some(&spine),
+                None,
+                &[
+                    (COLUMN_LABEL, &label),
+                    (COLUMN_ID, &item.idref),
+                    (COLUMN_SPINE, &(index as i32)),
+                ],
+            );
+            if self.selected_spine == Some(index) {
+                selected = Some(iter);
+            }
+        }
+
+        let manifest = self.store.insert_with_values(
+            None,
+            None,
+            &[
+                (COLUMN_LABEL, &gettext("Files")),
+                (COLUMN_ID, &""),
+                (COLUMN_SPINE, &-1),
+            ],
+        );
+        for item in self.package.manifest() {
+            let iter = self.store.insert_with_values(
+                Some(&manifest),
+                None,
+                &[
+                    (COLUMN_LABEL, &item.href),
+                    (COLUMN_ID, &item.id),
+                    (COLUMN_SPINE, &-1),
+                ],
+            );
+            if self.selected_spine.is_none() && self.selected.as_ref() == Some(&item.id) {
+                selected = Some(iter);
+            }
+        }
+
+        tree.expand_all();
+        if let Some(iter) = selected {
+            tree.selection().select_iter(&iter);
+        }
+    }
+
+    // shows the selected item in the source view, if it's text
+    fn load_selected(&mut self) {
+        let item = self
+            .selected
+            .as_deref()
+            .and_then(|id| self.package.item(id))
+            .cloned();
+        match item {
+            Some(item) if item.is_text() => {
+                let text = self
+                    .package
+                    .read(&item.id)
+                    .map(|data| String::from_utf8_lossy(data).into_owned())
+                    .unwrap_or_default();
+                self.syntax = Syntax::for_media_type(&item.media_type);
+                self.editable = true;
+                self.buffer.set_text(&text);
+            }
+            _ => {
+                self.syntax = Syntax::Plain;
+                self.editable = false;
+                self.buffer.set_text("");
+            }
+        }
+        self.buffer.set_modified(false);
+    }
+
+    // shows a file chooser, `message` is sent with the file picked
+    fn choose_file(
+        &mut self,
+        root: &gtk::Box,
+        title: &str,
+        accept: &str,
+        filter: Option<gtk::FileFilter>,
+        message: fn(PathBuf) -> EditorInput,
+        sender: &ComponentSender<Self>,
+    ) {
+        let window = root
+            .root()
+            .and_then(|root| root.downcast::<gtk::Window>().ok());
+        let chooser = gtk::FileChooserNative::new(
+            Some(title),
+            window.as_ref(),
+            gtk::FileChooserAction::Open,
+            Some(accept),
+            Some(&gettext("_Cancel")),
+        );
+        chooser.set_modal(true);
+        if let Some(filter) = filter {
+            chooser.add_filter(&filter);
+        }
+        chooser.connect_response(glib::clone!(@strong sender => move |chooser, response| {
+            if response == gtk::ResponseType::Accept {
+                if let Some(path) = chooser.file().and_then(|file| file.path()) {
+                    sender.input(message(path));
+                }
+            }
+        }));
+        chooser.show();
+        self.file_chooser = Some(chooser);
+    }
+
+    // puts the edits to the source back into the package
+    fn commit_buffer(&mut self) {
+        if !self.buffer.is_modified() {
+            return;
+        }
+        if let Some(id) = &self.selected {
+            let (start, end) = self.buffer.bounds();
+            let text = self.buffer.text(&start, &end, true);
+            self.package.replace(id, text.as_bytes().to_vec());
+        }
+        self.buffer.set_modified(false);
+    }
+
+    // Where a file added to the book goes: next to the files of the same
+    // type, under a name that isn't taken.
+    fn new_href(&self, path: &Path) -> String {
+        let name: String = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+            .chars()
+            .map(|c| {
+                if c.is_whitespace() || c == '#' || c == '%' {
+                    '_'
+                } else {
+                    c
+                }
+            })
+            .collect();
+        let media_type = media_type(&name);
+        let dir = self
+            .package
+            .manifest()
+            .iter()
+            .find(|item| item.media_type == media_type)
+            .and_then(|item| item.href.rsplit_once('/'))
+            .map(|(dir, _)| format!("{}/", dir))
+            .unwrap_or_default();
+        let (stem, extension) = match name.rsplit_once('.') {
+            Some((stem, extension)) => (stem.to_string(), format!(".{}", extension)),
+            None => (name.clone(), String::new()),
+        };
+        (0..)
+            .map(|n| match n {
+                0 => format!("{}{}", dir, name),
+                n => format!("{}{}-{}{}", dir, stem, n, extension),
+            })
+            .find(|href| {
+                !self
+                    .package
+                    .manifest()
+                    .iter()
+                    .any(|item| item.href == *href)
+            })
+            .unwrap_or_default()
+    }
+
+    fn save(&mut self) -> bool {
+        self.commit_buffer();
+        match self.package.save() {
+            Ok(()) => {
+                info!("Saved {:?}", self.package.path());
+                true
+            }
+            Err(e) => {
+                error!("Unable to save {:?}: {}", self.package.path(), e);
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+// Bookx - cover.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use relm4::gtk::gdk_pixbuf::{Pixbuf, PixbufLoader};
+use relm4::gtk::glib;
+use relm4::gtk::prelude::*;
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::formats::epub_writer::image_extension;
+use crate::formats::FormatError;
+
+// size of generated covers, the usual 2:3 of a book
+const WIDTH: i32 = 1200;
+const HEIGHT: i32 = 1800;
+const MARGIN: f64 = 120.0;
+
+// backgrounds from the GNOME palette, picked by title
+const BACKGROUNDS: &[(f64, f64, f64)] = &[
+    (0.11, 0.44, 0.85),
+    (0.15, 0.64, 0.41),
+    (0.78, 0.38, 0.0),
+    (0.75, 0.11, 0.16),
+    (0.38, 0.21, 0.51),
+    (0.39, 0.27, 0.18),
+    (0.24, 0.22, 0.27),
+];
+
+// A plain card with the title and authors, for books without a cover.
+pub fn generate(title: &str, authors: &[String]) -> Option<Vec<u8>> {
+    let surface = cairo::ImageSurface::create(cairo::Format::Rgb24, WIDTH, HEIGHT).ok()?;
+    {
+        let context = cairo::Context::new(&surface).ok()?;
+        let mut hasher = DefaultHasher::new();
+        title.hash(&mut hasher);
+        let (r, g, b) = BACKGROUNDS[hasher.finish() as usize % BACKGROUNDS.len()];
+        context.set_source_rgb(r, g, b);
+        context.paint().ok()?;
+
+        // a frame inside the margins
+        context.set_source_rgba(1.0, 1.0, 1.0, 0.6);
+        context.set_line_width(6.0);
+        context.rectangle(
+            MARGIN / 2.0,
+            MARGIN / 2.0,
+            WIDTH as f64 - MARGIN,
+            HEIGHT as f64 - MARGIN,
+        );
+        context.stroke().ok()?;
+
+        context.set_source_rgb(1.0, 1.0, 1.0);
+        context.select_font_face("Serif", cairo::FontSlant::Normal, cairo::FontWeight::Bold);
+        context.set_font_size(110.0);
+        let bottom = draw_lines(&context, title, HEIGHT as f64 / 3.0, 1.25)?;
+
+        context.select_font_face("Sans", cairo::FontSlant::Normal, cairo::FontWeight::Normal);
+        context.set_font_size(60.0);
+        draw_lines(&context, &authors.join(", "), bottom + 120.0, 1.4)?;
+    }
+
+    let mut data = Vec::new();
+    surface.write_to_png(&mut data).ok()?;
+    Some(data)
+}
+
+// Draws `text` centered and wrapped to the width of the card, starting at
+// `top`. Returns where the last line ends.
+fn draw_lines(context: &cairo::Context, text: &str, top: f64, spacing: f64) -> Option<f64> {
+    let width = WIDTH as f64 - 2.0 * MARGIN;
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        let line = match lines.last() {
+            Some(last) => format!("{} {}", last, word),
+            None => word.to_string(),
+        };
+        match lines.last_mut() {
+            Some(last) if context.text_extents(&line).ok()?.width() <= width => *last = line,
+            _ => lines.push(word.to_string()),
+        }
+    }
+
+    let line_height = context.font_extents().ok()?.height() * spacing;
+    let mut y = top;
+    for line in lines {
+        let extents = context.text_extents(&line).ok()?;
+        context.move_to(
+            (WIDTH as f64 - extents.width()) / 2.0 - extents.x_bearing(),
+            y,
+        );
+        context.show_text(&line).ok()?;
+        y += line_height;
+    }
+    Some(y)
+}
+
+// Encodes an image as `image/png` or `image/jpeg`, images already of that
+// type are kept as they are.
+pub fn encode(data: Vec<u8>, media_type: &str) -> Result<Vec<u8>, FormatError> {
+    let (extension, pixbuf_type) = match media_type {
+        "image/png" => ("png", "png"),
+        _ => ("jpg", "jpeg"),
+    };
+    if image_extension(&data) == Some(extension) {
+        return Ok(data);
+    }
+    let pixbuf = load(&data).map_err(|e| FormatError::Invalid(e.to_string()))?;
+    let options: &[(&str, &str)] = match pixbuf_type {
+        "jpeg" => &[("quality", "90")],
+        _ => &[],
+    };
+    pixbuf
+        .save_to_bufferv(pixbuf_type, options)
+        .map_err(|e| FormatError::Invalid(e.to_string()))
+}
+
+fn load(data: &[u8]) -> Result<Pixbuf, glib::Error> {
+    let loader = PixbufLoader::new();
+    loader.write(data)?;
+    loader.close()?;
+    loader
+        .pixbuf()
+        .ok_or_else(|| glib::Error::new(glib::FileError::Inval, "Unable to read the image"))
+}
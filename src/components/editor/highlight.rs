@@ -0,0 +1,202 @@
+// Bookx - highlight.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use relm4::gtk::{self, prelude::*};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    // XHTML, SVG, NCX and other XML
+    Markup,
+    Css,
+    Plain,
+}
+
+impl Syntax {
+    pub fn for_media_type(media_type: &str) -> Self {
+        match media_type {
+            "text/css" => Syntax::Css,
+            "text/plain" => Syntax::Plain,
+            _ => Syntax::Markup,
+        }
+    }
+}
+
+// colors from the GNOME palette, readable on light and dark backgrounds
+const TAGS: &[(&str, &str)] = &[
+    ("syntax-element", "#3584e4"),
+    ("syntax-attribute", "#e66100"),
+    ("syntax-value", "#2ec27e"),
+    ("syntax-comment", "#9a9996"),
+    ("syntax-property", "#c061cb"),
+];
+
+pub fn create_tags(buffer: &gtk::TextBuffer) {
+    let table = buffer.tag_table();
+    for (name, color) in TAGS {
+        table.add(
+            &gtk::TextTag::builder()
+                .name(*name)
+                .foreground(*color)
+                .build(),
+        );
+    }
+}
+
+// Colors the whole buffer again, books are split in files small enough
+// for that to keep up with typing.
+pub fn highlight(buffer: &gtk::TextBuffer, syntax: Syntax) {
+    let (start, end) = buffer.bounds();
+    buffer.remove_all_tags(&start, &end);
+    let text = buffer.text(&start, &end, true);
+    let spans = match syntax {
+        Syntax::Markup => markup_spans(&text),
+        Syntax::Css => css_spans(&text),
+        Syntax::Plain => Vec::new(),
+    };
+    for (tag, from, to) in spans {
+        let from = buffer.iter_at_offset(from as i32);
+        let to = buffer.iter_at_offset(to as i32);
+        buffer.apply_tag_by_name(tag, &from, &to);
+    }
+}
+
+// Tag name and character range of everything to color in markup.
+fn markup_spans(text: &str) -> Vec<(&'static str, usize, usize)> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '<' {
+            i += 1;
+            continue;
+        }
+        if starts_with(&chars, i, "<!--") {
+            let end = find(&chars, i + 4, "-->").map_or(chars.len(), |end| end + 3);
+            spans.push(("syntax-comment", i, end));
+            i = end;
+            continue;
+        }
+
+        // element name, with the angle bracket and slash
+        let start = i;
+        i += 1;
+        if chars.get(i) == Some(&'/') {
+            i += 1;
+        }
+        while i < chars.len() && !chars[i].is_whitespace() && !matches!(chars[i], '>' | '/') {
+            i += 1;
+        }
+        spans.push(("syntax-element", start, i));
+
+        // attributes up to the end of the tag
+        while i < chars.len() && chars[i] != '>' {
+            match chars[i] {
+                '"' | '\'' => {
+                    let quote = chars[i];
+                    let end = chars[i + 1..]
+                        .iter()
+                        .position(|&c| c == quote)
+                        .map_or(chars.len(), |end| i + 1 + end + 1);
+                    spans.push(("syntax-value", i, end));
+                    i = end;
+                }
+                c if c.is_alphabetic() => {
+                    let attribute = i;
+                    while i < chars.len()
+                        && (chars[i].is_alphanumeric() || matches!(chars[i], '-' | ':' | '_' | '.'))
+                    {
+                        i += 1;
+                    }
+                    spans.push(("syntax-attribute", attribute, i));
+                }
+                _ => i += 1,
+            }
+        }
+        if i < chars.len() {
+            spans.push(("syntax-element", i, i + 1));
+            i += 1;
+        }
+    }
+    spans
+}
+
+fn css_spans(text: &str) -> Vec<(&'static str, usize, usize)> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut spans = Vec::new();
+    let mut in_block = false;
+    // start of the selector or property being read
+    let mut token_start: Option<usize> = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if starts_with(&chars, i, "/*") {
+            let end = find(&chars, i + 2, "*/").map_or(chars.len(), |end| end + 2);
+            spans.push(("syntax-comment", i, end));
+            i = end;
+            continue;
+        }
+        match c {
+            '"' | '\'' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&other| other == c)
+                    .map_or(chars.len(), |end| i + 1 + end + 1);
+                spans.push(("syntax-value", i, end));
+                i = end;
+                continue;
+            }
+            '{' => {
+                if let Some(start) = token_start.take() {
+                    spans.push(("syntax-element", start, i));
+                }
+                in_block = true;
+            }
+            '}' => {
+                in_block = false;
+                token_start = None;
+            }
+            ':' if in_block => {
+                if let Some(start) = token_start.take() {
+                    spans.push(("syntax-property", start, i));
+                }
+                // the value runs up to the end of the declaration
+                let end = chars[i..]
+                    .iter()
+                    .position(|&c| c == ';' || c == '}')
+                    .map_or(chars.len(), |end| i + end);
+                spans.push(("syntax-value", i + 1, end));
+                i = end;
+                continue;
+            }
+            ';' => token_start = None,
+            c if !c.is_whitespace() && token_start.is_none() => token_start = Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    spans
+}
+
+fn starts_with(chars: &[char], at: usize, pattern: &str) -> bool {
+    pattern
+        .chars()
+        .enumerate()
+        .all(|(offset, c)| chars.get(at + offset) == Some(&c))
+}
+
+fn find(chars: &[char], from: usize, pattern: &str) -> Option<usize> {
+    (from..chars.len()).find(|&at| starts_with(chars, at, pattern))
+}
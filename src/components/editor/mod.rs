@@ -0,0 +1,5 @@
+mod bookx_editor;
+pub(crate) mod cover;
+mod highlight;
+
+pub use bookx_editor::{BookxEditor, EditorOutput};
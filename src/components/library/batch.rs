@@ -0,0 +1,335 @@
+// Bookx - batch.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::{gettext, ngettext};
+use relm4::gtk::{gio, prelude::*};
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use crate::formats::epub_package::{EpubPackage, MetadataChange};
+use crate::formats::{self, FormatError};
+
+// Work on many books at once, run one book after the other away from the
+// main thread.
+#[derive(Debug)]
+pub struct Batch {
+    // paths of the books, in the order they are shown
+    pub books: Vec<String>,
+    pub action: BatchAction,
+}
+
+impl Batch {
+    // puts back what an earlier batch changed
+    pub fn undo(steps: Vec<UndoStep>) -> Self {
+        let books = steps
+            .iter()
+            .map(|step| match step {
+                UndoStep::Package(book, _) | UndoStep::Trashed(book) => book.clone(),
+                UndoStep::Moved { from, .. } => from.clone(),
+            })
+            .collect();
+        Batch {
+            books,
+            action: BatchAction::Undo(steps),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BatchAction {
+    // with `renumber` the books get their position in the series from
+    // their order
+    Edit {
+        changes: Vec<MetadataChange>,
+        renumber: bool,
+    },
+    Move(PathBuf),
+    Delete,
+    Undo(Vec<UndoStep>),
+}
+
+// what it takes to put a book back the way it was before a batch
+#[derive(Debug)]
+pub enum UndoStep {
+    // the book and its package document before the changes
+    Package(String, String),
+    Trashed(String),
+    Moved { from: String, to: String },
+}
+
+#[derive(Debug, Default)]
+pub struct BatchResult {
+    // what was done, to tell the user
+    pub summary: String,
+    pub done: usize,
+    pub undo: Vec<UndoStep>,
+    pub errors: Vec<(String, FormatError)>,
+}
+
+// The books selected in the library and the batches run on them, one at
+// a time, with what the last one changed to undo it.
+#[derive(Debug, Default)]
+pub struct BatchController {
+    // selection mode, with the indices of the books selected in order
+    pub selecting: bool,
+    pub selected: Vec<usize>,
+    // batches waiting for the one running, and how far that one is
+    queue: VecDeque<Batch>,
+    progress: Option<(usize, usize)>,
+    // what the last batch changed, with its number so an older toast
+    // can't undo it
+    undo: Option<(u32, Vec<UndoStep>)>,
+    batches: u32,
+}
+
+impl BatchController {
+    pub fn progress(&self) -> Option<(usize, usize)> {
+        self.progress
+    }
+
+    pub fn push(&mut self, batch: Batch) {
+        self.queue.push_back(batch);
+    }
+
+    // a batch of the selected books of `books`, leaving selection mode
+    pub fn push_selected(&mut self, books: &[String], action: BatchAction) {
+        let books: Vec<String> = self
+            .selected
+            .iter()
+            .filter_map(|index| books.get(*index).cloned())
+            .collect();
+        self.selecting = false;
+        if !books.is_empty() {
+            self.push(Batch { books, action });
+        }
+    }
+
+    // queues undoing batch number `batch`, if it's still the last one
+    pub fn push_undo(&mut self, batch: u32) {
+        match self.undo.take() {
+            Some((number, steps)) if number == batch => self.push(Batch::undo(steps)),
+            undo => self.undo = undo,
+        }
+    }
+
+    // the batch to start, unless one is running
+    pub fn next(&mut self) -> Option<Batch> {
+        if self.progress.is_some() {
+            return None;
+        }
+        let batch = self.queue.pop_front()?;
+        self.progress = Some((0, batch.books.len()));
+        Some(batch)
+    }
+
+    pub fn advance(&mut self, done: usize) {
+        if let Some((_, total)) = self.progress {
+            self.progress = Some((done, total));
+        }
+    }
+
+    // ends the batch running, with the number to undo it by if it changed
+    // anything
+    pub fn finish(&mut self, undo: Vec<UndoStep>) -> Option<u32> {
+        self.progress = None;
+        self.batches += 1;
+        if undo.is_empty() {
+            return None;
+        }
+        self.undo = Some((self.batches, undo));
+        Some(self.batches)
+    }
+}
+
+// Runs the batch, `progress` is called with the number of books done after
+// each one.
+pub fn run(batch: Batch, progress: impl Fn(usize)) -> BatchResult {
+    let mut result = BatchResult::default();
+    match batch.action {
+        BatchAction::Undo(steps) => {
+            for (index, step) in steps.into_iter().enumerate() {
+                let (book, outcome) = match step {
+                    UndoStep::Package(book, opf) => {
+                        let outcome = restore_package(&book, opf);
+                        (book, outcome)
+                    }
+                    UndoStep::Trashed(book) => {
+                        let outcome = restore_from_trash(&book);
+                        (book, outcome)
+                    }
+                    UndoStep::Moved { from, to } => {
+                        let outcome = move_file(Path::new(&to), Path::new(&from));
+                        (from, outcome)
+                    }
+                };
+                result.record(book, outcome.map(|_| None));
+                progress(index + 1);
+            }
+            result.summary = gettext("Changes undone");
+        }
+        action => {
+            for (index, book) in batch.books.into_iter().enumerate() {
+                let outcome = match &action {
+                    BatchAction::Edit { changes, renumber } => {
+                        let position = if *renumber {
+                            Some(index as u32 + 1)
+                        } else {
+                            None
+                        };
+                        edit(&book, changes, position)
+                            .map(|opf| Some(UndoStep::Package(book.clone(), opf)))
+                    }
+                    BatchAction::Move(folder) => {
+                        let target = match Path::new(&book).file_name() {
+                            Some(name) => folder.join(name),
+                            None => continue,
+                        };
+                        move_file(Path::new(&book), &target).map(|_| {
+                            Some(UndoStep::Moved {
+                                from: book.clone(),
+                                to: target.display().to_string(),
+                            })
+                        })
+                    }
+                    BatchAction::Delete => gio::File::for_path(&book)
+                        .trash(None::<&gio::Cancellable>)
+                        .map(|_| Some(UndoStep::Trashed(book.clone())))
+                        .map_err(|e| FormatError::Invalid(e.to_string())),
+                    BatchAction::Undo(_) => unreachable!(),
+                };
+                result.record(book, outcome);
+                progress(index + 1);
+            }
+            let summary = match action {
+                BatchAction::Edit { .. } => {
+                    ngettext("%d book changed", "%d books changed", result.done as u32)
+                }
+                BatchAction::Move(_) => {
+                    ngettext("%d book moved", "%d books moved", result.done as u32)
+                }
+                _ => ngettext(
+                    "%d book moved to the trash",
+                    "%d books moved to the trash",
+                    result.done as u32,
+                ),
+            };
+            result.summary = summary.replace("%d", &result.done.to_string());
+        }
+    }
+    result
+}
+
+impl BatchResult {
+    fn record(&mut self, book: String, outcome: Result<Option<UndoStep>, FormatError>) {
+        match outcome {
+            Ok(step) => {
+                self.done += 1;
+                self.undo.extend(step);
+            }
+            Err(e) => self.errors.push((book, e)),
+        }
+    }
+}
+
+// Changes the metadata of an EPUB, returns its package document from
+// before so the changes can be undone.
+fn edit(
+    book: &str,
+    changes: &[MetadataChange],
+    position: Option<u32>,
+) -> Result<String, FormatError> {
+    let path = Path::new(book);
+    if !formats::detect(path)
+        .map(|format| format.editable)
+        .unwrap_or(false)
+    {
+        return Err(FormatError::Unsupported);
+    }
+    let mut package = EpubPackage::open(path)?;
+    let original = package.package_document();
+    for change in changes {
+        let change = match (change, position) {
+            (MetadataChange::Series(name, _), Some(position)) => {
+                MetadataChange::Series(name.clone(), Some(position))
+            }
+            (change, _) => change.clone(),
+        };
+        package.change_metadata(&change)?;
+    }
+    // numbering alone keeps the series each book is in
+    let has_series = changes
+        .iter()
+        .any(|change| matches!(change, MetadataChange::Series(..)));
+    if let (Some(position), false) = (position, has_series) {
+        package.change_metadata(&MetadataChange::Series(None, Some(position)))?;
+    }
+    package.save()?;
+    Ok(original)
+}
+
+fn restore_package(book: &str, opf: String) -> Result<(), FormatError> {
+    let mut package = EpubPackage::open(Path::new(book))?;
+    package.set_package_document(opf)?;
+    package.save()
+}
+
+// moving with gio works across file systems too
+fn move_file(from: &Path, to: &Path) -> Result<(), FormatError> {
+    if to.exists() {
+        return Err(FormatError::Invalid(format!(
+            "{} already exists",
+            to.display()
+        )));
+    }
+    gio::File::for_path(from)
+        .move_(
+            &gio::File::for_path(to),
+            gio::FileCopyFlags::NONE,
+            None::<&gio::Cancellable>,
+            None,
+        )
+        .map_err(|e| FormatError::Invalid(e.to_string()))
+}
+
+// Moves a book back from the trash to where it was deleted from.
+fn restore_from_trash(book: &str) -> Result<(), FormatError> {
+    let mut children = gio::File::for_uri("trash:///")
+        .enumerate_children(
+            "standard::name,trash::orig-path",
+            gio::FileQueryInfoFlags::NONE,
+            None::<&gio::Cancellable>,
+        )
+        .map_err(|e| FormatError::Invalid(e.to_string()))?;
+    while let Some(info) = children.next().and_then(|info| info.ok()) {
+        if info.attribute_byte_string("trash::orig-path").as_deref() == Some(book) {
+            // trashed files are only reachable by their uri
+            return children
+                .child(&info)
+                .move_(
+                    &gio::File::for_path(book),
+                    gio::FileCopyFlags::NONE,
+                    None::<&gio::Cancellable>,
+                    None,
+                )
+                .map_err(|e| FormatError::Invalid(e.to_string()));
+        }
+    }
+    Err(FormatError::Invalid(format!(
+        "{} is not in the trash",
+        book
+    )))
+}
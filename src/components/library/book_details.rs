@@ -0,0 +1,478 @@
+// Bookx - book_details.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::gettext;
+use relm4::{
+    adw::{self, prelude::*},
+    gtk::{self, glib},
+    Component, ComponentParts, ComponentSender,
+};
+
+use std::collections::BTreeMap;
+
+use crate::library_db::{
+    BookRecord, CustomField, FieldKind, FieldValue, ImportedMetadata, FIELD_KINDS,
+};
+
+#[derive(Debug)]
+pub struct BookDetails {
+    pub path: String,
+    pub title: String,
+    pub record: BookRecord,
+    pub fields: Vec<CustomField>,
+    pub write_to_books: bool,
+    // only files Bookx can write hold the fields
+    pub editable: bool,
+}
+
+// Tags and custom fields of a book, the fields themselves are defined here
+// too and apply to the whole library.
+pub struct BookxBookDetails {
+    path: String,
+    tags: String,
+    fields: Vec<CustomField>,
+    // values as typed, by field name
+    values: BTreeMap<String, String>,
+    write_to_books: bool,
+    editable: bool,
+    // what the dialog doesn't change is saved as it was
+    record: BookRecord,
+    fields_group: adw::PreferencesGroup,
+    // rows of the fields, to take them out again
+    rows: Vec<(String, gtk::Widget)>,
+    new_label: String,
+    new_kind: FieldKind,
+    new_values: String,
+}
+
+#[derive(Debug)]
+pub enum BookDetailsInput {
+    Tags(String),
+    // the field and its value as typed, empty to unset it
+    Value(String, String),
+    RemoveField(String),
+    NewFieldLabel(String),
+    NewFieldKind(u32),
+    NewFieldValues(String),
+    AddField,
+    WriteToBooks(bool),
+    Save,
+    Cancel,
+}
+
+#[derive(Debug)]
+pub enum BookDetailsOutput {
+    Save {
+        path: String,
+        fields: Vec<CustomField>,
+        record: BookRecord,
+        write_to_books: bool,
+    },
+}
+
+#[relm4_macros::component(pub)]
+impl Component for BookxBookDetails {
+    type Init = BookDetails;
+    type Input = BookDetailsInput;
+    type Output = BookDetailsOutput;
+    type CommandOutput = ();
+
+    view! {
+        #[name = "details_window"]
+        adw::Window {
+            set_title: Some(&details.title),
+            set_default_width: 520,
+            set_default_height: 640,
+            set_modal: true,
+
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                adw::HeaderBar {
+                    set_show_end_title_buttons: false,
+                    pack_start = &gtk::Button {
+                        set_label: &gettext("_Cancel"),
+                        set_use_underline: true,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(BookDetailsInput::Cancel);
+                        },
+                    },
+                    pack_end = &gtk::Button {
+                        set_label: &gettext("_Save"),
+                        set_use_underline: true,
+                        add_css_class: "suggested-action",
+                        #[watch]
+                        set_sensitive: model.is_valid(),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(BookDetailsInput::Save);
+                        },
+                    },
+                },
+
+                adw::PreferencesPage {
+                    set_vexpand: true,
+
+                    adw::PreferencesGroup {
+                        adw::EntryRow {
+                            set_title: &gettext("Tags, separated by commas"),
+                            set_text: &model.tags,
+                            connect_changed[sender] => move |row| {
+                                sender.input(BookDetailsInput::Tags(row.text().to_string()));
+                            },
+                        },
+                        adw::ActionRow {
+                            set_title: &gettext("Save in the Book File"),
+                            set_subtitle: &gettext("Tags and custom fields are written to EPUB books the way calibre keeps them"),
+                            set_sensitive: model.editable,
+
+                            #[name = "write_switch"]
+                            add_suffix = &gtk::Switch {
+                                set_valign: gtk::Align::Center,
+                                set_active: model.write_to_books,
+                                connect_active_notify[sender] => move |switch| {
+                                    sender.input(BookDetailsInput::WriteToBooks(switch.is_active()));
+                                },
+                            },
+                            set_activatable_widget: Some(&write_switch),
+                        },
+                    },
+
+                    adw::PreferencesGroup {
+                        set_title: &gettext("Imported from calibre"),
+                        set_visible: model.record.imported.is_some(),
+
+                        adw::ActionRow {
+                            set_title: &gettext("Authors"),
+                            set_subtitle: &authors,
+                        },
+                        adw::ActionRow {
+                            set_title: &gettext("Series"),
+                            set_subtitle: &series,
+                        },
+                        adw::ActionRow {
+                            set_title: &gettext("Rating"),
+                            set_subtitle: &rating,
+                        },
+                    },
+
+                    #[local_ref]
+                    fields_group -> adw::PreferencesGroup {
+                        set_title: &gettext("Custom Fields"),
+                        set_description: Some(&gettext("Fields are shared by all books of the library")),
+                    },
+
+                    adw::PreferencesGroup {
+                        set_title: &gettext("New Field"),
+
+                        #[name = "new_label_row"]
+                        adw::EntryRow {
+                            set_title: &gettext("Name"),
+                            connect_changed[sender] => move |row| {
+                                sender.input(BookDetailsInput::NewFieldLabel(row.text().to_string()));
+                            },
+                        },
+                        adw::ComboRow {
+                            set_title: &gettext("Type"),
+                            set_model: Some(&kinds),
+                            connect_selected_notify[sender] => move |row| {
+                                sender.input(BookDetailsInput::NewFieldKind(row.selected()));
+                            },
+                        },
+                        #[name = "new_values_row"]
+                        adw::EntryRow {
+                            set_title: &gettext("Choices, separated by commas"),
+                            #[watch]
+                            set_visible: model.new_kind == FieldKind::Enum,
+                            connect_changed[sender] => move |row| {
+                                sender.input(BookDetailsInput::NewFieldValues(row.text().to_string()));
+                            },
+                        },
+                        adw::ActionRow {
+                            add_suffix = &gtk::Button {
+                                set_label: &gettext("_Add Field"),
+                                set_use_underline: true,
+                                set_valign: gtk::Align::Center,
+                                #[watch]
+                                set_sensitive: model.new_field().is_some(),
+                                connect_clicked[sender] => move |_| {
+                                    sender.input(BookDetailsInput::AddField);
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+        }
+    }
+
+    fn init(
+        details: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let fields_group = adw::PreferencesGroup::new();
+        let kinds = gtk::StringList::new(&[]);
+        for kind in FIELD_KINDS {
+            kinds.append(&kind.label());
+        }
+        let values = details
+            .record
+            .fields
+            .iter()
+            .map(|(name, value)| (name.clone(), value.to_text()))
+            .collect();
+        let (authors, series, rating) = match &details.record.imported {
+            Some(imported) => imported_rows(imported),
+            None => Default::default(),
+        };
+        let mut model = BookxBookDetails {
+            path: details.path.clone(),
+            tags: details.record.tags.join(", "),
+            fields: Vec::new(),
+            values,
+            write_to_books: details.write_to_books,
+            editable: details.editable,
+            record: details.record.clone(),
+            fields_group: fields_group.clone(),
+            rows: Vec::new(),
+            new_label: String::new(),
+            new_kind: FieldKind::Text,
+            new_values: String::new(),
+        };
+        for field in details.fields.iter().cloned() {
+            model.add_field(field, &sender);
+        }
+        let widgets = view_output!();
+        root.present();
+        ComponentParts { model, widgets }
+    }
+
+    fn update_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        message: Self::Input,
+        sender: ComponentSender<Self>,
+        root: &Self::Root,
+    ) {
+        match message {
+            BookDetailsInput::Tags(tags) => self.tags = tags,
+            BookDetailsInput::Value(name, text) => {
+                self.values.insert(name, text);
+            }
+            BookDetailsInput::RemoveField(name) => {
+                self.fields.retain(|field| field.name != name);
+                self.values.remove(&name);
+                if let Some(index) = self.rows.iter().position(|(row_name, _)| *row_name == name) {
+                    let (_, row) = self.rows.remove(index);
+                    self.fields_group.remove(&row);
+                }
+            }
+            BookDetailsInput::NewFieldLabel(label) => self.new_label = label,
+            BookDetailsInput::NewFieldKind(index) => {
+                self.new_kind = FIELD_KINDS
+                    .get(index as usize)
+                    .copied()
+                    .unwrap_or(FieldKind::Text);
+            }
+            BookDetailsInput::NewFieldValues(values) => self.new_values = values,
+            BookDetailsInput::AddField => {
+                if let Some(field) = self.new_field() {
+                    self.add_field(field, &sender);
+                    widgets.new_label_row.set_text("");
+                    widgets.new_values_row.set_text("");
+                }
+            }
+            BookDetailsInput::WriteToBooks(write) => self.write_to_books = write,
+            BookDetailsInput::Save => {
+                let record = BookRecord {
+                    tags: split_list(&self.tags),
+                    fields: self
+                        .fields
+                        .iter()
+                        .filter_map(|field| {
+                            let value = FieldValue::parse(field, self.values.get(&field.name)?)?;
+                            Some((field.name.clone(), value))
+                        })
+                        .collect(),
+                    ..self.record.clone()
+                };
+                sender
+                    .output(BookDetailsOutput::Save {
+                        path: self.path.clone(),
+                        fields: self.fields.clone(),
+                        record,
+                        write_to_books: self.write_to_books,
+                    })
+                    .unwrap();
+                root.close();
+            }
+            BookDetailsInput::Cancel => root.close(),
+        }
+        self.update_view(widgets, sender);
+    }
+}
+
+impl BookxBookDetails {
+    // values that don't read as their field's type keep the dialog from
+    // saving
+    fn is_valid(&self) -> bool {
+        self.fields
+            .iter()
+            .all(|field| match self.values.get(&field.name) {
+                Some(text) if !text.trim().is_empty() => FieldValue::parse(field, text).is_some(),
+                _ => true,
+            })
+    }
+
+    // the field described in the new field group, if it can be added
+    fn new_field(&self) -> Option<CustomField> {
+        let label = self.new_label.trim();
+        let name: String = label
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        let values = split_list(&self.new_values);
+        let usable = name
+            .chars()
+            .next()
+            .map(char::is_alphabetic)
+            .unwrap_or(false)
+            && !self.fields.iter().any(|field| field.name == name)
+            && (self.new_kind != FieldKind::Enum || !values.is_empty());
+        usable.then(|| CustomField {
+            name,
+            label: label.to_string(),
+            kind: self.new_kind,
+            values,
+        })
+    }
+
+    fn add_field(&mut self, field: CustomField, sender: &ComponentSender<Self>) {
+        let value = self.values.get(&field.name).cloned().unwrap_or_default();
+        let name = field.name.clone();
+        let row: gtk::Widget = match field.kind {
+            FieldKind::Text | FieldKind::Number | FieldKind::Date => {
+                let row = adw::EntryRow::new();
+                row.set_title(&match field.kind {
+                    FieldKind::Date => gettext("%s, as YYYY-MM-DD").replace("%s", &field.label),
+                    _ => field.label.clone(),
+                });
+                row.set_text(&value);
+                let field = field.clone();
+                row.connect_changed(glib::clone!(@strong sender => move |row| {
+                    let text = row.text().to_string();
+                    if text.trim().is_empty() || FieldValue::parse(&field, &text).is_some() {
+                        row.remove_css_class("error");
+                    } else {
+                        row.add_css_class("error");
+                    }
+                    sender.input(BookDetailsInput::Value(field.name.clone(), text));
+                }));
+                row.add_suffix(&self.remove_button(&name, sender));
+                row.upcast()
+            }
+            FieldKind::Boolean => {
+                let row = adw::ActionRow::new();
+                row.set_title(&field.label);
+                let switch = gtk::Switch::new();
+                switch.set_valign(gtk::Align::Center);
+                switch.set_active(value == "yes");
+                switch.connect_active_notify(
+                    glib::clone!(@strong sender, @strong name => move |switch| {
+                        let text = if switch.is_active() { "yes" } else { "no" };
+                        sender.input(BookDetailsInput::Value(name.clone(), text.to_string()));
+                    }),
+                );
+                row.add_suffix(&switch);
+                row.set_activatable_widget(Some(&switch));
+                row.add_suffix(&self.remove_button(&name, sender));
+                row.upcast()
+            }
+            FieldKind::Enum => {
+                let row = adw::ComboRow::new();
+                row.set_title(&field.label);
+                let none = gettext("None");
+                let mut choices = vec![none.as_str()];
+                choices.extend(field.values.iter().map(String::as_str));
+                row.set_model(Some(&gtk::StringList::new(&choices)));
+                let selected = field
+                    .values
+                    .iter()
+                    .position(|choice| *choice == value)
+                    .map(|index| index + 1)
+                    .unwrap_or(0);
+                row.set_selected(selected as u32);
+                let values = field.values.clone();
+                row.connect_selected_notify(
+                    glib::clone!(@strong sender, @strong name => move |row| {
+                        let text = match row.selected() {
+                            0 => String::new(),
+                            index => values.get(index as usize - 1).cloned().unwrap_or_default(),
+                        };
+                        sender.input(BookDetailsInput::Value(name.clone(), text));
+                    }),
+                );
+                row.add_suffix(&self.remove_button(&name, sender));
+                row.upcast()
+            }
+        };
+        self.fields_group.add(&row);
+        self.rows.push((name, row));
+        self.fields.push(field);
+    }
+
+    fn remove_button(&self, name: &str, sender: &ComponentSender<Self>) -> gtk::Button {
+        let button = gtk::Button::from_icon_name("user-trash-symbolic");
+        button.set_valign(gtk::Align::Center);
+        button.add_css_class("flat");
+        button.set_tooltip_text(Some(&gettext("Remove the Field from All Books")));
+        let name = name.to_string();
+        button.connect_clicked(glib::clone!(@strong sender => move |_| {
+            sender.input(BookDetailsInput::RemoveField(name.clone()));
+        }));
+        button
+    }
+}
+
+fn split_list(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// authors, series and rating as shown
+fn imported_rows(imported: &ImportedMetadata) -> (String, String, String) {
+    let none = || gettext("None");
+    let authors = Some(imported.authors.join(", "))
+        .filter(|authors| !authors.is_empty())
+        .unwrap_or_else(none);
+    let series = match &imported.series {
+        Some(series) => format!("{} #{}", series, imported.series_index),
+        None => none(),
+    };
+    let rating = match imported.rating {
+        Some(rating) => {
+            let full = rating.floor() as usize;
+            let half = if rating.fract() >= 0.5 { "½" } else { "" };
+            format!("{}{}", "★".repeat(full), half)
+        }
+        None => none(),
+    };
+    (authors, series, rating)
+}
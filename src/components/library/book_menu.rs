@@ -0,0 +1,94 @@
+// Bookx - book_menu.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::gettext;
+use relm4::gtk::{self, gdk, glib, prelude::*};
+use relm4::ComponentSender;
+
+use std::path::Path;
+
+use crate::components::library::{BookxLibrary, LibraryInput};
+use crate::formats;
+
+// Context menu of a book in the library, to open it in a tab or a window
+// of its own, or to work on it.
+#[derive(Debug)]
+pub struct BookMenu {
+    popover: gtk::Popover,
+    edit_button: gtk::Button,
+    convert_button: gtk::Button,
+    check_button: gtk::Button,
+    // the book it was opened on
+    book: Option<usize>,
+}
+
+impl BookMenu {
+    pub fn new(library: &gtk::FlowBox, sender: &ComponentSender<BookxLibrary>) -> Self {
+        let popover = gtk::Popover::new();
+        popover.set_has_arrow(false);
+        popover.set_position(gtk::PositionType::Bottom);
+        popover.set_halign(gtk::Align::Start);
+        popover.set_parent(library);
+
+        let menu_box = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        let button = |label: &str, message: fn() -> LibraryInput| {
+            let button = gtk::Button::with_label(label);
+            button.add_css_class("flat");
+            button.connect_clicked(glib::clone!(@strong sender, @weak popover => move |_| {
+                popover.popdown();
+                sender.input(message());
+            }));
+            menu_box.append(&button);
+            button
+        };
+        button(&gettext("Open in New Tab"), || LibraryInput::OpenInNewTab);
+        button(&gettext("Open in New Window"), || {
+            LibraryInput::OpenInNewWindow
+        });
+        button(&gettext("Details"), || LibraryInput::ShowDetails);
+        let edit_button = button(&gettext("Edit"), || LibraryInput::EditBook);
+        let convert_button = button(&gettext("Convert to EPUB"), || LibraryInput::ConvertToEpub);
+        let check_button = button(&gettext("Check Book"), || LibraryInput::CheckBook);
+        popover.set_child(Some(&menu_box));
+
+        BookMenu {
+            popover,
+            edit_button,
+            convert_button,
+            check_button,
+            book: None,
+        }
+    }
+
+    // opens the menu on book `index` at `path`, where it was clicked
+    pub fn popup(&mut self, index: usize, path: &str, x: f64, y: f64) {
+        let format = formats::detect(Path::new(path));
+        let editable = format.map(|format| format.editable).unwrap_or(false);
+        self.edit_button.set_sensitive(editable);
+        self.convert_button
+            .set_sensitive(format.map(|format| format.convertible).unwrap_or(false));
+        self.check_button.set_sensitive(editable);
+        self.book = Some(index);
+        self.popover
+            .set_pointing_to(Some(&gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+        self.popover.popup();
+    }
+
+    // the book the menu was opened on, once
+    pub fn take(&mut self) -> Option<usize> {
+        self.book.take()
+    }
+}
@@ -14,27 +14,48 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use epub::doc::{DocError, EpubDoc};
+use gettextrs::gettext;
 use relm4::{
-    gtk::{self, gdk_pixbuf::Pixbuf, glib, prelude::*},
+    gtk::{
+        self,
+        gdk_pixbuf::{Colorspace, Pixbuf},
+        glib,
+        prelude::*,
+    },
     ComponentParts, ComponentSender, SimpleComponent,
 };
-use tracing::error;
+use tracing::{error, warn};
 
+use std::collections::hash_map::DefaultHasher;
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
 
+use crate::formats::{Format, FormatError};
+
+#[derive(Debug, Clone)]
 pub struct BookxBook {
+    pub path: String,
     pub title: String,
+    pub authors: Vec<String>,
     pub progress: f64,
     pub pixbuf: Pixbuf,
+    // where the cover image was cached, if the book has one
+    pub cover: Option<PathBuf>,
+    // open in a tab or a window
+    pub open: bool,
+}
+
+#[derive(Debug)]
+pub enum BookxBookInput {
+    SetOpen(bool),
 }
 
 #[relm4_macros::component(pub)]
 impl SimpleComponent for BookxBook {
     type Init = Self;
-    type Input = ();
+    type Input = BookxBookInput;
     type Output = ();
 
     view! {
@@ -72,6 +93,14 @@ impl SimpleComponent for BookxBook {
                         set_max_value: 100.0,
                         set_width_request: 70,
                         set_orientation: gtk::Orientation::Horizontal
+                    },
+                    gtk::Image {
+                        set_icon_name: Some("document-open-symbolic"),
+                        set_tooltip_text: Some(&gettext("Open")),
+                        set_margin_start: 6,
+                        add_css_class: "accent",
+                        #[watch]
+                        set_visible: model.open,
                     }
                 }
 
@@ -84,87 +113,126 @@ impl SimpleComponent for BookxBook {
         let widgets = view_output!();
         ComponentParts { model, widgets }
     }
+
+    fn update(&mut self, message: Self::Input, _sender: ComponentSender<Self>) {
+        match message {
+            BookxBookInput::SetOpen(open) => self.open = open,
+        }
+    }
 }
 
 impl BookxBook {
-    pub fn load_book(book_path: String) -> Result<Self, DocError> {
-        match EpubDoc::new(book_path.clone()) {
-            Ok(mut doc) => {
-                let identifier = match doc.mdata("identifier") {
-                    Some(identifier) => identifier,
-                    None => {
-                        error!("Cannot find MetaData `identifier` for Book at path: {:?}, skipped loading.", book_path);
-                        return Err(DocError::InvalidEpub);
-                    }
-                };
-                let title = match doc.mdata("title") {
-                    Some(title) => title,
-                    None => {
-                        error!(
-                            "Cannot find MetaData `title` for Book at path: {:?}. skipped loading.",
-                            book_path
-                        );
-                        return Err(DocError::InvalidEpub);
-                    }
-                };
-                let cover_path: PathBuf = glib::user_cache_dir()
-                    .join("cover_images")
-                    .join(format!("{}.png", identifier));
-
-                // TODO: not rewrite if exists
-                match doc.get_cover() {
-                    Some((cover_data, _)) => {
-                        let parent = cover_path.parent().unwrap();
-                        match fs::create_dir_all(parent) {
-                            Ok(()) => {
-                                let mut f = fs::File::create(cover_path.clone()).unwrap();
-                                let resp = f.write_all(&cover_data);
-                                tracing::debug!(
-                                    "Writing book cover for: {:?}; Response: {:?}",
-                                    book_path,
-                                    resp
-                                );
-                            }
-                            Err(e) => {
-                                error!(
-                                    "Error occurred when creating `cover_images` dir for
-                                        bookx cache."
-                                );
-                            }
-                        };
+    // `format` is what the library scan recognized the file as
+    pub fn load_book(book_path: String, format: &'static Format) -> Result<Self, FormatError> {
+        let mut source = match format.open(Path::new(&book_path)) {
+            Ok(source) => source,
+            Err(err) => {
+                error!("Error when loading book: {:?}: {}", book_path, err);
+                return Err(err);
+            }
+        };
+        let metadata = source.metadata();
+        // only EPUB, the one format Bookx writes, is expected to carry an
+        // identifier, other formats are keyed on their location instead
+        let identifier = match metadata.identifier {
+            Some(identifier) => identifier,
+            None if !format.editable => path_identifier(&book_path),
+            None => {
+                error!(
+                    "Cannot find MetaData `identifier` for Book at path: {:?}, skipped loading.",
+                    book_path
+                );
+                return Err(FormatError::Invalid("missing identifier".into()));
+            }
+        };
+        let title = match metadata.title {
+            Some(title) => title,
+            None => {
+                error!(
+                    "Cannot find MetaData `title` for Book at path: {:?}. skipped loading.",
+                    book_path
+                );
+                return Err(FormatError::Invalid("missing title".into()));
+            }
+        };
+        let cover_path: PathBuf = glib::user_cache_dir()
+            .join("cover_images")
+            .join(format!("{}.png", identifier));
+
+        // the cover is only extracted again once the book has changed
+        let has_cover = is_fresh(&cover_path, Path::new(&book_path))
+            || match source.cover() {
+                Some(cover_data) => match save_cover(&cover_path, &cover_data) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        error!("Unable to save the cover of {:?}: {}", book_path, e);
+                        false
                     }
-                    None => {
-                        error!("Cannot find cover for Book at path: {:?}", book_path);
-                        return Err(DocError::InvalidEpub);
+                },
+                None => {
+                    if format.editable {
+                        warn!("Cannot find cover for Book at path: {:?}", book_path);
                     }
-                };
-
-                let model = BookxBook {
-                    title,
-                    progress: 40.0,
-                    pixbuf: Pixbuf::from_file_at_scale(cover_path, 180, 180, true).unwrap(),
-                };
-
-                Ok(model)
-            }
-            Err(err) => match err {
-                DocError::ArchiveError(e) => {
-                    error!("Error when loading book: {:?} ", e);
-                    Err(DocError::ArchiveError(e))
-                }
-                DocError::XmlError(e) => {
-                    error!("Error when loading book: {:?}", e);
-                    Err(DocError::XmlError(e))
+                    false
                 }
-                DocError::IOError(e) => {
-                    error!("Error when loading book: {:?}", e);
-                    Err(DocError::IOError(e))
+            };
+        let mut cover = None;
+        // books without a cover still show up, so one can be set
+        let pixbuf = if has_cover {
+            match Pixbuf::from_file_at_scale(&cover_path, 180, 180, true) {
+                Ok(pixbuf) => {
+                    cover = Some(cover_path);
+                    pixbuf
                 }
-                DocError::InvalidEpub => {
-                    error!("The provided epub:{} is Invalid", book_path);
-                    Err(err)
+                Err(e) => {
+                    warn!("Unable to read the cover of {:?}: {}", book_path, e);
+                    placeholder_cover()
                 }
-            },
-        }
+            }
+        } else {
+            placeholder_cover()
+        };
+
+        let model = BookxBook {
+            path: book_path,
+            title,
+            authors: metadata.authors,
+            // set from the reading progress kept in the library
+            progress: 0.0,
+            pixbuf,
+            cover,
+            open: false,
+        };
+
+        Ok(model)
+    }
+}
+
+// whether the cover at `cover` was saved after the book last changed
+fn is_fresh(cover: &Path, book: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+    match (modified(cover), modified(book)) {
+        (Ok(cover), Ok(book)) => cover >= book,
+        _ => false,
+    }
+}
+
+fn save_cover(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
     }
+    fs::write(path, data)
+}
+
+fn path_identifier(book_path: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    book_path.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+// plain cover for books whose format has no cover image
+fn placeholder_cover() -> Pixbuf {
+    let pixbuf = Pixbuf::new(Colorspace::Rgb, false, 8, 120, 180).unwrap();
+    pixbuf.fill(0x9a9996ff);
+    pixbuf
 }
@@ -14,58 +14,1256 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::components::library::BookxBook;
-use crate::components::utils;
-use gettextrs::gettext;
+use crate::calibre::{self, CalibreLibrary};
+use crate::components::editor::cover;
+use crate::components::library::batch::{self, Batch, BatchAction, BatchController, BatchResult};
+use crate::components::library::book_details::{BookDetails, BookDetailsOutput, BookxBookDetails};
+use crate::components::library::book_menu::BookMenu;
+use crate::components::library::bulk_edit::{BookxBulkEdit, BulkEditOutput};
+use crate::components::library::duplicate_finder::{BookxDuplicateFinder, DuplicateFinderOutput};
+use crate::components::library::duplicates::{self, DuplicateGroup};
+use crate::components::library::import::import_book;
+use crate::components::library::new_book::{BookxNewBook, NewBook, NewBookOutput};
+use crate::components::library::reading_stats::ReadingStats;
+use crate::components::library::shelves::{shelf_options, Shelves};
+use crate::components::library::sorting::{sort_options, Sorting};
+use crate::components::library::statistics::{BookxStatistics, StatisticsInit};
+use crate::components::library::{BookxBook, BookxBookInput};
+use crate::components::{utils, ValidationReport};
+use crate::config::APP_ID;
+use crate::dbus_api;
+use crate::formats::epub_check::{self, Report};
+use crate::formats::epub_package::MetadataChange;
+use crate::formats::{self, epub_writer, FormatError};
+use crate::library_db::{
+    BookRecord, CatalogEntry, CustomField, Filter, ImportedMetadata, LibraryDb, ReadingSession,
+    SortKey,
+};
+use gettextrs::{gettext, ngettext};
 use gtk::prelude::*;
 use relm4::Component;
 use relm4::{
-    gtk::{self, gio},
-    ComponentController, ComponentParts, ComponentSender, SimpleComponent,
+    adw,
+    gtk::{self, gdk, gdk_pixbuf::Pixbuf, gio, glib},
+    ComponentController, ComponentParts, ComponentSender, Controller, RelmWidgetExt,
 };
+use tracing::{error, info, warn};
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// changes to the folder are collected for this long before rescanning
+const RESCAN_DELAY: Duration = Duration::from_secs(2);
 
 // responsible for displaying
-pub struct BookxLibrary {}
+pub struct BookxLibrary {
+    content_dir: String,
+    // whether the folder is a calibre library, whose metadata is imported
+    calibre: bool,
+    // paths and titles of the books in the order they are shown
+    books: Vec<String>,
+    titles: Vec<String>,
+    items: Vec<Controller<BookxBook>>,
+    // books found by the last scan, as loaded
+    loaded: Vec<BookxBook>,
+    // number of the last scan started, older ones are dropped
+    scans: u32,
+    // books open for reading, in any window
+    open_books: Vec<String>,
+    db: LibraryDb,
+    filter: Filter,
+    shelves: Shelves,
+    sorting: Sorting,
+    // one for the library folder and each folder in it, kept alive for as
+    // long as the library is, so changes to them trigger a rescan
+    monitors: Vec<gio::FileMonitor>,
+    rescan_pending: bool,
+    book_menu: BookMenu,
+    // validation report of the last book checked
+    report: Option<Controller<ValidationReport>>,
+    new_book: Option<Controller<BookxNewBook>>,
+    details: Option<Controller<BookxBookDetails>>,
+    bulk_edit: Option<Controller<BookxBulkEdit>>,
+    duplicates: Option<Controller<BookxDuplicateFinder>>,
+    statistics: Option<Controller<BookxStatistics>>,
+    file_chooser: Option<gtk::FileChooserNative>,
+    batch: BatchController,
+}
+
+#[derive(Debug)]
+pub enum LibraryInput {
+    BookActivated(usize),
+    // secondary click on a book, with where it happened in the library
+    BookMenu(usize, f64, f64),
+    OpenInNewTab,
+    OpenInNewWindow,
+    ShowDetails,
+    SaveDetails {
+        path: String,
+        fields: Vec<CustomField>,
+        record: BookRecord,
+        write_to_books: bool,
+    },
+    Filter(String),
+    // index in the shelves, all books at zero
+    ShowShelf(u32),
+    // saves the filter typed as a shelf, under the name given
+    SaveShelf,
+    RemoveShelf,
+    SortBy(u32),
+    EditBook,
+    ConvertToEpub,
+    CheckBook,
+    // asks for the chapters and metadata of a new book
+    NewBook,
+    CreateBook(NewBook),
+    SelectionMode(bool),
+    ToggleSelectionMode,
+    SelectionChanged,
+    EditSelected,
+    EditMetadata(Vec<MetadataChange>, bool),
+    ChooseFolder,
+    MoveSelected(PathBuf),
+    DeleteSelected,
+    // undoes the batch with that number
+    Undo(u32),
+    FindDuplicates,
+    TrashDuplicates(Vec<String>),
+    RecordSession(ReadingSession),
+    // percent read and a tag, asked for over D-Bus
+    SetProgress(String, f64),
+    AddTag(String, String),
+    // copies a book from elsewhere into the library folder
+    Import(PathBuf),
+    ShowStatistics,
+    // another folder was chosen for the library
+    SetRoot(String, bool),
+    FolderChanged,
+    Rescan,
+    OpenBooks(Vec<String>),
+}
+
+#[derive(Debug)]
+pub enum LibraryOutput {
+    OpenBook(String),
+    OpenInNewTab(String),
+    OpenInNewWindow(String),
+    EditBook(String),
+    // paths of all books in the library, sent after every scan
+    BooksChanged(Vec<String>),
+}
+
+#[derive(Debug)]
+pub enum LibraryCommand {
+    Converted(String, Result<PathBuf, FormatError>),
+    Checked(String, Result<Report, FormatError>),
+    Created(Result<PathBuf, FormatError>),
+    // number of books of the running batch done
+    BatchProgress(usize),
+    BatchDone(BatchResult),
+    DuplicatesFound(Vec<DuplicateGroup>),
+    CalibreRead(Result<CalibreLibrary, String>),
+    Imported(PathBuf, Result<PathBuf, FormatError>),
+    // books found by the scan with that number
+    Scanned(u32, Vec<BookxBook>),
+}
 
 #[relm4_macros::component(pub)]
-impl SimpleComponent for BookxLibrary {
-    type Init = String;
-    type Input = ();
-    type Output = ();
+impl Component for BookxLibrary {
+    // folder of the books and whether it's a calibre library
+    type Init = (String, bool);
+    type Input = LibraryInput;
+    type Output = LibraryOutput;
+    type CommandOutput = LibraryCommand;
 
     view! {
-        #[name = "library"]
-        gtk::FlowBox {
-            set_activate_on_single_click: true,
-            set_column_spacing: 12,
-            set_row_spacing: 12,
-            set_focus_on_click: true,
-            set_selection_mode: gtk::SelectionMode::None,
-            set_visible: true,
-            set_valign: gtk::Align::Start,
-            set_max_children_per_line: 100,
+        #[name = "toasts"]
+        adw::ToastOverlay {
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                gtk::Box {
+                    set_spacing: 6,
+                    set_margin_all: 6,
+
+                    #[local_ref]
+                    shelves -> gtk::DropDown {
+                        set_tooltip_text: Some(&gettext("Shelf")),
+                        connect_selected_notify[sender] => move |shelves| {
+                            sender.input(LibraryInput::ShowShelf(shelves.selected()));
+                        },
+                    },
+                    #[name = "search"]
+                    gtk::SearchEntry {
+                        set_hexpand: true,
+                        set_placeholder_text: Some(&gettext("Filter by title, tag:name or #field:value")),
+                        connect_search_changed[sender] => move |entry| {
+                            sender.input(LibraryInput::Filter(entry.text().to_string()));
+                        },
+                    },
+                    #[local_ref]
+                    sort_by -> gtk::DropDown {
+                        set_tooltip_text: Some(&gettext("Sort By")),
+                        connect_selected_notify[sender] => move |sort_by| {
+                            sender.input(LibraryInput::SortBy(sort_by.selected()));
+                        },
+                    },
+                    gtk::MenuButton {
+                        set_icon_name: "view-more-symbolic",
+                        set_tooltip_text: Some(&gettext("Shelves")),
+
+                        #[wrap(Some)]
+                        #[name = "shelf_menu"]
+                        set_popover = &gtk::Popover {
+                            gtk::Box {
+                                set_orientation: gtk::Orientation::Vertical,
+                                set_spacing: 6,
+
+                                #[name = "shelf_name"]
+                                gtk::Entry {
+                                    set_placeholder_text: Some(&gettext("Shelf Name")),
+                                    connect_activate[sender] => move |_| {
+                                        sender.input(LibraryInput::SaveShelf);
+                                    },
+                                },
+                                gtk::Button {
+                                    set_label: &gettext("_Save Filter as Shelf"),
+                                    set_use_underline: true,
+                                    #[watch]
+                                    set_sensitive: !model.filter.is_empty(),
+                                    connect_clicked[sender] => move |_| {
+                                        sender.input(LibraryInput::SaveShelf);
+                                    },
+                                },
+                                gtk::Button {
+                                    set_label: &gettext("_Remove Shelf"),
+                                    set_use_underline: true,
+                                    add_css_class: "flat",
+                                    #[watch]
+                                    set_sensitive: model.shelves.is_shown(),
+                                    connect_clicked[sender] => move |_| {
+                                        sender.input(LibraryInput::RemoveShelf);
+                                    },
+                                },
+                            },
+                        },
+                    },
+                },
+
+                #[name = "library_scroll"]
+                gtk::ScrolledWindow {
+                    set_hscrollbar_policy: gtk::PolicyType::Never,
+                    set_vexpand: true,
+
+                    gtk::Viewport {
+                        set_scroll_to_focus: true,
+
+                        #[local_ref]
+                        library -> gtk::FlowBox {
+                            set_activate_on_single_click: true,
+                            set_column_spacing: 12,
+                            set_row_spacing: 12,
+                            set_focus_on_click: true,
+                            #[watch]
+                            set_selection_mode: if model.batch.selecting {
+                                gtk::SelectionMode::Multiple
+                            } else {
+                                gtk::SelectionMode::None
+                            },
+                            set_visible: true,
+                            set_valign: gtk::Align::Start,
+                            set_max_children_per_line: 100,
+                            connect_child_activated[sender] => move |_, child| {
+                                sender.input(LibraryInput::BookActivated(child.index() as usize));
+                            },
+                            connect_selected_children_changed[sender] => move |_| {
+                                sender.input(LibraryInput::SelectionChanged);
+                            },
+                        },
+                    },
+                },
+
+                gtk::ActionBar {
+                    #[watch]
+                    set_revealed: model.batch.selecting || model.batch.progress().is_some(),
+
+                    pack_start = &gtk::Button {
+                        set_label: &gettext("_Cancel"),
+                        set_use_underline: true,
+                        #[watch]
+                        set_visible: model.batch.selecting,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(LibraryInput::SelectionMode(false));
+                        },
+                    },
+
+                    #[wrap(Some)]
+                    set_center_widget = &gtk::Box {
+                        gtk::Label {
+                            #[watch]
+                            set_visible: model.batch.progress().is_none(),
+                            #[watch]
+                            set_label: &ngettext("%d book selected", "%d books selected", model.batch.selected.len() as u32)
+                                .replace("%d", &model.batch.selected.len().to_string()),
+                        },
+                        gtk::ProgressBar {
+                            set_show_text: true,
+                            set_valign: gtk::Align::Center,
+                            #[watch]
+                            set_visible: model.batch.progress().is_some(),
+                            #[watch]
+                            set_fraction: model
+                                .batch
+                                .progress()
+                                .map(|(done, total)| done as f64 / total.max(1) as f64)
+                                .unwrap_or_default(),
+                            #[watch]
+                            set_text: model
+                                .batch
+                                .progress()
+                                .map(|(done, total)| {
+                                    gettext("%s of %s books")
+                                        .replacen("%s", &done.to_string(), 1)
+                                        .replacen("%s", &total.to_string(), 1)
+                                })
+                                .as_deref(),
+                        },
+                    },
+
+                    pack_end = &gtk::Button {
+                        set_label: &gettext("_Delete"),
+                        set_use_underline: true,
+                        add_css_class: "destructive-action",
+                        #[watch]
+                        set_visible: model.batch.selecting,
+                        #[watch]
+                        set_sensitive: !model.batch.selected.is_empty(),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(LibraryInput::DeleteSelected);
+                        },
+                    },
+                    pack_end = &gtk::Button {
+                        set_label: &gettext("_Move To…"),
+                        set_use_underline: true,
+                        #[watch]
+                        set_visible: model.batch.selecting,
+                        #[watch]
+                        set_sensitive: !model.batch.selected.is_empty(),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(LibraryInput::ChooseFolder);
+                        },
+                    },
+                    pack_end = &gtk::Button {
+                        set_label: &gettext("_Edit Metadata…"),
+                        set_use_underline: true,
+                        #[watch]
+                        set_visible: model.batch.selecting,
+                        #[watch]
+                        set_sensitive: !model.batch.selected.is_empty(),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(LibraryInput::EditSelected);
+                        },
+                    },
+                },
+            },
         }
     }
 
     fn init(
-        content_dir: Self::Init,
+        (content_dir, calibre): Self::Init,
         root: &Self::Root,
         sender: ComponentSender<Self>,
     ) -> ComponentParts<Self> {
-        let model = BookxLibrary {};
+        let monitors = watch(&content_dir, &sender);
+
+        let library = gtk::FlowBox::new();
+        let db = LibraryDb::load();
+        let sort_by = gtk::DropDown::builder().model(&sort_options(&db)).build();
+        let shelves = gtk::DropDown::builder().model(&shelf_options(&db)).build();
+        let book_menu = BookMenu::new(&library, &sender);
+
+        let click = gtk::GestureClick::new();
+        click.set_button(gdk::BUTTON_SECONDARY);
+        click.connect_pressed(
+            glib::clone!(@strong sender, @weak library => move |_, _, x, y| {
+                if let Some(child) = library.child_at_pos(x as i32, y as i32) {
+                    sender.input(LibraryInput::BookMenu(child.index() as usize, x, y));
+                }
+            }),
+        );
+        library.add_controller(click);
+
+        // in selection mode a click adds a book to the selection or takes
+        // it out, instead of selecting only that book
+        let select = gtk::GestureClick::new();
+        select.set_propagation_phase(gtk::PropagationPhase::Capture);
+        select.connect_pressed(glib::clone!(@weak library => move |gesture, _, x, y| {
+            if library.selection_mode() != gtk::SelectionMode::Multiple {
+                return;
+            }
+            if let Some(child) = library.child_at_pos(x as i32, y as i32) {
+                gesture.set_state(gtk::EventSequenceState::Claimed);
+                if child.is_selected() {
+                    library.unselect_child(&child);
+                } else {
+                    library.select_child(&child);
+                }
+            }
+        }));
+        library.add_controller(select);
+
+        let keys = gtk::EventControllerKey::new();
+        keys.connect_key_pressed(
+            glib::clone!(@strong sender, @weak library => @default-return gtk::Inhibit(false), move |_, key, _, _| {
+                if key == gdk::Key::Escape && library.selection_mode() == gtk::SelectionMode::Multiple {
+                    sender.input(LibraryInput::SelectionMode(false));
+                    return gtk::Inhibit(true);
+                }
+                gtk::Inhibit(false)
+            }),
+        );
+        library.add_controller(keys);
+
+        let mut model = BookxLibrary {
+            content_dir,
+            calibre,
+            books: Vec::new(),
+            items: Vec::new(),
+            loaded: Vec::new(),
+            scans: 0,
+            open_books: Vec::new(),
+            titles: Vec::new(),
+            db,
+            filter: Filter::default(),
+            shelves: Shelves::new(shelves.clone()),
+            sorting: Sorting::new(sort_by.clone()),
+            monitors,
+            rescan_pending: false,
+            book_menu,
+            report: None,
+            new_book: None,
+            details: None,
+            bulk_edit: None,
+            duplicates: None,
+            statistics: None,
+            file_chooser: None,
+            batch: BatchController::default(),
+        };
         let widgets = view_output!();
-        let book_files = utils::load_files_from_folder(&gio::File::for_path(content_dir), true);
-        for book_file in book_files {
-            if let Ok(bookx_book) =
-                BookxBook::load_book(book_file.path().unwrap().display().to_string())
+        model.scan(&sender);
+        model.import_calibre(&sender);
+
+        // back to where the library was scrolled to, once the books are
+        // laid out far enough to get there
+        let scroll = gio::Settings::new(APP_ID).double("library-scroll");
+        if scroll > 0.0 {
+            let restored = Cell::new(false);
+            widgets
+                .library_scroll
+                .vadjustment()
+                .connect_changed(move |adjustment| {
+                    if !restored.get() && adjustment.upper() - adjustment.page_size() >= scroll {
+                        restored.set(true);
+                        adjustment.set_value(scroll);
+                    }
+                });
+        }
+        ComponentParts { model, widgets }
+    }
+
+    fn update_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        message: Self::Input,
+        sender: ComponentSender<Self>,
+        root: &Self::Root,
+    ) {
+        match message {
+            // the flow box selects books itself in selection mode
+            LibraryInput::BookActivated(_) if self.batch.selecting => {}
+            LibraryInput::BookActivated(index) => {
+                if let Some(path) = self.books.get(index) {
+                    sender
+                        .output(LibraryOutput::OpenBook(path.clone()))
+                        .unwrap();
+                }
+            }
+            LibraryInput::BookMenu(..) if self.batch.selecting => {}
+            LibraryInput::BookMenu(index, x, y) => {
+                let path = match self.books.get(index) {
+                    Some(path) => path,
+                    None => return,
+                };
+                self.book_menu.popup(index, path, x, y);
+            }
+            LibraryInput::OpenInNewTab => {
+                if let Some(path) = self
+                    .book_menu
+                    .take()
+                    .and_then(|index| self.books.get(index))
+                {
+                    sender
+                        .output(LibraryOutput::OpenInNewTab(path.clone()))
+                        .unwrap();
+                }
+            }
+            LibraryInput::OpenInNewWindow => {
+                if let Some(path) = self
+                    .book_menu
+                    .take()
+                    .and_then(|index| self.books.get(index))
+                {
+                    sender
+                        .output(LibraryOutput::OpenInNewWindow(path.clone()))
+                        .unwrap();
+                }
+            }
+            LibraryInput::ShowDetails => {
+                let index = match self.book_menu.take() {
+                    Some(index) if index < self.books.len() => index,
+                    _ => return,
+                };
+                let path = self.books[index].clone();
+                let editable = is_editable(&path);
+                // a book starts out with the subjects it has as tags
+                let record = self
+                    .db
+                    .books
+                    .get(&path)
+                    .cloned()
+                    .unwrap_or_else(|| BookRecord {
+                        tags: formats::open(Path::new(&path))
+                            .map(|mut source| source.metadata().subjects)
+                            .unwrap_or_default(),
+                        ..Default::default()
+                    });
+                self.details = Some(
+                    BookxBookDetails::builder()
+                        .transient_for(root)
+                        .launch(BookDetails {
+                            title: self.titles[index].clone(),
+                            path,
+                            record,
+                            fields: self.db.fields.clone(),
+                            write_to_books: self.db.write_to_books,
+                            editable,
+                        })
+                        .forward(sender.input_sender(), |message| match message {
+                            BookDetailsOutput::Save {
+                                path,
+                                fields,
+                                record,
+                                write_to_books,
+                            } => LibraryInput::SaveDetails {
+                                path,
+                                fields,
+                                record,
+                                write_to_books,
+                            },
+                        }),
+                );
+            }
+            LibraryInput::SaveDetails {
+                path,
+                fields,
+                record,
+                write_to_books,
+            } => {
+                self.details = None;
+                let tags = record.tags.clone();
+                let labels = |db: &LibraryDb| -> Vec<String> {
+                    db.fields.iter().map(|field| field.label.clone()).collect()
+                };
+                let old_labels = labels(&self.db);
+                self.db.set_fields(fields);
+                self.db.set_record(&path, record);
+                self.db.write_to_books = write_to_books;
+                self.db.save();
+
+                if write_to_books && is_editable(&path) {
+                    let changes = vec![
+                        MetadataChange::Tags(tags),
+                        MetadataChange::CustomFields(self.db.calibre_user_metadata(&path)),
+                    ];
+                    self.batch.push(Batch {
+                        books: vec![path],
+                        action: BatchAction::Edit {
+                            changes,
+                            renumber: false,
+                        },
+                    });
+                    self.run_next(&sender);
+                }
+
+                if labels(&self.db) != old_labels {
+                    self.sorting.update_fields(&self.db);
+                }
+                if matches!(self.sorting.key, SortKey::Field(_)) {
+                    self.show_books(&widgets.library);
+                } else {
+                    self.apply_filter(&widgets.library);
+                }
+            }
+            LibraryInput::Filter(text) => {
+                self.filter = Filter::parse(&text);
+                self.apply_filter(&widgets.library);
+            }
+            LibraryInput::ShowShelf(index) => {
+                self.shelves.show(index, &self.db);
+                self.apply_filter(&widgets.library);
+            }
+            LibraryInput::SaveShelf => {
+                let name = widgets.shelf_name.text().trim().to_string();
+                let typed = widgets.search.text().trim().to_string();
+                if name.is_empty() || typed.is_empty() {
+                    return;
+                }
+                self.shelves.save(&mut self.db, &name, &typed);
+                widgets.shelf_name.set_text("");
+                widgets.shelf_menu.popdown();
+                widgets.search.set_text("");
+            }
+            LibraryInput::RemoveShelf => {
+                if self.shelves.remove(&mut self.db) {
+                    widgets.shelf_menu.popdown();
+                }
+            }
+            LibraryInput::SortBy(index) => {
+                if self.sorting.pick(index, &self.db) {
+                    self.show_books(&widgets.library);
+                }
+            }
+            LibraryInput::EditBook => {
+                if let Some(path) = self
+                    .book_menu
+                    .take()
+                    .and_then(|index| self.books.get(index))
+                {
+                    sender
+                        .output(LibraryOutput::EditBook(path.clone()))
+                        .unwrap();
+                }
+            }
+            LibraryInput::ConvertToEpub => {
+                let path = match self
+                    .book_menu
+                    .take()
+                    .and_then(|index| self.books.get(index))
+                {
+                    Some(path) => path.clone(),
+                    None => return,
+                };
+                info!("Converting {:?} to EPUB", path);
+                sender.spawn_oneshot_command(move || {
+                    let result = epub_writer::convert(Path::new(&path));
+                    LibraryCommand::Converted(path, result)
+                });
+            }
+            LibraryInput::CheckBook => {
+                let path = match self
+                    .book_menu
+                    .take()
+                    .and_then(|index| self.books.get(index))
+                {
+                    Some(path) => path.clone(),
+                    None => return,
+                };
+                info!("Checking {:?}", path);
+                sender.spawn_oneshot_command(move || {
+                    let result = epub_check::check(Path::new(&path));
+                    LibraryCommand::Checked(path, result)
+                });
+            }
+            LibraryInput::NewBook => {
+                self.new_book = Some(
+                    BookxNewBook::builder()
+                        .transient_for(root)
+                        .launch(())
+                        .forward(sender.input_sender(), |message| match message {
+                            NewBookOutput::Create(book) => LibraryInput::CreateBook(book),
+                        }),
+                );
+            }
+            LibraryInput::CreateBook(book) => {
+                self.new_book = None;
+                let library = self.content_dir.clone();
+                info!("Creating a book from {:?}", book.folder);
+                sender.spawn_oneshot_command(move || {
+                    let cover =
+                        match &book.cover {
+                            Some(path) => match std::fs::read(path)
+                                .map_err(FormatError::from)
+                                .and_then(|data| match epub_writer::image_extension(&data) {
+                                    Some(_) => Ok(data),
+                                    None => cover::encode(data, "image/jpeg"),
+                                }) {
+                                Ok(data) => Some(data),
+                                Err(e) => {
+                                    error!("Unable to use {:?} as cover: {}", path, e);
+                                    None
+                                }
+                            },
+                            None => None,
+                        }
+                        .or_else(|| {
+                            cover::generate(
+                                book.metadata.title.as_deref().unwrap_or_default(),
+                                &book.metadata.authors,
+                            )
+                        });
+                    LibraryCommand::Created(epub_writer::create_book(
+                        &book.folder,
+                        book.metadata,
+                        cover,
+                        Path::new(&library),
+                    ))
+                });
+            }
+            LibraryInput::SelectionMode(selecting) => {
+                // leaving selection mode clears the selection with it
+                self.batch.selecting = selecting;
+            }
+            LibraryInput::ToggleSelectionMode => self.batch.selecting = !self.batch.selecting,
+            LibraryInput::SelectionChanged => {
+                self.batch.selected = widgets
+                    .library
+                    .selected_children()
+                    .iter()
+                    .map(|child| child.index() as usize)
+                    .collect();
+                self.batch.selected.sort_unstable();
+            }
+            LibraryInput::EditSelected => {
+                self.bulk_edit = Some(
+                    BookxBulkEdit::builder()
+                        .transient_for(root)
+                        .launch(self.batch.selected.len())
+                        .forward(sender.input_sender(), |message| match message {
+                            BulkEditOutput::Apply(changes, renumber) => {
+                                LibraryInput::EditMetadata(changes, renumber)
+                            }
+                        }),
+                );
+            }
+            LibraryInput::EditMetadata(changes, renumber) => {
+                self.bulk_edit = None;
+                self.batch
+                    .push_selected(&self.books, BatchAction::Edit { changes, renumber });
+                self.run_next(&sender);
+            }
+            LibraryInput::ChooseFolder => {
+                let window = root
+                    .root()
+                    .and_then(|root| root.downcast::<gtk::Window>().ok());
+                let chooser = gtk::FileChooserNative::new(
+                    Some(&gettext("Move Books To")),
+                    window.as_ref(),
+                    gtk::FileChooserAction::SelectFolder,
+                    Some(&gettext("_Move")),
+                    Some(&gettext("_Cancel")),
+                );
+                chooser.set_modal(true);
+                chooser.connect_response(glib::clone!(@strong sender => move |chooser, response| {
+                    if response == gtk::ResponseType::Accept {
+                        if let Some(path) = chooser.file().and_then(|file| file.path()) {
+                            sender.input(LibraryInput::MoveSelected(path));
+                        }
+                    }
+                }));
+                chooser.show();
+                self.file_chooser = Some(chooser);
+            }
+            LibraryInput::MoveSelected(folder) => {
+                self.file_chooser = None;
+                self.batch
+                    .push_selected(&self.books, BatchAction::Move(folder));
+                self.run_next(&sender);
+            }
+            // the trash keeps them, and the toast can bring them back
+            LibraryInput::DeleteSelected => {
+                self.batch.push_selected(&self.books, BatchAction::Delete);
+                self.run_next(&sender);
+            }
+            LibraryInput::Undo(batch) => {
+                self.batch.push_undo(batch);
+                self.run_next(&sender);
+            }
+            LibraryInput::FindDuplicates => {
+                let books = self.books.clone();
+                info!("Looking for duplicates among {} books", books.len());
+                sender.spawn_oneshot_command(move || {
+                    LibraryCommand::DuplicatesFound(duplicates::find(&books))
+                });
+            }
+            LibraryInput::TrashDuplicates(books) => {
+                self.batch.push(Batch {
+                    books,
+                    action: BatchAction::Delete,
+                });
+                self.run_next(&sender);
+            }
+            LibraryInput::Import(file) => {
+                let library = PathBuf::from(&self.content_dir);
+                sender.spawn_oneshot_command(move || {
+                    let imported = import_book(&file, &library);
+                    LibraryCommand::Imported(file, imported)
+                });
+            }
+            LibraryInput::RecordSession(session) => {
+                let path = session.path.clone();
+                self.db.record_session(session);
+                self.db.save();
+                dbus_api::progress_changed(&self.db, &path);
+            }
+            LibraryInput::SetProgress(path, percent) => {
+                let now = glib::DateTime::now_utc()
+                    .map(|now| now.to_unix())
+                    .unwrap_or_default();
+                if self.db.set_progress(&path, percent, now) {
+                    self.db.save();
+                    dbus_api::progress_changed(&self.db, &path);
+                }
+            }
+            LibraryInput::AddTag(path, tag) => {
+                self.db.add_tag(&path, &tag);
+                self.db.save();
+                self.apply_filter(&widgets.library);
+            }
+            LibraryInput::ShowStatistics => {
+                let now = glib::DateTime::now_utc()
+                    .map(|now| now.to_unix())
+                    .unwrap_or_default();
+                let titles = self
+                    .books
+                    .iter()
+                    .cloned()
+                    .zip(self.titles.iter().cloned())
+                    .collect();
+                self.statistics = Some(
+                    BookxStatistics::builder()
+                        .transient_for(root)
+                        .launch(StatisticsInit {
+                            stats: ReadingStats::new(&self.db, now),
+                            sessions: self.db.sessions.clone(),
+                            titles,
+                        })
+                        .detach(),
+                );
+            }
+            LibraryInput::SetRoot(content_dir, calibre) => {
+                if content_dir == self.content_dir && calibre == self.calibre {
+                    return;
+                }
+                info!("Loading the library from {:?}", content_dir);
+                self.monitors = watch(&content_dir, &sender);
+                self.content_dir = content_dir;
+                self.calibre = calibre;
+                self.scan(&sender);
+                self.import_calibre(&sender);
+            }
+            LibraryInput::FolderChanged => {
+                if !self.rescan_pending && !self.monitors.is_empty() {
+                    self.rescan_pending = true;
+                    let sender = sender.clone();
+                    glib::timeout_add_local_once(RESCAN_DELAY, move || {
+                        sender.input(LibraryInput::Rescan);
+                    });
+                }
+            }
+            LibraryInput::Rescan => {
+                self.rescan_pending = false;
+                info!("Rescanning library at {:?}", self.content_dir);
+                // folders may have come and gone too
+                self.monitors = watch(&self.content_dir, &sender);
+                self.scan(&sender);
+            }
+            LibraryInput::OpenBooks(paths) => {
+                for (path, item) in self.books.iter().zip(&self.items) {
+                    item.emit(BookxBookInput::SetOpen(paths.contains(path)));
+                }
+                self.open_books = paths;
+            }
+        }
+        self.update_view(widgets, sender);
+    }
+
+    fn shutdown(&mut self, widgets: &mut Self::Widgets, _output: relm4::Sender<Self::Output>) {
+        let scroll = widgets.library_scroll.vadjustment().value();
+        if let Err(e) = gio::Settings::new(APP_ID).set_double("library-scroll", scroll) {
+            warn!(
+                "Unable to remember where the library was scrolled to: {}",
+                e
+            );
+        }
+    }
+
+    fn update_cmd_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        message: Self::CommandOutput,
+        sender: ComponentSender<Self>,
+        root: &Self::Root,
+    ) {
+        match message {
+            LibraryCommand::Scanned(scan, books) => {
+                // a newer scan is on its way
+                if scan != self.scans {
+                    return;
+                }
+                self.loaded = books;
+                self.show_books(&widgets.library);
+                sender
+                    .output(LibraryOutput::BooksChanged(self.books.clone()))
+                    .unwrap();
+            }
+            LibraryCommand::Converted(path, Ok(target)) => {
+                info!("Converted {:?} to {:?}", path, target);
+                // the folder monitor picks the new book up by itself
+                if self.monitors.is_empty() {
+                    self.scan(&sender);
+                }
+                widgets.toasts.add_toast(&adw::Toast::new(
+                    &gettext("Converted to %s").replace("%s", &file_name(&target)),
+                ));
+            }
+            LibraryCommand::Converted(path, Err(e)) => {
+                error!("Unable to convert {:?} to EPUB: {}", path, e);
+                widgets.toasts.add_toast(&adw::Toast::new(
+                    &gettext("%s could not be converted").replace("%s", &file_name(&path)),
+                ));
+            }
+            LibraryCommand::Checked(path, Ok(report)) => {
+                let title = file_name(&path);
+                self.report = Some(
+                    ValidationReport::builder()
+                        .transient_for(root)
+                        .launch((title, report))
+                        .detach(),
+                );
+            }
+            LibraryCommand::Checked(path, Err(e)) => {
+                error!("Unable to check {:?}: {}", path, e);
+                widgets.toasts.add_toast(&adw::Toast::new(
+                    &gettext("%s could not be checked").replace("%s", &file_name(&path)),
+                ));
+            }
+            LibraryCommand::Created(Ok(path)) => {
+                info!("Created {:?}", path);
+                if self.monitors.is_empty() {
+                    self.scan(&sender);
+                }
+                widgets.toasts.add_toast(&adw::Toast::new(
+                    &gettext("%s added to the library").replace("%s", &file_name(&path)),
+                ));
+            }
+            LibraryCommand::Created(Err(e)) => {
+                error!("Unable to create the book: {}", e);
+                widgets
+                    .toasts
+                    .add_toast(&adw::Toast::new(&gettext("The book could not be created")));
+            }
+            LibraryCommand::Imported(file, Ok(target)) => {
+                info!("Imported {:?} as {:?}", file, target);
+                if self.monitors.is_empty() {
+                    self.scan(&sender);
+                }
+                widgets.toasts.add_toast(&adw::Toast::new(
+                    &gettext("%s added to the library").replace("%s", &file_name(&target)),
+                ));
+            }
+            LibraryCommand::Imported(file, Err(e)) => {
+                error!("Unable to import {:?} into the library: {}", file, e);
+                widgets.toasts.add_toast(&adw::Toast::new(
+                    &gettext("%s could not be added to the library")
+                        .replace("%s", &file_name(&file)),
+                ));
+            }
+            LibraryCommand::DuplicatesFound(groups) if groups.is_empty() => {
+                widgets
+                    .toasts
+                    .add_toast(&adw::Toast::new(&gettext("No duplicate books found")));
+            }
+            LibraryCommand::DuplicatesFound(groups) => {
+                self.duplicates = Some(
+                    BookxDuplicateFinder::builder()
+                        .transient_for(root)
+                        .launch(groups)
+                        .forward(sender.input_sender(), |message| match message {
+                            DuplicateFinderOutput::Trash(books) => {
+                                LibraryInput::TrashDuplicates(books)
+                            }
+                        }),
+                );
+            }
+            LibraryCommand::CalibreRead(Ok(library)) => {
+                let count = library.merge_into(&mut self.db);
+                self.db.save();
+                // fields of custom columns can be sorted by too
+                self.sorting.update_fields(&self.db);
+                widgets.toasts.add_toast(&adw::Toast::new(
+                    &ngettext(
+                        "%d book imported from calibre",
+                        "%d books imported from calibre",
+                        count as u32,
+                    )
+                    .replace("%d", &count.to_string()),
+                ));
+                self.scan(&sender);
+            }
+            LibraryCommand::CalibreRead(Err(e)) => {
+                error!(
+                    "Unable to read the calibre library at {:?}: {}",
+                    self.content_dir, e
+                );
+                widgets.toasts.add_toast(&adw::Toast::new(&gettext(
+                    "The calibre library could not be read",
+                )));
+            }
+            LibraryCommand::BatchProgress(done) => self.batch.advance(done),
+            LibraryCommand::BatchDone(result) => {
+                for (book, e) in &result.errors {
+                    error!("Unable to change {:?}: {}", book, e);
+                }
+
+                let toast = adw::Toast::new(&result.summary);
+                if let Some(batch) = self.batch.finish(result.undo) {
+                    toast.set_button_label(Some(&gettext("_Undo")));
+                    toast.connect_button_clicked(glib::clone!(@strong sender => move |_| {
+                        sender.input(LibraryInput::Undo(batch));
+                    }));
+                }
+                widgets.toasts.add_toast(&toast);
+                if !result.errors.is_empty() {
+                    let count = result.errors.len();
+                    widgets.toasts.add_toast(&adw::Toast::new(
+                        &ngettext(
+                            "%d book could not be changed",
+                            "%d books could not be changed",
+                            count as u32,
+                        )
+                        .replace("%d", &count.to_string()),
+                    ));
+                }
+
+                if self.monitors.is_empty() {
+                    self.scan(&sender);
+                }
+                self.run_next(&sender);
+            }
+        }
+        self.update_view(widgets, sender);
+    }
+}
+
+impl BookxLibrary {
+    // hides the books the filter doesn't match
+    fn apply_filter(&self, library: &gtk::FlowBox) {
+        for (index, (path, title)) in self.books.iter().zip(&self.titles).enumerate() {
+            if let Some(child) = library.child_at_index(index as i32) {
+                child.set_visible(
+                    self.shelves.filter.matches(&self.db, path, title)
+                        && self.filter.matches(&self.db, path, title),
+                );
+            }
+        }
+    }
+
+    // reads the metadata of a calibre library away from the main thread
+    fn import_calibre(&self, sender: &ComponentSender<Self>) {
+        if !self.calibre {
+            return;
+        }
+        let folder = PathBuf::from(&self.content_dir);
+        sender.spawn_oneshot_command(move || {
+            LibraryCommand::CalibreRead(calibre::read(&folder).map_err(|e| e.to_string()))
+        });
+    }
+
+    // starts the next batch unless one is running
+    fn run_next(&mut self, sender: &ComponentSender<Self>) {
+        let batch = match self.batch.next() {
+            Some(batch) => batch,
+            None => return,
+        };
+        info!("Running a batch on {} books", batch.books.len());
+        sender.spawn_command(move |out| {
+            let result = batch::run(batch, |done| {
+                out.send(LibraryCommand::BatchProgress(done)).unwrap();
+            });
+            out.send(LibraryCommand::BatchDone(result)).unwrap();
+        });
+    }
+
+    // scans the folder and loads the books away from the main thread
+    fn scan(&mut self, sender: &ComponentSender<Self>) {
+        self.scans += 1;
+        let scan = self.scans;
+        let content_dir = self.content_dir.clone();
+        let documents = gio::Settings::new(APP_ID).boolean("library-documents");
+        let imported: BTreeMap<String, ImportedMetadata> = self
+            .db
+            .books
+            .iter()
+            .filter_map(|(path, record)| Some((path.clone(), record.imported.clone()?)))
+            .collect();
+        sender.spawn_oneshot_command(move || {
+            LibraryCommand::Scanned(scan, load_books(&content_dir, documents, &imported))
+        });
+    }
+
+    // puts the books of the last scan in the library in the sort order
+    fn show_books(&mut self, library: &gtk::FlowBox) {
+        // only books, the context menu is a child of the library too
+        while let Some(child) = library.child_at_index(0) {
+            library.remove(&child);
+        }
+        self.books.clear();
+        self.titles.clear();
+        self.items.clear();
+
+        let mut books = self.loaded.clone();
+        for book in &mut books {
+            if let Some(progress) = self
+                .db
+                .books
+                .get(&book.path)
+                .and_then(|record| record.progress.as_ref())
             {
-                let bookx_book_comp = BookxBook::builder().launch(bookx_book).detach();
-                let library = &widgets.library;
-                library.append(bookx_book_comp.widget());
+                book.progress = progress.percent();
             }
         }
-        ComponentParts { model, widgets }
+        books.sort_by(|a, b| {
+            self.db
+                .compare(&self.sorting.key, (&a.path, &a.title), (&b.path, &b.title))
+        });
+        // the search provider of the shell looks books up here
+        let catalog: BTreeMap<String, CatalogEntry> = books
+            .iter()
+            .map(|book| {
+                let entry = CatalogEntry {
+                    title: book.title.clone(),
+                    authors: book.authors.clone(),
+                    cover: book.cover.clone(),
+                };
+                (book.path.clone(), entry)
+            })
+            .collect();
+        if catalog != self.db.catalog {
+            self.db.catalog = catalog;
+            self.db.save();
+        }
+        for mut bookx_book in books {
+            self.books.push(bookx_book.path.clone());
+            self.titles.push(bookx_book.title.clone());
+            bookx_book.open = self.open_books.contains(&bookx_book.path);
+            let bookx_book_comp = BookxBook::builder().launch(bookx_book).detach();
+            library.append(bookx_book_comp.widget());
+            self.items.push(bookx_book_comp);
+        }
+        self.apply_filter(library);
+    }
+}
+
+// Loads every book in the library folder, with the metadata imported from
+// calibre in place of their own. Runs on a thread of its own.
+fn load_books(
+    content_dir: &str,
+    documents: bool,
+    imported: &BTreeMap<String, ImportedMetadata>,
+) -> Vec<BookxBook> {
+    let book_files =
+        utils::load_files_from_folder(&gio::File::for_path(content_dir), true, documents);
+    book_files
+        .iter()
+        .filter_map(|(book_file, format)| {
+            BookxBook::load_book(book_file.path().unwrap().display().to_string(), format).ok()
+        })
+        .map(|mut book| {
+            // calibre's title and cover win over the book's own
+            if let Some(imported) = imported.get(&book.path) {
+                book.title = imported.title.clone();
+                book.authors = imported.authors.clone();
+                if let Some(cover) = &imported.cover {
+                    match Pixbuf::from_file_at_scale(cover, 180, 180, true) {
+                        Ok(pixbuf) => {
+                            book.pixbuf = pixbuf;
+                            book.cover = Some(cover.clone());
+                        }
+                        Err(e) => warn!("Unable to read the cover {:?}: {}", cover, e),
+                    }
+                }
+            }
+            book
+        })
+        .collect()
+}
+
+// name of the file at `path`, to show in toasts
+fn file_name(path: impl AsRef<Path>) -> String {
+    let path = path.as_ref();
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+// whether the book at `path` is in a format Bookx can write to
+fn is_editable(path: &str) -> bool {
+    formats::detect(Path::new(path))
+        .map(|format| format.editable)
+        .unwrap_or(false)
+}
+
+// Watches the library folder and every folder in it, so new and removed
+// books show up by themselves. Folders made later are watched once the
+// rescan they cause is done.
+fn watch(content_dir: &str, sender: &ComponentSender<BookxLibrary>) -> Vec<gio::FileMonitor> {
+    let mut monitors = Vec::new();
+    let mut folders = vec![PathBuf::from(content_dir)];
+    while let Some(folder) = folders.pop() {
+        match gio::File::for_path(&folder).monitor_directory(
+            gio::FileMonitorFlags::WATCH_MOVES,
+            None::<&gio::Cancellable>,
+        ) {
+            Ok(monitor) => {
+                let sender = sender.clone();
+                monitor.connect_changed(move |_, file, _, event| {
+                    let changed = match event {
+                        gio::FileMonitorEvent::ChangesDoneHint
+                        | gio::FileMonitorEvent::Deleted
+                        | gio::FileMonitorEvent::MovedIn
+                        | gio::FileMonitorEvent::MovedOut
+                        | gio::FileMonitorEvent::Renamed => true,
+                        // a new folder has no changes done to wait for
+                        gio::FileMonitorEvent::Created => {
+                            file.query_file_type(
+                                gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+                                None::<&gio::Cancellable>,
+                            ) == gio::FileType::Directory
+                        }
+                        _ => false,
+                    };
+                    if changed {
+                        sender.input(LibraryInput::FolderChanged);
+                    }
+                });
+                monitors.push(monitor);
+            }
+            Err(e) => error!("Unable to watch {:?} for changes: {:?}", folder, e),
+        }
+        // symbolic links aren't followed, as in the scan
+        if let Ok(entries) = fs::read_dir(&folder) {
+            folders.extend(
+                entries
+                    .flatten()
+                    .filter(|entry| entry.file_type().map(|t| t.is_dir()).unwrap_or(false))
+                    .map(|entry| entry.path()),
+            );
+        }
     }
+    monitors
 }
 
 // TODO:
@@ -0,0 +1,210 @@
+// Bookx - bulk_edit.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::{gettext, ngettext};
+use relm4::{
+    adw::{self, prelude::*},
+    gtk, Component, ComponentParts, ComponentSender,
+};
+
+use crate::formats::epub_package::MetadataChange;
+
+// Metadata set on all selected books, fields left empty are not changed
+pub struct BookxBulkEdit {
+    authors: String,
+    series: String,
+    renumber: bool,
+    tags: String,
+    language: String,
+}
+
+#[derive(Debug)]
+pub enum BulkEditInput {
+    Authors(String),
+    Series(String),
+    Renumber(bool),
+    Tags(String),
+    Language(String),
+    Apply,
+    Cancel,
+}
+
+#[derive(Debug)]
+pub enum BulkEditOutput {
+    // the changes and whether to number the books in the series
+    Apply(Vec<MetadataChange>, bool),
+}
+
+#[relm4_macros::component(pub)]
+impl Component for BookxBulkEdit {
+    // number of books selected
+    type Init = usize;
+    type Input = BulkEditInput;
+    type Output = BulkEditOutput;
+    type CommandOutput = ();
+
+    view! {
+        #[name = "bulk_edit_window"]
+        adw::Window {
+            set_title: Some(&ngettext("Edit %d Book", "Edit %d Books", count as u32)
+                .replace("%d", &count.to_string())),
+            set_default_width: 480,
+            set_modal: true,
+
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                adw::HeaderBar {
+                    set_show_end_title_buttons: false,
+                    pack_start = &gtk::Button {
+                        set_label: &gettext("_Cancel"),
+                        set_use_underline: true,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(BulkEditInput::Cancel);
+                        },
+                    },
+                    pack_end = &gtk::Button {
+                        set_label: &gettext("_Apply"),
+                        set_use_underline: true,
+                        add_css_class: "suggested-action",
+                        #[watch]
+                        set_sensitive: !model.changes().is_empty() || model.renumber,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(BulkEditInput::Apply);
+                        },
+                    },
+                },
+
+                adw::PreferencesPage {
+                    adw::PreferencesGroup {
+                        set_description: Some(&gettext(
+                            "Fields left empty are kept as they are in each book.",
+                        )),
+
+                        adw::EntryRow {
+                            set_title: &gettext("Authors, separated by commas"),
+                            connect_changed[sender] => move |row| {
+                                sender.input(BulkEditInput::Authors(row.text().to_string()));
+                            },
+                        },
+                        adw::EntryRow {
+                            set_title: &gettext("Tags, separated by commas"),
+                            connect_changed[sender] => move |row| {
+                                sender.input(BulkEditInput::Tags(row.text().to_string()));
+                            },
+                        },
+                        adw::EntryRow {
+                            set_title: &gettext("Language"),
+                            connect_changed[sender] => move |row| {
+                                sender.input(BulkEditInput::Language(row.text().to_string()));
+                            },
+                        },
+                    },
+
+                    adw::PreferencesGroup {
+                        set_title: &gettext("Series"),
+
+                        adw::EntryRow {
+                            set_title: &gettext("Name"),
+                            connect_changed[sender] => move |row| {
+                                sender.input(BulkEditInput::Series(row.text().to_string()));
+                            },
+                        },
+                        adw::ActionRow {
+                            set_title: &gettext("Number in Order"),
+                            set_subtitle: &gettext("Books are numbered in the order they are shown"),
+
+                            #[name = "renumber_switch"]
+                            add_suffix = &gtk::Switch {
+                                set_valign: gtk::Align::Center,
+                                connect_active_notify[sender] => move |switch| {
+                                    sender.input(BulkEditInput::Renumber(switch.is_active()));
+                                },
+                            },
+                            set_activatable_widget: Some(&renumber_switch),
+                        },
+                    },
+                },
+            },
+        }
+    }
+
+    fn init(
+        count: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = BookxBulkEdit {
+            authors: String::new(),
+            series: String::new(),
+            renumber: false,
+            tags: String::new(),
+            language: String::new(),
+        };
+        let widgets = view_output!();
+        root.present();
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, message: Self::Input, sender: ComponentSender<Self>, root: &Self::Root) {
+        match message {
+            BulkEditInput::Authors(authors) => self.authors = authors,
+            BulkEditInput::Series(series) => self.series = series,
+            BulkEditInput::Renumber(renumber) => self.renumber = renumber,
+            BulkEditInput::Tags(tags) => self.tags = tags,
+            BulkEditInput::Language(language) => self.language = language,
+            BulkEditInput::Apply => {
+                sender
+                    .output(BulkEditOutput::Apply(self.changes(), self.renumber))
+                    .unwrap();
+                root.close();
+            }
+            BulkEditInput::Cancel => root.close(),
+        }
+    }
+}
+
+impl BookxBulkEdit {
+    fn changes(&self) -> Vec<MetadataChange> {
+        let list = |value: &str| -> Vec<String> {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        let mut changes = Vec::new();
+        let authors = list(&self.authors);
+        if !authors.is_empty() {
+            changes.push(MetadataChange::Authors(authors));
+        }
+        let tags = list(&self.tags);
+        if !tags.is_empty() {
+            changes.push(MetadataChange::Tags(tags));
+        }
+        if !self.language.trim().is_empty() {
+            changes.push(MetadataChange::Language(self.language.trim().to_string()));
+        }
+        if !self.series.trim().is_empty() {
+            changes.push(MetadataChange::Series(
+                Some(self.series.trim().to_string()),
+                None,
+            ));
+        }
+        changes
+    }
+}
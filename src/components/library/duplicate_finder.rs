@@ -0,0 +1,228 @@
+// Bookx - duplicate_finder.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::{gettext, ngettext};
+use relm4::{
+    adw::{self, prelude::*},
+    gtk::{self, glib, pango},
+    Component, ComponentParts, ComponentSender, RelmWidgetExt,
+};
+
+use std::path::Path;
+
+use crate::components::library::duplicates::{DuplicateBook, DuplicateGroup};
+
+// Window showing the books of each group of duplicates side by side, one
+// of them is kept and the rest can be moved to the trash.
+pub struct BookxDuplicateFinder {
+    // paths of the books of each group and the one to keep
+    groups: Vec<(Vec<String>, usize)>,
+    group_widgets: Vec<adw::PreferencesGroup>,
+    trashed: usize,
+}
+
+#[derive(Debug)]
+pub enum DuplicateFinderInput {
+    // group and book in it
+    Keep(usize, usize),
+    TrashOthers(usize),
+}
+
+#[derive(Debug)]
+pub enum DuplicateFinderOutput {
+    Trash(Vec<String>),
+}
+
+#[relm4_macros::component(pub)]
+impl Component for BookxDuplicateFinder {
+    type Init = Vec<DuplicateGroup>;
+    type Input = DuplicateFinderInput;
+    type Output = DuplicateFinderOutput;
+    type CommandOutput = ();
+
+    view! {
+        #[name = "duplicates_window"]
+        adw::PreferencesWindow {
+            set_title: Some(&gettext("Duplicate Books")),
+            set_default_width: 860,
+            set_default_height: 600,
+            set_modal: true,
+            set_search_enabled: false,
+
+            #[name = "page"]
+            add = &adw::PreferencesPage {
+                adw::PreferencesGroup {
+                    #[watch]
+                    set_description: Some(&summary(model.groups.len() - model.trashed)),
+                }
+            }
+        }
+    }
+
+    fn init(
+        groups: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let mut model = BookxDuplicateFinder {
+            groups: Vec::new(),
+            group_widgets: Vec::new(),
+            trashed: 0,
+        };
+        let widgets = view_output!();
+
+        for (group_index, group) in groups.into_iter().enumerate() {
+            let reasons: Vec<String> = group.reasons.iter().map(|reason| reason.label()).collect();
+            let group_widget = adw::PreferencesGroup::new();
+            group_widget.set_title(&reasons.join(", "));
+
+            let trash_button = gtk::Button::with_mnemonic(&gettext("_Trash Others"));
+            trash_button.add_css_class("destructive-action");
+            trash_button.set_valign(gtk::Align::Center);
+            trash_button.connect_clicked(glib::clone!(@strong sender => move |_| {
+                sender.input(DuplicateFinderInput::TrashOthers(group_index));
+            }));
+            group_widget.set_header_suffix(Some(&trash_button));
+
+            // a book that can be edited is the one worth keeping
+            let keep = group
+                .books
+                .iter()
+                .position(|book| book.format.editable)
+                .unwrap_or(0);
+            let books = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+            books.set_homogeneous(true);
+            let mut first_button: Option<gtk::CheckButton> = None;
+            for (book_index, book) in group.books.iter().enumerate() {
+                let keep_button = gtk::CheckButton::with_label(&gettext("Keep"));
+                keep_button.set_group(first_button.as_ref());
+                keep_button.set_active(book_index == keep);
+                keep_button.connect_toggled(glib::clone!(@strong sender => move |button| {
+                    if button.is_active() {
+                        sender.input(DuplicateFinderInput::Keep(group_index, book_index));
+                    }
+                }));
+                books.append(&book_card(book, &keep_button));
+                first_button.get_or_insert(keep_button);
+            }
+            group_widget.add(&books);
+            widgets.page.add(&group_widget);
+
+            let paths = group.books.into_iter().map(|book| book.path).collect();
+            model.groups.push((paths, keep));
+            model.group_widgets.push(group_widget);
+        }
+
+        root.present();
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, message: Self::Input, sender: ComponentSender<Self>, _root: &Self::Root) {
+        match message {
+            DuplicateFinderInput::Keep(group, book) => {
+                if let Some((_, keep)) = self.groups.get_mut(group) {
+                    *keep = book;
+                }
+            }
+            DuplicateFinderInput::TrashOthers(group) => {
+                let (paths, keep) = match self.groups.get(group) {
+                    Some(group) => group,
+                    None => return,
+                };
+                let others = paths
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| index != keep)
+                    .map(|(_, path)| path.clone())
+                    .collect();
+                sender.output(DuplicateFinderOutput::Trash(others)).unwrap();
+                // the library's toast can bring them back
+                self.group_widgets[group].set_visible(false);
+                self.trashed += 1;
+            }
+        }
+    }
+}
+
+// a card with what sets the book apart from its copies
+fn book_card(book: &DuplicateBook, keep_button: &gtk::CheckButton) -> gtk::Box {
+    let card = gtk::Box::new(gtk::Orientation::Vertical, 6);
+    card.add_css_class("card");
+    card.set_margin_all(6);
+
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 6);
+    content.set_margin_all(12);
+    content.append(keep_button);
+
+    let metadata = &book.metadata;
+    let title = gtk::Label::new(Some(metadata.title.as_deref().unwrap_or_default()));
+    title.add_css_class("heading");
+    title.set_wrap(true);
+    title.set_xalign(0.0);
+    content.append(&title);
+
+    let file_name = Path::new(&book.path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let size = glib::format_size(book.size).to_string();
+    let rows = [
+        (
+            gettext("Authors"),
+            Some(metadata.authors.join(", ")).filter(|authors| !authors.is_empty()),
+        ),
+        (gettext("Format"), Some(book.format.name.to_string())),
+        (gettext("Size"), Some(size)),
+        (gettext("Language"), metadata.language.clone()),
+        (gettext("Publisher"), metadata.publisher.clone()),
+        (gettext("Date"), metadata.date.clone()),
+        (gettext("Identifier"), metadata.identifier.clone()),
+        (gettext("File"), Some(file_name)),
+    ];
+    let grid = gtk::Grid::new();
+    grid.set_column_spacing(12);
+    grid.set_row_spacing(3);
+    for (row, (name, value)) in rows.iter().enumerate() {
+        let name = gtk::Label::new(Some(name));
+        name.add_css_class("dim-label");
+        name.set_xalign(0.0);
+        name.set_valign(gtk::Align::Start);
+        let value = gtk::Label::new(Some(value.as_deref().unwrap_or("—")));
+        value.set_xalign(0.0);
+        value.set_hexpand(true);
+        value.set_ellipsize(pango::EllipsizeMode::Middle);
+        value.set_selectable(true);
+        grid.attach(&name, 0, row as i32, 1, 1);
+        grid.attach(&value, 1, row as i32, 1, 1);
+    }
+    grid.set_tooltip_text(Some(&book.path));
+    content.append(&grid);
+
+    card.append(&content);
+    card
+}
+
+fn summary(count: usize) -> String {
+    if count == 0 {
+        return gettext("No duplicates left");
+    }
+    ngettext(
+        "%d group of books that look like copies of each other",
+        "%d groups of books that look like copies of each other",
+        count as u32,
+    )
+    .replace("%d", &count.to_string())
+}
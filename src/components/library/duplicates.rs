@@ -0,0 +1,356 @@
+// Bookx - duplicates.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::gettext;
+use relm4::gtk::glib;
+use tracing::error;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::formats::{self, BookMetadata, Format};
+
+// titles at least this similar, by edit distance, are taken for the same
+const TITLE_SIMILARITY: f64 = 0.9;
+const AUTHOR_SIMILARITY: f64 = 0.85;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Reason {
+    SameContent,
+    SameIdentifier,
+    SimilarTitle,
+}
+
+impl Reason {
+    pub fn label(&self) -> String {
+        match self {
+            Reason::SameContent => gettext("Same content"),
+            Reason::SameIdentifier => gettext("Same identifier"),
+            Reason::SimilarTitle => gettext("Similar title and authors"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DuplicateBook {
+    pub path: String,
+    pub format: &'static Format,
+    pub size: u64,
+    pub metadata: BookMetadata,
+}
+
+#[derive(Debug, Clone)]
+pub struct DuplicateGroup {
+    // why the books were grouped, strongest first
+    pub reasons: Vec<Reason>,
+    // in the order of the library
+    pub books: Vec<DuplicateBook>,
+}
+
+// Groups the books that look like copies of each other. Books only need
+// one reason to end up in a group, so copies found in different ways are
+// chained together.
+pub fn find(paths: &[String]) -> Vec<DuplicateGroup> {
+    let books: Vec<DuplicateBook> = paths
+        .iter()
+        .filter_map(|path| {
+            let format = formats::detect(Path::new(path))?;
+            let size = std::fs::metadata(path)
+                .map(|info| info.len())
+                .unwrap_or_default();
+            let metadata = match format.open(Path::new(path)) {
+                Ok(source) => source.metadata(),
+                Err(e) => {
+                    error!(
+                        "Unable to read {:?} while looking for duplicates: {}",
+                        path, e
+                    );
+                    BookMetadata::default()
+                }
+            };
+            Some(DuplicateBook {
+                path: path.clone(),
+                format,
+                size,
+                metadata,
+            })
+        })
+        .collect();
+
+    let mut pairs = Vec::new();
+    pairs.extend(
+        same_content(&books)
+            .into_iter()
+            .map(|(a, b)| (a, b, Reason::SameContent)),
+    );
+    pairs.extend(
+        same_key(&books, identifier_key)
+            .into_iter()
+            .map(|(a, b)| (a, b, Reason::SameIdentifier)),
+    );
+    pairs.extend(
+        similar_titles(&books)
+            .into_iter()
+            .map(|(a, b)| (a, b, Reason::SimilarTitle)),
+    );
+
+    // union-find over the books, linked by the pairs
+    let mut parents: Vec<usize> = (0..books.len()).collect();
+    fn root(parents: &mut [usize], mut index: usize) -> usize {
+        while parents[index] != index {
+            parents[index] = parents[parents[index]];
+            index = parents[index];
+        }
+        index
+    }
+    for (a, b, _) in &pairs {
+        let (a, b) = (root(&mut parents, *a), root(&mut parents, *b));
+        if a != b {
+            parents[a.max(b)] = a.min(b);
+        }
+    }
+
+    let mut groups: Vec<DuplicateGroup> = Vec::new();
+    let mut group_of_root: HashMap<usize, usize> = HashMap::new();
+    let mut reasons: HashMap<usize, Vec<Reason>> = HashMap::new();
+    for (a, _, reason) in &pairs {
+        let group = root(&mut parents, *a);
+        let group_reasons = reasons.entry(group).or_default();
+        if !group_reasons.contains(reason) {
+            group_reasons.push(*reason);
+        }
+    }
+    for (index, book) in books.into_iter().enumerate() {
+        let group = root(&mut parents, index);
+        match group_of_root.get(&group) {
+            Some(index) => groups[*index].books.push(book),
+            None => {
+                let mut group_reasons = reasons.remove(&group).unwrap_or_default();
+                group_reasons.sort();
+                group_of_root.insert(group, groups.len());
+                groups.push(DuplicateGroup {
+                    reasons: group_reasons,
+                    books: vec![book],
+                });
+            }
+        }
+    }
+    groups
+        .into_iter()
+        .filter(|group| group.books.len() > 1)
+        .collect()
+}
+
+// Books with the same bytes, only files of the same size are hashed.
+fn same_content(books: &[DuplicateBook]) -> Vec<(usize, usize)> {
+    let mut by_size: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (index, book) in books.iter().enumerate() {
+        by_size.entry(book.size).or_default().push(index);
+    }
+    let mut hashes = vec![None; books.len()];
+    for index in by_size
+        .into_values()
+        .filter(|indices| indices.len() > 1)
+        .flatten()
+    {
+        hashes[index] = content_hash(&books[index].path);
+    }
+    pairs_by_key(hashes)
+}
+
+fn content_hash(path: &str) -> Option<String> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            error!(
+                "Unable to read {:?} while looking for duplicates: {}",
+                path, e
+            );
+            return None;
+        }
+    };
+    let mut checksum = glib::Checksum::new(glib::ChecksumType::Sha256)?;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => checksum.update(&buffer[..read]),
+            Err(e) => {
+                error!(
+                    "Unable to read {:?} while looking for duplicates: {}",
+                    path, e
+                );
+                return None;
+            }
+        }
+    }
+    checksum.string().map(|hash| hash.to_string())
+}
+
+fn same_key(
+    books: &[DuplicateBook],
+    key: fn(&DuplicateBook) -> Option<String>,
+) -> Vec<(usize, usize)> {
+    pairs_by_key(books.iter().map(key).collect())
+}
+
+// links every book to the first one with the same key
+fn pairs_by_key(keys: Vec<Option<String>>) -> Vec<(usize, usize)> {
+    let mut first: HashMap<String, usize> = HashMap::new();
+    let mut pairs = Vec::new();
+    for (index, key) in keys.into_iter().enumerate() {
+        if let Some(key) = key {
+            match first.get(&key) {
+                Some(other) => pairs.push((*other, index)),
+                None => {
+                    first.insert(key, index);
+                }
+            }
+        }
+    }
+    pairs
+}
+
+// ISBNs without their prefix and hyphens so `urn:isbn:978-0-...` and
+// `9780...` match, other identifiers as they are
+fn identifier_key(book: &DuplicateBook) -> Option<String> {
+    let identifier = book.metadata.identifier.as_deref()?.trim();
+    let lowercase = identifier.to_lowercase();
+    let isbn = lowercase
+        .strip_prefix("urn:isbn:")
+        .or_else(|| lowercase.strip_prefix("isbn:"))
+        .unwrap_or(&lowercase);
+    let digits: String = isbn.chars().filter(|c| !matches!(c, '-' | ' ')).collect();
+    let is_isbn = matches!(digits.len(), 10 | 13)
+        && digits
+            .chars()
+            .enumerate()
+            .all(|(index, c)| c.is_ascii_digit() || (index == 9 && c == 'x'));
+    if is_isbn {
+        Some(format!("isbn:{}", digits))
+    } else if identifier.is_empty() {
+        None
+    } else {
+        Some(lowercase)
+    }
+}
+
+fn similar_titles(books: &[DuplicateBook]) -> Vec<(usize, usize)> {
+    let keys: Vec<Option<(String, Vec<String>)>> = books
+        .iter()
+        .map(|book| {
+            let title = normalize_title(book.metadata.title.as_deref()?);
+            let authors = book
+                .metadata
+                .authors
+                .iter()
+                .map(|author| normalize_author(author))
+                .collect();
+            Some((title, authors)).filter(|(title, _)| !title.is_empty())
+        })
+        .collect();
+
+    let mut pairs = Vec::new();
+    for (a, first) in keys.iter().enumerate() {
+        let (title_a, authors_a) = match first {
+            Some(key) => key,
+            None => continue,
+        };
+        for (b, second) in keys.iter().enumerate().skip(a + 1) {
+            let (title_b, authors_b) = match second {
+                Some(key) => key,
+                None => continue,
+            };
+            // volumes of a series differ in little more than their number
+            if numbers(title_a) != numbers(title_b)
+                || similarity(title_a, title_b) < TITLE_SIMILARITY
+            {
+                continue;
+            }
+            let same_authors = (authors_a.is_empty() && authors_b.is_empty())
+                || authors_a.iter().any(|author_a| {
+                    authors_b
+                        .iter()
+                        .any(|author_b| similarity(author_a, author_b) >= AUTHOR_SIMILARITY)
+                });
+            if same_authors {
+                pairs.push((a, b));
+            }
+        }
+    }
+    pairs
+}
+
+// lowercase words without punctuation or a leading article
+fn normalize_title(title: &str) -> String {
+    let words = words(title);
+    let words = match words.first().map(String::as_str) {
+        Some("the" | "a" | "an") if words.len() > 1 => &words[1..],
+        _ => &words[..],
+    };
+    words.join(" ")
+}
+
+// `Tolkien, J. R. R.` and `J.R.R. Tolkien` both become `j r r tolkien`
+fn normalize_author(author: &str) -> String {
+    let author = match author.split_once(',') {
+        Some((last, first)) => format!("{} {}", first, last),
+        None => author.to_string(),
+    };
+    words(&author.replace('.', " ")).join(" ")
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn numbers(text: &str) -> Vec<&str> {
+    text.split(' ')
+        .filter(|word| word.chars().all(|c| c.is_ascii_digit()))
+        .collect()
+}
+
+// 1 for the same text, down to 0 for nothing in common
+fn similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    // lengths alone rule most pairs out
+    if (a.len().abs_diff(b.len()) as f64) / (longest as f64)
+        > 1.0 - TITLE_SIMILARITY.min(AUTHOR_SIMILARITY)
+    {
+        return 0.0;
+    }
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, char_a) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, char_b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(char_a != char_b);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    1.0 - previous[b.len()] as f64 / longest as f64
+}
@@ -0,0 +1,49 @@
+// Bookx - import.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::formats::{self, FormatError};
+
+// Copies `file` into the library folder, next to a book of the same name
+// if there is one.
+pub fn import_book(file: &Path, library: &Path) -> Result<PathBuf, FormatError> {
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    // extensions like `.fb2.zip` stay in one piece
+    let extension = formats::for_path(file)
+        .and_then(|format| {
+            format
+                .extensions
+                .iter()
+                .find(|extension| name.to_lowercase().ends_with(*extension))
+        })
+        .map(|extension| extension.len())
+        .unwrap_or_default();
+    let (stem, extension) = name.split_at(name.len() - extension);
+    let target = (0..)
+        .map(|n| match n {
+            0 => library.join(&name),
+            n => library.join(format!("{} ({}){}", stem, n, extension)),
+        })
+        .find(|target| !target.exists())
+        .unwrap_or_default();
+    fs::copy(file, &target)?;
+    Ok(target)
+}
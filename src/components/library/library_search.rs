@@ -0,0 +1,239 @@
+// Bookx - library_search.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::{gettext, ngettext};
+use relm4::{
+    adw,
+    gtk::{self, glib, pango, prelude::*},
+    Component, ComponentParts, ComponentSender, RelmWidgetExt,
+};
+
+use std::sync::Arc;
+
+use crate::search::{LibraryHit, SharedIndex};
+
+const MAX_HITS: usize = 200;
+
+// Search results page listing the books, chapters and snippets
+// matching a query against the library-wide index.
+pub struct BookxLibrarySearch {
+    index: Arc<SharedIndex>,
+    hits: Vec<LibraryHit>,
+    generation: u64,
+    status: String,
+}
+
+#[derive(Debug)]
+pub enum LibrarySearchInput {
+    Search(String),
+    Activate(usize),
+    Focus,
+}
+
+#[derive(Debug)]
+pub enum LibrarySearchOutput {
+    OpenBook {
+        path: String,
+        chapter: usize,
+        start: usize,
+        end: usize,
+    },
+}
+
+#[derive(Debug)]
+pub struct LibrarySearchFound {
+    generation: u64,
+    hits: Vec<LibraryHit>,
+}
+
+#[relm4_macros::component(pub)]
+impl Component for BookxLibrarySearch {
+    type Init = Arc<SharedIndex>;
+    type Input = LibrarySearchInput;
+    type Output = LibrarySearchOutput;
+    type CommandOutput = LibrarySearchFound;
+
+    view! {
+        #[name = "library_search"]
+        gtk::Box {
+            set_orientation: gtk::Orientation::Vertical,
+            set_spacing: 12,
+            set_margin_all: 12,
+
+            adw::Clamp {
+                set_maximum_size: 600,
+                #[name = "search_entry"]
+                gtk::SearchEntry {
+                    set_placeholder_text: Some(&gettext("Search all books")),
+                    connect_search_changed[sender] => move |entry| {
+                        sender.input(LibrarySearchInput::Search(entry.text().to_string()));
+                    },
+                },
+            },
+            gtk::Label {
+                #[watch]
+                set_label: &model.status,
+                #[watch]
+                set_visible: !model.status.is_empty(),
+                add_css_class: "dim-label",
+            },
+            gtk::ScrolledWindow {
+                set_vexpand: true,
+                set_hscrollbar_policy: gtk::PolicyType::Never,
+
+                adw::Clamp {
+                    set_maximum_size: 800,
+                    #[name = "results_list"]
+                    gtk::ListBox {
+                        set_valign: gtk::Align::Start,
+                        set_selection_mode: gtk::SelectionMode::None,
+                        add_css_class: "boxed-list",
+                        connect_row_activated[sender] => move |_, row| {
+                            sender.input(LibrarySearchInput::Activate(row.index() as usize));
+                        },
+                    }
+                }
+            }
+        }
+    }
+
+    fn init(
+        index: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = BookxLibrarySearch {
+            index,
+            hits: Vec::new(),
+            generation: 0,
+            status: String::new(),
+        };
+        let widgets = view_output!();
+        ComponentParts { model, widgets }
+    }
+
+    fn update_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        message: Self::Input,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match message {
+            LibrarySearchInput::Search(query) => {
+                self.generation += 1;
+                if query.trim().is_empty() {
+                    self.hits.clear();
+                    self.status = String::new();
+                    show_hits(&widgets.results_list, &self.hits);
+                } else {
+                    if self.index.is_updating() {
+                        self.status = gettext("Indexing library, results may be incomplete…");
+                    }
+                    let index = self.index.clone();
+                    let generation = self.generation;
+                    sender.spawn_oneshot_command(move || LibrarySearchFound {
+                        generation,
+                        hits: index.search(&query, MAX_HITS),
+                    });
+                }
+            }
+            LibrarySearchInput::Activate(index) => {
+                if let Some(hit) = self.hits.get(index) {
+                    sender
+                        .output(LibrarySearchOutput::OpenBook {
+                            path: hit.path.clone(),
+                            chapter: hit.result.chapter,
+                            start: hit.result.start,
+                            end: hit.result.end,
+                        })
+                        .unwrap();
+                }
+            }
+            LibrarySearchInput::Focus => {
+                widgets.search_entry.grab_focus();
+            }
+        }
+        self.update_view(widgets, sender);
+    }
+
+    fn update_cmd_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        message: Self::CommandOutput,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        if message.generation != self.generation {
+            return;
+        }
+        let count = message.hits.len();
+        self.status = if count == 0 {
+            gettext("No books mention this")
+        } else {
+            ngettext("%d match", "%d matches", count as u32).replace("%d", &count.to_string())
+        };
+        if self.index.is_updating() {
+            self.status = format!("{} — {}", self.status, gettext("indexing library…"));
+        }
+        self.hits = message.hits;
+        show_hits(&widgets.results_list, &self.hits);
+        self.update_view(widgets, sender);
+    }
+}
+
+fn show_hits(list: &gtk::ListBox, hits: &[LibraryHit]) {
+    while let Some(row) = list.row_at_index(0) {
+        list.remove(&row);
+    }
+
+    for hit in hits {
+        let book = gtk::Label::builder()
+            .label(&hit.book_title)
+            .xalign(0.0)
+            .ellipsize(pango::EllipsizeMode::End)
+            .build();
+        book.add_css_class("heading");
+
+        let chapter = gtk::Label::builder()
+            .label(&hit.result.chapter_title)
+            .xalign(0.0)
+            .ellipsize(pango::EllipsizeMode::End)
+            .build();
+        chapter.add_css_class("dim-label");
+        chapter.add_css_class("caption");
+
+        let snippet = gtk::Label::builder()
+            .xalign(0.0)
+            .wrap(true)
+            .wrap_mode(pango::WrapMode::WordChar)
+            .build();
+        snippet.set_markup(&format!(
+            "…{}<b>{}</b>{}…",
+            glib::markup_escape_text(hit.result.before.trim_start()),
+            glib::markup_escape_text(&hit.result.matched),
+            glib::markup_escape_text(hit.result.after.trim_end()),
+        ));
+
+        let row_box = gtk::Box::new(gtk::Orientation::Vertical, 3);
+        row_box.set_margin_all(12);
+        row_box.append(&book);
+        row_box.append(&chapter);
+        row_box.append(&snippet);
+
+        list.append(&gtk::ListBoxRow::builder().child(&row_box).build());
+    }
+}
@@ -1,5 +1,19 @@
+mod batch;
+mod book_details;
+mod book_menu;
 mod bookx_book;
 mod bookx_library;
+mod bulk_edit;
+mod duplicate_finder;
+mod duplicates;
+mod import;
+mod library_search;
+mod new_book;
+mod reading_stats;
+mod shelves;
+mod sorting;
+mod statistics;
 
-pub use bookx_book::BookxBook;
-pub use bookx_library::BookxLibrary;
+pub use bookx_book::{BookxBook, BookxBookInput};
+pub use bookx_library::{BookxLibrary, LibraryInput, LibraryOutput};
+pub use library_search::{BookxLibrarySearch, LibrarySearchInput, LibrarySearchOutput};
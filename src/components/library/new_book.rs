@@ -0,0 +1,328 @@
+// Bookx - new_book.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::gettext;
+use relm4::{
+    adw::{self, prelude::*},
+    gtk::{self, glib},
+    Component, ComponentParts, ComponentSender,
+};
+
+use std::path::PathBuf;
+
+use crate::formats::BookMetadata;
+
+// What the dialog collected, the book is written by the library
+#[derive(Debug)]
+pub struct NewBook {
+    // folder of Markdown and HTML chapters
+    pub folder: PathBuf,
+    // image to use as cover, one is generated without it
+    pub cover: Option<PathBuf>,
+    pub metadata: BookMetadata,
+}
+
+pub struct BookxNewBook {
+    folder: Option<PathBuf>,
+    cover: Option<PathBuf>,
+    title: String,
+    authors: String,
+    language: String,
+    publisher: String,
+    description: String,
+    // kept alive while the dialog is shown
+    file_chooser: Option<gtk::FileChooserNative>,
+}
+
+#[derive(Debug)]
+pub enum NewBookInput {
+    Title(String),
+    Authors(String),
+    Language(String),
+    Publisher(String),
+    Description(String),
+    ChooseFolder,
+    FolderChosen(PathBuf),
+    ChooseCover,
+    CoverChosen(PathBuf),
+    Create,
+    Cancel,
+}
+
+#[derive(Debug)]
+pub enum NewBookOutput {
+    Create(NewBook),
+}
+
+#[relm4_macros::component(pub)]
+impl Component for BookxNewBook {
+    type Init = ();
+    type Input = NewBookInput;
+    type Output = NewBookOutput;
+    type CommandOutput = ();
+
+    view! {
+        #[name = "new_book_window"]
+        adw::Window {
+            set_title: Some(&gettext("New Book")),
+            set_default_width: 480,
+            set_modal: true,
+
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                adw::HeaderBar {
+                    set_show_end_title_buttons: false,
+                    pack_start = &gtk::Button {
+                        set_label: &gettext("_Cancel"),
+                        set_use_underline: true,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(NewBookInput::Cancel);
+                        },
+                    },
+                    pack_end = &gtk::Button {
+                        set_label: &gettext("C_reate"),
+                        set_use_underline: true,
+                        add_css_class: "suggested-action",
+                        #[watch]
+                        set_sensitive: model.folder.is_some() && !model.title.trim().is_empty(),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(NewBookInput::Create);
+                        },
+                    },
+                },
+
+                adw::PreferencesPage {
+                    adw::PreferencesGroup {
+                        set_title: &gettext("Contents"),
+                        set_description: Some(&gettext(
+                            "Markdown and HTML files of the folder become chapters, in the order of their names.",
+                        )),
+
+                        adw::ActionRow {
+                            set_title: &gettext("Chapters Folder"),
+                            #[watch]
+                            set_subtitle: &model
+                                .folder
+                                .as_ref()
+                                .map(|folder| folder.display().to_string())
+                                .unwrap_or_else(|| gettext("None")),
+                            add_suffix = &gtk::Button {
+                                set_icon_name: "folder-open-symbolic",
+                                set_tooltip_text: Some(&gettext("Choose Folder")),
+                                set_valign: gtk::Align::Center,
+                                add_css_class: "flat",
+                                connect_clicked[sender] => move |_| {
+                                    sender.input(NewBookInput::ChooseFolder);
+                                },
+                            },
+                        },
+                        adw::ActionRow {
+                            set_title: &gettext("Cover"),
+                            #[watch]
+                            set_subtitle: &model
+                                .cover
+                                .as_ref()
+                                .map(|cover| cover.display().to_string())
+                                .unwrap_or_else(|| gettext("Generated from the title")),
+                            add_suffix = &gtk::Button {
+                                set_icon_name: "image-x-generic-symbolic",
+                                set_tooltip_text: Some(&gettext("Choose Cover")),
+                                set_valign: gtk::Align::Center,
+                                add_css_class: "flat",
+                                connect_clicked[sender] => move |_| {
+                                    sender.input(NewBookInput::ChooseCover);
+                                },
+                            },
+                        },
+                    },
+
+                    adw::PreferencesGroup {
+                        set_title: &gettext("Metadata"),
+
+                        adw::EntryRow {
+                            set_title: &gettext("Title"),
+                            connect_changed[sender] => move |row| {
+                                sender.input(NewBookInput::Title(row.text().to_string()));
+                            },
+                        },
+                        adw::EntryRow {
+                            set_title: &gettext("Authors, separated by commas"),
+                            connect_changed[sender] => move |row| {
+                                sender.input(NewBookInput::Authors(row.text().to_string()));
+                            },
+                        },
+                        adw::EntryRow {
+                            set_title: &gettext("Language"),
+                            set_text: &model.language,
+                            connect_changed[sender] => move |row| {
+                                sender.input(NewBookInput::Language(row.text().to_string()));
+                            },
+                        },
+                        adw::EntryRow {
+                            set_title: &gettext("Publisher"),
+                            connect_changed[sender] => move |row| {
+                                sender.input(NewBookInput::Publisher(row.text().to_string()));
+                            },
+                        },
+                        adw::EntryRow {
+                            set_title: &gettext("Description"),
+                            connect_changed[sender] => move |row| {
+                                sender.input(NewBookInput::Description(row.text().to_string()));
+                            },
+                        },
+                    },
+                },
+            },
+        }
+    }
+
+    fn init(
+        _: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = BookxNewBook {
+            folder: None,
+            cover: None,
+            title: String::new(),
+            authors: String::new(),
+            language: default_language(),
+            publisher: String::new(),
+            description: String::new(),
+            file_chooser: None,
+        };
+        let widgets = view_output!();
+        root.present();
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, message: Self::Input, sender: ComponentSender<Self>, root: &Self::Root) {
+        match message {
+            NewBookInput::Title(title) => self.title = title,
+            NewBookInput::Authors(authors) => self.authors = authors,
+            NewBookInput::Language(language) => self.language = language,
+            NewBookInput::Publisher(publisher) => self.publisher = publisher,
+            NewBookInput::Description(description) => self.description = description,
+            NewBookInput::ChooseFolder => self.choose(
+                root,
+                &gettext("Choose Chapters Folder"),
+                gtk::FileChooserAction::SelectFolder,
+                None,
+                NewBookInput::FolderChosen,
+                &sender,
+            ),
+            NewBookInput::FolderChosen(folder) => {
+                self.file_chooser = None;
+                self.folder = Some(folder);
+            }
+            NewBookInput::ChooseCover => {
+                let filter = gtk::FileFilter::new();
+                filter.set_name(Some(&gettext("Images")));
+                filter.add_pixbuf_formats();
+                self.choose(
+                    root,
+                    &gettext("Choose Cover"),
+                    gtk::FileChooserAction::Open,
+                    Some(filter),
+                    NewBookInput::CoverChosen,
+                    &sender,
+                );
+            }
+            NewBookInput::CoverChosen(cover) => {
+                self.file_chooser = None;
+                self.cover = Some(cover);
+            }
+            NewBookInput::Create => {
+                let folder = match self.folder.take() {
+                    Some(folder) => folder,
+                    None => return,
+                };
+                let optional =
+                    |value: &str| Some(value.trim().to_string()).filter(|value| !value.is_empty());
+                let metadata = BookMetadata {
+                    title: optional(&self.title),
+                    authors: self.authors.split(',').filter_map(optional).collect(),
+                    language: optional(&self.language),
+                    publisher: optional(&self.publisher),
+                    description: optional(&self.description),
+                    date: glib::DateTime::now_local()
+                        .and_then(|now| now.format("%Y-%m-%d"))
+                        .ok()
+                        .map(|date| date.to_string()),
+                    ..Default::default()
+                };
+                sender
+                    .output(NewBookOutput::Create(NewBook {
+                        folder,
+                        cover: self.cover.take(),
+                        metadata,
+                    }))
+                    .unwrap();
+                root.close();
+            }
+            NewBookInput::Cancel => root.close(),
+        }
+    }
+}
+
+impl BookxNewBook {
+    // shows a file chooser, `message` is sent with the file or folder picked
+    fn choose(
+        &mut self,
+        root: &adw::Window,
+        title: &str,
+        action: gtk::FileChooserAction,
+        filter: Option<gtk::FileFilter>,
+        message: fn(PathBuf) -> NewBookInput,
+        sender: &ComponentSender<Self>,
+    ) {
+        let chooser = gtk::FileChooserNative::new(
+            Some(title),
+            Some(root),
+            action,
+            Some(&gettext("_Select")),
+            Some(&gettext("_Cancel")),
+        );
+        chooser.set_modal(true);
+        if let Some(filter) = filter {
+            chooser.add_filter(&filter);
+        }
+        chooser.connect_response(glib::clone!(@strong sender => move |chooser, response| {
+            if response == gtk::ResponseType::Accept {
+                if let Some(path) = chooser.file().and_then(|file| file.path()) {
+                    sender.input(message(path));
+                }
+            }
+        }));
+        chooser.show();
+        self.file_chooser = Some(chooser);
+    }
+}
+
+// language of the user interface as a language tag, `en_US.UTF-8` is `en-US`
+fn default_language() -> String {
+    glib::language_names()
+        .first()
+        .map(|name| {
+            name.split(['.', '@'])
+                .next()
+                .unwrap_or_default()
+                .replace('_', "-")
+        })
+        .filter(|language| language != "C" && language != "POSIX" && !language.is_empty())
+        .unwrap_or_else(|| String::from("en"))
+}
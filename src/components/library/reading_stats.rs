@@ -0,0 +1,257 @@
+// Bookx - reading_stats.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use relm4::gtk::glib;
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::library_db::{LibraryDb, ProgressUnit, ReadingSession};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+// how far back the charts go
+const DAYS_SHOWN: i64 = 14;
+const WEEKS_SHOWN: i64 = 8;
+const MONTHS_SHOWN: i32 = 12;
+
+// What the reading sessions kept in the library add up to. Days are
+// counted from the epoch in local time, a session counts on the day
+// it started.
+#[derive(Debug, Default)]
+pub struct ReadingStats {
+    // seconds read on each of the last days, oldest first
+    pub days: Vec<(i64, i64)>,
+    // seconds read in each of the last weeks, by the day they start on
+    pub weeks: Vec<(i64, i64)>,
+    // books finished in each of the last months, by year and month
+    pub months: Vec<((i32, u32), usize)>,
+    // characters or pages read in an hour
+    pub speeds: Vec<(ProgressUnit, f64)>,
+    // estimated seconds to finish each book being read, longest first
+    pub time_left: Vec<(String, i64)>,
+    pub total: i64,
+    // days read in a row up to today, and the most ever
+    pub streak: usize,
+    pub longest_streak: usize,
+}
+
+impl ReadingStats {
+    // `now` is in seconds since the epoch
+    pub fn new(db: &LibraryDb, now: i64) -> Self {
+        let today = local_day(now);
+        let mut per_day: BTreeMap<i64, i64> = BTreeMap::new();
+        // time and progress of each book and of each unit
+        let mut per_book: HashMap<&str, (i64, u64)> = HashMap::new();
+        let mut per_unit: HashMap<ProgressUnit, (i64, u64)> = HashMap::new();
+        for session in &db.sessions {
+            let duration = session.duration();
+            *per_day.entry(local_day(session.start)).or_default() += duration;
+            let book = per_book.entry(&session.path).or_default();
+            book.0 += duration;
+            book.1 += session.advanced;
+            let unit = per_unit.entry(session.unit).or_default();
+            unit.0 += duration;
+            unit.1 += session.advanced;
+        }
+
+        let days = (today - DAYS_SHOWN + 1..=today)
+            .map(|day| (day, per_day.get(&day).copied().unwrap_or_default()))
+            .collect();
+        let this_week = week_start(today);
+        let weeks = (0..WEEKS_SHOWN)
+            .rev()
+            .map(|weeks_ago| {
+                let start = this_week - weeks_ago * 7;
+                (
+                    start,
+                    per_day
+                        .range(start..start + 7)
+                        .map(|(_, seconds)| seconds)
+                        .sum(),
+                )
+            })
+            .collect();
+
+        let mut finished: HashMap<(i32, u32), usize> = HashMap::new();
+        for progress in db
+            .books
+            .values()
+            .filter_map(|record| record.progress.as_ref())
+        {
+            if let Some(time) = progress.finished {
+                *finished.entry(year_month(local_day(time))).or_default() += 1;
+            }
+        }
+        let (year, month) = year_month(today);
+        let months = (0..MONTHS_SHOWN)
+            .rev()
+            .map(|months_ago| {
+                let index = year * 12 + month as i32 - 1 - months_ago;
+                let key = (index.div_euclid(12), index.rem_euclid(12) as u32 + 1);
+                (key, finished.get(&key).copied().unwrap_or_default())
+            })
+            .collect();
+
+        let speed = |(seconds, advanced): (i64, u64)| {
+            (seconds > 0 && advanced > 0).then(|| advanced as f64 * 3600.0 / seconds as f64)
+        };
+        let mut speeds: Vec<(ProgressUnit, f64)> = per_unit
+            .iter()
+            .filter_map(|(unit, reading)| Some((*unit, speed(*reading)?)))
+            .collect();
+        speeds.sort_by_key(|(unit, _)| *unit == ProgressUnit::Pages);
+
+        // a book's own pace is the better guess, once there is one
+        let mut time_left: Vec<(String, i64)> = db
+            .books
+            .iter()
+            .filter_map(|(path, record)| {
+                let progress = record.progress.as_ref()?;
+                if progress.finished.is_some() || progress.length == 0 {
+                    return None;
+                }
+                let speed = per_book
+                    .get(path.as_str())
+                    .and_then(|reading| speed(*reading))
+                    .or_else(|| {
+                        per_unit
+                            .get(&progress.unit)
+                            .and_then(|reading| speed(*reading))
+                    })?;
+                let left = progress.length.saturating_sub(progress.position) as f64;
+                Some((path.clone(), (left * 3600.0 / speed) as i64))
+            })
+            .collect();
+        time_left.sort_by_key(|(_, seconds)| Reverse(*seconds));
+
+        let read_days: BTreeSet<i64> = per_day
+            .iter()
+            .filter(|(_, seconds)| **seconds > 0)
+            .map(|(day, _)| *day)
+            .collect();
+        // today isn't over, the streak holds until it is
+        let mut day = if read_days.contains(&today) {
+            today
+        } else {
+            today - 1
+        };
+        let mut streak = 0;
+        while read_days.contains(&day) {
+            streak += 1;
+            day -= 1;
+        }
+        let mut longest_streak = 0;
+        let mut run = 0;
+        let mut previous = None;
+        for day in &read_days {
+            run = if previous == Some(day - 1) {
+                run + 1
+            } else {
+                1
+            };
+            longest_streak = longest_streak.max(run);
+            previous = Some(*day);
+        }
+
+        ReadingStats {
+            days,
+            weeks,
+            months,
+            speeds,
+            time_left,
+            total: per_day.values().sum(),
+            streak,
+            longest_streak,
+        }
+    }
+}
+
+// The sessions as comma separated values, one row each with the times in
+// ISO 8601.
+pub fn sessions_csv(sessions: &[ReadingSession], titles: &HashMap<String, String>) -> String {
+    let mut csv = String::from("title,path,start,end,seconds,unit,advanced,position,length\n");
+    for session in sessions {
+        let title = titles
+            .get(&session.path)
+            .map(String::as_str)
+            .unwrap_or_default();
+        let unit = match session.unit {
+            ProgressUnit::Characters => "characters",
+            ProgressUnit::Pages => "pages",
+        };
+        let row = [
+            csv_field(title),
+            csv_field(&session.path),
+            iso_8601(session.start),
+            iso_8601(session.end),
+            session.duration().to_string(),
+            unit.to_string(),
+            session.advanced.to_string(),
+            session.position.to_string(),
+            session.length.to_string(),
+        ];
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn iso_8601(time: i64) -> String {
+    glib::DateTime::from_unix_local(time)
+        .and_then(|time| time.format("%FT%T%:z"))
+        .map(|time| time.to_string())
+        .unwrap_or_default()
+}
+
+// day since the epoch the time falls on where the user is
+pub fn local_day(time: i64) -> i64 {
+    let offset = glib::DateTime::from_unix_local(time)
+        .map(|time| time.utc_offset().as_seconds())
+        .unwrap_or_default();
+    (time + offset).div_euclid(SECONDS_PER_DAY)
+}
+
+// weeks start on monday, the epoch was a thursday
+fn week_start(day: i64) -> i64 {
+    day - (day + 3).rem_euclid(7)
+}
+
+// year and month of a day since the epoch, after Howard Hinnant's
+// `civil_from_days`
+fn year_month(day: i64) -> (i32, u32) {
+    let day = day + 719_468;
+    let era = day.div_euclid(146_097);
+    let day_of_era = day.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year as i32, month as u32)
+}
@@ -0,0 +1,89 @@
+// Bookx - shelves.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::gettext;
+use relm4::gtk::{self, prelude::*};
+
+use crate::library_db::{Filter, LibraryDb};
+
+// Filters saved under a name, picked from a drop-down above the library.
+#[derive(Debug)]
+pub struct Shelves {
+    drop_down: gtk::DropDown,
+    // the shelf shown, with its filter, narrows the one typed
+    shown: Option<usize>,
+    pub filter: Filter,
+}
+
+impl Shelves {
+    pub fn new(drop_down: gtk::DropDown) -> Self {
+        Shelves {
+            drop_down,
+            shown: None,
+            filter: Filter::default(),
+        }
+    }
+
+    pub fn is_shown(&self) -> bool {
+        self.shown.is_some()
+    }
+
+    // `index` is the one picked in the drop-down, 0 for all books
+    pub fn show(&mut self, index: u32, db: &LibraryDb) {
+        self.shown = (index as usize).checked_sub(1);
+        self.filter = self
+            .shown
+            .and_then(|index| db.shelves.get(index))
+            .map(|shelf| Filter::parse(&shelf.filter))
+            .unwrap_or_default();
+    }
+
+    // saves the filter `typed` as shelf `name` and picks it
+    pub fn save(&mut self, db: &mut LibraryDb, name: &str, typed: &str) {
+        // a shelf made while another is shown keeps its books
+        let filter = match self.shown.and_then(|index| db.shelves.get(index)) {
+            Some(shelf) => format!("{} {}", shelf.filter, typed),
+            None => typed.to_string(),
+        };
+        let index = db.save_shelf(name, &filter);
+        db.save();
+        self.drop_down.set_model(Some(&shelf_options(db)));
+        self.drop_down.set_selected(index as u32 + 1);
+    }
+
+    // removes the shelf shown, if there is one, and goes back to all books
+    pub fn remove(&mut self, db: &mut LibraryDb) -> bool {
+        let index = match self.shown.take() {
+            Some(index) => index,
+            None => return false,
+        };
+        db.shelves.remove(index);
+        db.save();
+        self.drop_down.set_model(Some(&shelf_options(db)));
+        self.drop_down.set_selected(0);
+        true
+    }
+}
+
+// all books and the shelves, in the order of `Shelves::show`
+pub fn shelf_options(db: &LibraryDb) -> gtk::StringList {
+    let options = gtk::StringList::new(&[]);
+    options.append(&gettext("All Books"));
+    for shelf in &db.shelves {
+        options.append(&shelf.name);
+    }
+    options
+}
@@ -0,0 +1,83 @@
+// Bookx - sorting.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::gettext;
+use relm4::gtk::{self, prelude::*};
+
+use crate::library_db::{LibraryDb, SortKey};
+
+// What the library is sorted by, picked from a drop-down of the file name,
+// the title and the custom fields.
+#[derive(Debug)]
+pub struct Sorting {
+    drop_down: gtk::DropDown,
+    pub key: SortKey,
+}
+
+impl Sorting {
+    pub fn new(drop_down: gtk::DropDown) -> Self {
+        Sorting {
+            drop_down,
+            key: SortKey::FileName,
+        }
+    }
+
+    // `index` is the one picked in the drop-down, returns whether the
+    // order changes
+    pub fn pick(&mut self, index: u32, db: &LibraryDb) -> bool {
+        let key = match index {
+            0 => SortKey::FileName,
+            1 => SortKey::Title,
+            index => match db.fields.get(index as usize - 2) {
+                Some(field) => SortKey::Field(field.name.clone()),
+                None => return false,
+            },
+        };
+        if key == self.key {
+            return false;
+        }
+        self.key = key;
+        true
+    }
+
+    // shows the fields of `db` in the drop-down, still sorting by the
+    // same field while it's there
+    pub fn update_fields(&self, db: &LibraryDb) {
+        let selected = match &self.key {
+            SortKey::FileName => 0,
+            SortKey::Title => 1,
+            SortKey::Field(name) => db
+                .fields
+                .iter()
+                .position(|field| field.name == *name)
+                .map(|index| index as u32 + 2)
+                .unwrap_or(0),
+        };
+        self.drop_down.set_model(Some(&sort_options(db)));
+        self.drop_down.set_selected(selected);
+    }
+}
+
+// what the library can be sorted by, in the order of `Sorting::pick`
+pub fn sort_options(db: &LibraryDb) -> gtk::StringList {
+    let options = gtk::StringList::new(&[]);
+    options.append(&gettext("File Name"));
+    options.append(&gettext("Title"));
+    for field in &db.fields {
+        options.append(&field.label);
+    }
+    options
+}
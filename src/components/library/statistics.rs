@@ -0,0 +1,282 @@
+// Bookx - statistics.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::{gettext, ngettext};
+use relm4::{
+    adw::{self, prelude::*},
+    gtk::{self, glib},
+    Component, ComponentParts, ComponentSender,
+};
+use tracing::error;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::components::library::reading_stats::{self, ReadingStats};
+use crate::library_db::{ProgressUnit, ReadingSession};
+
+// Window with how much and how fast books are read, from the sessions
+// kept in the library.
+pub struct BookxStatistics {
+    window: adw::PreferencesWindow,
+    sessions: Vec<ReadingSession>,
+    // titles by path, for the export
+    titles: HashMap<String, String>,
+    // kept alive while the chooser is shown
+    file_chooser: Option<gtk::FileChooserNative>,
+}
+
+pub struct StatisticsInit {
+    pub stats: ReadingStats,
+    pub sessions: Vec<ReadingSession>,
+    pub titles: HashMap<String, String>,
+}
+
+#[derive(Debug)]
+pub enum StatisticsInput {
+    Export,
+    ExportTo(PathBuf),
+}
+
+#[relm4_macros::component(pub)]
+impl Component for BookxStatistics {
+    type Init = StatisticsInit;
+    type Input = StatisticsInput;
+    type Output = ();
+    type CommandOutput = ();
+
+    view! {
+        #[name = "statistics_window"]
+        adw::PreferencesWindow {
+            set_title: Some(&gettext("Reading Statistics")),
+            set_default_width: 560,
+            set_default_height: 640,
+            set_modal: true,
+            set_search_enabled: false,
+
+            #[name = "page"]
+            add = &adw::PreferencesPage {
+                #[name = "overview"]
+                adw::PreferencesGroup {
+                    set_title: &gettext("Overview"),
+                    #[wrap(Some)]
+                    set_header_suffix = &gtk::Button {
+                        set_label: &gettext("Export CSV…"),
+                        set_valign: gtk::Align::Center,
+                        set_sensitive: !model.sessions.is_empty(),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(StatisticsInput::Export);
+                        },
+                    },
+                },
+            }
+        }
+    }
+
+    fn init(
+        init: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let StatisticsInit {
+            stats,
+            sessions,
+            titles,
+        } = init;
+        let model = BookxStatistics {
+            window: root.clone(),
+            sessions,
+            titles,
+            file_chooser: None,
+        };
+        let widgets = view_output!();
+
+        let streak = |days: usize| {
+            ngettext("%d day", "%d days", days as u32).replace("%d", &days.to_string())
+        };
+        widgets.overview.add(&row(
+            &gettext("Total Time Read"),
+            &duration_label(stats.total),
+        ));
+        widgets
+            .overview
+            .add(&row(&gettext("Current Streak"), &streak(stats.streak)));
+        widgets.overview.add(&row(
+            &gettext("Longest Streak"),
+            &streak(stats.longest_streak),
+        ));
+        for (unit, speed) in &stats.speeds {
+            let (title, speed) = match unit {
+                ProgressUnit::Characters => (
+                    gettext("Average Speed in Books"),
+                    gettext("%s characters an hour"),
+                ),
+                ProgressUnit::Pages => (
+                    gettext("Average Speed in Comics and PDFs"),
+                    gettext("%s pages an hour"),
+                ),
+            };
+            widgets
+                .overview
+                .add(&row(&title, &speed.replace("%s", &format!("{:.0}", speed))));
+        }
+
+        let days = chart(
+            &gettext("Last Two Weeks"),
+            stats.days.iter().map(|(day, seconds)| {
+                (
+                    day_label(*day, "%a %e %b"),
+                    *seconds as f64,
+                    duration_label(*seconds),
+                )
+            }),
+        );
+        widgets.page.add(&days);
+        let weeks = chart(
+            &gettext("Last Eight Weeks"),
+            stats.weeks.iter().map(|(day, seconds)| {
+                (
+                    gettext("Week of %s").replace("%s", &day_label(*day, "%e %b")),
+                    *seconds as f64,
+                    duration_label(*seconds),
+                )
+            }),
+        );
+        widgets.page.add(&weeks);
+        let months = chart(
+            &gettext("Books Finished"),
+            stats.months.iter().map(|((year, month), count)| {
+                let label = glib::DateTime::from_utc(*year, *month as i32, 1, 0, 0, 0.0)
+                    .and_then(|date| date.format("%B %Y"))
+                    .map(|label| label.to_string())
+                    .unwrap_or_default();
+                (label, *count as f64, count.to_string())
+            }),
+        );
+        widgets.page.add(&months);
+
+        if !stats.time_left.is_empty() {
+            let time_left = adw::PreferencesGroup::new();
+            time_left.set_title(&gettext("Time Left"));
+            time_left.set_description(Some(&gettext(
+                "Estimated from the pace each book has been read at so far",
+            )));
+            for (path, seconds) in &stats.time_left {
+                let title = model.titles.get(path).cloned().unwrap_or_else(|| {
+                    Path::new(path)
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default()
+                });
+                time_left.add(&row(&title, &duration_label(*seconds)));
+            }
+            widgets.page.add(&time_left);
+        }
+
+        root.present();
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, message: Self::Input, sender: ComponentSender<Self>, _root: &Self::Root) {
+        match message {
+            StatisticsInput::Export => {
+                let chooser = gtk::FileChooserNative::new(
+                    Some(&gettext("Export Reading Sessions")),
+                    Some(&self.window),
+                    gtk::FileChooserAction::Save,
+                    Some(&gettext("_Export")),
+                    Some(&gettext("_Cancel")),
+                );
+                chooser.set_modal(true);
+                chooser.set_current_name("reading-sessions.csv");
+                chooser.connect_response(glib::clone!(@strong sender => move |chooser, response| {
+                    if response == gtk::ResponseType::Accept {
+                        if let Some(path) = chooser.file().and_then(|file| file.path()) {
+                            sender.input(StatisticsInput::ExportTo(path));
+                        }
+                    }
+                }));
+                chooser.show();
+                self.file_chooser = Some(chooser);
+            }
+            StatisticsInput::ExportTo(path) => {
+                self.file_chooser = None;
+                let csv = reading_stats::sessions_csv(&self.sessions, &self.titles);
+                let message = match fs::write(&path, csv) {
+                    Ok(()) => gettext("Reading sessions exported"),
+                    Err(e) => {
+                        error!("Unable to export the reading sessions to {:?}: {}", path, e);
+                        gettext("Unable to export the reading sessions")
+                    }
+                };
+                self.window.add_toast(&adw::Toast::new(&message));
+            }
+        }
+    }
+}
+
+fn row(title: &str, value: &str) -> adw::ActionRow {
+    let row = adw::ActionRow::new();
+    row.set_title(title);
+    let value = gtk::Label::new(Some(value));
+    value.add_css_class("dim-label");
+    row.add_suffix(&value);
+    row
+}
+
+// a row for each value with a bar as long as the value
+fn chart(
+    title: &str,
+    values: impl Iterator<Item = (String, f64, String)>,
+) -> adw::PreferencesGroup {
+    let values: Vec<(String, f64, String)> = values.collect();
+    let most = values
+        .iter()
+        .map(|(_, value, _)| *value)
+        .fold(0.0, f64::max);
+    let group = adw::PreferencesGroup::new();
+    group.set_title(title);
+    for (label, value, value_label) in values {
+        let row = row(&label, &value_label);
+        let bar = gtk::LevelBar::new();
+        bar.set_max_value(most.max(1.0));
+        bar.set_value(value);
+        bar.set_width_request(160);
+        bar.set_valign(gtk::Align::Center);
+        row.add_suffix(&bar);
+        group.add(&row);
+    }
+    group
+}
+
+fn day_label(day: i64, format: &str) -> String {
+    glib::DateTime::from_unix_utc(day * 24 * 60 * 60)
+        .and_then(|date| date.format(format))
+        .map(|label| label.to_string())
+        .unwrap_or_default()
+}
+
+fn duration_label(seconds: i64) -> String {
+    let minutes = seconds / 60;
+    if minutes < 60 {
+        gettext("%s min").replace("%s", &minutes.to_string())
+    } else {
+        gettext("%s h %s min")
+            .replacen("%s", &(minutes / 60).to_string(), 1)
+            .replacen("%s", &(minutes % 60).to_string(), 1)
+    }
+}
@@ -14,45 +14,400 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::components::library::BookxLibrary;
+use crate::calibre;
+use crate::components::editor::{BookxEditor, EditorOutput};
+use crate::components::library::{
+    BookxLibrary, BookxLibrarySearch, LibraryInput, LibraryOutput, LibrarySearchInput,
+    LibrarySearchOutput,
+};
+use crate::components::reader::{
+    BookxReaderTabs, ReaderLocation, ReaderTabsInput, ReaderTabsOutput,
+};
+use crate::components::reader_window::{BookxReaderWindow, ReaderWindowOutput};
+use crate::config::APP_ID;
+use crate::formats::{self, epub_package::EpubPackage};
+use crate::library_db::ReadingSession;
+use crate::search::SharedIndex;
 use gettextrs::gettext;
 use relm4::{
-    gtk::{self, prelude::*},
+    adw,
+    gtk::{self, gio, glib, prelude::*},
     prelude::*,
     ComponentParts, ComponentSender, SimpleComponent,
 };
-use tracing::error;
+use tracing::{error, warn};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
 
 // serve as the main container for library, reader component
 // status page of library, add Toast messages
 pub struct BookxMainContainer {
     library: Controller<BookxLibrary>,
+    library_search: Controller<BookxLibrarySearch>,
+    tabs: Controller<BookxReaderTabs>,
+    // books open in the tabs of this window
+    open_books: Vec<String>,
+    // book in the selected tab
+    selected: Option<String>,
+    // the library over the open tabs
+    show_library: bool,
+    // reader windows and the books open in them
+    windows: HashMap<u32, (Controller<BookxReaderWindow>, Vec<String>)>,
+    next_window: u32,
+    editor: Option<Controller<BookxEditor>>,
+    index: Arc<SharedIndex>,
+    searching: bool,
+    // kept to hear about another library folder being chosen
+    settings: gio::Settings,
+}
+
+#[derive(Debug)]
+pub enum MainContainerInput {
+    OpenBook(String),
+    // books from outside the library, the first one is opened
+    OpenFiles(Vec<PathBuf>),
+    OpenBookAt {
+        path: String,
+        chapter: usize,
+        start: usize,
+        end: usize,
+    },
+    // the book that was open when Bookx was last closed
+    Reopen(ReaderLocation),
+    OpenInNewTab(String),
+    OpenInNewWindow(String),
+    // opens the book of the selected tab in a new window
+    NewWindow,
+    ShowLibrary,
+    BookSelected(Option<String>),
+    TabBooks(Vec<String>),
+    RecordSession(ReadingSession),
+    WindowBooks(u32, Vec<String>),
+    WindowClosed(u32),
+    EditBook(String),
+    CloseEditor,
+    BooksChanged(Vec<String>),
+    ToggleLibrarySearch,
+    RescanLibrary,
+    LibraryRootChanged,
+    NewBook,
+    ToggleSelectionMode,
+    FindDuplicates,
+    ShowStatistics,
+    // from the D-Bus interface, the percent read and a tag
+    SetProgress(String, f64),
+    AddTag(String, String),
 }
 
 #[relm4_macros::component(pub)]
 impl SimpleComponent for BookxMainContainer {
     type Init = ();
-    type Input = ();
+    type Input = MainContainerInput;
     type Output = ();
 
     view! {
         #[name = "main_container"]
-        gtk::ScrolledWindow {
-            set_hscrollbar_policy: gtk::PolicyType::Never,
-            gtk::Viewport {
-                set_vexpand: true,
-                set_scroll_to_focus: true,
-                set_child: Some(model.library.widget())
-            }
+        gtk::Stack {
+            set_transition_type: gtk::StackTransitionType::Crossfade,
+
+            add_named[Some("library")] = &adw::Bin {
+                set_child: Some(model.library.widget()),
+            },
+            add_named[Some("search")] = &adw::Bin {
+                set_child: Some(model.library_search.widget()),
+            },
+            add_named[Some("reader")] = &adw::Bin {
+                set_child: Some(model.tabs.widget()),
+            },
+            add_named[Some("editor")] = &adw::Bin {
+                #[watch]
+                set_child: model.editor.as_ref().map(|editor| editor.widget()),
+            },
+
+            #[watch]
+            set_visible_child_name: if model.reading() {
+                "reader"
+            } else if model.editor.is_some() {
+                "editor"
+            } else if model.searching {
+                "search"
+            } else {
+                "library"
+            },
         }
     }
 
     fn init(_: (), root: &Self::Root, sender: ComponentSender<Self>) -> ComponentParts<Self> {
+        let index = Arc::new(SharedIndex::default());
+        let settings = gio::Settings::new(APP_ID);
+        settings.connect_changed(
+            None,
+            glib::clone!(@strong sender => move |_, key| {
+                if key == "books-dir" || key == "calibre-library" {
+                    sender.input(MainContainerInput::LibraryRootChanged);
+                } else if key == "library-documents" {
+                    sender.input(MainContainerInput::RescanLibrary);
+                }
+            }),
+        );
         let library = BookxLibrary::builder()
-            .launch(String::from("/home/adhadse/Documents/sample_dir"))
-            .detach();
-        let model = Self { library };
+            .launch(library_root(&settings))
+            .forward(sender.input_sender(), |message| match message {
+                LibraryOutput::OpenBook(path) => MainContainerInput::OpenBook(path),
+                LibraryOutput::OpenInNewTab(path) => MainContainerInput::OpenInNewTab(path),
+                LibraryOutput::OpenInNewWindow(path) => MainContainerInput::OpenInNewWindow(path),
+                LibraryOutput::EditBook(path) => MainContainerInput::EditBook(path),
+                LibraryOutput::BooksChanged(paths) => MainContainerInput::BooksChanged(paths),
+            });
+        let library_search = BookxLibrarySearch::builder().launch(index.clone()).forward(
+            sender.input_sender(),
+            |message| match message {
+                LibrarySearchOutput::OpenBook {
+                    path,
+                    chapter,
+                    start,
+                    end,
+                } => MainContainerInput::OpenBookAt {
+                    path,
+                    chapter,
+                    start,
+                    end,
+                },
+            },
+        );
+        let tabs =
+            BookxReaderTabs::builder()
+                .launch(true)
+                .forward(sender.input_sender(), |message| match message {
+                    ReaderTabsOutput::Session(session) => {
+                        MainContainerInput::RecordSession(session)
+                    }
+                    ReaderTabsOutput::Selected(path) => MainContainerInput::BookSelected(path),
+                    ReaderTabsOutput::OpenBooks(paths) => MainContainerInput::TabBooks(paths),
+                    ReaderTabsOutput::ShowLibrary => MainContainerInput::ShowLibrary,
+                    // the main window's tabs only finish as Bookx quits
+                    ReaderTabsOutput::Finished => MainContainerInput::ShowLibrary,
+                });
+        let model = Self {
+            library,
+            library_search,
+            tabs,
+            open_books: Vec::new(),
+            selected: None,
+            show_library: false,
+            windows: HashMap::new(),
+            next_window: 0,
+            editor: None,
+            index,
+            searching: false,
+            settings,
+        };
         let widgets = view_output!();
+
+        if !model.settings.boolean("start-in-library") {
+            if let Some(location) = ReaderLocation::last(&model.settings) {
+                sender.input(MainContainerInput::Reopen(location));
+            }
+        }
         ComponentParts { model, widgets }
     }
+
+    fn update(&mut self, message: Self::Input, sender: ComponentSender<Self>) {
+        match message {
+            MainContainerInput::OpenBook(path) => self.open_book(path),
+            MainContainerInput::OpenFiles(files) => {
+                let (content_dir, _) = library_root(&self.settings);
+                let import = self.settings.boolean("import-opened-books");
+                let mut opened = false;
+                for file in files {
+                    if formats::detect(&file).is_none() {
+                        warn!("Not opening {:?}, Bookx can't read its format", file);
+                        continue;
+                    }
+                    if import && !file.starts_with(&content_dir) {
+                        self.library.emit(LibraryInput::Import(file.clone()));
+                    }
+                    if !opened {
+                        opened = true;
+                        self.searching = false;
+                        self.open_book(file.display().to_string());
+                    }
+                }
+            }
+            MainContainerInput::OpenBookAt {
+                path,
+                chapter,
+                start,
+                end,
+            } => {
+                self.show_reader();
+                self.tabs.emit(ReaderTabsInput::OpenAt {
+                    path,
+                    chapter,
+                    start,
+                    end,
+                });
+            }
+            MainContainerInput::Reopen(location) => {
+                self.tabs.emit(ReaderTabsInput::Reopen(location))
+            }
+            MainContainerInput::OpenInNewTab(path) => {
+                self.show_reader();
+                self.tabs.emit(ReaderTabsInput::OpenTab(path));
+            }
+            MainContainerInput::OpenInNewWindow(path) => self.open_window(path, &sender),
+            MainContainerInput::NewWindow => {
+                if let Some(path) = self.selected.clone() {
+                    self.open_window(path, &sender);
+                }
+            }
+            MainContainerInput::ShowLibrary => {
+                // the next start opens in the library too
+                self.show_library = true;
+                self.remember_book("");
+            }
+            MainContainerInput::BookSelected(path) => {
+                self.selected = path;
+                if !self.show_library {
+                    self.remember_book(self.selected.as_deref().unwrap_or_default());
+                }
+            }
+            MainContainerInput::TabBooks(paths) => {
+                self.open_books = paths;
+                self.books_opened();
+            }
+            MainContainerInput::RecordSession(session) => {
+                self.library.emit(LibraryInput::RecordSession(session))
+            }
+            MainContainerInput::WindowBooks(id, paths) => {
+                if let Some((_, books)) = self.windows.get_mut(&id) {
+                    *books = paths;
+                    self.books_opened();
+                }
+            }
+            MainContainerInput::WindowClosed(id) => {
+                // dropping the window ends its readers
+                if self.windows.remove(&id).is_some() {
+                    self.books_opened();
+                }
+            }
+            MainContainerInput::EditBook(path) => match EpubPackage::open(Path::new(&path)) {
+                Ok(package) => {
+                    let editor = BookxEditor::builder().launch(package).forward(
+                        sender.input_sender(),
+                        |message| match message {
+                            // picks up the new cover and title
+                            EditorOutput::Saved => MainContainerInput::RescanLibrary,
+                            EditorOutput::Close => MainContainerInput::CloseEditor,
+                        },
+                    );
+                    self.editor = Some(editor);
+                }
+                Err(e) => error!("{}: {}", gettext("Unable to edit book"), e),
+            },
+            MainContainerInput::CloseEditor => self.editor = None,
+            MainContainerInput::BooksChanged(paths) => {
+                // indexing reads every new book, keep it off the main thread
+                let index = self.index.clone();
+                thread::spawn(move || index.update(&paths));
+            }
+            MainContainerInput::ToggleLibrarySearch => {
+                if !self.reading() && self.editor.is_none() {
+                    self.searching = !self.searching;
+                    if self.searching {
+                        self.library_search.emit(LibrarySearchInput::Focus);
+                    }
+                }
+            }
+            MainContainerInput::RescanLibrary => self.library.emit(LibraryInput::Rescan),
+            MainContainerInput::LibraryRootChanged => {
+                let (content_dir, calibre) = library_root(&self.settings);
+                self.library
+                    .emit(LibraryInput::SetRoot(content_dir, calibre));
+            }
+            MainContainerInput::NewBook => self.library.emit(LibraryInput::NewBook),
+            MainContainerInput::ToggleSelectionMode => {
+                self.library.emit(LibraryInput::ToggleSelectionMode)
+            }
+            MainContainerInput::FindDuplicates => self.library.emit(LibraryInput::FindDuplicates),
+            MainContainerInput::ShowStatistics => self.library.emit(LibraryInput::ShowStatistics),
+            MainContainerInput::SetProgress(path, percent) => {
+                self.library.emit(LibraryInput::SetProgress(path, percent))
+            }
+            MainContainerInput::AddTag(path, tag) => {
+                self.library.emit(LibraryInput::AddTag(path, tag))
+            }
+        }
+    }
+}
+
+impl BookxMainContainer {
+    fn open_book(&mut self, path: String) {
+        self.show_reader();
+        self.tabs.emit(ReaderTabsInput::Open(path));
+    }
+
+    // back to the tabs, the book of the selected one is reopened on the
+    // next start, unless another tab gets selected
+    fn show_reader(&mut self) {
+        self.show_library = false;
+        self.remember_book(self.selected.as_deref().unwrap_or_default());
+    }
+
+    fn open_window(&mut self, path: String, sender: &ComponentSender<Self>) {
+        let id = self.next_window;
+        self.next_window += 1;
+        let window = BookxReaderWindow::builder().launch((id, path)).forward(
+            sender.input_sender(),
+            |message| match message {
+                ReaderWindowOutput::Session(session) => MainContainerInput::RecordSession(session),
+                ReaderWindowOutput::OpenBooks(id, paths) => {
+                    MainContainerInput::WindowBooks(id, paths)
+                }
+                ReaderWindowOutput::Closed(id) => MainContainerInput::WindowClosed(id),
+            },
+        );
+        self.windows.insert(id, (window, Vec::new()));
+    }
+
+    fn reading(&self) -> bool {
+        !self.open_books.is_empty() && !self.show_library
+    }
+
+    // the library marks the books open in any window
+    fn books_opened(&self) {
+        let mut open = self.open_books.clone();
+        for (_, books) in self.windows.values() {
+            open.extend(books.iter().cloned());
+        }
+        open.sort();
+        open.dedup();
+        self.library.emit(LibraryInput::OpenBooks(open));
+    }
+
+    // reopened on the next start, the library when it's empty
+    fn remember_book(&self, path: &str) {
+        if let Err(e) = self.settings.set_string("last-book", path) {
+            warn!("Unable to remember the open book: {}", e);
+        }
+    }
+}
+
+// Folder of the library from the settings, the documents folder until one
+// is chosen, and whether to import it as a calibre library.
+fn library_root(settings: &gio::Settings) -> (String, bool) {
+    let books_dir = settings.string("books-dir");
+    let books_dir = if books_dir.is_empty() {
+        glib::user_special_dir(glib::UserDirectory::Documents)
+            .unwrap_or_else(glib::home_dir)
+            .display()
+            .to_string()
+    } else {
+        books_dir.to_string()
+    };
+    let calibre = settings.boolean("calibre-library") && calibre::is_library(Path::new(&books_dir));
+    (books_dir, calibre)
 }
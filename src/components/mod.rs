@@ -1,9 +1,15 @@
 mod about;
+mod editor;
 mod library;
 mod main_container;
 mod preferences;
-mod utils;
+mod reader;
+mod reader_window;
+pub(crate) mod utils;
+mod validation_report;
 
 pub use about::AboutDialog;
-pub use main_container::BookxMainContainer;
+pub use main_container::{BookxMainContainer, MainContainerInput};
 pub use preferences::BookxPreferences;
+pub use reader::{BackAction, ForwardAction, SearchAction, SplitAction};
+pub use validation_report::ValidationReport;
@@ -17,15 +17,36 @@
 use gettextrs::gettext;
 use relm4::{
     adw::{self, prelude::*},
-    gtk, ComponentParts, ComponentSender, SimpleComponent,
+    gtk::{self, gio, glib},
+    ComponentParts, ComponentSender, SimpleComponent,
 };
+use tracing::error;
 
-pub struct BookxPreferences {}
+use std::path::PathBuf;
+
+use crate::calibre;
+use crate::config::APP_ID;
+
+pub struct BookxPreferences {
+    window: adw::PreferencesWindow,
+    settings: gio::Settings,
+    books_dir: String,
+    calibre: bool,
+    // kept alive while the chooser is shown
+    file_chooser: Option<gtk::FileChooserNative>,
+}
+
+#[derive(Debug)]
+pub enum PreferencesInput {
+    // with whether a calibre library is wanted
+    ChooseFolder(bool),
+    FolderChosen(PathBuf, bool),
+}
 
 #[relm4::component(pub)]
 impl SimpleComponent for BookxPreferences {
     type Init = ();
-    type Input = ();
+    type Input = PreferencesInput;
     type Output = ();
 
     view! {
@@ -40,11 +61,63 @@ impl SimpleComponent for BookxPreferences {
 
                 add = &adw::PreferencesGroup {
                     set_title: &gettext("Books Location"),
+                    #[watch]
+                    set_description: Some(&model.location()),
+
                     adw::ActionRow {
                         set_title: &gettext("Load books from folder"),
-                        #[wrap(Some)]
-                        set_activatable_widget = &gtk::Button::builder().valign(gtk::Align::Center).build(),
-                    }
+                        set_subtitle: &gettext("Books in the folder and its subfolders"),
+                        add_suffix = &gtk::Button {
+                            set_label: &gettext("Choose…"),
+                            set_valign: gtk::Align::Center,
+                            connect_clicked[sender] => move |_| {
+                                sender.input(PreferencesInput::ChooseFolder(false));
+                            },
+                        },
+                    },
+                    adw::ActionRow {
+                        set_title: &gettext("Import a calibre library"),
+                        set_subtitle: &gettext("Tags, series, ratings and custom columns are brought in, the books stay where calibre keeps them"),
+                        add_suffix = &gtk::Button {
+                            set_label: &gettext("Choose…"),
+                            set_valign: gtk::Align::Center,
+                            connect_clicked[sender] => move |_| {
+                                sender.input(PreferencesInput::ChooseFolder(true));
+                            },
+                        },
+                    },
+                    adw::ActionRow {
+                        set_title: &gettext("Show documents as books"),
+                        set_subtitle: &gettext("Text, Markdown and HTML files in the folder are added to the library"),
+                        set_activatable_widget: Some(&documents_switch),
+                        #[name = "documents_switch"]
+                        add_suffix = &gtk::Switch {
+                            set_valign: gtk::Align::Center,
+                        },
+                    },
+                    adw::ActionRow {
+                        set_title: &gettext("Add opened books to the library"),
+                        set_subtitle: &gettext("Books opened from the file manager are copied into the books folder"),
+                        set_activatable_widget: Some(&import_switch),
+                        #[name = "import_switch"]
+                        add_suffix = &gtk::Switch {
+                            set_valign: gtk::Align::Center,
+                        },
+                    },
+                },
+
+                add = &adw::PreferencesGroup {
+                    set_title: &gettext("Startup"),
+
+                    adw::ActionRow {
+                        set_title: &gettext("Always start in the library"),
+                        set_subtitle: &gettext("Instead of reopening the book that was open where it was left"),
+                        set_activatable_widget: Some(&library_switch),
+                        #[name = "library_switch"]
+                        add_suffix = &gtk::Switch {
+                            set_valign: gtk::Align::Center,
+                        },
+                    },
                 }
             }
         }
@@ -53,12 +126,90 @@ impl SimpleComponent for BookxPreferences {
     fn init(
         _: Self::Init,
         root: &Self::Root,
-        _sender: ComponentSender<Self>,
+        sender: ComponentSender<Self>,
     ) -> ComponentParts<Self> {
-        let model = Self {};
+        let settings = gio::Settings::new(APP_ID);
+        let model = Self {
+            window: root.clone(),
+            books_dir: settings.string("books-dir").to_string(),
+            calibre: settings.boolean("calibre-library"),
+            settings,
+            file_chooser: None,
+        };
 
         let widgets = view_output!();
+        model
+            .settings
+            .bind("library-documents", &widgets.documents_switch, "active")
+            .build();
+        model
+            .settings
+            .bind("import-opened-books", &widgets.import_switch, "active")
+            .build();
+        model
+            .settings
+            .bind("start-in-library", &widgets.library_switch, "active")
+            .build();
 
         ComponentParts { model, widgets }
     }
+
+    fn update(&mut self, message: Self::Input, sender: ComponentSender<Self>) {
+        match message {
+            PreferencesInput::ChooseFolder(calibre) => {
+                let title = if calibre {
+                    gettext("Choose calibre Library")
+                } else {
+                    gettext("Choose Books Folder")
+                };
+                let chooser = gtk::FileChooserNative::new(
+                    Some(&title),
+                    Some(&self.window),
+                    gtk::FileChooserAction::SelectFolder,
+                    Some(&gettext("_Select")),
+                    Some(&gettext("_Cancel")),
+                );
+                chooser.set_modal(true);
+                chooser.connect_response(glib::clone!(@strong sender => move |chooser, response| {
+                    if response == gtk::ResponseType::Accept {
+                        if let Some(path) = chooser.file().and_then(|file| file.path()) {
+                            sender.input(PreferencesInput::FolderChosen(path, calibre));
+                        }
+                    }
+                }));
+                chooser.show();
+                self.file_chooser = Some(chooser);
+            }
+            PreferencesInput::FolderChosen(path, calibre) => {
+                self.file_chooser = None;
+                // a folder without calibre's database is loaded as it is
+                let calibre = calibre && calibre::is_library(&path);
+                self.books_dir = path.display().to_string();
+                self.calibre = calibre;
+                // both keys change together, so the library loads once
+                self.settings.delay();
+                let saved = self
+                    .settings
+                    .set_string("books-dir", &self.books_dir)
+                    .and_then(|_| self.settings.set_boolean("calibre-library", calibre));
+                if let Err(e) = saved {
+                    error!("Unable to save the books folder: {}", e);
+                }
+                self.settings.apply();
+            }
+        }
+    }
+}
+
+impl BookxPreferences {
+    // where books are loaded from, as told to the user
+    fn location(&self) -> String {
+        if self.books_dir.is_empty() {
+            gettext("Books are loaded from the documents folder")
+        } else if self.calibre {
+            gettext("calibre library in %s").replace("%s", &self.books_dir)
+        } else {
+            self.books_dir.clone()
+        }
+    }
 }
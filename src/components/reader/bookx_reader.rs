@@ -0,0 +1,1177 @@
+// Bookx - bookx_reader.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::gettext;
+use relm4::{
+    actions::{ActionGroupName, RelmAction, RelmActionGroup},
+    adw,
+    gtk::{self, gdk, gdk_pixbuf::PixbufLoader, gio, glib, pango, prelude::*},
+    Component, ComponentController, ComponentParts, ComponentSender, Controller, RelmWidgetExt,
+};
+use tracing::{error, warn};
+
+use std::path::{Path, PathBuf};
+
+use crate::components::reader::history::{NavigationHistory, Position};
+use crate::components::reader::lightbox::{BookxLightbox, LightboxImage, LightboxInit};
+use crate::components::reader::search::{BookxSearch, SearchInput, SearchOutput};
+use crate::components::reader::session::{ReadProgress, ReaderLocation};
+use crate::formats::{self, BookSource, FormatError};
+use crate::xhtml::{resolve_href, ChapterText, Style, OBJECT_REPLACEMENT};
+
+// images wider than this are scaled down to fit the text column
+const MAX_IMAGE_WIDTH: i32 = 600;
+
+relm4::new_action_group!(pub(crate) ReaderActionGroup, "reader");
+relm4::new_stateless_action!(pub SearchAction, ReaderActionGroup, "search");
+relm4::new_stateless_action!(pub SplitAction, ReaderActionGroup, "split");
+relm4::new_stateless_action!(ShowInPaneAction, ReaderActionGroup, "show-in-pane");
+relm4::new_stateless_action!(pub BackAction, ReaderActionGroup, "back");
+relm4::new_stateless_action!(pub ForwardAction, ReaderActionGroup, "forward");
+
+// displays one chapter of a book at a time
+pub struct BookxReader {
+    path: String,
+    source: Box<dyn BookSource>,
+    title: String,
+    chapter_index: usize,
+    // path of the current chapter inside the archive
+    chapter_path: PathBuf,
+    chapter: ChapterText,
+    buffer: gtk::TextBuffer,
+    search: Controller<BookxSearch>,
+    search_visible: bool,
+    // offset to scroll to once the view is updated, and how far down the
+    // view it ends up, from 0 at the top to 1 at the bottom
+    scroll_to: Option<(usize, f64)>,
+    // furthest offset of the chapter shown so far
+    read_to: usize,
+    // characters in each chapter, empty until they are counted
+    chapter_lengths: Vec<usize>,
+    // second view of the same book next to the text
+    reference: ReferencePane,
+    split_visible: bool,
+    // offset the context menu was opened on
+    pointed: Option<usize>,
+    // note of the reference clicked on, with the chapter and offset of the
+    // note and of the reference
+    note_popover: gtk::Popover,
+    note_label: gtk::Label,
+    note_target: Option<(Position, Position)>,
+    history: NavigationHistory,
+    lightbox: Option<Controller<BookxLightbox>>,
+}
+
+// Another position in the book, for keeping a figure, table or appendix in
+// sight while reading on. It isn't counted as read.
+struct ReferencePane {
+    // none until something is shown in it
+    chapter_index: Option<usize>,
+    chapter_path: PathBuf,
+    chapter: ChapterText,
+    buffer: gtk::TextBuffer,
+    scroll_to: Option<usize>,
+}
+
+pub struct BookxReaderInit {
+    pub path: String,
+    pub source: Box<dyn BookSource>,
+}
+
+#[derive(Debug)]
+pub enum ReaderInput {
+    PreviousChapter,
+    NextChapter,
+    ToggleSearch,
+    HideSearch,
+    JumpTo {
+        chapter: usize,
+        start: usize,
+        end: usize,
+    },
+    // brings the offset to the top of the view, where the book was left
+    GoTo {
+        chapter: usize,
+        offset: usize,
+    },
+    Scrolled,
+    ToggleSplit,
+    // shows what the link, image or text at the offset points to in the
+    // second pane, none when the context menu is opened from the keyboard
+    Pointed(Option<usize>),
+    ShowInPane,
+    PanePreviousChapter,
+    PaneNextChapter,
+    // a click at the offset, which may be on a link or an image
+    Clicked(usize),
+    GoToNote,
+    Back,
+    Forward,
+    Close,
+}
+
+#[derive(Debug)]
+pub enum ReaderOutput {
+    Read(ReadProgress),
+    Close,
+}
+
+#[derive(Debug)]
+pub enum ReaderCommand {
+    Lengths(Vec<usize>),
+}
+
+#[relm4_macros::component(pub)]
+impl Component for BookxReader {
+    type Init = BookxReaderInit;
+    type Input = ReaderInput;
+    type Output = ReaderOutput;
+    type CommandOutput = ReaderCommand;
+
+    view! {
+        #[name = "reader"]
+        gtk::Box {
+            set_orientation: gtk::Orientation::Vertical,
+
+            gtk::CenterBox {
+                set_margin_all: 6,
+
+                #[wrap(Some)]
+                set_start_widget = &gtk::Button {
+                    set_icon_name: "go-previous-symbolic",
+                    set_tooltip_text: Some(&gettext("Back to Library")),
+                    connect_clicked[sender] => move |_| {
+                        sender.input(ReaderInput::Close);
+                    },
+                },
+                #[wrap(Some)]
+                set_center_widget = &gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    gtk::Label {
+                        #[watch]
+                        set_label: &model.title,
+                        set_ellipsize: pango::EllipsizeMode::End,
+                        add_css_class: "heading",
+                    },
+                    gtk::Label {
+                        #[watch]
+                        set_label: model.chapter.title.as_deref().unwrap_or_default(),
+                        set_ellipsize: pango::EllipsizeMode::End,
+                        add_css_class: "dim-label",
+                    },
+                },
+                #[wrap(Some)]
+                set_end_widget = &gtk::Box {
+                    set_spacing: 6,
+                    gtk::Button {
+                        set_icon_name: "go-previous-symbolic",
+                        set_tooltip_text: Some(&gettext("Back")),
+                        #[watch]
+                        set_sensitive: model.history.can_go_back(),
+                        set_action_name: Some("reader.back"),
+                    },
+                    gtk::Button {
+                        set_icon_name: "go-next-symbolic",
+                        set_tooltip_text: Some(&gettext("Forward")),
+                        #[watch]
+                        set_sensitive: model.history.can_go_forward(),
+                        set_action_name: Some("reader.forward"),
+                    },
+                    gtk::Button {
+                        set_icon_name: "go-up-symbolic",
+                        set_tooltip_text: Some(&gettext("Previous Chapter")),
+                        #[watch]
+                        set_sensitive: model.chapter_index > 0,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(ReaderInput::PreviousChapter);
+                        },
+                    },
+                    gtk::Button {
+                        set_icon_name: "go-down-symbolic",
+                        set_tooltip_text: Some(&gettext("Next Chapter")),
+                        #[watch]
+                        set_sensitive: model.chapter_index + 1 < model.source.chapter_count(),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(ReaderInput::NextChapter);
+                        },
+                    },
+                    gtk::ToggleButton {
+                        set_icon_name: "view-dual-symbolic",
+                        set_tooltip_text: Some(&gettext("Split View")),
+                        #[watch]
+                        set_active: model.split_visible,
+                        set_action_name: Some("reader.split"),
+                    },
+                    gtk::ToggleButton {
+                        set_icon_name: "system-search-symbolic",
+                        set_tooltip_text: Some(&gettext("Search in Book")),
+                        #[watch]
+                        set_active: model.search_visible,
+                        set_action_name: Some("reader.search"),
+                    },
+                },
+            },
+
+            #[name = "toasts"]
+            adw::ToastOverlay {
+                set_vexpand: true,
+
+                adw::Flap {
+                    set_flap_position: gtk::PackType::End,
+                    set_vexpand: true,
+                    #[watch]
+                    set_reveal_flap: model.search_visible,
+                    set_flap: Some(model.search.widget()),
+                    connect_reveal_flap_notify[sender] => move |flap| {
+                        if !flap.reveals_flap() {
+                            sender.input(ReaderInput::HideSearch);
+                        }
+                    },
+
+                    #[wrap(Some)]
+                    set_content = &gtk::Paned {
+                        set_shrink_start_child: false,
+                        set_shrink_end_child: false,
+
+                        #[wrap(Some)]
+                        set_start_child = &gtk::ScrolledWindow {
+                            set_hscrollbar_policy: gtk::PolicyType::Never,
+                            set_hexpand: true,
+
+                            #[name = "text_view"]
+                            gtk::TextView {
+                                set_buffer: Some(&model.buffer),
+                                set_editable: false,
+                                set_cursor_visible: false,
+                                set_wrap_mode: gtk::WrapMode::WordChar,
+                                set_pixels_below_lines: 8,
+                                set_left_margin: 48,
+                                set_right_margin: 48,
+                                set_top_margin: 24,
+                                set_bottom_margin: 24,
+                                set_extra_menu: Some(&pane_menu),
+                            }
+                        },
+                        #[wrap(Some)]
+                        set_end_child = &gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            set_hexpand: true,
+                            #[watch]
+                            set_visible: model.split_visible,
+
+                            gtk::CenterBox {
+                                set_margin_all: 6,
+
+                                #[wrap(Some)]
+                                set_start_widget = &gtk::Button {
+                                    set_icon_name: "window-close-symbolic",
+                                    set_tooltip_text: Some(&gettext("Close Second Pane")),
+                                    add_css_class: "flat",
+                                    set_action_name: Some("reader.split"),
+                                },
+                                #[wrap(Some)]
+                                set_center_widget = &gtk::Label {
+                                    #[watch]
+                                    set_label: model
+                                        .reference
+                                        .chapter
+                                        .title
+                                        .as_deref()
+                                        .unwrap_or_default(),
+                                    set_ellipsize: pango::EllipsizeMode::End,
+                                    add_css_class: "dim-label",
+                                },
+                                #[wrap(Some)]
+                                set_end_widget = &gtk::Box {
+                                    set_spacing: 6,
+                                    gtk::Button {
+                                        set_icon_name: "go-up-symbolic",
+                                        set_tooltip_text: Some(&gettext("Previous Chapter")),
+                                        add_css_class: "flat",
+                                        #[watch]
+                                        set_sensitive: model
+                                            .reference
+                                            .chapter_index
+                                            .unwrap_or_default() > 0,
+                                        connect_clicked[sender] => move |_| {
+                                            sender.input(ReaderInput::PanePreviousChapter);
+                                        },
+                                    },
+                                    gtk::Button {
+                                        set_icon_name: "go-down-symbolic",
+                                        set_tooltip_text: Some(&gettext("Next Chapter")),
+                                        add_css_class: "flat",
+                                        #[watch]
+                                        set_sensitive: model
+                                            .reference
+                                            .chapter_index
+                                            .map(|index| index + 1 < model.source.chapter_count())
+                                            .unwrap_or(false),
+                                        connect_clicked[sender] => move |_| {
+                                            sender.input(ReaderInput::PaneNextChapter);
+                                        },
+                                    },
+                                },
+                            },
+                            gtk::ScrolledWindow {
+                                set_hscrollbar_policy: gtk::PolicyType::Never,
+                                set_vexpand: true,
+
+                                #[name = "pane_view"]
+                                gtk::TextView {
+                                    set_buffer: Some(&model.reference.buffer),
+                                    set_editable: false,
+                                    set_cursor_visible: false,
+                                    set_wrap_mode: gtk::WrapMode::WordChar,
+                                    set_pixels_below_lines: 8,
+                                    set_left_margin: 24,
+                                    set_right_margin: 24,
+                                    set_top_margin: 24,
+                                    set_bottom_margin: 24,
+                                }
+                            }
+                        },
+                    }
+                }
+            }
+        }
+    }
+
+    fn init(
+        init: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let BookxReaderInit { path, source } = init;
+        let title = source.metadata().title.unwrap_or_default();
+        let search =
+            BookxSearch::builder()
+                .launch(path.clone())
+                .forward(sender.input_sender(), |message| match message {
+                    SearchOutput::JumpTo {
+                        chapter,
+                        start,
+                        end,
+                    } => ReaderInput::JumpTo {
+                        chapter,
+                        start,
+                        end,
+                    },
+                    SearchOutput::Close => ReaderInput::HideSearch,
+                });
+
+        let buffer = gtk::TextBuffer::new(None);
+        create_tags(&buffer);
+        let reference_buffer = gtk::TextBuffer::new(None);
+        create_tags(&reference_buffer);
+        let note_label = gtk::Label::builder()
+            .wrap(true)
+            .max_width_chars(50)
+            .xalign(0.0)
+            .build();
+        let note_scroll = gtk::ScrolledWindow::builder()
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            .propagate_natural_height(true)
+            .max_content_height(300)
+            .child(&note_label)
+            .build();
+        let go_to_note = gtk::Button::with_label(&gettext("Go to Note"));
+        go_to_note.set_halign(gtk::Align::End);
+        go_to_note.add_css_class("flat");
+        let note_box = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        note_box.append(&note_scroll);
+        note_box.append(&go_to_note);
+        let note_popover = gtk::Popover::new();
+        note_popover.set_child(Some(&note_box));
+        go_to_note.connect_clicked(
+            glib::clone!(@strong sender, @weak note_popover => move |_| {
+                note_popover.popdown();
+                sender.input(ReaderInput::GoToNote);
+            }),
+        );
+        let pane_menu = gio::Menu::new();
+        pane_menu.append(
+            Some(&gettext("Show in Second Pane")),
+            Some("reader.show-in-pane"),
+        );
+
+        let mut model = BookxReader {
+            path: path.clone(),
+            source,
+            title,
+            chapter_index: 0,
+            chapter_path: PathBuf::new(),
+            chapter: ChapterText::default(),
+            buffer,
+            search,
+            search_visible: false,
+            scroll_to: None,
+            read_to: 0,
+            chapter_lengths: Vec::new(),
+            reference: ReferencePane {
+                chapter_index: None,
+                chapter_path: PathBuf::new(),
+                chapter: ChapterText::default(),
+                buffer: reference_buffer,
+                scroll_to: None,
+            },
+            split_visible: false,
+            pointed: None,
+            note_popover,
+            note_label,
+            note_target: None,
+            history: NavigationHistory::load(&path),
+            lightbox: None,
+        };
+        model.load_chapter(0);
+
+        let widgets = view_output!();
+
+        if let Some(adjustment) = widgets.text_view.vadjustment() {
+            adjustment.connect_value_changed(glib::clone!(@strong sender => move |_| {
+                sender.input(ReaderInput::Scrolled);
+            }));
+        }
+        // remembers what the context menu is about, before it shows up
+        let click = gtk::GestureClick::new();
+        click.set_button(gdk::BUTTON_SECONDARY);
+        click.set_propagation_phase(gtk::PropagationPhase::Capture);
+        click.connect_pressed(
+            glib::clone!(@strong sender, @weak widgets.text_view as text_view => move |_, _, x, y| {
+                if let Some(iter) = iter_at(&text_view, x, y) {
+                    sender.input(ReaderInput::Pointed(Some(iter.offset() as usize)));
+                }
+            }),
+        );
+        widgets.text_view.add_controller(click);
+        // a menu opened with a key is about the cursor instead
+        let menu_keys = gtk::EventControllerKey::new();
+        menu_keys.set_propagation_phase(gtk::PropagationPhase::Capture);
+        menu_keys.connect_key_pressed(glib::clone!(@strong sender => move |_, _, _, _| {
+            sender.input(ReaderInput::Pointed(None));
+            gtk::Inhibit(false)
+        }));
+        widgets.text_view.add_controller(menu_keys);
+
+        let link_click = gtk::GestureClick::new();
+        link_click.connect_released(
+            glib::clone!(@strong sender, @weak widgets.text_view as text_view => move |_, presses, x, y| {
+                // a click that selected text isn't meant for a link
+                if presses != 1 || text_view.buffer().has_selection() {
+                    return;
+                }
+                if let Some(iter) = iter_at(&text_view, x, y) {
+                    sender.input(ReaderInput::Clicked(iter.offset() as usize));
+                }
+            }),
+        );
+        widgets.text_view.add_controller(link_click);
+
+        // links and images show the hand cursor
+        let motion = gtk::EventControllerMotion::new();
+        motion.connect_motion(
+            glib::clone!(@weak widgets.text_view as text_view => move |_, x, y| {
+                let link = text_view.buffer().tag_table().lookup("link");
+                let clickable = iter_at(&text_view, x, y)
+                    .map(|iter| {
+                        iter.paintable().is_some()
+                            || link.map(|link| iter.has_tag(&link)).unwrap_or(false)
+                    })
+                    .unwrap_or(false);
+                text_view.set_cursor_from_name(Some(if clickable { "pointer" } else { "text" }));
+            }),
+        );
+        widgets.text_view.add_controller(motion);
+        model.note_popover.set_parent(&widgets.text_view);
+
+        // the back and forward buttons of the mouse
+        let history_click = gtk::GestureClick::new();
+        history_click.set_button(0);
+        history_click.set_propagation_phase(gtk::PropagationPhase::Capture);
+        history_click.connect_pressed(glib::clone!(@strong sender => move |gesture, _, _, _| {
+            match gesture.current_button() {
+                8 => sender.input(ReaderInput::Back),
+                9 => sender.input(ReaderInput::Forward),
+                _ => {}
+            }
+        }));
+        root.add_controller(history_click);
+
+        // positions in the whole book need the length of every chapter
+        sender.spawn_oneshot_command(move || ReaderCommand::Lengths(chapter_lengths(&path)));
+
+        let mut actions = RelmActionGroup::<ReaderActionGroup>::new();
+        let search_action = {
+            let sender = sender.input_sender().clone();
+            RelmAction::<SearchAction>::new_stateless(move |_| {
+                sender.send(ReaderInput::ToggleSearch).unwrap();
+            })
+        };
+        let split_action = {
+            let sender = sender.input_sender().clone();
+            RelmAction::<SplitAction>::new_stateless(move |_| {
+                sender.send(ReaderInput::ToggleSplit).unwrap();
+            })
+        };
+        let show_in_pane_action = {
+            let sender = sender.input_sender().clone();
+            RelmAction::<ShowInPaneAction>::new_stateless(move |_| {
+                sender.send(ReaderInput::ShowInPane).unwrap();
+            })
+        };
+        let back_action = {
+            let sender = sender.input_sender().clone();
+            RelmAction::<BackAction>::new_stateless(move |_| {
+                sender.send(ReaderInput::Back).unwrap();
+            })
+        };
+        let forward_action = {
+            let sender = sender.input_sender().clone();
+            RelmAction::<ForwardAction>::new_stateless(move |_| {
+                sender.send(ReaderInput::Forward).unwrap();
+            })
+        };
+        actions.add_action(search_action);
+        actions.add_action(split_action);
+        actions.add_action(show_in_pane_action);
+        actions.add_action(back_action);
+        actions.add_action(forward_action);
+        root.insert_action_group(ReaderActionGroup::NAME, Some(&actions.into_action_group()));
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        message: Self::Input,
+        sender: ComponentSender<Self>,
+        root: &Self::Root,
+    ) {
+        // where a jump to another place was made from
+        let mut jumped_from = None;
+        match message {
+            ReaderInput::PreviousChapter => {
+                if self.chapter_index > 0 {
+                    self.load_chapter(self.chapter_index - 1);
+                    self.scroll_to = Some((0, 0.0));
+                }
+            }
+            ReaderInput::NextChapter => {
+                if self.chapter_index + 1 < self.source.chapter_count() {
+                    // moving on counts the rest of the chapter as read
+                    let rest = self
+                        .chapter
+                        .text
+                        .chars()
+                        .count()
+                        .saturating_sub(self.read_to);
+                    self.load_chapter(self.chapter_index + 1);
+                    self.report(rest as u64, &sender);
+                    self.scroll_to = Some((0, 0.0));
+                }
+            }
+            ReaderInput::ToggleSearch => {
+                self.search_visible = !self.search_visible;
+                if self.search_visible {
+                    self.search.emit(SearchInput::Focus);
+                }
+            }
+            ReaderInput::HideSearch => self.search_visible = false,
+            ReaderInput::JumpTo {
+                chapter,
+                start,
+                end,
+            } => {
+                jumped_from = Some(self.position(&widgets.text_view));
+                self.go_to(chapter, start, 0.3);
+                self.highlight(start, end);
+            }
+            ReaderInput::GoTo { chapter, offset } => self.go_to(chapter, offset, 0.0),
+            ReaderInput::Scrolled => {
+                // the text is read up to the bottom of the view
+                let rect = widgets.text_view.visible_rect();
+                let offset = widgets
+                    .text_view
+                    .iter_at_location(rect.x(), rect.y() + rect.height())
+                    .map(|iter| iter.offset() as usize)
+                    .unwrap_or(self.read_to);
+                if offset > self.read_to {
+                    let advanced = offset - self.read_to;
+                    self.read_to = offset;
+                    self.report(advanced as u64, &sender);
+                }
+            }
+            ReaderInput::ToggleSplit => {
+                self.split_visible = !self.split_visible;
+                // starts out where the reader is
+                if self.split_visible && self.reference.chapter_index.is_none() {
+                    let here = self.position(&widgets.text_view);
+                    self.reference.load(&mut *self.source, here.chapter);
+                    self.reference.scroll_to = Some(here.offset);
+                }
+            }
+            ReaderInput::Pointed(offset) => self.pointed = offset,
+            ReaderInput::ShowInPane => {
+                let buffer = widgets.text_view.buffer();
+                let offset = self
+                    .pointed
+                    .take()
+                    .unwrap_or_else(|| buffer.iter_at_mark(&buffer.get_insert()).offset() as usize)
+                    .min(buffer.char_count() as usize);
+                // a link shows where it leads, anything else shows itself
+                let target = self
+                    .chapter
+                    .links
+                    .iter()
+                    .find(|link| link.start <= offset && offset < link.end)
+                    .map(|link| link.href.clone())
+                    .and_then(|href| self.link_target(&href));
+                match target {
+                    Some((chapter, fragment)) => {
+                        self.reference.load(&mut *self.source, chapter);
+                        let offset = fragment
+                            .and_then(|fragment| self.reference.chapter.anchors.get(&fragment))
+                            .copied()
+                            .unwrap_or_default();
+                        self.reference.scroll_to = Some(offset);
+                    }
+                    None => {
+                        self.reference.load(&mut *self.source, self.chapter_index);
+                        self.reference.scroll_to = Some(offset);
+                    }
+                }
+                self.split_visible = true;
+            }
+            ReaderInput::PanePreviousChapter => {
+                if let Some(index) = self.reference.chapter_index.filter(|&index| index > 0) {
+                    self.reference.load(&mut *self.source, index - 1);
+                    self.reference.scroll_to = Some(0);
+                }
+            }
+            ReaderInput::PaneNextChapter => {
+                let count = self.source.chapter_count();
+                if let Some(index) = self
+                    .reference
+                    .chapter_index
+                    .filter(|&index| index + 1 < count)
+                {
+                    self.reference.load(&mut *self.source, index + 1);
+                    self.reference.scroll_to = Some(0);
+                }
+            }
+            ReaderInput::Clicked(offset) => {
+                if self
+                    .chapter
+                    .images
+                    .iter()
+                    .any(|image| image.offset == offset)
+                {
+                    return self.show_image(offset, root);
+                }
+                let link = match self
+                    .chapter
+                    .links
+                    .iter()
+                    .find(|link| link.start <= offset && offset < link.end)
+                {
+                    Some(link) => link.clone(),
+                    None => return,
+                };
+                let (chapter, fragment) = match self.link_target(&link.href) {
+                    Some(target) => target,
+                    None => {
+                        // links out of the book open in the browser
+                        let window = widgets
+                            .text_view
+                            .root()
+                            .and_then(|root| root.downcast::<gtk::Window>().ok());
+                        gtk::show_uri(window.as_ref(), &link.href, gdk::CURRENT_TIME);
+                        return;
+                    }
+                };
+                let note = if self.chapter.is_note_ref(&link) {
+                    // the note may be in another chapter, read without leaving
+                    let other = (chapter != self.chapter_index)
+                        .then(|| read_chapter(&mut *self.source, chapter).0);
+                    let text = other.as_ref().unwrap_or(&self.chapter);
+                    fragment.as_deref().and_then(|fragment| {
+                        let offset = text.anchors.get(fragment).copied()?;
+                        Some((text.note(fragment)?, offset))
+                    })
+                } else {
+                    None
+                };
+                match note {
+                    Some((note, note_offset)) => {
+                        self.note_label.set_text(&note);
+                        self.note_target = Some((
+                            Position {
+                                chapter,
+                                offset: note_offset,
+                            },
+                            Position {
+                                chapter: self.chapter_index,
+                                offset: link.start,
+                            },
+                        ));
+                        let iter = self.buffer.iter_at_offset(link.start as i32);
+                        let rect = widgets.text_view.iter_location(&iter);
+                        let (x, y) = widgets.text_view.buffer_to_window_coords(
+                            gtk::TextWindowType::Widget,
+                            rect.x(),
+                            rect.y(),
+                        );
+                        self.note_popover.set_pointing_to(Some(&gdk::Rectangle::new(
+                            x,
+                            y,
+                            rect.width().max(1),
+                            rect.height(),
+                        )));
+                        self.note_popover.popup();
+                    }
+                    None => {
+                        jumped_from = Some(self.position(&widgets.text_view));
+                        self.follow(chapter, fragment);
+                    }
+                }
+            }
+            ReaderInput::GoToNote => {
+                if let Some((note, reference)) = self.note_target.take() {
+                    jumped_from = Some(reference);
+                    self.go_to(note.chapter, note.offset, 0.1);
+                }
+            }
+            ReaderInput::Back => {
+                let here = self.position(&widgets.text_view);
+                if let Some(to) = self.history.back(here) {
+                    self.go_to(to.chapter, to.offset, 0.0);
+                }
+            }
+            ReaderInput::Forward => {
+                let here = self.position(&widgets.text_view);
+                if let Some(to) = self.history.forward(here) {
+                    self.go_to(to.chapter, to.offset, 0.0);
+                }
+            }
+            ReaderInput::Close => sender.output(ReaderOutput::Close).unwrap(),
+        }
+
+        if let Some(from) = jumped_from {
+            self.history.push(from);
+            let to = Position {
+                chapter: self.chapter_index,
+                offset: self.scroll_to.map(|(offset, _)| offset).unwrap_or_default(),
+            };
+            // after a large jump it's easy to lose the way back
+            if from.is_far_from(to) {
+                let toast = adw::Toast::new(&gettext("Moved away from where you were reading"));
+                toast.set_button_label(Some(&gettext("_Return")));
+                toast.set_action_name(Some("reader.back"));
+                widgets.toasts.add_toast(&toast);
+            }
+        }
+
+        if let Some((start, yalign)) = self.scroll_to.take() {
+            scroll_to(&widgets.text_view, &self.buffer, start, yalign);
+        }
+        if let Some(start) = self.reference.scroll_to.take() {
+            scroll_to(&widgets.pane_view, &self.reference.buffer, start, 0.0);
+        }
+
+        self.update_view(widgets, sender);
+    }
+
+    fn shutdown(&mut self, widgets: &mut Self::Widgets, _output: relm4::Sender<Self::Output>) {
+        // reading picks up again from the top of the view
+        let here = self.position(&widgets.text_view);
+        ReaderLocation {
+            path: self.path.clone(),
+            chapter: here.chapter,
+            offset: here.offset,
+            // the search is the sidebar of text books
+            sidebar: self.search_visible,
+        }
+        .save();
+        self.history.save(&self.path);
+    }
+
+    fn update_cmd(
+        &mut self,
+        message: Self::CommandOutput,
+        _sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match message {
+            ReaderCommand::Lengths(lengths) => self.chapter_lengths = lengths,
+        }
+    }
+}
+
+impl BookxReader {
+    pub fn open(book_path: String) -> Result<BookxReaderInit, FormatError> {
+        match formats::open(Path::new(&book_path)) {
+            Ok(source) => Ok(BookxReaderInit {
+                path: book_path,
+                source,
+            }),
+            Err(e) => {
+                error!("Unable to open book {:?} for reading: {:?}", book_path, e);
+                Err(e)
+            }
+        }
+    }
+
+    fn load_chapter(&mut self, index: usize) {
+        if index >= self.source.chapter_count() {
+            return;
+        }
+        self.chapter_index = index;
+        self.read_to = 0;
+        // offsets into the chapter before mean nothing in this one
+        self.pointed = None;
+        let (chapter, chapter_path) = read_chapter(&mut *self.source, index);
+        self.chapter = chapter;
+        self.chapter_path = chapter_path;
+        render_chapter(
+            &self.buffer,
+            &self.chapter,
+            &self.chapter_path,
+            &mut *self.source,
+        );
+    }
+
+    // opens the lightbox on the image at the offset, with the other images
+    // of the chapter to step through
+    fn show_image(&mut self, offset: usize, root: &gtk::Box) {
+        let mut images = Vec::new();
+        let mut index = 0;
+        for image in &self.chapter.images {
+            let resolved = resolve_href(&self.chapter_path, &image.src);
+            let data = match resolved
+                .as_ref()
+                .and_then(|(path, _)| self.source.resource(path))
+            {
+                Some(data) => data,
+                None => continue,
+            };
+            if image.offset == offset {
+                index = images.len();
+            }
+            let name = resolved
+                .and_then(|(path, _)| path.file_name().map(|name| name.to_os_string()))
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| gettext("image"));
+            images.push(LightboxImage { name, data });
+        }
+        if images.is_empty() {
+            return;
+        }
+        let lightbox = BookxLightbox::builder()
+            .transient_for(root)
+            .launch(LightboxInit { images, index })
+            .detach();
+        self.lightbox = Some(lightbox);
+    }
+
+    // chapter and offset at the top of the view
+    fn position(&self, view: &gtk::TextView) -> Position {
+        let rect = view.visible_rect();
+        let offset = view
+            .iter_at_location(rect.x(), rect.y())
+            .map(|iter| iter.offset() as usize)
+            .unwrap_or_default();
+        Position {
+            chapter: self.chapter_index,
+            offset,
+        }
+    }
+
+    fn go_to(&mut self, chapter: usize, offset: usize, yalign: f64) {
+        if chapter != self.chapter_index {
+            self.load_chapter(chapter);
+        }
+        // text jumped over isn't read
+        self.read_to = offset;
+        self.scroll_to = Some((offset, yalign));
+    }
+
+    // goes to the anchor in the chapter, or to its start
+    fn follow(&mut self, chapter: usize, fragment: Option<String>) {
+        if chapter != self.chapter_index {
+            self.load_chapter(chapter);
+        }
+        let offset = fragment
+            .and_then(|fragment| self.chapter.anchors.get(&fragment))
+            .copied()
+            .unwrap_or_default();
+        self.go_to(chapter, offset, 0.0);
+    }
+
+    // chapter and anchor a link in the current chapter leads to, none for
+    // links out of the book
+    fn link_target(&mut self, href: &str) -> Option<(usize, Option<String>)> {
+        let (path, fragment) = resolve_href(&self.chapter_path, href)?;
+        let chapter = if path == self.chapter_path {
+            self.chapter_index
+        } else {
+            self.source.chapter_index(&path)?
+        };
+        Some((chapter, fragment))
+    }
+
+    // tells how far into the book the reader is
+    fn report(&self, advanced: u64, sender: &ComponentSender<Self>) {
+        let (position, length) = if self.chapter_lengths.len() == self.source.chapter_count() {
+            let before: usize = self.chapter_lengths[..self.chapter_index].iter().sum();
+            (
+                (before + self.read_to) as u64,
+                self.chapter_lengths.iter().sum::<usize>() as u64,
+            )
+        } else {
+            (0, 0)
+        };
+        sender
+            .output(ReaderOutput::Read(ReadProgress {
+                advanced,
+                position,
+                length,
+            }))
+            .unwrap();
+    }
+
+    fn highlight(&self, start: usize, end: usize) {
+        let buffer = &self.buffer;
+        buffer.remove_tag_by_name("search-match", &buffer.start_iter(), &buffer.end_iter());
+        buffer.apply_tag_by_name(
+            "search-match",
+            &buffer.iter_at_offset(start as i32),
+            &buffer.iter_at_offset(end as i32),
+        );
+    }
+}
+
+impl ReferencePane {
+    fn load(&mut self, source: &mut dyn BookSource, index: usize) {
+        if index >= source.chapter_count() || self.chapter_index == Some(index) {
+            return;
+        }
+        let (chapter, chapter_path) = read_chapter(source, index);
+        self.chapter_index = Some(index);
+        self.chapter = chapter;
+        self.chapter_path = chapter_path;
+        render_chapter(&self.buffer, &self.chapter, &self.chapter_path, source);
+    }
+}
+
+fn read_chapter(source: &mut dyn BookSource, index: usize) -> (ChapterText, PathBuf) {
+    match source.chapter(index) {
+        Some(chapter) => (ChapterText::parse(&chapter.content), chapter.path),
+        None => {
+            warn!("Chapter {} is missing from the book", index);
+            (ChapterText::default(), PathBuf::new())
+        }
+    }
+}
+
+fn render_chapter(
+    buffer: &gtk::TextBuffer,
+    chapter: &ChapterText,
+    chapter_path: &Path,
+    source: &mut dyn BookSource,
+) {
+    buffer.set_text(&chapter.text);
+
+    for span in &chapter.styles {
+        buffer.apply_tag_by_name(
+            style_tag_name(span.style),
+            &buffer.iter_at_offset(span.start as i32),
+            &buffer.iter_at_offset(span.end as i32),
+        );
+    }
+    for link in &chapter.links {
+        buffer.apply_tag_by_name(
+            "link",
+            &buffer.iter_at_offset(link.start as i32),
+            &buffer.iter_at_offset(link.end as i32),
+        );
+    }
+
+    // the text holds a placeholder for every image, swap each one for
+    // the picture itself so offsets don't change
+    for image in &chapter.images {
+        let texture = resolve_href(chapter_path, &image.src)
+            .and_then(|(path, _)| source.resource(&path))
+            .and_then(|data| load_image(&data, MAX_IMAGE_WIDTH));
+        let mut start = buffer.iter_at_offset(image.offset as i32);
+        let mut end = buffer.iter_at_offset(image.offset as i32 + 1);
+        if start.char() != OBJECT_REPLACEMENT {
+            continue;
+        }
+        buffer.delete(&mut start, &mut end);
+        match texture {
+            Some(texture) => buffer.insert_paintable(&mut start, &texture),
+            None => {
+                warn!("Unable to load image {:?}", image.src);
+                buffer.insert(&mut start, " ");
+            }
+        }
+    }
+}
+
+// iter under a point of the view, in the view's coordinates
+fn iter_at(view: &gtk::TextView, x: f64, y: f64) -> Option<gtk::TextIter> {
+    let (x, y) = view.window_to_buffer_coords(gtk::TextWindowType::Widget, x as i32, y as i32);
+    view.iter_at_location(x, y)
+}
+
+// The mark is kept, a view that isn't laid out yet scrolls to it only once
+// it is.
+fn scroll_to(view: &gtk::TextView, buffer: &gtk::TextBuffer, offset: usize, yalign: f64) {
+    let iter = buffer.iter_at_offset(offset as i32);
+    let mark = match buffer.mark("scroll-target") {
+        Some(mark) => {
+            buffer.move_mark(&mark, &iter);
+            mark
+        }
+        None => buffer.create_mark(Some("scroll-target"), &iter, true),
+    };
+    view.scroll_to_mark(&mark, 0.1, true, 0.0, yalign);
+}
+
+// number of characters in the text of every chapter
+fn chapter_lengths(book_path: &str) -> Vec<usize> {
+    let mut source = match formats::open(Path::new(book_path)) {
+        Ok(source) => source,
+        Err(e) => {
+            warn!("Unable to count the characters of {:?}: {:?}", book_path, e);
+            return Vec::new();
+        }
+    };
+    (0..source.chapter_count())
+        .map(|index| {
+            source
+                .chapter(index)
+                .map(|chapter| ChapterText::parse(&chapter.content).text.chars().count())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+pub(super) fn load_image(data: &[u8], max_width: i32) -> Option<gdk::Texture> {
+    let loader = PixbufLoader::new();
+    if let Err(e) = loader.write(data).and_then(|_| loader.close()) {
+        warn!("Unable to decode image: {:?}", e);
+        return None;
+    }
+    let mut pixbuf = loader.pixbuf()?;
+    if pixbuf.width() > max_width {
+        let height = pixbuf.height() * max_width / pixbuf.width();
+        pixbuf = pixbuf.scale_simple(
+            max_width,
+            height.max(1),
+            gtk::gdk_pixbuf::InterpType::Bilinear,
+        )?;
+    }
+    Some(gdk::Texture::for_pixbuf(&pixbuf))
+}
+
+fn style_tag_name(style: Style) -> &'static str {
+    match style {
+        Style::Heading(1) => "h1",
+        Style::Heading(2) => "h2",
+        Style::Heading(3) => "h3",
+        Style::Heading(_) => "h4",
+        Style::Emphasis => "emphasis",
+        Style::Strong => "strong",
+        Style::Code => "code",
+        Style::Preformatted => "preformatted",
+        Style::Quote => "quote",
+        Style::Superscript => "superscript",
+        Style::Subscript => "subscript",
+    }
+}
+
+fn create_tags(buffer: &gtk::TextBuffer) {
+    let table = buffer.tag_table();
+    let tags = [
+        gtk::TextTag::builder()
+            .name("h1")
+            .weight(800)
+            .scale(2.0)
+            .pixels_above_lines(16)
+            .build(),
+        gtk::TextTag::builder()
+            .name("h2")
+            .weight(800)
+            .scale(1.6)
+            .pixels_above_lines(12)
+            .build(),
+        gtk::TextTag::builder()
+            .name("h3")
+            .weight(700)
+            .scale(1.3)
+            .pixels_above_lines(8)
+            .build(),
+        gtk::TextTag::builder()
+            .name("h4")
+            .weight(700)
+            .scale(1.1)
+            .build(),
+        gtk::TextTag::builder()
+            .name("emphasis")
+            .style(pango::Style::Italic)
+            .build(),
+        gtk::TextTag::builder().name("strong").weight(700).build(),
+        gtk::TextTag::builder()
+            .name("code")
+            .family("monospace")
+            .build(),
+        gtk::TextTag::builder()
+            .name("preformatted")
+            .family("monospace")
+            .wrap_mode(gtk::WrapMode::None)
+            .build(),
+        gtk::TextTag::builder()
+            .name("quote")
+            .left_margin(72)
+            .style(pango::Style::Italic)
+            .build(),
+        gtk::TextTag::builder()
+            .name("superscript")
+            .rise(6 * pango::SCALE)
+            .scale(0.8)
+            .build(),
+        gtk::TextTag::builder()
+            .name("subscript")
+            .rise(-3 * pango::SCALE)
+            .scale(0.8)
+            .build(),
+        gtk::TextTag::builder()
+            .name("link")
+            .underline(pango::Underline::Single)
+            .foreground("#3584e4")
+            .build(),
+        gtk::TextTag::builder()
+            .name("search-match")
+            .background("#f6d32d")
+            .foreground("#000000")
+            .build(),
+    ];
+    for tag in tags {
+        table.add(&tag);
+    }
+}
@@ -0,0 +1,470 @@
+// Bookx - comic_reader.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::gettext;
+use relm4::{
+    gtk::{self, gdk, glib, pango, prelude::*},
+    Component, ComponentParts, ComponentSender, RelmWidgetExt,
+};
+use tracing::{error, warn};
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::components::reader::session::{ReadProgress, ReaderLocation};
+use crate::formats::{ComicSource, FormatError};
+
+// pages decoded ahead of and behind the visible ones
+const PRELOAD_AHEAD: usize = 3;
+const PRELOAD_BEHIND: usize = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFit {
+    Width,
+    Page,
+}
+
+// shows the pages of a comic book archive, one or two at a time
+pub struct BookxComicReader {
+    source: Arc<ComicSource>,
+    title: String,
+    // first visible page
+    page: usize,
+    fit: PageFit,
+    spread: bool,
+    right_to_left: bool,
+    textures: HashMap<usize, gdk::Texture>,
+    // pages being read from the archive
+    loading: HashSet<usize>,
+}
+
+#[derive(Debug)]
+pub enum ComicReaderInput {
+    // the left and right buttons and arrow keys, which page they turn
+    // to depends on the reading direction
+    Left,
+    Right,
+    NextPage,
+    PreviousPage,
+    // zero based, from outside the reader
+    GoToPage(usize),
+    SetFit(PageFit),
+    ToggleSpread,
+    ToggleRightToLeft,
+    Resized,
+    Close,
+}
+
+#[derive(Debug)]
+pub enum ComicReaderOutput {
+    Read(ReadProgress),
+    Close,
+}
+
+#[derive(Debug)]
+pub enum ComicReaderCommand {
+    PageLoaded(usize, Option<Vec<u8>>),
+}
+
+#[relm4_macros::component(pub)]
+impl Component for BookxComicReader {
+    type Init = ComicSource;
+    type Input = ComicReaderInput;
+    type Output = ComicReaderOutput;
+    type CommandOutput = ComicReaderCommand;
+
+    view! {
+        #[name = "reader"]
+        gtk::Box {
+            set_orientation: gtk::Orientation::Vertical,
+
+            gtk::CenterBox {
+                set_margin_all: 6,
+
+                #[wrap(Some)]
+                set_start_widget = &gtk::Button {
+                    set_icon_name: "go-previous-symbolic",
+                    set_tooltip_text: Some(&gettext("Back to Library")),
+                    connect_clicked[sender] => move |_| {
+                        sender.input(ComicReaderInput::Close);
+                    },
+                },
+                #[wrap(Some)]
+                set_center_widget = &gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    gtk::Label {
+                        set_label: &model.title,
+                        set_ellipsize: pango::EllipsizeMode::End,
+                        add_css_class: "heading",
+                    },
+                    gtk::Label {
+                        #[watch]
+                        set_label: &model.page_label(),
+                        add_css_class: "dim-label",
+                    },
+                },
+                #[wrap(Some)]
+                set_end_widget = &gtk::Box {
+                    set_spacing: 6,
+                    gtk::Box {
+                        add_css_class: "linked",
+                        gtk::Button {
+                            set_icon_name: "go-previous-symbolic",
+                            #[watch]
+                            set_tooltip_text: Some(&if model.right_to_left {
+                                gettext("Next Page")
+                            } else {
+                                gettext("Previous Page")
+                            }),
+                            connect_clicked[sender] => move |_| {
+                                sender.input(ComicReaderInput::Left);
+                            },
+                        },
+                        gtk::Button {
+                            set_icon_name: "go-next-symbolic",
+                            #[watch]
+                            set_tooltip_text: Some(&if model.right_to_left {
+                                gettext("Previous Page")
+                            } else {
+                                gettext("Next Page")
+                            }),
+                            connect_clicked[sender] => move |_| {
+                                sender.input(ComicReaderInput::Right);
+                            },
+                        },
+                    },
+                    gtk::Box {
+                        add_css_class: "linked",
+                        #[name = "fit_width"]
+                        gtk::ToggleButton {
+                            set_icon_name: "zoom-fit-best-symbolic",
+                            set_tooltip_text: Some(&gettext("Fit Width")),
+                            #[watch]
+                            set_active: model.fit == PageFit::Width,
+                            connect_clicked[sender] => move |button| {
+                                if button.is_active() {
+                                    sender.input(ComicReaderInput::SetFit(PageFit::Width));
+                                }
+                            },
+                        },
+                        gtk::ToggleButton {
+                            set_icon_name: "view-fullscreen-symbolic",
+                            set_tooltip_text: Some(&gettext("Fit Page")),
+                            set_group: Some(&fit_width),
+                            #[watch]
+                            set_active: model.fit == PageFit::Page,
+                            connect_clicked[sender] => move |button| {
+                                if button.is_active() {
+                                    sender.input(ComicReaderInput::SetFit(PageFit::Page));
+                                }
+                            },
+                        },
+                    },
+                    gtk::ToggleButton {
+                        set_icon_name: "view-dual-symbolic",
+                        set_tooltip_text: Some(&gettext("Two-Page Spread")),
+                        #[watch]
+                        set_active: model.spread,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(ComicReaderInput::ToggleSpread);
+                        },
+                    },
+                    gtk::ToggleButton {
+                        set_icon_name: "format-text-direction-rtl-symbolic",
+                        set_tooltip_text: Some(&gettext("Right to Left")),
+                        #[watch]
+                        set_active: model.right_to_left,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(ComicReaderInput::ToggleRightToLeft);
+                        },
+                    },
+                },
+            },
+
+            #[name = "scrolled"]
+            gtk::ScrolledWindow {
+                set_hscrollbar_policy: gtk::PolicyType::Never,
+                #[watch]
+                set_vscrollbar_policy: match model.fit {
+                    PageFit::Width => gtk::PolicyType::Automatic,
+                    PageFit::Page => gtk::PolicyType::Never,
+                },
+                set_vexpand: true,
+
+                #[name = "pages"]
+                gtk::Box {
+                    set_homogeneous: true,
+                    set_halign: gtk::Align::Fill,
+                    set_valign: gtk::Align::Fill,
+
+                    #[name = "left_page"]
+                    gtk::Picture {
+                        set_can_shrink: true,
+                        set_hexpand: true,
+                        set_vexpand: true,
+                    },
+                    #[name = "right_page"]
+                    gtk::Picture {
+                        set_can_shrink: true,
+                        set_hexpand: true,
+                        set_vexpand: true,
+                    },
+                }
+            }
+        }
+    }
+
+    fn init(
+        source: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let mut model = BookxComicReader {
+            title: source.metadata().title.unwrap_or_default(),
+            right_to_left: source.right_to_left(),
+            source: Arc::new(source),
+            page: 0,
+            fit: PageFit::Page,
+            spread: false,
+            textures: HashMap::new(),
+            loading: HashSet::new(),
+        };
+        let widgets = view_output!();
+
+        // fit width depends on the width of the view
+        widgets.scrolled.hadjustment().connect_page_size_notify(
+            glib::clone!(@strong sender => move |_| {
+                sender.input(ComicReaderInput::Resized);
+            }),
+        );
+
+        let keys = gtk::EventControllerKey::new();
+        keys.connect_key_pressed(glib::clone!(@strong sender => move |_, key, _, _| {
+            let message = match key {
+                gdk::Key::Left => ComicReaderInput::Left,
+                gdk::Key::Right => ComicReaderInput::Right,
+                gdk::Key::Page_Down | gdk::Key::space => ComicReaderInput::NextPage,
+                gdk::Key::Page_Up | gdk::Key::BackSpace => ComicReaderInput::PreviousPage,
+                _ => return gtk::Inhibit(false),
+            };
+            sender.input(message);
+            gtk::Inhibit(true)
+        }));
+        root.add_controller(keys);
+        root.set_focusable(true);
+        root.connect_map(|root| {
+            root.grab_focus();
+        });
+
+        model.preload(&sender);
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        message: Self::Input,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        let page = self.page;
+        let mut jumped = false;
+        match message {
+            ComicReaderInput::Left if self.right_to_left => self.next_page(),
+            ComicReaderInput::Left => self.previous_page(),
+            ComicReaderInput::Right if self.right_to_left => self.previous_page(),
+            ComicReaderInput::Right => self.next_page(),
+            ComicReaderInput::NextPage => self.next_page(),
+            ComicReaderInput::PreviousPage => self.previous_page(),
+            ComicReaderInput::GoToPage(target) => {
+                let last = self.source.page_count().saturating_sub(1);
+                self.page = self.spread_start(target.min(last));
+                jumped = true;
+            }
+            ComicReaderInput::SetFit(fit) => self.fit = fit,
+            ComicReaderInput::ToggleSpread => {
+                self.spread = !self.spread;
+                self.page = self.spread_start(self.page);
+            }
+            ComicReaderInput::ToggleRightToLeft => self.right_to_left = !self.right_to_left,
+            ComicReaderInput::Resized => {}
+            ComicReaderInput::Close => sender.output(ComicReaderOutput::Close).unwrap(),
+        }
+        if self.page != page {
+            // the pages turned past are read, going back or jumping reads
+            // nothing
+            let read_to = self.page + self.visible_pages().len();
+            let advanced = if jumped {
+                0
+            } else {
+                self.page.saturating_sub(page)
+            };
+            sender
+                .output(ComicReaderOutput::Read(ReadProgress {
+                    advanced: advanced as u64,
+                    position: read_to as u64,
+                    length: self.source.page_count() as u64,
+                }))
+                .unwrap();
+        }
+
+        self.preload(&sender);
+        self.show_pages(widgets);
+        self.update_view(widgets, sender);
+    }
+
+    fn update_cmd_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        message: Self::CommandOutput,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match message {
+            ComicReaderCommand::PageLoaded(index, data) => {
+                self.loading.remove(&index);
+                let texture = data.and_then(|data| {
+                    gdk::Texture::from_bytes(&glib::Bytes::from_owned(data))
+                        .map_err(|e| warn!("Unable to decode page {}: {}", index + 1, e))
+                        .ok()
+                });
+                if let Some(texture) = texture {
+                    self.textures.insert(index, texture);
+                }
+            }
+        }
+        self.show_pages(widgets);
+        self.update_view(widgets, sender);
+    }
+
+    fn shutdown(&mut self, _widgets: &mut Self::Widgets, _output: relm4::Sender<Self::Output>) {
+        ReaderLocation {
+            path: self.source.path().display().to_string(),
+            chapter: self.page,
+            offset: 0,
+            // comics have no sidebar
+            sidebar: false,
+        }
+        .save();
+    }
+}
+
+impl BookxComicReader {
+    pub fn open(book_path: &str) -> Result<ComicSource, FormatError> {
+        ComicSource::open(Path::new(book_path)).map_err(|e| {
+            error!("Unable to open comic {:?} for reading: {}", book_path, e);
+            e
+        })
+    }
+
+    fn page_label(&self) -> String {
+        let visible = self.visible_pages();
+        let count = self.source.page_count().to_string();
+        match visible.as_slice() {
+            [first, last] => gettext("Pages %s–%s of %s")
+                .replacen("%s", &(first + 1).to_string(), 1)
+                .replacen("%s", &(last + 1).to_string(), 1)
+                .replacen("%s", &count, 1),
+            _ => gettext("Page %s of %s")
+                .replacen("%s", &(self.page + 1).to_string(), 1)
+                .replacen("%s", &count, 1),
+        }
+    }
+
+    // In a spread the cover is shown on its own, after it pages are
+    // paired up the way they are printed.
+    fn spread_start(&self, page: usize) -> usize {
+        if !self.spread || page == 0 || page % 2 == 1 {
+            page
+        } else {
+            page - 1
+        }
+    }
+
+    // pages on screen, in reading order
+    fn visible_pages(&self) -> Vec<usize> {
+        if self.spread && self.page > 0 && self.page + 1 < self.source.page_count() {
+            vec![self.page, self.page + 1]
+        } else {
+            vec![self.page]
+        }
+    }
+
+    fn next_page(&mut self) {
+        let next = self.page + self.visible_pages().len();
+        if next < self.source.page_count() {
+            self.page = next;
+        }
+    }
+
+    fn previous_page(&mut self) {
+        if self.page > 0 {
+            self.page = self.spread_start(self.page - 1);
+        }
+    }
+
+    // Starts reading the pages around the visible ones that aren't decoded
+    // yet, and drops the ones far enough away.
+    fn preload(&mut self, sender: &ComponentSender<Self>) {
+        let first = self.page.saturating_sub(PRELOAD_BEHIND);
+        let last = (self.page + 1 + PRELOAD_AHEAD).min(self.source.page_count().saturating_sub(1));
+        let wanted = first..=last;
+
+        self.textures.retain(|index, _| wanted.contains(index));
+        for index in wanted {
+            if self.textures.contains_key(&index) || !self.loading.insert(index) {
+                continue;
+            }
+            let source = self.source.clone();
+            sender.spawn_oneshot_command(move || {
+                ComicReaderCommand::PageLoaded(index, source.page(index))
+            });
+        }
+    }
+
+    fn show_pages(&self, widgets: &<Self as Component>::Widgets) {
+        let mut visible = self.visible_pages();
+        if self.right_to_left {
+            visible.reverse();
+        }
+        let pictures = [&widgets.left_page, &widgets.right_page];
+        for (position, picture) in pictures.iter().enumerate() {
+            let texture = visible
+                .get(position)
+                .and_then(|index| self.textures.get(index));
+            picture.set_paintable(texture);
+            picture.set_visible(position < visible.len());
+        }
+
+        // fitting the width makes pages as tall as their aspect ratio
+        // needs and lets the view scroll through them
+        let height = match self.fit {
+            PageFit::Width => {
+                let width = widgets.scrolled.width() / visible.len() as i32;
+                visible
+                    .iter()
+                    .filter_map(|index| self.textures.get(index))
+                    .map(|texture| width * texture.height() / texture.width().max(1))
+                    .max()
+                    .unwrap_or(-1)
+            }
+            PageFit::Page => -1,
+        };
+        widgets.pages.set_size_request(-1, height);
+    }
+}
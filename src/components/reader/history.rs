@@ -0,0 +1,104 @@
+// Bookx - history.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::storage;
+
+// places kept in each direction
+const MAX_ENTRIES: usize = 50;
+// characters apart for a jump in the same chapter to count as a large one
+const FAR: usize = 3000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    pub chapter: usize,
+    pub offset: usize,
+}
+
+impl Position {
+    pub fn is_far_from(&self, other: Position) -> bool {
+        self.chapter != other.chapter || self.offset.abs_diff(other.offset) > FAR
+    }
+}
+
+// Places left by following links, notes and search results, to go back and
+// forth between. It's kept per book, so it's still there when the book is
+// opened again.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct NavigationHistory {
+    back: Vec<Position>,
+    forward: Vec<Position>,
+}
+
+impl NavigationHistory {
+    fn file_path() -> PathBuf {
+        storage::data_dir().join("navigation_history.json")
+    }
+
+    pub fn load(path: &str) -> Self {
+        let mut books: BTreeMap<String, Self> = storage::load_json(&Self::file_path());
+        books.remove(path).unwrap_or_default()
+    }
+
+    // other books may have saved theirs since this one was loaded
+    pub fn save(&self, path: &str) {
+        let mut books: BTreeMap<String, Self> = storage::load_json(&Self::file_path());
+        if self.back.is_empty() && self.forward.is_empty() {
+            books.remove(path);
+        } else {
+            books.insert(path.to_string(), self.clone());
+        }
+        if let Err(e) = storage::save_json(&Self::file_path(), &books) {
+            error!("Unable to save the navigation history: {:?}", e);
+        }
+    }
+
+    // leaving `from` for somewhere else, what was ahead is dropped
+    pub fn push(&mut self, from: Position) {
+        if self.back.last() != Some(&from) {
+            self.back.push(from);
+        }
+        if self.back.len() > MAX_ENTRIES {
+            self.back.remove(0);
+        }
+        self.forward.clear();
+    }
+
+    pub fn back(&mut self, current: Position) -> Option<Position> {
+        let to = self.back.pop()?;
+        self.forward.push(current);
+        Some(to)
+    }
+
+    pub fn forward(&mut self, current: Position) -> Option<Position> {
+        let to = self.forward.pop()?;
+        self.back.push(current);
+        Some(to)
+    }
+
+    pub fn can_go_back(&self) -> bool {
+        !self.back.is_empty()
+    }
+
+    pub fn can_go_forward(&self) -> bool {
+        !self.forward.is_empty()
+    }
+}
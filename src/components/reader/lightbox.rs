@@ -0,0 +1,390 @@
+// Bookx - lightbox.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::gettext;
+use relm4::{
+    adw::{self, prelude::*},
+    gtk::{self, gdk, glib},
+    Component, ComponentParts, ComponentSender,
+};
+use tracing::error;
+
+use std::cell::Cell;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::components::reader::bookx_reader::load_image;
+
+// every step of the keyboard and the buttons zooms by this much
+const ZOOM_STEP: f64 = 1.25;
+const MIN_ZOOM: f64 = 0.1;
+const MAX_ZOOM: f64 = 16.0;
+
+pub struct LightboxImage {
+    // file name of the image in the book
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+pub struct LightboxInit {
+    pub images: Vec<LightboxImage>,
+    pub index: usize,
+}
+
+// Window with the images of a chapter at full size, one at a time, to zoom
+// into and pan around the diagrams too small to read in the text.
+pub struct BookxLightbox {
+    window: adw::Window,
+    images: Vec<LightboxImage>,
+    index: usize,
+    texture: Option<gdk::Texture>,
+    // none while the image fits the window
+    zoom: Option<f64>,
+    // zoom a pinch started at
+    pinch_from: Option<f64>,
+    // kept alive while the chooser is shown
+    file_chooser: Option<gtk::FileChooserNative>,
+}
+
+#[derive(Debug)]
+pub enum LightboxInput {
+    Previous,
+    Next,
+    ZoomIn,
+    ZoomOut,
+    Fit,
+    // scale of a pinch since it started
+    Pinch(f64),
+    PinchEnded,
+    Save,
+    SaveTo(PathBuf),
+}
+
+#[relm4_macros::component(pub)]
+impl Component for BookxLightbox {
+    type Init = LightboxInit;
+    type Input = LightboxInput;
+    type Output = ();
+    type CommandOutput = ();
+
+    view! {
+        #[name = "lightbox"]
+        adw::Window {
+            set_default_width: 900,
+            set_default_height: 700,
+            set_modal: true,
+
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                adw::HeaderBar {
+                    #[wrap(Some)]
+                    set_title_widget = &adw::WindowTitle {
+                        #[watch]
+                        set_title: &model.images[model.index].name,
+                        #[watch]
+                        set_subtitle: &gettext("%s of %s")
+                            .replacen("%s", &(model.index + 1).to_string(), 1)
+                            .replacen("%s", &model.images.len().to_string(), 1),
+                    },
+                    pack_start = &gtk::Button {
+                        set_icon_name: "go-previous-symbolic",
+                        set_tooltip_text: Some(&gettext("Previous Image")),
+                        #[watch]
+                        set_sensitive: model.index > 0,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(LightboxInput::Previous);
+                        },
+                    },
+                    pack_start = &gtk::Button {
+                        set_icon_name: "go-next-symbolic",
+                        set_tooltip_text: Some(&gettext("Next Image")),
+                        #[watch]
+                        set_sensitive: model.index + 1 < model.images.len(),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(LightboxInput::Next);
+                        },
+                    },
+                    pack_end = &gtk::Button {
+                        set_icon_name: "document-save-symbolic",
+                        set_tooltip_text: Some(&gettext("Save Image…")),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(LightboxInput::Save);
+                        },
+                    },
+                    pack_end = &gtk::Box {
+                        add_css_class: "linked",
+                        gtk::Button {
+                            set_icon_name: "zoom-out-symbolic",
+                            set_tooltip_text: Some(&gettext("Zoom Out")),
+                            connect_clicked[sender] => move |_| {
+                                sender.input(LightboxInput::ZoomOut);
+                            },
+                        },
+                        gtk::Button {
+                            set_icon_name: "zoom-fit-best-symbolic",
+                            set_tooltip_text: Some(&gettext("Fit to Window")),
+                            #[watch]
+                            set_sensitive: model.zoom.is_some(),
+                            connect_clicked[sender] => move |_| {
+                                sender.input(LightboxInput::Fit);
+                            },
+                        },
+                        gtk::Button {
+                            set_icon_name: "zoom-in-symbolic",
+                            set_tooltip_text: Some(&gettext("Zoom In")),
+                            connect_clicked[sender] => move |_| {
+                                sender.input(LightboxInput::ZoomIn);
+                            },
+                        },
+                    },
+                },
+
+                #[name = "toasts"]
+                adw::ToastOverlay {
+                    set_vexpand: true,
+
+                    #[name = "scroll"]
+                    gtk::ScrolledWindow {
+                        // the image shrinks to the window until zoomed
+                        #[watch]
+                        set_hscrollbar_policy: if model.zoom.is_some() {
+                            gtk::PolicyType::Automatic
+                        } else {
+                            gtk::PolicyType::Never
+                        },
+                        #[watch]
+                        set_vscrollbar_policy: if model.zoom.is_some() {
+                            gtk::PolicyType::Automatic
+                        } else {
+                            gtk::PolicyType::Never
+                        },
+
+                        gtk::Picture {
+                            set_can_shrink: true,
+                            set_halign: gtk::Align::Center,
+                            set_valign: gtk::Align::Center,
+                            #[watch]
+                            set_paintable: model.texture.as_ref(),
+                            #[watch]
+                            set_width_request: model.size().0,
+                            #[watch]
+                            set_height_request: model.size().1,
+                        },
+                    },
+                },
+            },
+        }
+    }
+
+    fn init(
+        init: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let LightboxInit { images, index } = init;
+        let mut model = BookxLightbox {
+            window: root.clone(),
+            images,
+            index,
+            texture: None,
+            zoom: None,
+            pinch_from: None,
+            file_chooser: None,
+        };
+        model.load();
+        let widgets = view_output!();
+
+        let window = root.clone();
+        let keys = gtk::EventControllerKey::new();
+        keys.connect_key_pressed(
+            glib::clone!(@strong sender, @weak window => @default-return gtk::Inhibit(false), move |_, key, _, _| {
+                let message = match key {
+                    gdk::Key::plus | gdk::Key::equal | gdk::Key::KP_Add => LightboxInput::ZoomIn,
+                    gdk::Key::minus | gdk::Key::KP_Subtract => LightboxInput::ZoomOut,
+                    gdk::Key::_0 | gdk::Key::KP_0 => LightboxInput::Fit,
+                    gdk::Key::Left | gdk::Key::Page_Up => LightboxInput::Previous,
+                    gdk::Key::Right | gdk::Key::Page_Down => LightboxInput::Next,
+                    gdk::Key::Escape => {
+                        window.close();
+                        return gtk::Inhibit(true);
+                    }
+                    _ => return gtk::Inhibit(false),
+                };
+                sender.input(message);
+                gtk::Inhibit(true)
+            }),
+        );
+        root.add_controller(keys);
+
+        // zooms with the control key held while scrolling
+        let wheel = gtk::EventControllerScroll::new(gtk::EventControllerScrollFlags::VERTICAL);
+        wheel.connect_scroll(glib::clone!(@strong sender => move |wheel, _, dy| {
+            if !wheel.current_event_state().contains(gdk::ModifierType::CONTROL_MASK) {
+                return gtk::Inhibit(false);
+            }
+            sender.input(if dy < 0.0 {
+                LightboxInput::ZoomIn
+            } else {
+                LightboxInput::ZoomOut
+            });
+            gtk::Inhibit(true)
+        }));
+        widgets.scroll.add_controller(wheel);
+
+        let pinch = gtk::GestureZoom::new();
+        pinch.connect_scale_changed(glib::clone!(@strong sender => move |_, scale| {
+            sender.input(LightboxInput::Pinch(scale));
+        }));
+        pinch.connect_end(glib::clone!(@strong sender => move |_, _| {
+            sender.input(LightboxInput::PinchEnded);
+        }));
+        widgets.scroll.add_controller(pinch);
+
+        // the image is dragged around once it's larger than the window
+        let drag = gtk::GestureDrag::new();
+        let drag_start = Rc::new(Cell::new((0.0, 0.0)));
+        drag.connect_drag_begin(
+            glib::clone!(@weak widgets.scroll as scroll, @strong drag_start => move |_, _, _| {
+                drag_start.set((scroll.hadjustment().value(), scroll.vadjustment().value()));
+            }),
+        );
+        drag.connect_drag_update(
+            glib::clone!(@weak widgets.scroll as scroll, @strong drag_start => move |_, dx, dy| {
+                let (x, y) = drag_start.get();
+                scroll.hadjustment().set_value(x - dx);
+                scroll.vadjustment().set_value(y - dy);
+            }),
+        );
+        widgets.scroll.add_controller(drag);
+
+        root.present();
+        ComponentParts { model, widgets }
+    }
+
+    fn update_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        message: Self::Input,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match message {
+            LightboxInput::Previous => {
+                if self.index > 0 {
+                    self.index -= 1;
+                    self.load();
+                }
+            }
+            LightboxInput::Next => {
+                if self.index + 1 < self.images.len() {
+                    self.index += 1;
+                    self.load();
+                }
+            }
+            LightboxInput::ZoomIn => {
+                let zoom = self.current_zoom(&widgets.scroll) * ZOOM_STEP;
+                self.zoom = Some(zoom.min(MAX_ZOOM));
+            }
+            LightboxInput::ZoomOut => {
+                let zoom = self.current_zoom(&widgets.scroll) / ZOOM_STEP;
+                self.zoom = Some(zoom.max(MIN_ZOOM));
+            }
+            LightboxInput::Fit => self.zoom = None,
+            LightboxInput::Pinch(scale) => {
+                let from = match self.pinch_from {
+                    Some(from) => from,
+                    None => {
+                        let from = self.current_zoom(&widgets.scroll);
+                        self.pinch_from = Some(from);
+                        from
+                    }
+                };
+                self.zoom = Some((from * scale).clamp(MIN_ZOOM, MAX_ZOOM));
+            }
+            LightboxInput::PinchEnded => self.pinch_from = None,
+            LightboxInput::Save => {
+                let chooser = gtk::FileChooserNative::new(
+                    Some(&gettext("Save Image")),
+                    Some(&self.window),
+                    gtk::FileChooserAction::Save,
+                    Some(&gettext("_Save")),
+                    Some(&gettext("_Cancel")),
+                );
+                chooser.set_modal(true);
+                chooser.set_current_name(&self.images[self.index].name);
+                chooser.connect_response(glib::clone!(@strong sender => move |chooser, response| {
+                    if response == gtk::ResponseType::Accept {
+                        if let Some(path) = chooser.file().and_then(|file| file.path()) {
+                            sender.input(LightboxInput::SaveTo(path));
+                        }
+                    }
+                }));
+                chooser.show();
+                self.file_chooser = Some(chooser);
+            }
+            LightboxInput::SaveTo(path) => {
+                self.file_chooser = None;
+                let message = match fs::write(&path, &self.images[self.index].data) {
+                    Ok(()) => gettext("Image saved"),
+                    Err(e) => {
+                        error!("Unable to save the image to {:?}: {}", path, e);
+                        gettext("Unable to save the image")
+                    }
+                };
+                widgets.toasts.add_toast(&adw::Toast::new(&message));
+            }
+        }
+        self.update_view(widgets, sender);
+    }
+}
+
+impl BookxLightbox {
+    // images are decoded at full size, SVGs at the size they give
+    fn load(&mut self) {
+        self.texture = load_image(&self.images[self.index].data, i32::MAX);
+        self.zoom = None;
+        self.pinch_from = None;
+    }
+
+    // size of the image at the zoom, or none to fit the window
+    fn size(&self) -> (i32, i32) {
+        match (&self.texture, self.zoom) {
+            (Some(texture), Some(zoom)) => (
+                (texture.width() as f64 * zoom) as i32,
+                (texture.height() as f64 * zoom) as i32,
+            ),
+            _ => (-1, -1),
+        }
+    }
+
+    // while the image fits, the zoom it's shown at
+    fn current_zoom(&self, scroll: &gtk::ScrolledWindow) -> f64 {
+        if let Some(zoom) = self.zoom {
+            return zoom;
+        }
+        match &self.texture {
+            Some(texture) if texture.width() > 0 && texture.height() > 0 => {
+                let width = scroll.width() as f64 / texture.width() as f64;
+                let height = scroll.height() as f64 / texture.height() as f64;
+                // a small image isn't blown up to fit
+                width.min(height).min(1.0)
+            }
+            _ => 1.0,
+        }
+    }
+}
@@ -0,0 +1,17 @@
+mod bookx_reader;
+mod comic_reader;
+mod history;
+mod lightbox;
+mod pdf_reader;
+mod search;
+mod session;
+mod tabs;
+
+pub use bookx_reader::{
+    BackAction, BookxReader, ForwardAction, ReaderInput, ReaderOutput, SearchAction, SplitAction,
+};
+pub use comic_reader::{BookxComicReader, ComicReaderInput, ComicReaderOutput};
+pub use pdf_reader::{BookxPdfReader, PdfReaderInput, PdfReaderOutput};
+pub use search::BookxSearch;
+pub use session::{ReadProgress, ReaderLocation, SessionTracker};
+pub use tabs::{BookxReaderTabs, ReaderTabsInput, ReaderTabsOutput};
@@ -0,0 +1,703 @@
+// Bookx - pdf_reader.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::gettext;
+use relm4::{
+    adw,
+    gtk::{self, gdk, glib, pango, prelude::*},
+    Component, ComponentParts, ComponentSender, RelmWidgetExt,
+};
+use tracing::{error, warn};
+
+use std::cell::{Cell, RefCell};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::components::reader::session::{ReadProgress, ReaderLocation};
+use crate::formats::{BookSource, FormatError, PdfSource, TocEntry};
+
+const MIN_ZOOM: f64 = 0.25;
+const MAX_ZOOM: f64 = 4.0;
+const ZOOM_STEP: f64 = 1.2;
+// space between pages, in pixels
+const PAGE_SPACING: i32 = 12;
+const THUMBNAIL_WIDTH: f64 = 120.0;
+// pages kept rendered around the visible ones
+const RENDER_MARGIN: usize = 1;
+
+// a text selection on a single page, in page coordinates (points)
+struct Selection {
+    page: usize,
+    start: (f64, f64),
+    end: (f64, f64),
+}
+
+// shows every page of a PDF one after the other
+pub struct BookxPdfReader {
+    path: String,
+    document: poppler::Document,
+    title: String,
+    toc: Vec<TocEntry>,
+    // size of every page in points
+    page_sizes: Vec<(f64, f64)>,
+    pictures: Vec<gtk::Picture>,
+    // drawn over the pages, only the selection is on them
+    highlights: Vec<gtk::DrawingArea>,
+    zoom: f64,
+    current_page: usize,
+    // last page in view, the pages up to it count as read
+    last_page: usize,
+    // pages rendered at the current zoom
+    rendered: Vec<bool>,
+    selection: Option<Selection>,
+    // what the selection covers at the current zoom, and its page
+    selected: Rc<RefCell<Option<(usize, cairo::Region)>>>,
+    sidebar_visible: bool,
+    // thumbnails are rendered once, the first time the sidebar is shown
+    thumbnails_started: bool,
+    // page to bring into view once the pages are laid out
+    scroll_to: Option<usize>,
+    // the next scroll is a jump, the pages skipped aren't read
+    jumped: bool,
+}
+
+pub struct BookxPdfReaderInit {
+    pub source: PdfSource,
+    pub document: poppler::Document,
+}
+
+#[derive(Debug)]
+pub enum PdfReaderInput {
+    ZoomIn,
+    ZoomOut,
+    ZoomReset,
+    Scrolled,
+    GoToPage(usize),
+    ToggleSidebar,
+    HideSidebar,
+    SelectStart { page: usize, x: f64, y: f64 },
+    SelectUpdate { x: f64, y: f64 },
+    SelectEnd,
+    Copy,
+    Close,
+}
+
+#[derive(Debug)]
+pub enum PdfReaderOutput {
+    Read(ReadProgress),
+    Close,
+}
+
+#[relm4_macros::component(pub)]
+impl Component for BookxPdfReader {
+    type Init = BookxPdfReaderInit;
+    type Input = PdfReaderInput;
+    type Output = PdfReaderOutput;
+    type CommandOutput = ();
+
+    view! {
+        #[name = "reader"]
+        gtk::Box {
+            set_orientation: gtk::Orientation::Vertical,
+
+            gtk::CenterBox {
+                set_margin_all: 6,
+
+                #[wrap(Some)]
+                set_start_widget = &gtk::Box {
+                    set_spacing: 6,
+                    gtk::Button {
+                        set_icon_name: "go-previous-symbolic",
+                        set_tooltip_text: Some(&gettext("Back to Library")),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(PdfReaderInput::Close);
+                        },
+                    },
+                    gtk::ToggleButton {
+                        set_icon_name: "sidebar-show-symbolic",
+                        set_tooltip_text: Some(&gettext("Pages and Outline")),
+                        #[watch]
+                        set_active: model.sidebar_visible,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(PdfReaderInput::ToggleSidebar);
+                        },
+                    },
+                },
+                #[wrap(Some)]
+                set_center_widget = &gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    gtk::Label {
+                        set_label: &model.title,
+                        set_ellipsize: pango::EllipsizeMode::End,
+                        add_css_class: "heading",
+                    },
+                    gtk::Label {
+                        #[watch]
+                        set_label: &gettext("Page %s of %s")
+                            .replacen("%s", &(model.current_page + 1).to_string(), 1)
+                            .replacen("%s", &model.page_sizes.len().to_string(), 1),
+                        add_css_class: "dim-label",
+                    },
+                },
+                #[wrap(Some)]
+                set_end_widget = &gtk::Box {
+                    add_css_class: "linked",
+                    gtk::Button {
+                        set_icon_name: "zoom-out-symbolic",
+                        set_tooltip_text: Some(&gettext("Zoom Out")),
+                        #[watch]
+                        set_sensitive: model.zoom > MIN_ZOOM,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(PdfReaderInput::ZoomOut);
+                        },
+                    },
+                    gtk::Button {
+                        #[watch]
+                        set_label: &format!("{:.0}%", model.zoom * 100.0),
+                        set_tooltip_text: Some(&gettext("Reset Zoom")),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(PdfReaderInput::ZoomReset);
+                        },
+                    },
+                    gtk::Button {
+                        set_icon_name: "zoom-in-symbolic",
+                        set_tooltip_text: Some(&gettext("Zoom In")),
+                        #[watch]
+                        set_sensitive: model.zoom < MAX_ZOOM,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(PdfReaderInput::ZoomIn);
+                        },
+                    },
+                },
+            },
+
+            adw::Flap {
+                set_vexpand: true,
+                #[watch]
+                set_reveal_flap: model.sidebar_visible,
+                connect_reveal_flap_notify[sender] => move |flap| {
+                    if !flap.reveals_flap() {
+                        sender.input(PdfReaderInput::HideSidebar);
+                    }
+                },
+
+                #[wrap(Some)]
+                set_flap = &gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_width_request: 200,
+                    add_css_class: "background",
+
+                    gtk::StackSwitcher {
+                        set_stack: Some(&sidebar_stack),
+                        set_margin_all: 6,
+                        set_halign: gtk::Align::Center,
+                    },
+                    #[name = "sidebar_stack"]
+                    gtk::Stack {
+                        set_vexpand: true,
+
+                        add_titled[Some("pages"), &gettext("Pages")] = &gtk::ScrolledWindow {
+                            set_hscrollbar_policy: gtk::PolicyType::Never,
+                            #[name = "thumbnails"]
+                            gtk::ListBox {
+                                add_css_class: "navigation-sidebar",
+                                connect_row_activated[sender] => move |_, row| {
+                                    sender.input(PdfReaderInput::GoToPage(row.index() as usize));
+                                },
+                            },
+                        },
+                        add_titled[Some("outline"), &gettext("Outline")] = &gtk::ScrolledWindow {
+                            set_hscrollbar_policy: gtk::PolicyType::Never,
+                            #[name = "outline"]
+                            gtk::ListBox {
+                                add_css_class: "navigation-sidebar",
+                            },
+                        },
+                    },
+                },
+
+                #[wrap(Some)]
+                #[name = "scrolled"]
+                set_content = &gtk::ScrolledWindow {
+                    set_hexpand: true,
+
+                    gtk::Viewport {
+                        #[name = "pages"]
+                        gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            set_spacing: PAGE_SPACING,
+                            set_halign: gtk::Align::Center,
+                            set_margin_all: PAGE_SPACING,
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn init(
+        init: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let BookxPdfReaderInit { source, document } = init;
+        let page_sizes: Vec<(f64, f64)> = (0..document.n_pages())
+            .map(|index| {
+                document
+                    .page(index)
+                    .map(|page| page.size())
+                    .unwrap_or((612.0, 792.0))
+            })
+            .collect();
+
+        let mut model = BookxPdfReader {
+            path: source.path().display().to_string(),
+            title: source.metadata().title.unwrap_or_default(),
+            toc: source.toc(),
+            document,
+            pictures: Vec::new(),
+            highlights: Vec::new(),
+            rendered: vec![false; page_sizes.len()],
+            page_sizes,
+            zoom: 1.0,
+            current_page: 0,
+            last_page: 0,
+            selection: None,
+            selected: Rc::default(),
+            sidebar_visible: false,
+            thumbnails_started: false,
+            scroll_to: None,
+            jumped: false,
+        };
+        let widgets = view_output!();
+
+        for index in 0..model.page_sizes.len() {
+            let picture = gtk::Picture::builder()
+                .can_shrink(false)
+                .content_fit(gtk::ContentFit::Fill)
+                .cursor(&gdk::Cursor::from_name("text", None).unwrap())
+                .build();
+            picture.add_css_class("card");
+
+            let drag = gtk::GestureDrag::new();
+            drag.connect_drag_begin(glib::clone!(@strong sender => move |_, x, y| {
+                sender.input(PdfReaderInput::SelectStart { page: index, x, y });
+            }));
+            drag.connect_drag_update(glib::clone!(@strong sender => move |drag, x, y| {
+                if let Some((start_x, start_y)) = drag.start_point() {
+                    sender.input(PdfReaderInput::SelectUpdate {
+                        x: start_x + x,
+                        y: start_y + y,
+                    });
+                }
+            }));
+            drag.connect_drag_end(glib::clone!(@strong sender => move |_, _, _| {
+                sender.input(PdfReaderInput::SelectEnd);
+            }));
+            picture.add_controller(drag);
+
+            // the selection changes with every move of the pointer, so it
+            // is drawn on its own instead of rendering the page again
+            let highlight = gtk::DrawingArea::builder().can_target(false).build();
+            let selected = model.selected.clone();
+            highlight.set_draw_func(move |_, context, _, _| {
+                if let Some((page, region)) = selected.borrow().as_ref() {
+                    if *page == index {
+                        draw_region(context, region);
+                    }
+                }
+            });
+            let overlay = gtk::Overlay::builder().child(&picture).build();
+            overlay.add_overlay(&highlight);
+
+            widgets.pages.append(&overlay);
+            model.pictures.push(picture);
+            model.highlights.push(highlight);
+
+            let row = gtk::Box::builder()
+                .orientation(gtk::Orientation::Vertical)
+                .spacing(4)
+                .margin_top(6)
+                .margin_bottom(6)
+                .build();
+            let (width, height) = model.page_sizes[index];
+            let thumbnail = gtk::Picture::builder()
+                .width_request(THUMBNAIL_WIDTH as i32)
+                .height_request((THUMBNAIL_WIDTH * height / width.max(1.0)) as i32)
+                .halign(gtk::Align::Center)
+                .build();
+            row.append(&thumbnail);
+            row.append(&gtk::Label::new(Some(&(index + 1).to_string())));
+            widgets.thumbnails.append(&row);
+        }
+        model.layout_pages();
+
+        let mut entries = Vec::new();
+        flatten_toc(&model.toc, 0, &mut entries);
+        let outline_pages: Vec<usize> = entries.iter().map(|(_, entry)| entry.chapter).collect();
+        for (depth, entry) in entries {
+            let label = gtk::Label::builder()
+                .label(&entry.label)
+                .xalign(0.0)
+                .ellipsize(pango::EllipsizeMode::End)
+                .margin_start(12 * depth as i32)
+                .tooltip_text(&entry.label)
+                .build();
+            widgets.outline.append(&label);
+        }
+        widgets
+            .outline
+            .connect_row_activated(glib::clone!(@strong sender => move |_, row| {
+                if let Some(&page) = outline_pages.get(row.index() as usize) {
+                    sender.input(PdfReaderInput::GoToPage(page));
+                }
+            }));
+
+        widgets.scrolled.vadjustment().connect_value_changed(
+            glib::clone!(@strong sender => move |_| {
+                sender.input(PdfReaderInput::Scrolled);
+            }),
+        );
+        widgets.scrolled.vadjustment().connect_page_size_notify(
+            glib::clone!(@strong sender => move |_| {
+                sender.input(PdfReaderInput::Scrolled);
+            }),
+        );
+
+        let keys = gtk::EventControllerKey::new();
+        keys.connect_key_pressed(glib::clone!(@strong sender => move |_, key, _, modifiers| {
+            let control = modifiers.contains(gdk::ModifierType::CONTROL_MASK);
+            let message = match key {
+                gdk::Key::c if control => PdfReaderInput::Copy,
+                gdk::Key::plus | gdk::Key::equal if control => PdfReaderInput::ZoomIn,
+                gdk::Key::minus if control => PdfReaderInput::ZoomOut,
+                gdk::Key::_0 if control => PdfReaderInput::ZoomReset,
+                _ => return gtk::Inhibit(false),
+            };
+            sender.input(message);
+            gtk::Inhibit(true)
+        }));
+        root.add_controller(keys);
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        message: Self::Input,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        let last_page = self.last_page;
+        let scrolled = matches!(message, PdfReaderInput::Scrolled);
+        match message {
+            PdfReaderInput::ZoomIn => self.set_zoom(self.zoom * ZOOM_STEP),
+            PdfReaderInput::ZoomOut => self.set_zoom(self.zoom / ZOOM_STEP),
+            PdfReaderInput::ZoomReset => self.set_zoom(1.0),
+            PdfReaderInput::Scrolled => {}
+            PdfReaderInput::GoToPage(page) => {
+                if page < self.page_sizes.len() {
+                    self.scroll_to = Some(page);
+                }
+            }
+            PdfReaderInput::ToggleSidebar => {
+                self.sidebar_visible = !self.sidebar_visible;
+                if self.sidebar_visible && !self.thumbnails_started {
+                    self.thumbnails_started = true;
+                    render_thumbnails(&self.document, &widgets.thumbnails);
+                }
+            }
+            PdfReaderInput::HideSidebar => self.sidebar_visible = false,
+            PdfReaderInput::SelectStart { page, x, y } => {
+                let previous = self.selection.take().map(|selection| selection.page);
+                let point = (x / self.zoom, y / self.zoom);
+                self.selection = Some(Selection {
+                    page,
+                    start: point,
+                    end: point,
+                });
+                self.highlight_selection(previous);
+            }
+            PdfReaderInput::SelectUpdate { x, y } => {
+                if let Some(selection) = self.selection.as_mut() {
+                    selection.end = (x / self.zoom, y / self.zoom);
+                    self.highlight_selection(None);
+                }
+            }
+            PdfReaderInput::SelectEnd => {
+                // selected text also goes to the primary selection, as in
+                // any other text view
+                if let Some(text) = self.selected_text() {
+                    widgets.reader.primary_clipboard().set_text(&text);
+                }
+            }
+            PdfReaderInput::Copy => {
+                if let Some(text) = self.selected_text() {
+                    widgets.reader.clipboard().set_text(&text);
+                }
+            }
+            PdfReaderInput::Close => sender.output(PdfReaderOutput::Close).unwrap(),
+        }
+
+        if let Some(page) = self.scroll_to.take() {
+            self.jumped = true;
+            // wait for the new page sizes to be allocated
+            let adjustment = widgets.scrolled.vadjustment();
+            let offset = self.page_offset(page);
+            glib::idle_add_local_once(move || adjustment.set_value(offset));
+        }
+        self.render_visible(&widgets.scrolled.vadjustment());
+        if scrolled && self.last_page != last_page {
+            let advanced = if self.jumped {
+                0
+            } else {
+                self.last_page.saturating_sub(last_page)
+            };
+            sender
+                .output(PdfReaderOutput::Read(ReadProgress {
+                    advanced: advanced as u64,
+                    position: self.last_page as u64 + 1,
+                    length: self.page_sizes.len() as u64,
+                }))
+                .unwrap();
+        }
+        if scrolled {
+            self.jumped = false;
+        }
+        self.update_view(widgets, sender);
+    }
+
+    fn shutdown(&mut self, _widgets: &mut Self::Widgets, _output: relm4::Sender<Self::Output>) {
+        ReaderLocation {
+            path: self.path.clone(),
+            chapter: self.current_page,
+            offset: 0,
+            sidebar: self.sidebar_visible,
+        }
+        .save();
+    }
+}
+
+impl BookxPdfReader {
+    pub fn open(book_path: &str) -> Result<BookxPdfReaderInit, FormatError> {
+        let opened = PdfSource::open(Path::new(book_path))
+            .and_then(|source| Ok((source.document()?, source)));
+        match opened {
+            Ok((document, source)) => Ok(BookxPdfReaderInit { source, document }),
+            Err(e) => {
+                error!("Unable to open PDF {:?} for reading: {}", book_path, e);
+                Err(e)
+            }
+        }
+    }
+
+    fn set_zoom(&mut self, zoom: f64) {
+        let zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        if (zoom - self.zoom).abs() < f64::EPSILON {
+            return;
+        }
+        self.zoom = zoom;
+        self.rendered
+            .iter_mut()
+            .for_each(|rendered| *rendered = false);
+        self.layout_pages();
+        self.highlight_selection(None);
+        // stay on the same page
+        self.scroll_to = Some(self.current_page);
+    }
+
+    // Works out what the selection covers and has it drawn over its page,
+    // and `previous` cleared.
+    fn highlight_selection(&self, previous: Option<usize>) {
+        let page = self.selection.as_ref().map(|selection| selection.page);
+        let region = self.selection.as_ref().and_then(|selection| {
+            let page = self.document.page(selection.page as i32)?;
+            let mut rectangle = selection_rectangle(selection);
+            page.selected_region(self.zoom, poppler::SelectionStyle::Glyph, &mut rectangle)
+        });
+        *self.selected.borrow_mut() = page.zip(region);
+        for page in previous.into_iter().chain(page) {
+            self.highlights[page].queue_draw();
+        }
+    }
+
+    fn layout_pages(&self) {
+        for (picture, (width, height)) in self.pictures.iter().zip(&self.page_sizes) {
+            picture.set_size_request(
+                (width * self.zoom).round() as i32,
+                (height * self.zoom).round() as i32,
+            );
+        }
+    }
+
+    // distance from the top of the view to the top of the page, in pixels
+    fn page_offset(&self, page: usize) -> f64 {
+        self.page_sizes[..page]
+            .iter()
+            .map(|(_, height)| (height * self.zoom).round() + PAGE_SPACING as f64)
+            .sum::<f64>()
+            + PAGE_SPACING as f64
+    }
+
+    // Renders the pages in view (and their neighbours) and releases the
+    // others, so only a handful of pages are ever held in memory.
+    fn render_visible(&mut self, adjustment: &gtk::Adjustment) {
+        let top = adjustment.value();
+        let bottom = top + adjustment.page_size();
+
+        let mut visible = Vec::new();
+        let mut offset = PAGE_SPACING as f64;
+        for (index, (_, height)) in self.page_sizes.iter().enumerate() {
+            let height = (height * self.zoom).round();
+            if offset < bottom && offset + height > top {
+                visible.push(index);
+            }
+            offset += height + PAGE_SPACING as f64;
+        }
+        let (first, last) = match (visible.first(), visible.last()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return,
+        };
+        self.current_page = first;
+        self.last_page = last;
+
+        let keep = first.saturating_sub(RENDER_MARGIN)..=(last + RENDER_MARGIN);
+        for index in 0..self.page_sizes.len() {
+            if !keep.contains(&index) {
+                if self.rendered[index] {
+                    self.pictures[index].set_paintable(None::<&gdk::Texture>);
+                    self.rendered[index] = false;
+                }
+                continue;
+            }
+            if self.rendered[index] {
+                continue;
+            }
+            let texture = self
+                .document
+                .page(index as i32)
+                .and_then(|page| render_page(&page, self.zoom));
+            self.pictures[index].set_paintable(texture.as_ref());
+            self.rendered[index] = true;
+        }
+    }
+
+    fn selected_text(&self) -> Option<String> {
+        let selection = self.selection.as_ref()?;
+        let page = self.document.page(selection.page as i32)?;
+        let mut rectangle = selection_rectangle(selection);
+        page.selected_text(poppler::SelectionStyle::Glyph, &mut rectangle)
+            .map(|text| text.to_string())
+            .filter(|text| !text.is_empty())
+    }
+}
+
+fn selection_rectangle(selection: &Selection) -> poppler::Rectangle {
+    let mut rectangle = poppler::Rectangle::new();
+    rectangle.set_x1(selection.start.0);
+    rectangle.set_y1(selection.start.1);
+    rectangle.set_x2(selection.end.0);
+    rectangle.set_y2(selection.end.1);
+    rectangle
+}
+
+fn flatten_toc<'a>(toc: &'a [TocEntry], depth: usize, out: &mut Vec<(usize, &'a TocEntry)>) {
+    for entry in toc {
+        out.push((depth, entry));
+        flatten_toc(&entry.children, depth + 1, out);
+    }
+}
+
+fn render_page(page: &poppler::Page, scale: f64) -> Option<gdk::Texture> {
+    let (width, height) = page.size();
+    let (width, height) = (
+        (width * scale).ceil() as i32,
+        (height * scale).ceil() as i32,
+    );
+    let mut surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height).ok()?;
+    {
+        let context = cairo::Context::new(&surface).ok()?;
+        context.set_source_rgb(1.0, 1.0, 1.0);
+        context.paint().ok()?;
+        context.scale(scale, scale);
+        page.render(&context);
+    }
+
+    let stride = surface.stride() as usize;
+    let data = match surface.data() {
+        Ok(data) => glib::Bytes::from(&*data),
+        Err(e) => {
+            warn!("Unable to read rendered page: {}", e);
+            return None;
+        }
+    };
+    Some(
+        gdk::MemoryTexture::new(
+            width,
+            height,
+            gdk::MemoryFormat::B8g8r8a8Premultiplied,
+            &data,
+            stride,
+        )
+        .upcast(),
+    )
+}
+
+// fills the rectangles of `region`, which is in pixels of the page
+fn draw_region(context: &cairo::Context, region: &cairo::Region) {
+    for index in 0..region.num_rectangles() {
+        let rectangle = region.rectangle(index);
+        context.rectangle(
+            rectangle.x() as f64,
+            rectangle.y() as f64,
+            rectangle.width() as f64,
+            rectangle.height() as f64,
+        );
+    }
+    // the blue of the accent color, with the text showing through
+    context.set_source_rgba(0.21, 0.52, 0.89, 0.35);
+    if let Err(e) = context.fill() {
+        warn!("Unable to draw the selection: {}", e);
+    }
+}
+
+// Thumbnails are rendered one at a time while the main loop is idle,
+// so opening the sidebar of a long document doesn't block the window.
+fn render_thumbnails(document: &poppler::Document, list: &gtk::ListBox) {
+    let document = document.clone();
+    let list = list.clone();
+    let next = Rc::new(Cell::new(0i32));
+    glib::idle_add_local(move || {
+        let index = next.get();
+        let row = match list.row_at_index(index) {
+            Some(row) => row,
+            None => return glib::Continue(false),
+        };
+        next.set(index + 1);
+
+        let picture = row
+            .child()
+            .and_then(|child| child.first_child())
+            .and_then(|child| child.downcast::<gtk::Picture>().ok());
+        if let (Some(picture), Some(page)) = (picture, document.page(index)) {
+            let (width, _) = page.size();
+            let texture = render_page(&page, THUMBNAIL_WIDTH / width.max(1.0));
+            picture.set_paintable(texture.as_ref());
+        }
+        glib::Continue(true)
+    });
+}
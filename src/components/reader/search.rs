@@ -0,0 +1,278 @@
+// Bookx - search.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::{gettext, ngettext};
+use relm4::{
+    gtk::{self, glib, pango, prelude::*},
+    Component, ComponentParts, ComponentSender, RelmWidgetExt,
+};
+use tracing::error;
+
+use std::sync::Arc;
+
+use crate::search::{BookText, SearchResult};
+
+// more results than this are of no use to anyone scrolling a list
+const MAX_RESULTS: usize = 500;
+
+// Full-text search over the book opened in the reader, the text of the
+// book is extracted and searched on a background thread.
+pub struct BookxSearch {
+    book: Option<Arc<BookText>>,
+    results: Vec<SearchResult>,
+    query: String,
+    // bumped on every query, results of older queries are dropped
+    generation: u64,
+    status: String,
+}
+
+#[derive(Debug)]
+pub enum SearchInput {
+    Search(String),
+    Activate(usize),
+    Focus,
+    StopSearch,
+}
+
+#[derive(Debug)]
+pub enum SearchOutput {
+    JumpTo {
+        chapter: usize,
+        start: usize,
+        end: usize,
+    },
+    Close,
+}
+
+#[derive(Debug)]
+pub enum SearchCommand {
+    Extracted(Option<Arc<BookText>>),
+    Found {
+        generation: u64,
+        results: Vec<SearchResult>,
+    },
+}
+
+#[relm4_macros::component(pub)]
+impl Component for BookxSearch {
+    type Init = String;
+    type Input = SearchInput;
+    type Output = SearchOutput;
+    type CommandOutput = SearchCommand;
+
+    view! {
+        #[name = "search"]
+        gtk::Box {
+            set_orientation: gtk::Orientation::Vertical,
+            set_spacing: 6,
+            set_margin_all: 6,
+            set_width_request: 300,
+
+            #[name = "search_entry"]
+            gtk::SearchEntry {
+                set_placeholder_text: Some(&gettext("Search in book")),
+                connect_search_changed[sender] => move |entry| {
+                    sender.input(SearchInput::Search(entry.text().to_string()));
+                },
+                connect_stop_search[sender] => move |_| {
+                    sender.input(SearchInput::StopSearch);
+                },
+            },
+            gtk::Label {
+                #[watch]
+                set_label: &model.status,
+                #[watch]
+                set_visible: !model.status.is_empty(),
+                set_xalign: 0.0,
+                set_wrap: true,
+                add_css_class: "dim-label",
+            },
+            gtk::ScrolledWindow {
+                set_vexpand: true,
+                set_hscrollbar_policy: gtk::PolicyType::Never,
+
+                #[name = "results_list"]
+                gtk::ListBox {
+                    set_selection_mode: gtk::SelectionMode::Single,
+                    add_css_class: "navigation-sidebar",
+                    connect_row_activated[sender] => move |_, row| {
+                        sender.input(SearchInput::Activate(row.index() as usize));
+                    },
+                }
+            }
+        }
+    }
+
+    fn init(
+        book_path: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = BookxSearch {
+            book: None,
+            results: Vec::new(),
+            query: String::new(),
+            generation: 0,
+            status: gettext("Preparing book for search…"),
+        };
+        let widgets = view_output!();
+
+        sender.spawn_oneshot_command(move || match BookText::extract(&book_path) {
+            Ok(book) => SearchCommand::Extracted(Some(Arc::new(book))),
+            Err(e) => {
+                error!(
+                    "Unable to extract text of {:?} for search: {:?}",
+                    book_path, e
+                );
+                SearchCommand::Extracted(None)
+            }
+        });
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        message: Self::Input,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match message {
+            SearchInput::Search(query) => {
+                self.query = query;
+                if self.query.trim().is_empty() {
+                    self.results.clear();
+                    show_results(&widgets.results_list, &self.results);
+                }
+                self.run_search(&sender);
+            }
+            SearchInput::Activate(index) => {
+                if let Some(result) = self.results.get(index) {
+                    sender
+                        .output(SearchOutput::JumpTo {
+                            chapter: result.chapter,
+                            start: result.start,
+                            end: result.end,
+                        })
+                        .unwrap();
+                }
+            }
+            SearchInput::Focus => {
+                widgets.search_entry.grab_focus();
+                widgets.search_entry.select_region(0, -1);
+            }
+            SearchInput::StopSearch => sender.output(SearchOutput::Close).unwrap(),
+        }
+        self.update_view(widgets, sender);
+    }
+
+    fn update_cmd_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        message: Self::CommandOutput,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match message {
+            SearchCommand::Extracted(Some(book)) => {
+                self.book = Some(book);
+                self.status = String::new();
+                self.run_search(&sender);
+            }
+            SearchCommand::Extracted(None) => {
+                self.status = gettext("This book can't be searched");
+            }
+            SearchCommand::Found {
+                generation,
+                results,
+            } => {
+                if generation != self.generation {
+                    return;
+                }
+                self.status = if results.is_empty() {
+                    gettext("No results")
+                } else if results.len() >= MAX_RESULTS {
+                    gettext("Showing the first %d results").replace("%d", &MAX_RESULTS.to_string())
+                } else {
+                    ngettext("%d result", "%d results", results.len() as u32)
+                        .replace("%d", &results.len().to_string())
+                };
+                self.results = results;
+                show_results(&widgets.results_list, &self.results);
+            }
+        }
+        self.update_view(widgets, sender);
+    }
+}
+
+impl BookxSearch {
+    fn run_search(&mut self, sender: &ComponentSender<Self>) {
+        self.generation += 1;
+        let book = match &self.book {
+            Some(book) => book.clone(),
+            // searched again once the text is extracted
+            None => return,
+        };
+        if self.query.trim().is_empty() {
+            self.status = String::new();
+            return;
+        }
+
+        let generation = self.generation;
+        let query = self.query.clone();
+        sender.spawn_oneshot_command(move || SearchCommand::Found {
+            generation,
+            results: book.search(&query, MAX_RESULTS),
+        });
+    }
+}
+
+fn show_results(list: &gtk::ListBox, results: &[SearchResult]) {
+    while let Some(row) = list.row_at_index(0) {
+        list.remove(&row);
+    }
+
+    for result in results {
+        let chapter = gtk::Label::builder()
+            .label(&result.chapter_title)
+            .xalign(0.0)
+            .ellipsize(pango::EllipsizeMode::End)
+            .build();
+        chapter.add_css_class("dim-label");
+        chapter.add_css_class("caption");
+
+        let context = gtk::Label::builder()
+            .xalign(0.0)
+            .wrap(true)
+            .wrap_mode(pango::WrapMode::WordChar)
+            .build();
+        context.set_markup(&format!(
+            "…{}<b>{}</b>{}…",
+            glib::markup_escape_text(result.before.trim_start()),
+            glib::markup_escape_text(&result.matched),
+            glib::markup_escape_text(result.after.trim_end()),
+        ));
+
+        let row_box = gtk::Box::new(gtk::Orientation::Vertical, 3);
+        row_box.set_margin_top(6);
+        row_box.set_margin_bottom(6);
+        row_box.append(&chapter);
+        row_box.append(&context);
+
+        list.append(&gtk::ListBoxRow::builder().child(&row_box).build());
+    }
+}
@@ -0,0 +1,171 @@
+// Bookx - session.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use relm4::gtk::gio::{self, prelude::*};
+use tracing::error;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::APP_ID;
+use crate::library_db::{ProgressUnit, ReadingSession};
+
+// a pause longer than this ends the session, time away isn't reading
+const IDLE_LIMIT: i64 = 5 * 60;
+// sessions this short without any progress are only a book being opened
+const MIN_IDLE_SESSION: i64 = 60;
+
+// What a reader reports after moving through a book
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadProgress {
+    // characters or pages read past since the last report
+    pub advanced: u64,
+    // where the reader is in the whole book and how long it is, both zero
+    // while the length isn't known yet
+    pub position: u64,
+    pub length: u64,
+}
+
+// Where a reader was left, kept in the settings so the book can be
+// reopened there on the next start.
+#[derive(Debug, Clone)]
+pub struct ReaderLocation {
+    pub path: String,
+    // the page for comics and PDFs
+    pub chapter: usize,
+    // characters into the chapter
+    pub offset: usize,
+    pub sidebar: bool,
+}
+
+impl ReaderLocation {
+    // the location in the book that was open when Bookx was closed
+    pub fn last(settings: &gio::Settings) -> Option<Self> {
+        let book = settings.string("last-book");
+        let (path, chapter, offset, sidebar): (String, u32, u32, bool) =
+            settings.get("last-location");
+        if book.is_empty() || book != path {
+            return None;
+        }
+        Some(ReaderLocation {
+            path,
+            chapter: chapter as usize,
+            offset: offset as usize,
+            sidebar,
+        })
+    }
+
+    // Only the book that's reopened is saved, of all the books open in
+    // tabs and windows.
+    pub fn save(&self) {
+        let settings = gio::Settings::new(APP_ID);
+        if settings.string("last-book") != self.path {
+            return;
+        }
+        let location = (
+            self.path.clone(),
+            self.chapter as u32,
+            self.offset as u32,
+            self.sidebar,
+        );
+        if let Err(e) = settings.set("last-location", &location) {
+            error!("Unable to save where {:?} was left: {}", self.path, e);
+        }
+    }
+}
+
+// Turns the progress reported while a book is open into reading sessions.
+pub struct SessionTracker {
+    path: String,
+    unit: ProgressUnit,
+    start: i64,
+    // last time the reader moved
+    last: i64,
+    advanced: u64,
+    position: u64,
+    length: u64,
+}
+
+impl SessionTracker {
+    pub fn new(path: String, unit: ProgressUnit) -> Self {
+        let now = now();
+        SessionTracker {
+            path,
+            unit,
+            start: now,
+            last: now,
+            advanced: 0,
+            position: 0,
+            length: 0,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // Adds the progress, returns the session a long pause before it ended.
+    pub fn record(&mut self, progress: ReadProgress) -> Option<ReadingSession> {
+        let now = now();
+        let ended = if now - self.last > IDLE_LIMIT {
+            let session = self.session(self.last);
+            self.start = now;
+            self.advanced = 0;
+            session
+        } else {
+            None
+        };
+        self.advanced += progress.advanced;
+        if progress.length > 0 {
+            self.position = progress.position;
+            self.length = progress.length;
+        }
+        self.last = now;
+        ended
+    }
+
+    // ends the session as the book is closed
+    pub fn finish(self) -> Option<ReadingSession> {
+        let now = now();
+        let end = if now - self.last > IDLE_LIMIT {
+            self.last
+        } else {
+            now
+        };
+        self.session(end)
+    }
+
+    fn session(&self, end: i64) -> Option<ReadingSession> {
+        if self.advanced == 0 && end - self.start < MIN_IDLE_SESSION {
+            return None;
+        }
+        Some(ReadingSession {
+            path: self.path.clone(),
+            start: self.start,
+            end,
+            unit: self.unit,
+            advanced: self.advanced,
+            position: self.position,
+            length: self.length,
+        })
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,373 @@
+// Bookx - tabs.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::gettext;
+use relm4::{
+    adw::{self, prelude::*},
+    gtk::{self, glib},
+    Component, ComponentController, ComponentParts, ComponentSender, Controller,
+};
+use tracing::error;
+
+use std::path::Path;
+
+use crate::components::reader::{
+    BookxComicReader, BookxPdfReader, BookxReader, ComicReaderInput, ComicReaderOutput,
+    PdfReaderInput, PdfReaderOutput, ReadProgress, ReaderInput, ReaderLocation, ReaderOutput,
+    SessionTracker,
+};
+use crate::dbus_api;
+use crate::formats::{self, BookSource, FormatKind};
+use crate::library_db::{LibraryDb, ProgressUnit, ReadingSession};
+
+// Books open for reading, each in a tab of its own with its own reader.
+pub struct BookxReaderTabs {
+    tab_view: adw::TabView,
+    tabs: Vec<ReaderTab>,
+    next_id: u32,
+    // reading session of the selected tab
+    tracker: Option<SessionTracker>,
+}
+
+struct ReaderTab {
+    id: u32,
+    path: String,
+    unit: ProgressUnit,
+    page: adw::TabPage,
+    reader: OpenReader,
+}
+
+// comics and PDFs have readers of their own
+enum OpenReader {
+    Book(Controller<BookxReader>),
+    Comic(Controller<BookxComicReader>),
+    Pdf(Controller<BookxPdfReader>),
+}
+
+impl OpenReader {
+    fn widget(&self) -> &gtk::Widget {
+        match self {
+            OpenReader::Book(reader) => reader.widget().upcast_ref(),
+            OpenReader::Comic(reader) => reader.widget().upcast_ref(),
+            OpenReader::Pdf(reader) => reader.widget().upcast_ref(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ReaderTabsInput {
+    // selects the tab the book is open in, or opens it in a new one
+    Open(String),
+    // opens the book in a new tab even if it's open already
+    OpenTab(String),
+    OpenAt {
+        path: String,
+        chapter: usize,
+        start: usize,
+        end: usize,
+    },
+    Reopen(ReaderLocation),
+    Read(u32, ReadProgress),
+    // the reader of the tab asked to be closed
+    Close(u32),
+    // a tab was closed, from its close button or by `Close`
+    Closed(adw::TabPage),
+    Selected,
+    // ends the reading session before the tabs go away
+    Finish,
+}
+
+#[derive(Debug)]
+pub enum ReaderTabsOutput {
+    Session(ReadingSession),
+    // book in the selected tab, none once the last tab is closed
+    Selected(Option<String>),
+    // books open in the tabs, in tab order
+    OpenBooks(Vec<String>),
+    ShowLibrary,
+    // the session, if any, went out before this
+    Finished,
+}
+
+#[relm4_macros::component(pub)]
+impl Component for BookxReaderTabs {
+    // whether there's a library to go back to
+    type Init = bool;
+    type Input = ReaderTabsInput;
+    type Output = ReaderTabsOutput;
+    type CommandOutput = ();
+
+    view! {
+        #[name = "reader_tabs"]
+        gtk::Box {
+            set_orientation: gtk::Orientation::Vertical,
+
+            adw::TabBar {
+                set_view: Some(&tab_view),
+                set_autohide: false,
+                #[wrap(Some)]
+                set_start_action_widget = &gtk::Button {
+                    set_visible: with_library,
+                    set_icon_name: "view-grid-symbolic",
+                    set_tooltip_text: Some(&gettext("Library")),
+                    add_css_class: "flat",
+                    connect_clicked[sender] => move |_| {
+                        sender.output(ReaderTabsOutput::ShowLibrary).unwrap();
+                    },
+                },
+            },
+            #[local_ref]
+            tab_view -> adw::TabView {
+                set_vexpand: true,
+                connect_page_detached[sender] => move |_, page, _| {
+                    sender.input(ReaderTabsInput::Closed(page.clone()));
+                },
+                connect_selected_page_notify[sender] => move |_| {
+                    sender.input(ReaderTabsInput::Selected);
+                },
+            },
+        }
+    }
+
+    fn init(
+        with_library: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let tab_view = adw::TabView::new();
+        let model = BookxReaderTabs {
+            tab_view: tab_view.clone(),
+            tabs: Vec::new(),
+            next_id: 0,
+            tracker: None,
+        };
+        let widgets = view_output!();
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, message: Self::Input, sender: ComponentSender<Self>, _root: &Self::Root) {
+        match message {
+            ReaderTabsInput::Open(path) => self.open(path, &sender),
+            ReaderTabsInput::OpenTab(path) => self.open_tab(path, &sender),
+            ReaderTabsInput::OpenAt {
+                path,
+                chapter,
+                start,
+                end,
+            } => {
+                self.open(path, &sender);
+                match self.selected().map(|tab| &tab.reader) {
+                    Some(OpenReader::Book(reader)) => reader.emit(ReaderInput::JumpTo {
+                        chapter,
+                        start,
+                        end,
+                    }),
+                    // every page of a PDF is indexed as a chapter
+                    Some(OpenReader::Pdf(reader)) => reader.emit(PdfReaderInput::GoToPage(chapter)),
+                    Some(OpenReader::Comic(reader)) => {
+                        reader.emit(ComicReaderInput::GoToPage(chapter))
+                    }
+                    None => {}
+                }
+            }
+            ReaderTabsInput::Reopen(location) => {
+                self.open_tab(location.path, &sender);
+                match self.selected().map(|tab| &tab.reader) {
+                    Some(OpenReader::Book(reader)) => {
+                        reader.emit(ReaderInput::GoTo {
+                            chapter: location.chapter,
+                            offset: location.offset,
+                        });
+                        if location.sidebar {
+                            reader.emit(ReaderInput::ToggleSearch);
+                        }
+                    }
+                    Some(OpenReader::Pdf(reader)) => {
+                        reader.emit(PdfReaderInput::GoToPage(location.chapter));
+                        if location.sidebar {
+                            reader.emit(PdfReaderInput::ToggleSidebar);
+                        }
+                    }
+                    Some(OpenReader::Comic(reader)) => {
+                        reader.emit(ComicReaderInput::GoToPage(location.chapter))
+                    }
+                    None => {}
+                }
+            }
+            ReaderTabsInput::Read(id, progress) => {
+                // only the selected tab is being read
+                if self.selected().map(|tab| tab.id) != Some(id) {
+                    return;
+                }
+                let session = self
+                    .tracker
+                    .as_mut()
+                    .and_then(|tracker| tracker.record(progress));
+                if let Some(session) = session {
+                    sender.output(ReaderTabsOutput::Session(session)).unwrap();
+                }
+            }
+            ReaderTabsInput::Close(id) => {
+                if let Some(tab) = self.tabs.iter().find(|tab| tab.id == id) {
+                    self.tab_view.close_page(&tab.page);
+                }
+            }
+            ReaderTabsInput::Closed(page) => {
+                // dropping the reader ends it
+                self.tabs.retain(|tab| tab.page != page);
+                self.books_changed(&sender);
+                if self.tabs.is_empty() {
+                    self.select(&sender);
+                }
+            }
+            ReaderTabsInput::Selected => self.select(&sender),
+            ReaderTabsInput::Finish => {
+                if let Some(session) = self.tracker.take().and_then(SessionTracker::finish) {
+                    sender.output(ReaderTabsOutput::Session(session)).unwrap();
+                }
+                sender.output(ReaderTabsOutput::Finished).unwrap();
+            }
+        }
+    }
+
+    fn shutdown(&mut self, _widgets: &mut Self::Widgets, _output: relm4::Sender<Self::Output>) {
+        // the library may be gone already, the session goes straight to
+        // the database
+        if let Some(session) = self.tracker.take().and_then(SessionTracker::finish) {
+            let mut db = LibraryDb::load();
+            db.record_session(session);
+            db.save();
+        }
+    }
+}
+
+impl BookxReaderTabs {
+    fn open(&mut self, path: String, sender: &ComponentSender<Self>) {
+        match self.tabs.iter().find(|tab| tab.path == path) {
+            Some(tab) => self.tab_view.set_selected_page(&tab.page),
+            None => self.open_tab(path, sender),
+        }
+    }
+
+    fn open_tab(&mut self, path: String, sender: &ComponentSender<Self>) {
+        let id = self.next_id;
+        let kind = formats::detect(Path::new(&path)).map(|format| format.kind);
+        let opened = match kind {
+            Some(FormatKind::Pdf) => BookxPdfReader::open(&path).ok().map(|init| {
+                let title = init.source.metadata().title;
+                let reader = BookxPdfReader::builder().launch(init).forward(
+                    sender.input_sender(),
+                    move |message| match message {
+                        PdfReaderOutput::Read(progress) => ReaderTabsInput::Read(id, progress),
+                        PdfReaderOutput::Close => ReaderTabsInput::Close(id),
+                    },
+                );
+                (OpenReader::Pdf(reader), ProgressUnit::Pages, title)
+            }),
+            Some(FormatKind::Comic) => BookxComicReader::open(&path).ok().map(|source| {
+                let title = source.metadata().title;
+                let reader = BookxComicReader::builder().launch(source).forward(
+                    sender.input_sender(),
+                    move |message| match message {
+                        ComicReaderOutput::Read(progress) => ReaderTabsInput::Read(id, progress),
+                        ComicReaderOutput::Close => ReaderTabsInput::Close(id),
+                    },
+                );
+                (OpenReader::Comic(reader), ProgressUnit::Pages, title)
+            }),
+            _ => match BookxReader::open(path.clone()) {
+                Ok(init) => {
+                    let title = init.source.metadata().title;
+                    let reader = BookxReader::builder().launch(init).forward(
+                        sender.input_sender(),
+                        move |message| match message {
+                            ReaderOutput::Read(progress) => ReaderTabsInput::Read(id, progress),
+                            ReaderOutput::Close => ReaderTabsInput::Close(id),
+                        },
+                    );
+                    Some((OpenReader::Book(reader), ProgressUnit::Characters, title))
+                }
+                Err(e) => {
+                    error!("{}: {:?}", gettext("Unable to open book"), e);
+                    None
+                }
+            },
+        };
+        let (reader, unit, title) = match opened {
+            Some(opened) => opened,
+            None => {
+                if self.tabs.is_empty() {
+                    sender.output(ReaderTabsOutput::Selected(None)).unwrap();
+                }
+                return;
+            }
+        };
+
+        self.next_id += 1;
+        let page = self.tab_view.append(reader.widget());
+        let title = title.unwrap_or_else(|| {
+            Path::new(&path)
+                .file_stem()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        });
+        page.set_title(&title);
+        page.set_tooltip(&glib::markup_escape_text(&path));
+        self.tabs.push(ReaderTab {
+            id,
+            path: path.clone(),
+            unit,
+            page: page.clone(),
+            reader,
+        });
+        dbus_api::book_opened(&path);
+        self.books_changed(sender);
+        if self.tab_view.selected_page().as_ref() == Some(&page) {
+            // the first tab is selected as it's added
+            self.select(sender);
+        } else {
+            self.tab_view.set_selected_page(&page);
+        }
+    }
+
+    fn selected(&self) -> Option<&ReaderTab> {
+        let page = self.tab_view.selected_page()?;
+        self.tabs.iter().find(|tab| tab.page == page)
+    }
+
+    // the session moves over to the book in the selected tab
+    fn select(&mut self, sender: &ComponentSender<Self>) {
+        let selected = self.selected().map(|tab| (tab.path.clone(), tab.unit));
+        let tracking = self.tracker.as_ref().map(|tracker| tracker.path());
+        if selected.as_ref().map(|(path, _)| path.as_str()) == tracking {
+            return;
+        }
+        if let Some(session) = self.tracker.take().and_then(SessionTracker::finish) {
+            sender.output(ReaderTabsOutput::Session(session)).unwrap();
+        }
+        self.tracker = selected
+            .as_ref()
+            .map(|(path, unit)| SessionTracker::new(path.clone(), *unit));
+        sender
+            .output(ReaderTabsOutput::Selected(selected.map(|(path, _)| path)))
+            .unwrap();
+    }
+
+    fn books_changed(&self, sender: &ComponentSender<Self>) {
+        let books = self.tabs.iter().map(|tab| tab.path.clone()).collect();
+        sender.output(ReaderTabsOutput::OpenBooks(books)).unwrap();
+    }
+}
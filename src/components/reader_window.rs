@@ -0,0 +1,115 @@
+// Bookx - reader_window.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::gettext;
+use relm4::{
+    adw::{self, prelude::*},
+    gtk, main_application, Component, ComponentController, ComponentParts, ComponentSender,
+    Controller,
+};
+
+use crate::components::reader::{BookxReaderTabs, ReaderTabsInput, ReaderTabsOutput};
+use crate::library_db::ReadingSession;
+
+// A window of its own for reading, next to the library's, so two books can
+// be read side by side. It has tabs but no library.
+pub struct BookxReaderWindow {
+    id: u32,
+    tabs: Controller<BookxReaderTabs>,
+}
+
+#[derive(Debug)]
+pub enum ReaderWindowInput {
+    Close,
+    Tabs(ReaderTabsOutput),
+}
+
+#[derive(Debug)]
+pub enum ReaderWindowOutput {
+    Session(ReadingSession),
+    // books open in the window, by the window's id
+    OpenBooks(u32, Vec<String>),
+    Closed(u32),
+}
+
+#[relm4_macros::component(pub)]
+impl Component for BookxReaderWindow {
+    // id of the window and the book to open in it
+    type Init = (u32, String);
+    type Input = ReaderWindowInput;
+    type Output = ReaderWindowOutput;
+    type CommandOutput = ();
+
+    view! {
+        #[name = "reader_window"]
+        adw::ApplicationWindow {
+            set_application: Some(&main_application()),
+            set_default_width: 800,
+            set_default_height: 650,
+            set_title: Some(&gettext("Bookx")),
+            // closed once the tabs are done with the session
+            connect_close_request[sender] => move |_| {
+                sender.input(ReaderWindowInput::Close);
+                gtk::Inhibit(true)
+            },
+
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                adw::HeaderBar {},
+                append: model.tabs.widget(),
+            },
+        }
+    }
+
+    fn init(
+        (id, path): Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let tabs = BookxReaderTabs::builder()
+            .launch(false)
+            .forward(sender.input_sender(), ReaderWindowInput::Tabs);
+        tabs.emit(ReaderTabsInput::Open(path));
+        let model = BookxReaderWindow { id, tabs };
+        let widgets = view_output!();
+        root.present();
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, message: Self::Input, sender: ComponentSender<Self>, root: &Self::Root) {
+        let message = match message {
+            ReaderWindowInput::Close => return self.tabs.emit(ReaderTabsInput::Finish),
+            ReaderWindowInput::Tabs(message) => message,
+        };
+        match message {
+            ReaderTabsOutput::Session(session) => {
+                sender.output(ReaderWindowOutput::Session(session)).unwrap()
+            }
+            ReaderTabsOutput::OpenBooks(books) => sender
+                .output(ReaderWindowOutput::OpenBooks(self.id, books))
+                .unwrap(),
+            // the last tab was closed
+            ReaderTabsOutput::Selected(None) => self.tabs.emit(ReaderTabsInput::Finish),
+            ReaderTabsOutput::Finished => {
+                sender.output(ReaderWindowOutput::Closed(self.id)).unwrap();
+                root.destroy();
+            }
+            // there's no library here to show
+            ReaderTabsOutput::Selected(Some(_)) | ReaderTabsOutput::ShowLibrary => {}
+        }
+    }
+}
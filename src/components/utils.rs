@@ -6,11 +6,19 @@ use relm4::gtk::{
 use std::time::Instant;
 use tracing::{debug, info};
 
-pub fn load_files_from_folder(folder: &gio::File, recursive: bool) -> Vec<gio::File> {
+use crate::formats::{self, Format};
+
+// Books in `folder` with their format. Text, Markdown and HTML files are
+// only books when `documents` is set.
+pub fn load_files_from_folder(
+    folder: &gio::File,
+    recursive: bool,
+    documents: bool,
+) -> Vec<(gio::File, &'static Format)> {
     info!("Starting to lad books from folder: {:?}", folder.path());
     let now = Instant::now();
 
-    let res = load_files_from_folder_internal(folder, folder, recursive);
+    let res = load_files_from_folder_internal(folder, folder, recursive, documents);
     debug!(
         "Folder enumeration: {} us (recursive: {}), total files: {}",
         now.elapsed().as_micros(),
@@ -25,7 +33,8 @@ fn load_files_from_folder_internal(
     base: &gio::File,
     folder: &gio::File,
     recursive: bool,
-) -> Vec<gio::File> {
+    documents: bool,
+) -> Vec<(gio::File, &'static Format)> {
     let mut enumerator = folder
         .enumerate_children(
             "standard::name,standard::type,standard::content-type",
@@ -38,16 +47,27 @@ fn load_files_from_folder_internal(
     while let Some(info) = enumerator.next().and_then(|s| s.ok()) {
         let child = enumerator.child(&info);
         if recursive && info.file_type() == gio::FileType::Directory {
-            let mut res = load_files_from_folder_internal(&base, &child, recursive);
+            let mut res = load_files_from_folder_internal(&base, &child, recursive, documents);
             files.append(&mut res);
         } else if info.file_type() == gio::FileType::Regular {
             if let Some(content_type) = info.content_type().map(|t| t.to_string()) {
-                match content_type.as_str() {
-                    "application/epub+zip" => {
-                        // currently only epub is supported
-                        files.push(child.clone());
+                // archives like `.fb2.zip` only report a generic content type,
+                // those are recognized by their file name instead
+                let format = formats::for_content_type(&content_type)
+                    .or_else(|| child.path().and_then(|path| formats::for_path(&path)));
+                match format {
+                    Some(format) if documents || !format.document => {
+                        debug!("Found {} book {:?}", format.name, child.path());
+                        files.push((child.clone(), format));
+                    }
+                    Some(format) => {
+                        debug!(
+                            "Not taking {} file {:?} for a book",
+                            format.name,
+                            child.path()
+                        );
                     }
-                    &_ => {
+                    None => {
                         info!("File is not supported {:?}", child.path());
                     }
                 }
@@ -60,7 +80,7 @@ fn load_files_from_folder_internal(
     // implicit order; if anything, this will queue books in the same
     // order in which they appear in the directory when browsing its
     // contents
-    files.sort_by(|a, b| cmp_two_files(Some(base), a, b));
+    files.sort_by(|(a, _), (b, _)| cmp_two_files(Some(base), a, b));
 
     files
 }
@@ -101,7 +121,7 @@ pub fn cmp_two_files(base: Option<&gio::File>, a: &gio::File, b: &gio::File) ->
     order
 }
 
-fn cmp_like_nautilus(filename_a: &str, filename_b: &str) -> Ordering {
+pub(crate) fn cmp_like_nautilus(filename_a: &str, filename_b: &str) -> Ordering {
     let order;
 
     let sort_last_a = filename_a.as_bytes()[0] == b'.' || filename_a.as_bytes()[0] == b'#';
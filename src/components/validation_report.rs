@@ -0,0 +1,123 @@
+// Bookx - validation_report.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::gettext;
+use relm4::{
+    adw::{self, prelude::*},
+    gtk, ComponentParts, ComponentSender, SimpleComponent,
+};
+
+use crate::formats::epub_check::{Report, Severity};
+
+const SEVERITIES: [Severity; 4] = [
+    Severity::Fatal,
+    Severity::Error,
+    Severity::Warning,
+    Severity::Usage,
+];
+
+// Window listing what the EPUB checker found, grouped by severity
+pub struct ValidationReport {}
+
+#[relm4::component(pub)]
+impl SimpleComponent for ValidationReport {
+    // title of the book and its report
+    type Init = (String, Report);
+    type Input = ();
+    type Output = ();
+
+    view! {
+        #[name = "report_window"]
+        adw::PreferencesWindow {
+            set_title: Some(&gettext("Validation Report")),
+            set_default_width: 640,
+            set_default_height: 560,
+            set_modal: true,
+
+            #[name = "page"]
+            add = &adw::PreferencesPage {
+                set_title: &gettext("Report"),
+
+                adw::PreferencesGroup {
+                    set_title: &title,
+                    set_description: Some(&summary(&report)),
+                }
+            }
+        }
+    }
+
+    fn init(
+        (title, report): Self::Init,
+        root: &Self::Root,
+        _sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = Self {};
+        let widgets = view_output!();
+
+        for severity in SEVERITIES {
+            let messages: Vec<_> = report
+                .messages
+                .iter()
+                .filter(|message| message.severity == severity)
+                .collect();
+            if messages.is_empty() {
+                continue;
+            }
+            let group = adw::PreferencesGroup::new();
+            group.set_title(&format!("{} ({})", severity.label(), messages.len()));
+            for message in messages {
+                let row = adw::ActionRow::new();
+                // messages quote markup from the book
+                row.set_use_markup(false);
+                row.set_title(&message.text);
+                if let Some(location) = message.location() {
+                    row.set_subtitle(&location);
+                }
+                row.add_prefix(&gtk::Image::from_icon_name(match severity {
+                    Severity::Fatal | Severity::Error => "dialog-error-symbolic",
+                    Severity::Warning => "dialog-warning-symbolic",
+                    Severity::Usage => "dialog-information-symbolic",
+                }));
+                group.add(&row);
+            }
+            widgets.page.add(&group);
+        }
+
+        root.present();
+        ComponentParts { model, widgets }
+    }
+}
+
+fn summary(report: &Report) -> String {
+    if report.messages.is_empty() {
+        return gettext("No problems found");
+    }
+    let counts = [
+        report.count(Severity::Fatal) + report.count(Severity::Error),
+        report.count(Severity::Warning),
+        report.count(Severity::Usage),
+    ]
+    .iter()
+    .fold(
+        gettext("%s errors, %s warnings, %s usage notes"),
+        |text, count| text.replacen("%s", &count.to_string(), 1),
+    );
+    if report.is_valid() {
+        format!("{} — {}", gettext("The book is valid"), counts)
+    } else {
+        format!("{} — {}", gettext("The book is not valid"), counts)
+    }
+}
@@ -0,0 +1,313 @@
+// Bookx - dbus_api.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use relm4::gtk::{
+    gio::{self, prelude::*},
+    glib::{self, ToVariant},
+};
+use tracing::{error, warn};
+
+use std::cell::RefCell;
+use std::fs;
+use std::time::SystemTime;
+
+use crate::app;
+use crate::components::MainContainerInput;
+use crate::library_db::LibraryDb;
+
+const INTERFACE: &str = "com.adhadse.Bookx";
+const INTERFACE_XML: &str = r#"
+<node>
+  <interface name="com.adhadse.Bookx">
+    <method name="OpenBook">
+      <arg type="s" name="path" direction="in"/>
+      <arg type="u" name="chapter" direction="in"/>
+      <arg type="u" name="offset" direction="in"/>
+    </method>
+    <method name="ListBooks">
+      <arg type="a(ssasd)" name="books" direction="out"/>
+    </method>
+    <method name="GetProgress">
+      <arg type="s" name="path" direction="in"/>
+      <arg type="d" name="percent" direction="out"/>
+      <arg type="t" name="position" direction="out"/>
+      <arg type="t" name="length" direction="out"/>
+      <arg type="b" name="finished" direction="out"/>
+    </method>
+    <method name="SetProgress">
+      <arg type="s" name="path" direction="in"/>
+      <arg type="d" name="percent" direction="in"/>
+    </method>
+    <method name="AddToShelf">
+      <arg type="s" name="path" direction="in"/>
+      <arg type="s" name="shelf" direction="in"/>
+    </method>
+    <method name="Rescan"/>
+    <signal name="ProgressChanged">
+      <arg type="s" name="path"/>
+      <arg type="d" name="percent"/>
+    </signal>
+    <signal name="BookOpened">
+      <arg type="s" name="path"/>
+    </signal>
+  </interface>
+</node>
+"#;
+const NO_SUCH_BOOK: &str = "com.adhadse.Bookx.Error.NoSuchBook";
+const NO_PROGRESS: &str = "com.adhadse.Bookx.Error.NoProgress";
+
+thread_local! {
+    // where the signals are emitted from, once registered
+    static EXPORTED: RefCell<Option<(gio::DBusConnection, String)>> = RefCell::new(None);
+    // the library database as last read, with when its file was changed
+    static LIBRARY_DB: RefCell<Option<(Option<SystemTime>, LibraryDb)>> = RefCell::new(None);
+}
+
+// Exports the `com.adhadse.Bookx` interface on the object path of the
+// application, for scripts to drive the library. Books are named by their
+// path, as listed by `ListBooks`, and shelves are the tags of the library.
+// `OpenBook` takes the chapter and the character offset in it, for comics
+// and PDFs the chapter is the page.
+pub fn register(app: &gio::Application) {
+    let (connection, object_path) = match (app.dbus_connection(), app.dbus_object_path()) {
+        (Some(connection), Some(object_path)) => (connection, object_path.to_string()),
+        _ => {
+            warn!("Not on the session bus, the D-Bus interface isn't available");
+            return;
+        }
+    };
+    let interface = match gio::DBusNodeInfo::for_xml(INTERFACE_XML) {
+        Ok(node) => node.lookup_interface(INTERFACE),
+        Err(e) => {
+            error!("Unable to parse the D-Bus interface: {}", e);
+            return;
+        }
+    };
+    let interface = match interface {
+        Some(interface) => interface,
+        None => return,
+    };
+
+    let app = app.clone();
+    let registered = connection.register_object(
+        &object_path,
+        &interface,
+        move |_, _, _, _, method, parameters, invocation| {
+            let _guard = app.hold();
+            match method {
+                "OpenBook" => {
+                    let (path, chapter, offset) = match parameters.get::<(String, u32, u32)>() {
+                        Some(parameters) => parameters,
+                        None => return invalid_args(invocation),
+                    };
+                    if !in_library(&path) {
+                        return no_such_book(invocation, &path);
+                    }
+                    // the window comes up first if it isn't open, and opens
+                    // the book once it's there
+                    app.activate();
+                    app::remote_queued(MainContainerInput::OpenBookAt {
+                        path,
+                        chapter: chapter as usize,
+                        start: offset as usize,
+                        end: offset as usize,
+                    });
+                    invocation.return_value(None);
+                }
+                "ListBooks" => {
+                    let books: Vec<(String, String, Vec<String>, f64)> = with_db(|db| {
+                        db.catalog
+                            .iter()
+                            .map(|(path, entry)| {
+                                let percent = db
+                                    .books
+                                    .get(path)
+                                    .and_then(|record| record.progress.as_ref())
+                                    .map(|progress| progress.percent())
+                                    .unwrap_or_default();
+                                (
+                                    path.clone(),
+                                    entry.title.clone(),
+                                    entry.authors.clone(),
+                                    percent,
+                                )
+                            })
+                            .collect()
+                    });
+                    invocation.return_value(Some(&(books,).to_variant()));
+                }
+                "GetProgress" => {
+                    let path = match parameters.get::<(String,)>() {
+                        Some((path,)) => path,
+                        None => return invalid_args(invocation),
+                    };
+                    if !in_library(&path) {
+                        return no_such_book(invocation, &path);
+                    }
+                    let reply = with_db(|db| {
+                        let progress = db
+                            .books
+                            .get(&path)
+                            .and_then(|record| record.progress.as_ref());
+                        match progress {
+                            Some(progress) => (
+                                progress.percent(),
+                                progress.position,
+                                progress.length,
+                                progress.finished.is_some(),
+                            ),
+                            None => (0.0, 0, 0, false),
+                        }
+                    });
+                    invocation.return_value(Some(&reply.to_variant()));
+                }
+                "SetProgress" => {
+                    let (path, percent) = match parameters.get::<(String, f64)>() {
+                        Some(parameters) => parameters,
+                        None => return invalid_args(invocation),
+                    };
+                    if !in_library(&path) {
+                        return no_such_book(invocation, &path);
+                    }
+                    let set = with_db(|db| {
+                        if !db.set_progress(&path, percent, now()) {
+                            return false;
+                        }
+                        // the window's library makes the change when it's up
+                        if !app::remote(MainContainerInput::SetProgress(path.clone(), percent)) {
+                            db.save();
+                            progress_changed(db, &path);
+                        }
+                        true
+                    });
+                    if !set {
+                        return invocation.return_dbus_error(
+                            NO_PROGRESS,
+                            &format!("{} hasn't been read yet, its length isn't known", path),
+                        );
+                    }
+                    invocation.return_value(None);
+                }
+                "AddToShelf" => {
+                    let (path, shelf) = match parameters.get::<(String, String)>() {
+                        Some(parameters) => parameters,
+                        None => return invalid_args(invocation),
+                    };
+                    if !in_library(&path) {
+                        return no_such_book(invocation, &path);
+                    }
+                    let shelf = shelf.trim().to_string();
+                    if shelf.is_empty() {
+                        return invalid_args(invocation);
+                    }
+                    if !app::remote(MainContainerInput::AddTag(path.clone(), shelf.clone())) {
+                        with_db(|db| {
+                            db.add_tag(&path, &shelf);
+                            db.save();
+                        });
+                    }
+                    invocation.return_value(None);
+                }
+                "Rescan" => {
+                    // kept for the window when it isn't up yet
+                    if !app::remote_queued(MainContainerInput::RescanLibrary) {
+                        app.activate();
+                    }
+                    invocation.return_value(None);
+                }
+                _ => invocation.return_dbus_error(
+                    "org.freedesktop.DBus.Error.UnknownMethod",
+                    &format!("No method named {}", method),
+                ),
+            }
+        },
+        // the interface has no properties
+        |_, _, _, _, _| ().to_variant(),
+        |_, _, _, _, _, _| false,
+    );
+    match registered {
+        Ok(_) => EXPORTED.with(|exported| {
+            *exported.borrow_mut() = Some((connection, object_path));
+        }),
+        Err(e) => error!("Unable to export the D-Bus interface: {}", e),
+    }
+}
+
+// emits `ProgressChanged` with the progress the database has for the book
+pub fn progress_changed(db: &LibraryDb, path: &str) {
+    let percent = db
+        .books
+        .get(path)
+        .and_then(|record| record.progress.as_ref())
+        .map(|progress| progress.percent())
+        .unwrap_or_default();
+    emit("ProgressChanged", (path.to_string(), percent).to_variant());
+}
+
+pub fn book_opened(path: &str) {
+    emit("BookOpened", (path.to_string(),).to_variant());
+}
+
+fn emit(signal: &str, parameters: glib::Variant) {
+    EXPORTED.with(|exported| {
+        if let Some((connection, object_path)) = exported.borrow().as_ref() {
+            let emitted =
+                connection.emit_signal(None, object_path, INTERFACE, signal, Some(&parameters));
+            if let Err(e) = emitted {
+                warn!("Unable to emit {}: {}", signal, e);
+            }
+        }
+    });
+}
+
+// Runs `f` on the library database, which is read again only once its file
+// has changed, by the window's library or by anything else.
+fn with_db<R>(f: impl FnOnce(&mut LibraryDb) -> R) -> R {
+    let modified = fs::metadata(LibraryDb::file_path())
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    LIBRARY_DB.with(|cached| {
+        let mut cached = cached.borrow_mut();
+        let fresh =
+            modified.is_some() && matches!(cached.as_ref(), Some((read, _)) if *read == modified);
+        if !fresh {
+            *cached = Some((modified, LibraryDb::load()));
+        }
+        let (_, db) = cached.as_mut().unwrap();
+        f(db)
+    })
+}
+
+fn in_library(path: &str) -> bool {
+    with_db(|db| db.catalog.contains_key(path))
+}
+
+fn invalid_args(invocation: gio::DBusMethodInvocation) {
+    invocation.return_dbus_error(
+        "org.freedesktop.DBus.Error.InvalidArgs",
+        "Wrong arguments for the method",
+    );
+}
+
+fn no_such_book(invocation: gio::DBusMethodInvocation, path: &str) {
+    invocation.return_dbus_error(NO_SUCH_BOOK, &format!("{} isn't in the library", path));
+}
+
+fn now() -> i64 {
+    glib::DateTime::now_utc()
+        .map(|now| now.to_unix())
+        .unwrap_or_default()
+}
@@ -0,0 +1,379 @@
+// Bookx - comic.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use compress_tools::{ArchiveContents, ArchiveIterator};
+use relm4::gtk::glib;
+use tracing::{info, warn};
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::components::utils::cmp_like_nautilus;
+use crate::formats::{title_from_path, BookMetadata, BookSource, Chapter, FormatError, TocEntry};
+use crate::xhtml::{escape, Token, Tokenizer};
+
+const IMAGE_EXTENSIONS: &[&str] = &[".jpg", ".jpeg", ".png", ".gif", ".webp", ".avif", ".bmp"];
+
+// Comic book archive (CBZ, CBR or CB7), every image in it is a page.
+// Archives are read through libarchive so all three work the same way.
+pub struct ComicSource {
+    path: PathBuf,
+    // image entries of the archive in reading order
+    pages: Vec<String>,
+    metadata: BookMetadata,
+    right_to_left: bool,
+    unpacked: Arc<(Mutex<Unpacked>, Condvar)>,
+}
+
+// Pages of the archive written out to the cache. Solid RAR and 7z archives
+// are decompressed from the start to get at any page, so the whole archive
+// is unpacked once in one pass instead of once for every page.
+struct Unpacked {
+    dir: PathBuf,
+    // indices of the pages in `dir` so far
+    pages: HashSet<usize>,
+    started: bool,
+    running: bool,
+    // the source is gone, and so is what was unpacked
+    closed: bool,
+}
+
+impl ComicSource {
+    pub fn open(path: &Path) -> Result<Self, FormatError> {
+        let entries = compress_tools::list_archive_files(File::open(path)?)
+            .map_err(|e| FormatError::Invalid(e.to_string()))?;
+
+        let mut pages: Vec<String> = entries
+            .iter()
+            .filter(|entry| is_page(entry))
+            .cloned()
+            .collect();
+        if pages.is_empty() {
+            return Err(FormatError::Invalid(String::from(
+                "Archive doesn't contain any images",
+            )));
+        }
+        // archives rarely store their pages in order, and page numbers
+        // are seldom zero padded
+        pages.sort_by(|a, b| cmp_like_nautilus(a, b));
+
+        let comic_info = entries
+            .iter()
+            .find(|entry| file_name(entry).eq_ignore_ascii_case("ComicInfo.xml"));
+        let (mut metadata, right_to_left) = match comic_info {
+            Some(entry) => match read_entry(path, entry) {
+                Some(data) => parse_comic_info(&String::from_utf8_lossy(&data)),
+                None => (BookMetadata::default(), false),
+            },
+            None => (BookMetadata::default(), false),
+        };
+        if metadata.title.is_none() {
+            metadata.title = title_from_path(path);
+        }
+
+        let unpacked = Unpacked {
+            dir: unpacked_dir().join(glib::uuid_string_random().as_str()),
+            pages: HashSet::new(),
+            started: false,
+            running: false,
+            closed: false,
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            pages,
+            metadata,
+            right_to_left,
+            unpacked: Arc::new((Mutex::new(unpacked), Condvar::new())),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Removes the pages a previous run didn't get to clean up, to be
+    // called before any archive is opened.
+    pub fn remove_unpacked() {
+        let dir = unpacked_dir();
+        match fs::remove_dir_all(&dir) {
+            Ok(()) => info!("Removed comic book pages left in {:?}", dir),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Unable to remove {:?}: {}", dir, e),
+        }
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    // encoded image of the page, waits for the archive to be unpacked
+    // that far
+    pub fn page(&self, index: usize) -> Option<Vec<u8>> {
+        let entry = self.pages.get(index)?;
+        let (state, changed) = &*self.unpacked;
+        let mut unpacked = state.lock().unwrap();
+        if !unpacked.started {
+            unpacked.started = true;
+            unpacked.running = true;
+            self.unpack();
+        }
+        while unpacked.running && !unpacked.pages.contains(&index) {
+            unpacked = changed.wait(unpacked).unwrap();
+        }
+        if unpacked.pages.contains(&index) {
+            match fs::read(unpacked.dir.join(index.to_string())) {
+                Ok(data) => return Some(data),
+                Err(e) => warn!("Unable to read page {} of {:?}: {}", index, self.path, e),
+            }
+        }
+        drop(unpacked);
+        // the archive stopped unpacking before that page
+        read_entry(&self.path, entry)
+    }
+
+    fn unpack(&self) {
+        let path = self.path.clone();
+        let pages = self.pages.clone();
+        let unpacked = self.unpacked.clone();
+        thread::spawn(move || {
+            if let Err(e) = unpack_pages(&path, &pages, &unpacked) {
+                warn!("Unable to unpack {:?}: {}", path, e);
+            }
+            let (state, changed) = &*unpacked;
+            let mut unpacked = state.lock().unwrap();
+            unpacked.running = false;
+            if unpacked.closed {
+                let _ = fs::remove_dir_all(&unpacked.dir);
+            }
+            changed.notify_all();
+        });
+    }
+
+    // manga are read from right to left
+    pub fn right_to_left(&self) -> bool {
+        self.right_to_left
+    }
+}
+
+fn file_name(entry: &str) -> &str {
+    entry.rsplit('/').next().unwrap_or(entry)
+}
+
+fn is_page(entry: &str) -> bool {
+    let name = file_name(entry);
+    let lower = name.to_lowercase();
+    // skip metadata folders macOS adds to archives and hidden files
+    !entry.starts_with("__MACOSX/")
+        && !name.starts_with('.')
+        && IMAGE_EXTENSIONS.iter().any(|ext| lower.ends_with(ext))
+}
+
+// Writes every page of the archive to the cache as it comes, stops early
+// once the source is dropped.
+fn unpack_pages(
+    path: &Path,
+    pages: &[String],
+    unpacked: &(Mutex<Unpacked>, Condvar),
+) -> Result<(), FormatError> {
+    let (state, changed) = unpacked;
+    let dir = state.lock().unwrap().dir.clone();
+    fs::create_dir_all(&dir)?;
+
+    let indices: HashMap<&str, usize> = pages
+        .iter()
+        .enumerate()
+        .map(|(index, page)| (page.as_str(), index))
+        .collect();
+    let entries = ArchiveIterator::from_read(File::open(path)?)
+        .map_err(|e| FormatError::Invalid(e.to_string()))?;
+    let mut page: Option<(usize, Vec<u8>)> = None;
+    for contents in entries {
+        match contents {
+            ArchiveContents::StartOfEntry(name, _) => {
+                page = indices.get(name.as_str()).map(|index| (*index, Vec::new()));
+            }
+            ArchiveContents::DataChunk(chunk) => {
+                if let Some((_, data)) = &mut page {
+                    data.extend_from_slice(&chunk);
+                }
+            }
+            ArchiveContents::EndOfEntry => {
+                if let Some((index, data)) = page.take() {
+                    fs::write(dir.join(index.to_string()), data)?;
+                    let mut unpacked = state.lock().unwrap();
+                    unpacked.pages.insert(index);
+                    changed.notify_all();
+                    if unpacked.closed {
+                        return Ok(());
+                    }
+                }
+            }
+            ArchiveContents::Err(e) => return Err(FormatError::Invalid(e.to_string())),
+        }
+    }
+    Ok(())
+}
+
+fn read_entry(path: &Path, entry: &str) -> Option<Vec<u8>> {
+    let file = File::open(path).ok()?;
+    let mut data = Vec::new();
+    match compress_tools::uncompress_archive_file(file, &mut data, entry) {
+        Ok(_) => Some(data),
+        Err(e) => {
+            warn!("Unable to read {:?} from {:?}: {}", entry, path, e);
+            None
+        }
+    }
+}
+
+// Reads the fields of a ComicInfo.xml (the ComicRack schema) the library
+// cares about, also returns whether the comic is a right to left manga.
+fn parse_comic_info(xml: &str) -> (BookMetadata, bool) {
+    let mut metadata = BookMetadata::default();
+    let mut right_to_left = false;
+    let mut element: Option<String> = None;
+    let mut value = String::new();
+    let (mut series, mut number) = (None, None);
+    let (mut year, mut month, mut day) = (None, None, None);
+
+    let list = |value: &str| -> Vec<String> {
+        value
+            .split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect()
+    };
+
+    for (_, token) in Tokenizer::new(xml) {
+        match token {
+            Token::Start { name, .. } => {
+                element = Some(name);
+                value.clear();
+            }
+            Token::Text(text) => value.push_str(&text),
+            Token::End { name } => {
+                if element.take().as_deref() != Some(name.as_str()) {
+                    continue;
+                }
+                let value = value.trim().to_string();
+                if value.is_empty() {
+                    continue;
+                }
+                match name.as_str() {
+                    "title" => metadata.title = Some(value),
+                    "series" => series = Some(value),
+                    "number" => number = Some(value),
+                    "summary" => metadata.description = Some(value),
+                    "writer" | "penciller" => metadata.authors.extend(list(&value)),
+                    "publisher" => metadata.publisher = Some(value),
+                    "genre" => metadata.subjects.extend(list(&value)),
+                    "languageiso" => metadata.language = Some(value),
+                    "gtin" => metadata.identifier = Some(value),
+                    "year" => year = Some(value),
+                    "month" => month = value.parse::<u32>().ok(),
+                    "day" => day = value.parse::<u32>().ok(),
+                    "manga" => right_to_left = value == "YesAndRightToLeft",
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // single issues are usually only named by their series
+    if metadata.title.is_none() {
+        metadata.title = match (series, number) {
+            (Some(series), Some(number)) => Some(format!("{} #{}", series, number)),
+            (series, _) => series,
+        };
+    }
+    metadata.date = match (year, month, day) {
+        (Some(year), Some(month), Some(day)) => Some(format!("{}-{:02}-{:02}", year, month, day)),
+        (Some(year), Some(month), None) => Some(format!("{}-{:02}", year, month)),
+        (year, _, _) => year,
+    };
+    // writers often draw too, only the first mention is kept
+    let mut seen = HashSet::new();
+    metadata
+        .authors
+        .retain(|author| seen.insert(author.clone()));
+
+    (metadata, right_to_left)
+}
+
+impl Drop for ComicSource {
+    fn drop(&mut self) {
+        let mut unpacked = self.unpacked.0.lock().unwrap();
+        unpacked.closed = true;
+        // otherwise the thread unpacking cleans up once it stops
+        if unpacked.started && !unpacked.running {
+            let _ = fs::remove_dir_all(&unpacked.dir);
+        }
+    }
+}
+
+// every archive being read gets a folder of its own in here
+fn unpacked_dir() -> PathBuf {
+    glib::user_cache_dir().join("comic_pages")
+}
+
+// Pages are also exposed as chapters holding a single image, so parts
+// of the app that only know about text books still get something sensible.
+impl BookSource for ComicSource {
+    fn metadata(&self) -> BookMetadata {
+        self.metadata.clone()
+    }
+
+    // only the first page, the library doesn't need the rest unpacked
+    fn cover(&mut self) -> Option<Vec<u8>> {
+        read_entry(&self.path, self.pages.first()?)
+    }
+
+    fn toc(&self) -> Vec<TocEntry> {
+        Vec::new()
+    }
+
+    fn chapter_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn chapter(&mut self, index: usize) -> Option<Chapter> {
+        let page = self.pages.get(index)?;
+        Some(Chapter {
+            path: PathBuf::new(),
+            content: format!(
+                "<html><body><img src=\"{}\" alt=\"\"/></body></html>",
+                escape(page)
+            ),
+        })
+    }
+
+    fn resource(&mut self, path: &Path) -> Option<Vec<u8>> {
+        let entry = path.to_string_lossy();
+        if !self.pages.iter().any(|page| *page == entry) {
+            return None;
+        }
+        read_entry(&self.path, &entry)
+    }
+
+    fn chapter_index(&mut self, path: &Path) -> Option<usize> {
+        let entry = path.to_string_lossy();
+        self.pages.iter().position(|page| *page == entry)
+    }
+}
@@ -0,0 +1,109 @@
+// Bookx - epub.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use epub::doc::{EpubDoc, NavPoint};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use crate::formats::{BookMetadata, BookSource, Chapter, FormatError, TocEntry};
+
+pub struct EpubSource {
+    pub doc: EpubDoc<BufReader<File>>,
+    // path of every spine item to its index in the spine
+    spine_paths: HashMap<PathBuf, usize>,
+}
+
+impl EpubSource {
+    pub fn open(path: &Path) -> Result<Self, FormatError> {
+        let doc = EpubDoc::new(path)?;
+        let spine_paths = doc
+            .spine
+            .iter()
+            .enumerate()
+            .filter_map(|(index, id)| doc.resources.get(id).map(|(path, _)| (path.clone(), index)))
+            .collect();
+        Ok(Self { doc, spine_paths })
+    }
+
+    fn toc_entry(&self, nav_point: &NavPoint) -> Option<TocEntry> {
+        let content = nav_point.content.to_string_lossy();
+        let (path, fragment) = match content.split_once('#') {
+            Some((path, fragment)) => (path, Some(fragment.to_string())),
+            None => (content.as_ref(), None),
+        };
+        let chapter = *self.spine_paths.get(Path::new(path))?;
+        Some(TocEntry {
+            label: nav_point.label.trim().to_string(),
+            chapter,
+            fragment,
+            children: nav_point
+                .children
+                .iter()
+                .filter_map(|child| self.toc_entry(child))
+                .collect(),
+        })
+    }
+}
+
+impl BookSource for EpubSource {
+    fn metadata(&self) -> BookMetadata {
+        let all = |name: &str| self.doc.metadata.get(name).cloned().unwrap_or_default();
+        BookMetadata {
+            identifier: self.doc.mdata("identifier"),
+            title: self.doc.mdata("title"),
+            authors: all("creator"),
+            language: self.doc.mdata("language"),
+            publisher: self.doc.mdata("publisher"),
+            description: self.doc.mdata("description"),
+            date: self.doc.mdata("date"),
+            subjects: all("subject"),
+        }
+    }
+
+    fn cover(&mut self) -> Option<Vec<u8>> {
+        self.doc.get_cover().map(|(data, _)| data)
+    }
+
+    fn toc(&self) -> Vec<TocEntry> {
+        self.doc
+            .toc
+            .iter()
+            .filter_map(|nav_point| self.toc_entry(nav_point))
+            .collect()
+    }
+
+    fn chapter_count(&self) -> usize {
+        self.doc.spine.len()
+    }
+
+    fn chapter(&mut self, index: usize) -> Option<Chapter> {
+        let id = self.doc.spine.get(index)?.clone();
+        let path = self.doc.resources.get(&id)?.0.clone();
+        let (content, _) = self.doc.get_resource_str(&id)?;
+        Some(Chapter { path, content })
+    }
+
+    fn resource(&mut self, path: &Path) -> Option<Vec<u8>> {
+        self.doc.get_resource_by_path(path)
+    }
+
+    fn chapter_index(&mut self, path: &Path) -> Option<usize> {
+        self.spine_paths.get(path).copied()
+    }
+}
@@ -0,0 +1,1363 @@
+// Bookx - epub_check.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::gettext;
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::formats::epub_writer::media_type;
+use crate::formats::FormatError;
+use crate::xhtml::{resolve_href, Token, Tokenizer};
+
+// Severities as epubcheck grades them, most severe first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    // the book can't be read past this point
+    Fatal,
+    Error,
+    Warning,
+    // valid, but likely not what the author meant
+    Usage,
+}
+
+impl Severity {
+    pub fn label(&self) -> String {
+        match self {
+            Severity::Fatal => gettext("Fatal"),
+            Severity::Error => gettext("Error"),
+            Severity::Warning => gettext("Warning"),
+            Severity::Usage => gettext("Usage"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub severity: Severity,
+    // path inside the archive and line in it, when the message is about
+    // a particular place
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub text: String,
+}
+
+impl Message {
+    // `file:line` as shown next to the message
+    pub fn location(&self) -> Option<String> {
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => Some(format!("{}:{}", file, line)),
+            (Some(file), None) => Some(file.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub messages: Vec<Message>,
+}
+
+impl Report {
+    pub fn count(&self, severity: Severity) -> usize {
+        self.messages
+            .iter()
+            .filter(|message| message.severity == severity)
+            .count()
+    }
+
+    // valid as long as nothing worse than a warning was found
+    pub fn is_valid(&self) -> bool {
+        self.count(Severity::Fatal) == 0 && self.count(Severity::Error) == 0
+    }
+
+    fn add(&mut self, severity: Severity, file: Option<&str>, line: Option<usize>, text: String) {
+        self.messages.push(Message {
+            severity,
+            file: file.map(str::to_string),
+            line,
+            text,
+        });
+    }
+}
+
+const MIMETYPE: &str = "application/epub+zip";
+const CONTAINER: &str = "META-INF/container.xml";
+const REQUIRED_METADATA: [&str; 3] = ["title", "identifier", "language"];
+
+// Checks the EPUB at `path`, the archive is read directly rather than
+// through `EpubDoc` since the order and compression of the entries matter
+// and broken books have to be reported, not refused.
+pub fn check(path: &Path) -> Result<Report, FormatError> {
+    let mut report = Report::default();
+    let mut archive = match zip::ZipArchive::new(File::open(path)?) {
+        Ok(archive) => archive,
+        Err(e) => {
+            report.add(
+                Severity::Fatal,
+                None,
+                None,
+                message("The file is not a ZIP archive: %s", &[&e.to_string()]),
+            );
+            return Ok(report);
+        }
+    };
+
+    let mut files = Vec::with_capacity(archive.len());
+    for index in 0..archive.len() {
+        let mut entry = match archive.by_index(index) {
+            Ok(entry) => entry,
+            Err(e) => {
+                report.add(Severity::Fatal, None, None, e.to_string());
+                return Ok(report);
+            }
+        };
+        let name = entry.name().to_string();
+        if name == "mimetype" {
+            if index != 0 {
+                report.add(
+                    Severity::Error,
+                    Some("mimetype"),
+                    None,
+                    gettext("The mimetype file must be the first entry of the archive"),
+                );
+            }
+            if entry.compression() != zip::CompressionMethod::Stored {
+                report.add(
+                    Severity::Error,
+                    Some("mimetype"),
+                    None,
+                    gettext("The mimetype file must not be compressed"),
+                );
+            }
+        }
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        if let Err(e) = entry.read_to_end(&mut data) {
+            report.add(Severity::Fatal, Some(&name), None, e.to_string());
+            continue;
+        }
+        files.push((name, data));
+    }
+
+    match files.iter().find(|(name, _)| name == "mimetype") {
+        Some((_, data)) if data.as_slice() != MIMETYPE.as_bytes() => report.add(
+            Severity::Error,
+            Some("mimetype"),
+            None,
+            message("The mimetype file must contain exactly “%s”", &[MIMETYPE]),
+        ),
+        Some(_) => {}
+        None => report.add(
+            Severity::Error,
+            None,
+            None,
+            gettext("The archive has no mimetype file"),
+        ),
+    }
+
+    report.messages.extend(check_files(&files).messages);
+    Ok(report)
+}
+
+// Checks the contents of an EPUB, `files` are the archive entries with
+// their paths. Used as is for books open in the editor.
+pub fn check_files(files: &[(String, Vec<u8>)]) -> Report {
+    let mut checker = Checker {
+        files: files
+            .iter()
+            .filter(|(name, _)| !name.ends_with('/'))
+            .map(|(name, data)| (name.as_str(), data.as_slice()))
+            .collect(),
+        report: Report::default(),
+        manifest: Vec::new(),
+        ids: HashMap::new(),
+        referenced: HashSet::new(),
+    };
+    checker.check();
+    checker.report
+}
+
+struct ManifestEntry {
+    id: String,
+    // path inside the archive
+    path: String,
+    media_type: String,
+    properties: Vec<String>,
+    fallback: bool,
+    line: usize,
+}
+
+struct Checker<'a> {
+    files: Vec<(&'a str, &'a [u8])>,
+    report: Report,
+    manifest: Vec<ManifestEntry>,
+    // ids defined in each XHTML document, to check fragments against
+    ids: HashMap<String, HashSet<String>>,
+    // paths something in the book links to or embeds
+    referenced: HashSet<String>,
+}
+
+impl<'a> Checker<'a> {
+    fn file(&self, path: &str) -> Option<&'a [u8]> {
+        self.files
+            .iter()
+            .find(|(name, _)| *name == path)
+            .map(|(_, data)| *data)
+    }
+
+    fn add(&mut self, severity: Severity, file: &str, line: Option<usize>, text: String) {
+        self.report.add(severity, Some(file), line, text);
+    }
+
+    // text of an XML file, reporting it when it isn't UTF-8 or well-formed
+    fn xml(&mut self, path: &str) -> Option<String> {
+        let data = self.file(path)?;
+        let text = match std::str::from_utf8(data) {
+            Ok(text) => text.trim_start_matches('\u{FEFF}').to_string(),
+            Err(e) => {
+                let line = line_at(
+                    &String::from_utf8_lossy(&data[..e.valid_up_to()]),
+                    e.valid_up_to(),
+                );
+                self.add(
+                    Severity::Error,
+                    path,
+                    Some(line),
+                    gettext("The file is not valid UTF-8"),
+                );
+                String::from_utf8_lossy(data).into_owned()
+            }
+        };
+        if let Some((offset, error)) = well_formedness_error(&text) {
+            self.add(
+                Severity::Error,
+                path,
+                Some(line_at(&text, offset)),
+                message("The document is not well-formed: %s", &[&error]),
+            );
+        }
+        Some(text)
+    }
+
+    fn check(&mut self) {
+        let container = match self.file(CONTAINER) {
+            Some(_) => self.xml(CONTAINER).unwrap_or_default(),
+            None => {
+                self.report.add(
+                    Severity::Fatal,
+                    None,
+                    None,
+                    message("The archive has no %s", &[CONTAINER]),
+                );
+                return;
+            }
+        };
+        let rootfile = Tokenizer::new(&container).find_map(|(offset, token)| match token {
+            Token::Start { ref name, .. } if local_name(name) == "rootfile" => Some((
+                offset,
+                token.attr("full-path").map(str::to_string),
+                token.attr("media-type").map(str::to_string),
+            )),
+            _ => None,
+        });
+        let opf_path = match rootfile {
+            Some((offset, Some(path), media_type)) => {
+                if media_type.as_deref() != Some("application/oebps-package+xml") {
+                    self.add(
+                        Severity::Error,
+                        CONTAINER,
+                        Some(line_at(&container, offset)),
+                        gettext(
+                            "The rootfile must have the media type application/oebps-package+xml",
+                        ),
+                    );
+                }
+                path
+            }
+            _ => {
+                self.add(
+                    Severity::Fatal,
+                    CONTAINER,
+                    None,
+                    gettext("No rootfile with a full-path is declared"),
+                );
+                return;
+            }
+        };
+        let opf = match self.file(&opf_path) {
+            Some(_) => self.xml(&opf_path).unwrap_or_default(),
+            None => {
+                self.add(
+                    Severity::Fatal,
+                    CONTAINER,
+                    None,
+                    message("The package document %s is missing", &[&opf_path]),
+                );
+                return;
+            }
+        };
+
+        let package = self.check_package(&opf_path, &opf);
+        self.check_documents();
+        self.check_navigation(&opf_path, &package);
+        self.check_unused(&opf_path, &package);
+    }
+
+    // metadata, manifest and spine of the package document
+    fn check_package(&mut self, opf_path: &str, opf: &str) -> Package {
+        let mut package = Package::default();
+        let base = Path::new(opf_path);
+        let mut metadata: HashMap<&str, bool> = HashMap::new();
+        let mut identifiers = Vec::new();
+        let mut current = None;
+        let mut modified = false;
+        let mut spine_ids = HashSet::new();
+        let mut spine_line = None;
+        let mut in_manifest = false;
+
+        for (offset, token) in Tokenizer::new(opf) {
+            let line = line_at(opf, offset);
+            match token {
+                Token::Start {
+                    ref name,
+                    self_closing,
+                    ..
+                } => match local_name(name) {
+                    "package" => {
+                        package.version = token.attr("version").unwrap_or_default().to_string();
+                        package.unique_identifier =
+                            token.attr("unique-identifier").map(str::to_string);
+                    }
+                    local if name.starts_with("dc:") && REQUIRED_METADATA.contains(&local) => {
+                        let key = REQUIRED_METADATA[REQUIRED_METADATA
+                            .iter()
+                            .position(|&key| key == local)
+                            .unwrap_or(0)];
+                        metadata.entry(key).or_insert(false);
+                        if key == "identifier" {
+                            identifiers.push(token.attr("id").map(str::to_string));
+                        }
+                        if !self_closing {
+                            current = Some(key);
+                        }
+                    }
+                    "meta" if token.attr("property") == Some("dcterms:modified") => modified = true,
+                    "manifest" => in_manifest = !self_closing,
+                    "item" if in_manifest => self.manifest_item(opf_path, base, &token, line),
+                    "spine" => {
+                        spine_line = Some(line);
+                        package.toc = token.attr("toc").map(str::to_string);
+                    }
+                    "itemref" => {
+                        let idref = token.attr("idref").unwrap_or_default().to_string();
+                        if !spine_ids.insert(idref.clone()) {
+                            self.add(
+                                Severity::Error,
+                                opf_path,
+                                Some(line),
+                                message("The item %s is in the spine more than once", &[&idref]),
+                            );
+                        }
+                        let item = self
+                            .manifest
+                            .iter()
+                            .find(|item| item.id == idref)
+                            .map(|item| {
+                                (item.path.clone(), item.media_type.clone(), item.fallback)
+                            });
+                        match item {
+                            Some((path, media_type, fallback)) => {
+                                if !is_content_document(&media_type) && !fallback {
+                                    self.add(
+                                        Severity::Error,
+                                        opf_path,
+                                        Some(line),
+                                        message(
+                                            "The spine item %s is a %s file without a fallback",
+                                            &[&idref, &media_type],
+                                        ),
+                                    );
+                                }
+                                package.spine.push(path);
+                            }
+                            None => self.add(
+                                Severity::Error,
+                                opf_path,
+                                Some(line),
+                                message(
+                                    "The spine refers to %s, which is not in the manifest",
+                                    &[&idref],
+                                ),
+                            ),
+                        }
+                    }
+                    _ => {}
+                },
+                Token::Text(text) => {
+                    if let Some(name) = current {
+                        if !text.trim().is_empty() {
+                            metadata.insert(name, true);
+                        }
+                    }
+                }
+                Token::End { ref name } => {
+                    current = None;
+                    if local_name(name) == "manifest" {
+                        in_manifest = false;
+                    }
+                }
+            }
+        }
+
+        for name in REQUIRED_METADATA {
+            match metadata.get(name) {
+                Some(true) => {}
+                Some(false) => self.add(
+                    Severity::Error,
+                    opf_path,
+                    None,
+                    message("The dc:%s element is empty", &[name]),
+                ),
+                None => self.add(
+                    Severity::Error,
+                    opf_path,
+                    None,
+                    message("The metadata has no dc:%s element", &[name]),
+                ),
+            }
+        }
+        match &package.unique_identifier {
+            Some(id)
+                if identifiers
+                    .iter()
+                    .any(|other| other.as_deref() == Some(id.as_str())) => {}
+            Some(id) => self.add(
+                Severity::Error,
+                opf_path,
+                None,
+                message(
+                    "The unique-identifier %s does not refer to a dc:identifier",
+                    &[id],
+                ),
+            ),
+            None => self.add(
+                Severity::Error,
+                opf_path,
+                None,
+                gettext("The package element has no unique-identifier"),
+            ),
+        }
+        match package.version.as_str() {
+            "2.0" => {}
+            "3.0" if !modified => self.add(
+                Severity::Error,
+                opf_path,
+                None,
+                gettext("The metadata has no dcterms:modified property"),
+            ),
+            "3.0" => {}
+            version => self.add(
+                Severity::Error,
+                opf_path,
+                None,
+                message("Unknown package version “%s”", &[version]),
+            ),
+        }
+
+        if package.spine.is_empty() {
+            self.add(
+                Severity::Error,
+                opf_path,
+                spine_line,
+                gettext("The spine has no items"),
+            );
+        }
+        package
+    }
+
+    fn manifest_item(&mut self, opf_path: &str, base: &Path, token: &Token, line: usize) {
+        let (id, href, item_media_type) = match (
+            token.attr("id"),
+            token.attr("href"),
+            token.attr("media-type"),
+        ) {
+            (Some(id), Some(href), Some(media_type)) => (id, href, media_type),
+            _ => {
+                self.add(
+                    Severity::Error,
+                    opf_path,
+                    Some(line),
+                    gettext("Manifest items need an id, href and media-type"),
+                );
+                return;
+            }
+        };
+        if self.manifest.iter().any(|item| item.id == id) {
+            self.add(
+                Severity::Error,
+                opf_path,
+                Some(line),
+                message("The id %s is used more than once", &[id]),
+            );
+        }
+        if href.contains('#') {
+            self.add(
+                Severity::Error,
+                opf_path,
+                Some(line),
+                message("The href %s must not have a fragment", &[href]),
+            );
+        }
+        // remote resources aren't in the archive
+        let path = match resolve_href(base, href) {
+            Some((path, _)) => path.to_string_lossy().into_owned(),
+            None => return,
+        };
+        if self.manifest.iter().any(|item| item.path == path) {
+            self.add(
+                Severity::Error,
+                opf_path,
+                Some(line),
+                message("The file %s is declared more than once", &[href]),
+            );
+        }
+        if self.file(&path).is_none() {
+            self.add(
+                Severity::Error,
+                opf_path,
+                Some(line),
+                message(
+                    "The file %s is in the manifest but not in the archive",
+                    &[href],
+                ),
+            );
+        }
+        let expected = media_type(&path);
+        if expected != "application/octet-stream"
+            && !expected.starts_with("font/")
+            && expected != item_media_type
+        {
+            self.add(
+                Severity::Warning,
+                opf_path,
+                Some(line),
+                message(
+                    "The file %s is declared as %s but looks like %s",
+                    &[href, item_media_type, expected],
+                ),
+            );
+        }
+        let properties: Vec<String> = token
+            .attr("properties")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+        if properties.iter().any(|property| property == "cover-image")
+            && !item_media_type.starts_with("image/")
+        {
+            self.add(
+                Severity::Error,
+                opf_path,
+                Some(line),
+                message("The cover image %s is not an image", &[href]),
+            );
+        }
+        self.manifest.push(ManifestEntry {
+            id: id.to_string(),
+            path,
+            media_type: item_media_type.to_string(),
+            properties,
+            fallback: token.attr("fallback").is_some(),
+            line,
+        });
+    }
+
+    // well-formedness, ids and links of the XHTML documents and style sheets
+    fn check_documents(&mut self) {
+        let mut documents = Vec::new();
+        for index in 0..self.manifest.len() {
+            let (path, media_type) = (
+                self.manifest[index].path.clone(),
+                self.manifest[index].media_type.clone(),
+            );
+            if self.file(&path).is_none() {
+                continue;
+            }
+            match media_type.as_str() {
+                "application/xhtml+xml" | "image/svg+xml" | "application/x-dtbncx+xml" => {
+                    if let Some(text) = self.xml(&path) {
+                        let ids = self.collect_ids(&path, &text);
+                        self.ids.insert(path.clone(), ids);
+                        documents.push((path, text));
+                    }
+                }
+                "text/css" => {
+                    let text =
+                        String::from_utf8_lossy(self.file(&path).unwrap_or_default()).into_owned();
+                    self.check_style_sheet(&path, &text);
+                }
+                _ => {}
+            }
+        }
+        // links are checked once every document's ids are known
+        for (path, text) in documents {
+            self.check_links(&path, &text);
+        }
+    }
+
+    fn collect_ids(&mut self, path: &str, text: &str) -> HashSet<String> {
+        let mut ids = HashSet::new();
+        for (offset, token) in Tokenizer::new(text) {
+            if let Some(id) = token.attr("id") {
+                if !ids.insert(id.to_string()) {
+                    self.add(
+                        Severity::Error,
+                        path,
+                        Some(line_at(text, offset)),
+                        message("The id %s is used more than once", &[id]),
+                    );
+                }
+            }
+        }
+        ids
+    }
+
+    fn check_links(&mut self, path: &str, text: &str) {
+        let base = Path::new(path);
+        for (offset, token) in Tokenizer::new(text) {
+            let (name, attrs) = match &token {
+                Token::Start { name, attrs, .. } => (local_name(name), attrs),
+                _ => continue,
+            };
+            for (key, value) in attrs {
+                let hyperlink = match (name, key.as_str()) {
+                    // NCX entries point to the spine like links do
+                    ("a", "href") | ("area", "href") | ("content", "src") => true,
+                    ("link", "href")
+                    | ("img", "src")
+                    | ("script", "src")
+                    | ("source", "src")
+                    | ("audio", "src")
+                    | ("video", "src")
+                    | ("track", "src")
+                    | ("iframe", "src")
+                    | ("object", "data")
+                    | ("image", "href")
+                    | ("image", "xlink:href")
+                    | ("use", "xlink:href") => false,
+                    _ => continue,
+                };
+                self.check_reference(path, base, value, hyperlink, Some(line_at(text, offset)));
+            }
+        }
+    }
+
+    fn check_style_sheet(&mut self, path: &str, text: &str) {
+        let base = Path::new(path);
+        let mut rest = text;
+        while let Some(start) = rest.find("url(") {
+            let offset = text.len() - rest.len() + start;
+            rest = &rest[start + 4..];
+            let end = rest.find(')').unwrap_or(rest.len());
+            let url = rest[..end].trim().trim_matches(|c| c == '"' || c == '\'');
+            if !url.is_empty() && !url.starts_with("data:") {
+                self.check_reference(path, base, url, false, Some(line_at(text, offset)));
+            }
+            rest = &rest[end..];
+        }
+    }
+
+    // `hyperlink` references have to point to the spine, the others
+    // embed the target
+    fn check_reference(
+        &mut self,
+        path: &str,
+        base: &Path,
+        href: &str,
+        hyperlink: bool,
+        line: Option<usize>,
+    ) {
+        if href.starts_with("data:") || href.starts_with("javascript:") {
+            return;
+        }
+        let (target, fragment) = match resolve_href(base, href) {
+            Some((target, fragment)) => (target.to_string_lossy().into_owned(), fragment),
+            None => return,
+        };
+        self.referenced.insert(target.clone());
+        if self.file(&target).is_none() {
+            self.add(
+                Severity::Error,
+                path,
+                line,
+                message("The referenced file %s is missing", &[href]),
+            );
+            return;
+        }
+        let item = match self.manifest.iter().find(|item| item.path == target) {
+            Some(item) => item,
+            None => {
+                self.add(
+                    Severity::Error,
+                    path,
+                    line,
+                    message("The referenced file %s is not in the manifest", &[href]),
+                );
+                return;
+            }
+        };
+        if hyperlink && !is_content_document(&item.media_type) {
+            self.add(
+                Severity::Error,
+                path,
+                line,
+                message("The link %s does not point to a content document", &[href]),
+            );
+            return;
+        }
+        let fragment = match fragment {
+            Some(fragment) if !fragment.is_empty() => fragment,
+            _ => return,
+        };
+        // fragments of SVG views and media aren't ids
+        let missing = match self.ids.get(&target) {
+            Some(ids) => !ids.contains(&fragment) && !fragment.contains('('),
+            None => false,
+        };
+        if missing {
+            self.add(
+                Severity::Error,
+                path,
+                line,
+                message("The fragment identifier %s is not defined", &[href]),
+            );
+        }
+    }
+
+    // the EPUB 3 navigation document and the EPUB 2 NCX
+    fn check_navigation(&mut self, opf_path: &str, package: &Package) {
+        let navs: Vec<(String, usize)> = self
+            .manifest
+            .iter()
+            .filter(|item| item.properties.iter().any(|property| property == "nav"))
+            .map(|item| (item.path.clone(), item.line))
+            .collect();
+        if package.version == "3.0" {
+            match navs.as_slice() {
+                [] => self.add(
+                    Severity::Error,
+                    opf_path,
+                    None,
+                    gettext("No manifest item has the nav property"),
+                ),
+                [(path, _)] => self.check_nav_document(path, &package.spine),
+                [_, (_, line), ..] => self.add(
+                    Severity::Error,
+                    opf_path,
+                    Some(*line),
+                    gettext("Only one manifest item can have the nav property"),
+                ),
+            }
+        }
+
+        let ncx = match &package.toc {
+            Some(toc) => match self.manifest.iter().find(|item| item.id == *toc) {
+                Some(item) if item.media_type == "application/x-dtbncx+xml" => {
+                    Some(item.path.clone())
+                }
+                Some(_) => {
+                    self.add(
+                        Severity::Error,
+                        opf_path,
+                        None,
+                        message("The spine toc %s is not an NCX document", &[toc]),
+                    );
+                    None
+                }
+                None => {
+                    self.add(
+                        Severity::Error,
+                        opf_path,
+                        None,
+                        message("The spine toc %s is not in the manifest", &[toc]),
+                    );
+                    None
+                }
+            },
+            None => {
+                if package.version == "2.0" {
+                    self.add(
+                        Severity::Error,
+                        opf_path,
+                        None,
+                        gettext("The spine has no toc attribute pointing to the NCX"),
+                    );
+                }
+                None
+            }
+        };
+        if let Some(ncx) = ncx {
+            self.check_ncx(&ncx, opf_path, package);
+        }
+    }
+
+    fn check_nav_document(&mut self, path: &str, spine: &[String]) {
+        let text = match self.file(path) {
+            Some(data) => String::from_utf8_lossy(data).into_owned(),
+            None => return,
+        };
+        let mut in_toc = false;
+        let mut has_toc = false;
+        let mut has_list = false;
+        for (_, token) in Tokenizer::new(&text) {
+            match token {
+                Token::Start { ref name, .. } if local_name(name) == "nav" => {
+                    in_toc = token
+                        .attr("epub:type")
+                        .map(|types| types.split_whitespace().any(|t| t == "toc"))
+                        .unwrap_or(false);
+                    has_toc |= in_toc;
+                }
+                Token::Start { ref name, .. } if local_name(name) == "ol" && in_toc => {
+                    has_list = true
+                }
+                Token::End { ref name } if local_name(name) == "nav" => in_toc = false,
+                _ => {}
+            }
+        }
+        if !has_toc {
+            self.add(
+                Severity::Error,
+                path,
+                None,
+                gettext("The navigation document has no nav element of type toc"),
+            );
+        } else if !has_list {
+            self.add(
+                Severity::Error,
+                path,
+                None,
+                gettext("The toc nav element has no ol list"),
+            );
+        }
+        for (offset, token) in Tokenizer::new(&text) {
+            if let (Token::Start { name, .. }, Some(href)) = (&token, token.attr("href")) {
+                if local_name(name) == "a" {
+                    if let Some((target, _)) = resolve_href(Path::new(path), href) {
+                        let target = target.to_string_lossy().into_owned();
+                        if self.file(&target).is_some() && !spine.contains(&target) {
+                            self.add(
+                                Severity::Warning,
+                                path,
+                                Some(line_at(&text, offset)),
+                                message(
+                                    "The table of contents links to %s, which is not in the spine",
+                                    &[href],
+                                ),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_ncx(&mut self, path: &str, opf_path: &str, package: &Package) {
+        let text = match self.file(path) {
+            Some(data) => String::from_utf8_lossy(data).into_owned(),
+            None => return,
+        };
+        let mut uid = None;
+        let mut has_nav_map = false;
+        for (offset, token) in Tokenizer::new(&text) {
+            match &token {
+                Token::Start { name, .. }
+                    if local_name(name) == "meta" && token.attr("name") == Some("dtb:uid") =>
+                {
+                    uid = Some((
+                        offset,
+                        token.attr("content").unwrap_or_default().trim().to_string(),
+                    ))
+                }
+                Token::Start { name, .. } if local_name(name) == "navmap" => has_nav_map = true,
+                _ => {}
+            }
+        }
+        if !has_nav_map {
+            self.add(
+                Severity::Error,
+                path,
+                None,
+                gettext("The NCX has no navMap"),
+            );
+        }
+
+        // the NCX has to carry the same identifier as the package
+        let identifier = package.unique_identifier.as_deref().and_then(|id| {
+            let opf = String::from_utf8_lossy(self.file(opf_path)?).into_owned();
+            let mut found = false;
+            for (_, token) in Tokenizer::new(&opf) {
+                match token {
+                    Token::Start { ref name, .. } if local_name(name) == "identifier" => {
+                        found = token.attr("id") == Some(id);
+                    }
+                    Token::Text(text) if found => return Some(text.trim().to_string()),
+                    _ => found = false,
+                }
+            }
+            None
+        });
+        if let (Some((offset, uid)), Some(identifier)) = (uid, identifier) {
+            if uid != identifier {
+                self.add(
+                    Severity::Error,
+                    path,
+                    Some(line_at(&text, offset)),
+                    message(
+                        "The NCX dtb:uid %s does not match the package identifier %s",
+                        &[&uid, &identifier],
+                    ),
+                );
+            }
+        }
+    }
+
+    // files in the archive the manifest doesn't declare, and declared
+    // files nothing uses
+    fn check_unused(&mut self, opf_path: &str, package: &Package) {
+        let undeclared: Vec<String> = self
+            .files
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .filter(|name| {
+                name != "mimetype"
+                    && !name.starts_with("META-INF/")
+                    && name != opf_path
+                    && !self.manifest.iter().any(|item| item.path == *name)
+            })
+            .collect();
+        for name in undeclared {
+            self.add(
+                Severity::Warning,
+                &name,
+                None,
+                gettext("The file is not declared in the manifest"),
+            );
+        }
+
+        let unused: Vec<(String, usize)> = self
+            .manifest
+            .iter()
+            .filter(|item| {
+                !package.spine.contains(&item.path)
+                    && !self.referenced.contains(&item.path)
+                    && package.toc.as_deref() != Some(item.id.as_str())
+                    && !item
+                        .properties
+                        .iter()
+                        .any(|property| property == "nav" || property == "cover-image")
+                    && self.file(&item.path).is_some()
+            })
+            .map(|item| (item.path.clone(), item.line))
+            .collect();
+        for (path, line) in unused {
+            self.add(
+                Severity::Usage,
+                opf_path,
+                Some(line),
+                message(
+                    "The file %s is declared in the manifest but never used",
+                    &[&path],
+                ),
+            );
+        }
+        for (name, _) in self.files.clone() {
+            if name.contains(' ') {
+                self.add(
+                    Severity::Usage,
+                    name,
+                    None,
+                    gettext("File names with spaces are not supported by every reading system"),
+                );
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Package {
+    version: String,
+    unique_identifier: Option<String>,
+    // id of the NCX item
+    toc: Option<String>,
+    // paths of the spine items in order
+    spine: Vec<String>,
+}
+
+fn is_content_document(media_type: &str) -> bool {
+    matches!(media_type, "application/xhtml+xml" | "image/svg+xml")
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+fn line_at(text: &str, offset: usize) -> usize {
+    text.as_bytes()[..offset.min(text.len())]
+        .iter()
+        .filter(|&&b| b == b'\n')
+        .count()
+        + 1
+}
+
+// translated message with every `%s` replaced by the next argument
+fn message(template: &str, args: &[&str]) -> String {
+    args.iter()
+        .fold(gettext(template), |text, arg| text.replacen("%s", arg, 1))
+}
+
+// The first well-formedness error of an XML document and where it is. The
+// tokenizer used for everything else forgives too much for this, so this
+// is a small strict scanner; it doesn't look into DTDs.
+fn well_formedness_error(text: &str) -> Option<(usize, String)> {
+    let mut open: Vec<(&str, usize)> = Vec::new();
+    let mut seen_root = false;
+    let mut pos = 0;
+
+    while pos < text.len() {
+        let rest = &text[pos..];
+        if !rest.starts_with('<') {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let content = &rest[..end];
+            if open.is_empty() && !content.trim().is_empty() {
+                return Some((pos, String::from("text outside the root element")));
+            }
+            if let Some((offset, error)) = entity_error(content) {
+                return Some((pos + offset, error));
+            }
+            pos += end;
+            continue;
+        }
+
+        let (skip, terminator) = if rest.starts_with("<!--") {
+            (4, "-->")
+        } else if rest.starts_with("<![CDATA[") {
+            if open.is_empty() {
+                return Some((pos, String::from("CDATA outside the root element")));
+            }
+            (9, "]]>")
+        } else if rest.starts_with("<?") {
+            (2, "?>")
+        } else if rest.starts_with("<!DOCTYPE") || rest.starts_with("<!doctype") {
+            // the internal subset ends with `]>`
+            let bracket = rest.find('[');
+            let close = rest.find('>');
+            match (bracket, close) {
+                (Some(bracket), Some(close)) if bracket < close => (9, "]>"),
+                _ => (9, ">"),
+            }
+        } else {
+            (0, "")
+        };
+        if !terminator.is_empty() {
+            match rest[skip..].find(terminator) {
+                Some(end) => pos += skip + end + terminator.len(),
+                None => return Some((pos, String::from("unterminated markup"))),
+            }
+            continue;
+        }
+
+        if let Some(inner) = rest.strip_prefix("</") {
+            let end = match inner.find('>') {
+                Some(end) => end,
+                None => return Some((pos, String::from("unterminated end tag"))),
+            };
+            let name = inner[..end].trim_end();
+            match open.pop() {
+                Some((expected, _)) if expected == name => {}
+                Some((expected, _)) => {
+                    return Some((
+                        pos,
+                        format!("</{}> found where </{}> was expected", name, expected),
+                    ))
+                }
+                None => return Some((pos, format!("</{}> closes no element", name))),
+            }
+            pos += end + 3;
+            continue;
+        }
+
+        let inner = &rest[1..];
+        let name_end = inner
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap_or(inner.len());
+        let name = &inner[..name_end];
+        if name.is_empty() || !name.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            return Some((pos, String::from("“<” must be escaped as &lt;")));
+        }
+        if open.is_empty() && seen_root {
+            return Some((pos, format!("<{}> is a second root element", name)));
+        }
+
+        // attributes up to the end of the tag
+        let mut i = name_end;
+        let mut attributes = HashSet::new();
+        let self_closing = loop {
+            let attr_rest = &inner[i..];
+            let trimmed = attr_rest.trim_start();
+            i += attr_rest.len() - trimmed.len();
+            if trimmed.starts_with("/>") {
+                i += 2;
+                break true;
+            }
+            if trimmed.starts_with('>') {
+                i += 1;
+                break false;
+            }
+            if trimmed.is_empty() {
+                return Some((pos, format!("unterminated <{}> tag", name)));
+            }
+            let key_end = trimmed
+                .find(|c: char| c.is_whitespace() || c == '=' || c == '>' || c == '/')
+                .unwrap_or(trimmed.len());
+            let key = &trimmed[..key_end];
+            if key.is_empty() {
+                return Some((
+                    pos + 1 + i,
+                    format!("unexpected “{}” in <{}>", &trimmed[..1], name),
+                ));
+            }
+            if !attributes.insert(key) {
+                return Some((pos + 1 + i, format!("duplicate attribute {}", key)));
+            }
+            let after = trimmed[key_end..].trim_start();
+            let value = match after.strip_prefix('=') {
+                Some(value) => value.trim_start(),
+                None => return Some((pos + 1 + i, format!("attribute {} has no value", key))),
+            };
+            let quote = match value.chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => quote,
+                _ => return Some((pos + 1 + i, format!("the value of {} is not quoted", key))),
+            };
+            let end = match value[1..].find(quote) {
+                Some(end) => end,
+                None => return Some((pos + 1 + i, format!("unterminated value of {}", key))),
+            };
+            let content = &value[1..end + 1];
+            if content.contains('<') {
+                return Some((pos + 1 + i, format!("“<” in the value of {}", key)));
+            }
+            if let Some((_, error)) = entity_error(content) {
+                return Some((pos + 1 + i, error));
+            }
+            // skip to the closing quote
+            i += trimmed.len() - value.len() + end + 2;
+        };
+        seen_root = true;
+        if !self_closing {
+            open.push((name, pos));
+        }
+        pos += 1 + i;
+    }
+
+    match open.last() {
+        Some((name, offset)) => Some((*offset, format!("<{}> is never closed", name))),
+        None if !seen_root => Some((0, String::from("the document has no root element"))),
+        None => None,
+    }
+}
+
+// only the entities XML predefines are allowed without a DTD
+fn entity_error(text: &str) -> Option<(usize, String)> {
+    let mut from = 0;
+    while let Some(amp) = text[from..].find('&') {
+        let start = from + amp;
+        let rest = &text[start + 1..];
+        let end = match rest.find(';') {
+            Some(end) if end > 0 && end <= 32 => end,
+            _ => return Some((start, String::from("“&” must be escaped as &amp;"))),
+        };
+        let name = &rest[..end];
+        let valid = match name.strip_prefix('#') {
+            Some(number) => match number
+                .strip_prefix('x')
+                .or_else(|| number.strip_prefix('X'))
+            {
+                Some(hex) => u32::from_str_radix(hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .is_some(),
+                None => number
+                    .parse::<u32>()
+                    .ok()
+                    .and_then(char::from_u32)
+                    .is_some(),
+            },
+            None => matches!(name, "amp" | "lt" | "gt" | "quot" | "apos"),
+        };
+        if !valid {
+            return Some((start, format!("the entity &{}; is not defined", name)));
+        }
+        from = start + 1 + end + 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPF: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="uid">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="uid">urn:uuid:5d2b0c1e-4f1a-4a8e-9c1b-2f7e1c9d0a11</dc:identifier>
+    <dc:title>Test Book</dc:title>
+    <dc:language>en</dc:language>
+    <meta property="dcterms:modified">2023-01-01T00:00:00Z</meta>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+    <item id="ch1" href="text/ch1.xhtml" media-type="application/xhtml+xml"/>
+    <item id="ch2" href="text/ch2.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>
+  <spine>
+    <itemref idref="ch1"/>
+    <itemref idref="ch2"/>
+  </spine>
+</package>"#;
+
+    const NAV: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head><title>Contents</title></head>
+<body>
+  <nav epub:type="toc"><ol>
+    <li><a href="text/ch1.xhtml">One</a></li>
+    <li><a href="text/ch2.xhtml">Two</a></li>
+  </ol></nav>
+</body>
+</html>"#;
+
+    fn chapter(body: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml">
+<head><title>Chapter</title></head>
+<body>
+{}
+</body>
+</html>"#,
+            body
+        )
+    }
+
+    fn book(opf: &str, ch1: &str) -> Vec<(String, Vec<u8>)> {
+        let container = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#;
+        [
+            ("mimetype", MIMETYPE.to_string()),
+            (CONTAINER, container.to_string()),
+            ("OEBPS/content.opf", opf.to_string()),
+            ("OEBPS/nav.xhtml", NAV.to_string()),
+            ("OEBPS/text/ch1.xhtml", chapter(ch1)),
+            (
+                "OEBPS/text/ch2.xhtml",
+                chapter(r#"<p id="end">The end.</p>"#),
+            ),
+        ]
+        .into_iter()
+        .map(|(name, text)| (name.to_string(), text.into_bytes()))
+        .collect()
+    }
+
+    fn errors(report: &Report) -> Vec<String> {
+        report
+            .messages
+            .iter()
+            .filter(|message| message.severity <= Severity::Error)
+            .map(|message| message.text.clone())
+            .collect()
+    }
+
+    #[test]
+    fn valid_book_has_no_errors() {
+        let report = check_files(&book(
+            OPF,
+            r#"<p>One &amp; <a href="ch2.xhtml#end">two</a>.</p>"#,
+        ));
+        assert!(report.is_valid(), "{:?}", report.messages);
+        assert!(report.messages.is_empty(), "{:?}", report.messages);
+    }
+
+    #[test]
+    fn missing_container_is_fatal() {
+        let files: Vec<(String, Vec<u8>)> = book(OPF, "<p>One</p>")
+            .into_iter()
+            .filter(|(name, _)| name != CONTAINER)
+            .collect();
+        let report = check_files(&files);
+        assert_eq!(report.count(Severity::Fatal), 1);
+    }
+
+    #[test]
+    fn metadata_and_spine_are_checked() {
+        let opf = OPF
+            .replace("<dc:title>Test Book</dc:title>", "")
+            .replace(r#"<itemref idref="ch2"/>"#, r#"<itemref idref="ch3"/>"#);
+        let errors = errors(&check_files(&book(&opf, "<p>One</p>")));
+        assert!(errors.contains(&String::from("The metadata has no dc:title element")));
+        assert!(errors.contains(&String::from(
+            "The spine refers to ch3, which is not in the manifest"
+        )));
+    }
+
+    #[test]
+    fn broken_documents_and_links_are_reported() {
+        let report = check_files(&book(OPF, "<p>One&nbsp;two</p>"));
+        let message = report
+            .messages
+            .iter()
+            .find(|message| message.file.as_deref() == Some("OEBPS/text/ch1.xhtml"))
+            .expect("no message about the chapter");
+        assert_eq!(message.severity, Severity::Error);
+        assert_eq!(message.line, Some(5));
+
+        let report = check_files(&book(OPF, r#"<p><a href="ch2.xhtml#gone">Two</a></p>"#));
+        assert_eq!(
+            errors(&report),
+            vec![String::from(
+                "The fragment identifier ch2.xhtml#gone is not defined"
+            )]
+        );
+    }
+
+    #[test]
+    fn well_formedness() {
+        assert_eq!(well_formedness_error("<a><b/></a>"), None);
+        assert!(well_formedness_error("<a><b></a>").is_some());
+        assert!(well_formedness_error("<a/>text").is_some());
+        assert!(well_formedness_error("").is_some());
+        assert_eq!(entity_error("&lt; &#233; &#x263A;"), None);
+        assert_eq!(entity_error("Q&A").map(|(offset, _)| offset), Some(1));
+        assert_eq!(entity_error("a &nbsp;").map(|(offset, _)| offset), Some(2));
+    }
+}
@@ -0,0 +1,988 @@
+// Bookx - epub_package.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs::File;
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::formats::epub_check::{self, Report};
+use crate::formats::epub_writer::{image_extension, media_type, write_epub};
+use crate::formats::{BookMetadata, FormatError};
+use crate::xhtml::{escape, resolve_href, Token, Tokenizer};
+
+#[derive(Debug, Clone)]
+pub struct ManifestItem {
+    pub id: String,
+    pub href: String,
+    pub media_type: String,
+    pub properties: Option<String>,
+    // attributes kept as they are, like `fallback`
+    other: Vec<(String, String)>,
+}
+
+impl ManifestItem {
+    // whether the item can be edited as text
+    pub fn is_text(&self) -> bool {
+        matches!(
+            self.media_type.as_str(),
+            "application/xhtml+xml"
+                | "text/html"
+                | "text/css"
+                | "image/svg+xml"
+                | "application/x-dtbncx+xml"
+                | "application/xml"
+                | "text/xml"
+        )
+    }
+}
+
+// Metadata that can be changed on many books at once
+#[derive(Debug, Clone)]
+pub enum MetadataChange {
+    // only ever given for one book
+    Title(String),
+    Authors(Vec<String>),
+    // subjects of the book
+    Tags(Vec<String>),
+    Language(String),
+    // name of the series, the one the book is in when there is none, and
+    // the position of the book in it
+    Series(Option<String>, Option<u32>),
+    // calibre custom columns, as their `#name` and JSON description
+    CustomFields(Vec<(String, String)>),
+}
+
+#[derive(Debug, Clone)]
+pub struct SpineItem {
+    pub idref: String,
+    pub linear: bool,
+    other: Vec<(String, String)>,
+}
+
+// An EPUB opened for editing. Every file of the archive is kept in memory;
+// the package document is only rewritten where the manifest and spine are,
+// so metadata and everything else in it is saved untouched.
+pub struct EpubPackage {
+    path: PathBuf,
+    // archive entries in their original order
+    files: Vec<(String, Vec<u8>)>,
+    opf_path: String,
+    opf: String,
+    // where the contents of the manifest and spine are in `opf`
+    manifest_range: Range<usize>,
+    spine_range: Range<usize>,
+    // namespace prefix of the package elements, usually empty
+    prefix: String,
+    manifest: Vec<ManifestItem>,
+    spine: Vec<SpineItem>,
+    modified: bool,
+}
+
+impl EpubPackage {
+    pub fn open(path: &Path) -> Result<Self, FormatError> {
+        let mut archive = zip::ZipArchive::new(File::open(path)?)
+            .map_err(|e| FormatError::Invalid(e.to_string()))?;
+        let mut files = Vec::with_capacity(archive.len());
+        for index in 0..archive.len() {
+            let mut entry = archive
+                .by_index(index)
+                .map_err(|e| FormatError::Invalid(e.to_string()))?;
+            let mut data = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut data)?;
+            files.push((entry.name().to_string(), data));
+        }
+
+        let container = files
+            .iter()
+            .find(|(name, _)| name == "META-INF/container.xml")
+            .map(|(_, data)| String::from_utf8_lossy(data).into_owned())
+            .ok_or_else(|| FormatError::Invalid(String::from("Missing META-INF/container.xml")))?;
+        let opf_path = Tokenizer::new(&container)
+            .find_map(|(_, token)| token.attr("full-path").map(str::to_string))
+            .ok_or_else(|| FormatError::Invalid(String::from("Missing package document")))?;
+        let opf = files
+            .iter()
+            .find(|(name, _)| *name == opf_path)
+            .map(|(_, data)| String::from_utf8_lossy(data).into_owned())
+            .ok_or_else(|| FormatError::Invalid(format!("Missing {}", opf_path)))?;
+
+        let mut package = Self {
+            path: path.to_path_buf(),
+            files,
+            opf_path,
+            opf: String::new(),
+            manifest_range: 0..0,
+            spine_range: 0..0,
+            prefix: String::new(),
+            manifest: Vec::new(),
+            spine: Vec::new(),
+            modified: false,
+        };
+        package.parse_package_document(opf)?;
+        Ok(package)
+    }
+
+    fn parse_package_document(&mut self, opf: String) -> Result<(), FormatError> {
+        let tokens: Vec<(usize, Token)> = Tokenizer::new(&opf).collect();
+        let mut manifest_start = None;
+        let mut manifest_end = None;
+        let mut spine_start = None;
+        let mut spine_end = None;
+        let mut manifest = Vec::new();
+        let mut spine = Vec::new();
+
+        for (index, (offset, token)) in tokens.iter().enumerate() {
+            // contents start where the next token does
+            let next = tokens.get(index + 1).map(|(offset, _)| *offset);
+            match token {
+                Token::Start {
+                    name,
+                    attrs,
+                    self_closing,
+                } => match local_name(name) {
+                    "manifest" if !self_closing => {
+                        self.prefix = name[..name.len() - "manifest".len()].to_string();
+                        manifest_start = next;
+                    }
+                    "spine" if !self_closing => spine_start = next,
+                    "item" if manifest_start.is_some() && manifest_end.is_none() => {
+                        let mut item = ManifestItem {
+                            id: String::new(),
+                            href: String::new(),
+                            media_type: String::new(),
+                            properties: None,
+                            other: Vec::new(),
+                        };
+                        for (key, value) in attrs {
+                            match key.as_str() {
+                                "id" => item.id = value.clone(),
+                                "href" => item.href = value.clone(),
+                                "media-type" => item.media_type = value.clone(),
+                                "properties" => item.properties = Some(value.clone()),
+                                _ => item.other.push((key.clone(), value.clone())),
+                            }
+                        }
+                        manifest.push(item);
+                    }
+                    "itemref" if spine_start.is_some() && spine_end.is_none() => {
+                        let mut item = SpineItem {
+                            idref: String::new(),
+                            linear: true,
+                            other: Vec::new(),
+                        };
+                        for (key, value) in attrs {
+                            match key.as_str() {
+                                "idref" => item.idref = value.clone(),
+                                "linear" => item.linear = value != "no",
+                                _ => item.other.push((key.clone(), value.clone())),
+                            }
+                        }
+                        spine.push(item);
+                    }
+                    _ => {}
+                },
+                Token::End { name } => match local_name(name) {
+                    "manifest" => manifest_end = Some(*offset),
+                    "spine" => spine_end = Some(*offset),
+                    _ => {}
+                },
+                Token::Text(_) => {}
+            }
+        }
+
+        match (manifest_start, manifest_end, spine_start, spine_end) {
+            (Some(manifest_start), Some(manifest_end), Some(spine_start), Some(spine_end)) => {
+                self.manifest_range = manifest_start..manifest_end;
+                self.spine_range = spine_start..spine_end;
+            }
+            _ => {
+                return Err(FormatError::Invalid(String::from(
+                    "Package document has no manifest or spine",
+                )))
+            }
+        }
+        self.opf = opf;
+        self.manifest = manifest;
+        self.spine = spine;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn manifest(&self) -> &[ManifestItem] {
+        &self.manifest
+    }
+
+    pub fn spine(&self) -> &[SpineItem] {
+        &self.spine
+    }
+
+    // whether there are changes that haven't been saved
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    pub fn item(&self, id: &str) -> Option<&ManifestItem> {
+        self.manifest.iter().find(|item| item.id == id)
+    }
+
+    // path of an item inside the archive, hrefs are relative to the
+    // package document
+    fn item_path(&self, href: &str) -> Option<String> {
+        let (path, _) = resolve_href(Path::new(&self.opf_path), href)?;
+        Some(path.to_string_lossy().to_string())
+    }
+
+    pub fn read(&self, id: &str) -> Option<&[u8]> {
+        let path = self.item_path(&self.item(id)?.href)?;
+        self.files
+            .iter()
+            .find(|(name, _)| *name == path)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    pub fn replace(&mut self, id: &str, data: Vec<u8>) {
+        let path = match self.item(id).and_then(|item| self.item_path(&item.href)) {
+            Some(path) => path,
+            None => return,
+        };
+        match self.files.iter_mut().find(|(name, _)| *name == path) {
+            Some((_, existing)) => *existing = data,
+            None => self.files.push((path, data)),
+        }
+        self.modified = true;
+    }
+
+    // Adds a file to the book at `href`, relative to the package document,
+    // and to the end of the spine if asked. Returns the id of the new item.
+    pub fn add_item(
+        &mut self,
+        href: &str,
+        data: Vec<u8>,
+        in_spine: bool,
+    ) -> Result<String, FormatError> {
+        let path = self
+            .item_path(href)
+            .ok_or_else(|| FormatError::Invalid(format!("Invalid file name {}", href)))?;
+        if self.files.iter().any(|(name, _)| *name == path) {
+            return Err(FormatError::Invalid(format!("{} already exists", href)));
+        }
+
+        let stem: String = href
+            .rsplit('/')
+            .next()
+            .unwrap_or(href)
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        // ids are XML names, they can't start with a digit
+        let base = match stem.chars().next() {
+            Some(c) if c.is_ascii_alphabetic() => stem,
+            _ => format!("item_{}", stem),
+        };
+        let id = (0..)
+            .map(|n| match n {
+                0 => base.clone(),
+                n => format!("{}-{}", base, n),
+            })
+            .find(|id| self.item(id).is_none())
+            .unwrap_or_default();
+
+        self.manifest.push(ManifestItem {
+            id: id.clone(),
+            href: href.to_string(),
+            media_type: media_type(href).to_string(),
+            properties: None,
+            other: Vec::new(),
+        });
+        if in_spine {
+            self.spine.push(SpineItem {
+                idref: id.clone(),
+                linear: true,
+                other: Vec::new(),
+            });
+        }
+        self.files.push((path, data));
+        self.modified = true;
+        Ok(id)
+    }
+
+    // removes an item from the manifest, the spine and the archive
+    pub fn remove_item(&mut self, id: &str) {
+        let path = self.item(id).and_then(|item| self.item_path(&item.href));
+        self.manifest.retain(|item| item.id != id);
+        self.spine.retain(|item| item.idref != id);
+        if let Some(path) = path {
+            self.files.retain(|(name, _)| *name != path);
+        }
+        self.modified = true;
+    }
+
+    pub fn move_spine_item(&mut self, from: usize, to: usize) {
+        if from >= self.spine.len() || to >= self.spine.len() || from == to {
+            return;
+        }
+        let item = self.spine.remove(from);
+        self.spine.insert(to, item);
+        self.modified = true;
+    }
+
+    // metadata of the book as its package document has it now
+    pub fn metadata(&self) -> BookMetadata {
+        let mut metadata = BookMetadata::default();
+        let mut unique_identifier = None;
+        let mut identifiers = Vec::new();
+        // element being read, with its id
+        let mut current: Option<(String, Option<String>)> = None;
+        let mut text = String::new();
+
+        for (_, token) in Tokenizer::new(&self.opf) {
+            match token {
+                Token::Start {
+                    ref name,
+                    self_closing,
+                    ..
+                } => {
+                    if local_name(name) == "package" {
+                        unique_identifier = token.attr("unique-identifier").map(str::to_string);
+                    } else if name.starts_with("dc:") && !self_closing {
+                        current = Some((
+                            local_name(name).to_string(),
+                            token.attr("id").map(str::to_string),
+                        ));
+                        text.clear();
+                    }
+                }
+                Token::Text(content) => {
+                    if current.is_some() {
+                        text.push_str(&content);
+                    }
+                }
+                Token::End { .. } => {
+                    let (field, id) = match current.take() {
+                        Some(current) => current,
+                        None => continue,
+                    };
+                    let value = text.trim().to_string();
+                    if value.is_empty() {
+                        continue;
+                    }
+                    match field.as_str() {
+                        "identifier" => identifiers.push((id, value)),
+                        "title" if metadata.title.is_none() => metadata.title = Some(value),
+                        "creator" => metadata.authors.push(value),
+                        "language" if metadata.language.is_none() => {
+                            metadata.language = Some(value)
+                        }
+                        "publisher" if metadata.publisher.is_none() => {
+                            metadata.publisher = Some(value)
+                        }
+                        "description" if metadata.description.is_none() => {
+                            metadata.description = Some(value)
+                        }
+                        "date" if metadata.date.is_none() => metadata.date = Some(value),
+                        "subject" => metadata.subjects.push(value),
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        // the unique identifier, or the first one if it isn't marked
+        metadata.identifier = identifiers
+            .iter()
+            .find(|(id, _)| id.is_some() && *id == unique_identifier)
+            .or_else(|| identifiers.first())
+            .map(|(_, value)| value.clone());
+        metadata
+    }
+
+    fn version(&self) -> String {
+        Tokenizer::new(&self.opf)
+            .find_map(|(_, token)| match token {
+                Token::Start { ref name, .. } if local_name(name) == "package" => {
+                    token.attr("version").map(str::to_string)
+                }
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    // The cover image, marked the EPUB 3 way with a property or with the
+    // `meta name="cover"` of EPUB 2.
+    pub fn cover(&self) -> Option<&ManifestItem> {
+        self.manifest
+            .iter()
+            .find(|item| has_property(item, "cover-image"))
+            .or_else(|| {
+                let id = Tokenizer::new(&self.opf).find_map(|(_, token)| match token {
+                    Token::Start { ref name, .. }
+                        if local_name(name) == "meta" && token.attr("name") == Some("cover") =>
+                    {
+                        token.attr("content").map(str::to_string)
+                    }
+                    _ => None,
+                })?;
+                self.item(&id)
+            })
+    }
+
+    // Makes `data` the cover of the book. The current cover image is
+    // replaced when it's of the same type so pages showing it keep working,
+    // otherwise the image is added next to it. Returns the id of the cover.
+    pub fn set_cover(&mut self, data: Vec<u8>) -> Result<String, FormatError> {
+        let extension = image_extension(&data).ok_or_else(|| {
+            FormatError::Invalid(String::from("The cover is not a supported image"))
+        })?;
+        let cover_type = media_type(&format!("cover.{}", extension));
+        let current = self
+            .cover()
+            .map(|item| (item.id.clone(), item.href.clone(), item.media_type.clone()));
+
+        let id = match current {
+            Some((id, _, current_type)) if current_type == cover_type => {
+                self.replace(&id, data);
+                id
+            }
+            current => {
+                let directory = current
+                    .as_ref()
+                    .map(|(_, href, _)| href.clone())
+                    .or_else(|| {
+                        self.manifest
+                            .iter()
+                            .find(|item| item.media_type.starts_with("image/"))
+                            .map(|item| item.href.clone())
+                    })
+                    .and_then(|href| {
+                        href.rsplit_once('/')
+                            .map(|(directory, _)| format!("{}/", directory))
+                    })
+                    .unwrap_or_default();
+                let href = (0..)
+                    .map(|n| match n {
+                        0 => format!("{}cover.{}", directory, extension),
+                        n => format!("{}cover-{}.{}", directory, n, extension),
+                    })
+                    .find(|href| match self.item_path(href) {
+                        Some(path) => !self.files.iter().any(|(name, _)| *name == path),
+                        None => false,
+                    })
+                    .unwrap_or_default();
+                // the old image stays, only as an image
+                if let Some((old, _, _)) = current {
+                    if let Some(item) = self.manifest.iter_mut().find(|item| item.id == old) {
+                        set_property(item, "cover-image", false);
+                    }
+                }
+                self.add_item(&href, data, false)?
+            }
+        };
+
+        if self.version().starts_with('3') {
+            if let Some(item) = self.manifest.iter_mut().find(|item| item.id == id) {
+                set_property(item, "cover-image", true);
+            }
+        }
+        // reading systems for EPUB 2 only know the meta, EPUB 3 keeps it
+        // for them
+        let opf = set_cover_meta(&self.package_document(), &self.prefix, &id);
+        self.parse_package_document(opf)?;
+        self.modified = true;
+        Ok(id)
+    }
+
+    // The series the book is in and its position, from the calibre metas
+    // or an EPUB 3 collection.
+    pub fn series(&self) -> Option<(String, Option<String>)> {
+        let mut calibre = None;
+        let mut calibre_index = None;
+        // id of the collection and its name
+        let mut collection: Option<(Option<String>, String)> = None;
+        let mut position = None;
+        // property of the meta being read and what it refines
+        let mut current: Option<(String, Option<String>)> = None;
+        let mut text = String::new();
+
+        for (_, token) in Tokenizer::new(&self.opf) {
+            match token {
+                Token::Start {
+                    ref name,
+                    self_closing,
+                    ..
+                } if local_name(name) == "meta" => match token.attr("name") {
+                    Some("calibre:series") => calibre = token.attr("content").map(str::to_string),
+                    Some("calibre:series_index") => {
+                        calibre_index = token.attr("content").map(str::to_string)
+                    }
+                    _ => {
+                        if let (Some(property), false) = (token.attr("property"), self_closing) {
+                            let key = match token.attr("refines") {
+                                Some(refines) => Some(refines.trim_start_matches('#').to_string()),
+                                None => token.attr("id").map(str::to_string),
+                            };
+                            current = Some((property.to_string(), key));
+                            text.clear();
+                        }
+                    }
+                },
+                Token::Text(content) if current.is_some() => text.push_str(&content),
+                Token::End { .. } => {
+                    let (property, key) = match current.take() {
+                        Some(current) => current,
+                        None => continue,
+                    };
+                    let value = text.trim().to_string();
+                    match property.as_str() {
+                        "belongs-to-collection" if collection.is_none() => {
+                            collection = Some((key, value))
+                        }
+                        "group-position" => position = Some((key, value)),
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        match (calibre, collection) {
+            (Some(name), _) => Some((name, calibre_index)),
+            (None, Some((id, name))) => {
+                let position = position
+                    .filter(|(key, _)| id.is_some() && *key == id)
+                    .map(|(_, value)| value);
+                Some((name, position))
+            }
+            (None, None) => None,
+        }
+    }
+
+    pub fn change_metadata(&mut self, change: &MetadataChange) -> Result<(), FormatError> {
+        let opf = self.package_document();
+        let dc = dc_prefix(&opf);
+        let opf = match change {
+            MetadataChange::Title(title) => {
+                set_dc_elements(&opf, &dc, "title", std::slice::from_ref(title))
+            }
+            MetadataChange::Authors(authors) => set_dc_elements(&opf, &dc, "creator", authors),
+            MetadataChange::Tags(tags) => set_dc_elements(&opf, &dc, "subject", tags),
+            MetadataChange::Language(language) => {
+                set_dc_elements(&opf, &dc, "language", std::slice::from_ref(language))
+            }
+            MetadataChange::Series(name, position) => {
+                let current = self.series();
+                let name = match name
+                    .clone()
+                    .or_else(|| current.as_ref().map(|(name, _)| name.clone()))
+                {
+                    Some(name) => name,
+                    // nothing to number
+                    None => return Ok(()),
+                };
+                // a book keeps its position when only the series is renamed
+                let position = position
+                    .map(|position| position.to_string())
+                    .or_else(|| current.and_then(|(_, position)| position));
+                set_series(
+                    &opf,
+                    &self.prefix,
+                    &name,
+                    position.as_deref(),
+                    self.version().starts_with('3'),
+                )
+            }
+            MetadataChange::CustomFields(fields) => {
+                let (opf, position) = remove_metadata(&opf, |name, token| {
+                    local_name(name) == "meta"
+                        && token
+                            .attr("name")
+                            .map(|name| name.starts_with("calibre:user_metadata:"))
+                            .unwrap_or(false)
+                });
+                let elements: Vec<String> = fields
+                    .iter()
+                    .map(|(lookup, description)| {
+                        let name = format!("calibre:user_metadata:{}", lookup);
+                        format!(
+                            "<{}meta{}/>",
+                            self.prefix,
+                            attributes(&[("name", &name), ("content", description)])
+                        )
+                    })
+                    .collect();
+                insert_metadata(opf, position, &elements)
+            }
+        };
+        self.parse_package_document(opf)?;
+        self.modified = true;
+        Ok(())
+    }
+
+    // Puts back a package document taken with `package_document`, to undo
+    // changes to the metadata.
+    pub fn set_package_document(&mut self, opf: String) -> Result<(), FormatError> {
+        self.parse_package_document(opf)?;
+        self.modified = true;
+        Ok(())
+    }
+
+    // the package document with the current manifest and spine
+    pub fn package_document(&self) -> String {
+        let prefix = &self.prefix;
+        let mut manifest = String::from("\n");
+        for item in &self.manifest {
+            let mut attrs = vec![
+                ("id", item.id.as_str()),
+                ("href", item.href.as_str()),
+                ("media-type", item.media_type.as_str()),
+            ];
+            attrs.extend(
+                item.properties
+                    .as_deref()
+                    .map(|properties| ("properties", properties)),
+            );
+            attrs.extend(
+                item.other
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str())),
+            );
+            manifest.push_str(&format!("    <{}item{}/>\n", prefix, attributes(&attrs)));
+        }
+        manifest.push_str("  ");
+
+        let mut spine = String::from("\n");
+        for item in &self.spine {
+            let mut attrs = vec![("idref", item.idref.as_str())];
+            if !item.linear {
+                attrs.push(("linear", "no"));
+            }
+            attrs.extend(
+                item.other
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str())),
+            );
+            spine.push_str(&format!("    <{}itemref{}/>\n", prefix, attributes(&attrs)));
+        }
+        spine.push_str("  ");
+
+        // the later range is replaced first so the other stays valid
+        let mut opf = self.opf.clone();
+        let mut ranges = [
+            (self.manifest_range.clone(), manifest),
+            (self.spine_range.clone(), spine),
+        ];
+        ranges.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+        for (range, contents) in ranges {
+            opf.replace_range(range, &contents);
+        }
+        opf
+    }
+
+    // validation report of the book as it is being edited
+    pub fn check(&self) -> Report {
+        let mut files = self.files.clone();
+        match files.iter_mut().find(|(name, _)| *name == self.opf_path) {
+            Some((_, data)) => *data = self.package_document().into_bytes(),
+            None => files.push((self.opf_path.clone(), self.package_document().into_bytes())),
+        }
+        epub_check::check_files(&files)
+    }
+
+    pub fn save(&mut self) -> Result<(), FormatError> {
+        let opf = self.package_document();
+        match self
+            .files
+            .iter_mut()
+            .find(|(name, _)| *name == self.opf_path)
+        {
+            Some((_, data)) => *data = opf.clone().into_bytes(),
+            None => self
+                .files
+                .push((self.opf_path.clone(), opf.clone().into_bytes())),
+        }
+        write_epub(&self.path, &self.files)?;
+        self.parse_package_document(opf)?;
+        self.modified = false;
+        Ok(())
+    }
+}
+
+fn has_property(item: &ManifestItem, property: &str) -> bool {
+    item.properties
+        .as_deref()
+        .map(|properties| properties.split_whitespace().any(|other| other == property))
+        .unwrap_or(false)
+}
+
+fn set_property(item: &mut ManifestItem, property: &str, set: bool) {
+    let mut properties: Vec<&str> = item
+        .properties
+        .as_deref()
+        .unwrap_or_default()
+        .split_whitespace()
+        .filter(|other| *other != property)
+        .collect();
+    if set {
+        properties.push(property);
+    }
+    item.properties = Some(properties.join(" ")).filter(|properties| !properties.is_empty());
+}
+
+// points the `meta name="cover"` of the package document at `id`, adding
+// it at the end of the metadata if there is none
+fn set_cover_meta(opf: &str, prefix: &str, id: &str) -> String {
+    let mut metadata_end = None;
+    for (offset, token) in Tokenizer::new(opf) {
+        match token {
+            Token::Start {
+                ref name,
+                self_closing,
+                ..
+            } if local_name(name) == "meta" && token.attr("name") == Some("cover") => {
+                let end = match opf[offset..].find('>') {
+                    Some(end) => offset + end + 1,
+                    None => break,
+                };
+                let tag = format!(
+                    "<{}{}{}>",
+                    name,
+                    attributes(&[("name", "cover"), ("content", id)]),
+                    if self_closing { "/" } else { "" }
+                );
+                let mut opf = opf.to_string();
+                opf.replace_range(offset..end, &tag);
+                return opf;
+            }
+            Token::End { ref name } if local_name(name) == "metadata" => {
+                metadata_end = Some(offset);
+            }
+            _ => {}
+        }
+    }
+
+    let mut opf = opf.to_string();
+    if let Some(offset) = metadata_end {
+        let tag = format!(
+            "  <{}meta{}/>\n  ",
+            prefix,
+            attributes(&[("name", "cover"), ("content", id)])
+        );
+        opf.insert_str(offset, &tag);
+    }
+    opf
+}
+
+// prefix of the Dublin Core elements, taken from the title every book has
+fn dc_prefix(opf: &str) -> String {
+    Tokenizer::new(opf)
+        .find_map(|(_, token)| match token {
+            Token::Start { ref name, .. } if local_name(name) == "title" => name
+                .rsplit_once(':')
+                .map(|(prefix, _)| format!("{}:", prefix)),
+            _ => None,
+        })
+        .unwrap_or_else(|| String::from("dc:"))
+}
+
+// replaces all `element`s of the metadata with one for each value
+fn set_dc_elements(opf: &str, dc: &str, element: &str, values: &[String]) -> String {
+    let (opf, position) = remove_metadata(opf, |name, _| {
+        name.starts_with(dc) && local_name(name) == element
+    });
+    let elements: Vec<String> = values
+        .iter()
+        .map(|value| format!("<{}{}>{}</{}{}>", dc, element, escape(value), dc, element))
+        .collect();
+    insert_metadata(opf, position, &elements)
+}
+
+// A book is in one series, both the calibre metas and EPUB 3 collections
+// are written so every reading system finds it.
+fn set_series(opf: &str, prefix: &str, name: &str, position: Option<&str>, epub3: bool) -> String {
+    let (opf, position_in_opf) = remove_metadata(opf, |name, token| {
+        local_name(name) == "meta"
+            && (matches!(
+                token.attr("name"),
+                Some("calibre:series") | Some("calibre:series_index")
+            ) || token.attr("property") == Some("belongs-to-collection"))
+    });
+
+    let mut elements = vec![format!(
+        "<{}meta{}/>",
+        prefix,
+        attributes(&[("name", "calibre:series"), ("content", name)])
+    )];
+    if let Some(position) = position {
+        elements.push(format!(
+            "<{}meta{}/>",
+            prefix,
+            attributes(&[("name", "calibre:series_index"), ("content", position)])
+        ));
+    }
+    if epub3 {
+        let id = (0..)
+            .map(|n| match n {
+                0 => String::from("series"),
+                n => format!("series-{}", n),
+            })
+            .find(|id| !opf.contains(&format!("id=\"{}\"", id)))
+            .unwrap_or_default();
+        let refines = format!("#{}", id);
+        elements.push(format!(
+            "<{0}meta{1}>{2}</{0}meta>",
+            prefix,
+            attributes(&[("property", "belongs-to-collection"), ("id", &id)]),
+            escape(name)
+        ));
+        elements.push(format!(
+            "<{0}meta{1}>series</{0}meta>",
+            prefix,
+            attributes(&[("refines", &refines), ("property", "collection-type")])
+        ));
+        if let Some(position) = position {
+            elements.push(format!(
+                "<{0}meta{1}>{2}</{0}meta>",
+                prefix,
+                attributes(&[("refines", &refines), ("property", "group-position")]),
+                escape(position)
+            ));
+        }
+    }
+    insert_metadata(opf, position_in_opf, &elements)
+}
+
+// Removes the elements `matches` picks and the metas refining them, along
+// with their lines. Returns the document and where the first one was.
+fn remove_metadata(opf: &str, matches: impl Fn(&str, &Token) -> bool) -> (String, Option<usize>) {
+    let tokens: Vec<(usize, Token)> = Tokenizer::new(opf).collect();
+    let mut ranges = element_ranges(opf, &tokens, |name, token| matches(name, token));
+    let ids: Vec<String> = ranges.iter().filter_map(|(_, id)| id.clone()).collect();
+    ranges.extend(element_ranges(opf, &tokens, |name, token| {
+        local_name(name) == "meta"
+            && token
+                .attr("refines")
+                .map(|refines| ids.iter().any(|id| refines.trim_start_matches('#') == id))
+                .unwrap_or(false)
+    }));
+
+    let mut ranges: Vec<Range<usize>> = ranges
+        .into_iter()
+        .map(|(range, _)| {
+            // the indentation and line break before the element go too
+            let line = opf[..range.start].trim_end_matches([' ', '\t']);
+            let line = line.strip_suffix('\n').unwrap_or(line);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            line.len()..range.end
+        })
+        .collect();
+    ranges.sort_by_key(|range| std::cmp::Reverse(range.start));
+    let first = ranges.last().map(|range| range.start);
+    let mut opf = opf.to_string();
+    let mut removed_from = usize::MAX;
+    for range in ranges {
+        // skips elements inside one already removed
+        if range.end > removed_from {
+            continue;
+        }
+        removed_from = range.start;
+        opf.replace_range(range, "");
+    }
+    (opf, first)
+}
+
+// ranges of the elements `matches` picks, with their ids
+fn element_ranges(
+    opf: &str,
+    tokens: &[(usize, Token)],
+    matches: impl Fn(&str, &Token) -> bool,
+) -> Vec<(Range<usize>, Option<String>)> {
+    let tag_end = |offset: usize| {
+        opf[offset..]
+            .find('>')
+            .map(|end| offset + end + 1)
+            .unwrap_or(opf.len())
+    };
+    let mut ranges = Vec::new();
+    // element being removed, with where it starts and its id
+    let mut open: Option<(&str, usize, Option<String>)> = None;
+    for (offset, token) in tokens {
+        match token {
+            Token::Start {
+                name, self_closing, ..
+            } if open.is_none() && matches(name, token) => {
+                let id = token.attr("id").map(str::to_string);
+                if *self_closing {
+                    ranges.push((*offset..tag_end(*offset), id));
+                } else {
+                    open = Some((name, *offset, id));
+                }
+            }
+            Token::End { name } => {
+                let closes = open
+                    .as_ref()
+                    .map(|(open_name, _, _)| *open_name == name.as_str())
+                    .unwrap_or(false);
+                if closes {
+                    if let Some((_, start, id)) = open.take() {
+                        ranges.push((start..tag_end(*offset), id));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    ranges
+}
+
+// Adds elements to the metadata, at `position` or at its end.
+fn insert_metadata(mut opf: String, position: Option<usize>, elements: &[String]) -> String {
+    let position = position.or_else(|| {
+        Tokenizer::new(&opf).find_map(|(offset, token)| match token {
+            Token::End { ref name } if local_name(name) == "metadata" => {
+                Some(opf[..offset].trim_end().len())
+            }
+            _ => None,
+        })
+    });
+    if let Some(position) = position {
+        let contents: String = elements
+            .iter()
+            .map(|element| format!("\n    {}", element))
+            .collect();
+        opf.insert_str(position, &contents);
+    }
+    opf
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+fn attributes(attrs: &[(&str, &str)]) -> String {
+    attrs
+        .iter()
+        .map(|(key, value)| format!(" {}=\"{}\"", key, escape(value)))
+        .collect()
+}
@@ -0,0 +1,740 @@
+// Bookx - epub_writer.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use relm4::gtk::glib;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Seek, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::components::utils::cmp_like_nautilus;
+use crate::formats::{self, BookMetadata, BookSource, FormatError, TocEntry};
+use crate::xhtml::{
+    escape, resolve_href, to_xhtml_body, xhtml_document, ChapterText, Token, Tokenizer,
+};
+
+// every file of the book lives under this folder of the archive
+const PACKAGE_DIR: &str = "OEBPS";
+
+const CONTAINER_XML: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">
+  <rootfiles>
+    <rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>
+  </rootfiles>
+</container>
+";
+
+// path of the style sheet in the package folder
+const STYLESHEET: &str = "style.css";
+
+const DEFAULT_STYLESHEET: &str = "body {
+  margin: 0 5%;
+  line-height: 1.5;
+  text-align: justify;
+  hyphens: auto;
+}
+
+h1, h2, h3, h4, h5, h6 {
+  line-height: 1.2;
+  text-align: left;
+  page-break-after: avoid;
+}
+
+img {
+  max-width: 100%;
+}
+
+pre, code {
+  font-family: monospace;
+  white-space: pre-wrap;
+}
+
+blockquote {
+  margin: 1em 2em;
+  font-style: italic;
+}
+
+table {
+  border-collapse: collapse;
+}
+
+td, th {
+  border: 1px solid #999;
+  padding: 0.25em 0.5em;
+}
+";
+
+// Builds an EPUB 3 package, with an NCX as well for older reading systems.
+// Paths given to it are relative to the package folder.
+pub struct EpubWriter {
+    metadata: BookMetadata,
+    // path and XHTML document of every chapter, in reading order
+    chapters: Vec<(String, String)>,
+    resources: Vec<(String, Vec<u8>)>,
+    cover: Option<String>,
+    toc: Vec<TocEntry>,
+    stylesheet: bool,
+}
+
+impl EpubWriter {
+    pub fn new(metadata: BookMetadata) -> Self {
+        Self {
+            metadata,
+            chapters: Vec::new(),
+            resources: Vec::new(),
+            cover: None,
+            toc: Vec::new(),
+            stylesheet: false,
+        }
+    }
+
+    pub fn add_chapter(&mut self, path: &str, document: String) {
+        self.chapters.push((path.to_string(), document));
+    }
+
+    pub fn add_resource(&mut self, path: &str, data: Vec<u8>) {
+        if !self.resources.iter().any(|(existing, _)| existing == path) {
+            self.resources.push((path.to_string(), data));
+        }
+    }
+
+    pub fn set_cover(&mut self, data: Vec<u8>) {
+        let path = format!("cover.{}", image_extension(&data).unwrap_or("jpg"));
+        self.resources.retain(|(existing, _)| *existing != path);
+        self.resources.push((path.clone(), data));
+        self.cover = Some(path);
+    }
+
+    // entries point at chapters by the order they were added in
+    pub fn set_toc(&mut self, toc: Vec<TocEntry>) {
+        self.toc = toc;
+    }
+
+    // style sheet linked from every chapter
+    pub fn set_stylesheet(&mut self, css: String) {
+        self.resources
+            .retain(|(existing, _)| existing != STYLESHEET);
+        self.resources
+            .push((String::from(STYLESHEET), css.into_bytes()));
+        self.stylesheet = true;
+    }
+
+    // Copies a book of any format, chapters are re-serialized as XHTML and
+    // the images they reference are carried over.
+    pub fn from_source(source: &mut dyn BookSource) -> Self {
+        let mut writer = Self::new(source.metadata());
+        writer.add_sources(&mut [source]);
+        if let Some(cover) = source.cover() {
+            writer.set_cover(cover);
+        }
+        writer
+    }
+
+    // Builds a book from the Markdown and HTML files of a folder, in the
+    // order of their names. A style sheet in the folder is used instead of
+    // the default one.
+    pub fn from_folder(dir: &Path, metadata: BookMetadata) -> Result<Self, FormatError> {
+        let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        files.sort_by(|a, b| cmp_like_nautilus(&a.to_string_lossy(), &b.to_string_lossy()));
+
+        let mut sources = Vec::new();
+        let mut stylesheet = None;
+        for path in files {
+            if path
+                .extension()
+                .map(|extension| extension == "css")
+                .unwrap_or(false)
+            {
+                if stylesheet.is_none() {
+                    stylesheet = Some(fs::read_to_string(&path)?);
+                }
+                continue;
+            }
+            match formats::for_path(&path) {
+                Some(format) if format.name == "Markdown" || format.name == "HTML" => {
+                    sources.push(format.open(&path)?);
+                }
+                _ => {}
+            }
+        }
+        if sources.is_empty() {
+            return Err(FormatError::Invalid(String::from(
+                "The folder has no Markdown or HTML files",
+            )));
+        }
+
+        let mut writer = Self::new(metadata);
+        let mut sources: Vec<&mut dyn BookSource> = sources
+            .iter_mut()
+            .map(|source| -> &mut dyn BookSource { source.as_mut() })
+            .collect();
+        writer.add_sources(&mut sources);
+        writer.set_stylesheet(stylesheet.unwrap_or_else(|| String::from(DEFAULT_STYLESHEET)));
+        Ok(writer)
+    }
+
+    // Appends the chapters of every source with the images they reference,
+    // and their tables of contents. Sources have to share a folder for
+    // links between them to be kept.
+    fn add_sources(&mut self, sources: &mut [&mut dyn BookSource]) {
+        let language = self.metadata.language.clone();
+        let chapters: Vec<Vec<_>> = sources
+            .iter_mut()
+            .map(|source| {
+                (0..source.chapter_count())
+                    .filter_map(|index| source.chapter(index))
+                    .collect()
+            })
+            .collect();
+
+        // chapters keep their path unless it's missing or taken
+        let mut paths: Vec<PathBuf> = self
+            .chapters
+            .iter()
+            .map(|(path, _)| PathBuf::from(path))
+            .collect();
+        let mut renamed: HashMap<PathBuf, PathBuf> = HashMap::new();
+        let mut anchors: HashMap<(PathBuf, String), PathBuf> = HashMap::new();
+        for chapter in chapters.iter().flatten() {
+            let usable = is_document_path(&chapter.path) && !paths.contains(&chapter.path);
+            let path = if usable {
+                chapter.path.clone()
+            } else {
+                (paths.len() + 1..)
+                    .map(|number| PathBuf::from(format!("chapter{}.xhtml", number)))
+                    .find(|path| !paths.contains(path))
+                    .unwrap_or_default()
+            };
+            renamed
+                .entry(chapter.path.clone())
+                .or_insert_with(|| path.clone());
+            // a Markdown file split at its headings gives several chapters
+            // with the same path, links into it go where the anchor went
+            for id in ids(&chapter.content) {
+                anchors
+                    .entry((chapter.path.clone(), id))
+                    .or_insert_with(|| path.clone());
+            }
+            paths.push(path);
+        }
+        let mut paths = paths.into_iter().skip(self.chapters.len());
+
+        for (source, chapters) in sources.iter_mut().zip(&chapters) {
+            let first = self.chapters.len();
+            let mut titles = Vec::new();
+            for chapter in chapters {
+                let path = paths.next().unwrap_or_default();
+                let mut images = Vec::new();
+                let body = to_xhtml_body(&chapter.content, |name, attrs| {
+                    for (key, value) in attrs.iter_mut() {
+                        let is_image = key == "src" && name == "img";
+                        if !is_image && key != "href" {
+                            continue;
+                        }
+                        let (target, fragment) = match resolve_href(&chapter.path, value) {
+                            Some(resolved) => resolved,
+                            None => continue,
+                        };
+                        let target = if is_image {
+                            images.push(target.clone());
+                            target
+                        } else {
+                            let anchor = fragment.as_ref().and_then(|fragment| {
+                                anchors.get(&(target.clone(), fragment.clone()))
+                            });
+                            match anchor.or_else(|| renamed.get(&target)) {
+                                Some(target) => target.clone(),
+                                None => continue,
+                            }
+                        };
+                        let mut href = relative_href(&path, &target);
+                        if let Some(fragment) = fragment {
+                            href.push('#');
+                            href.push_str(&fragment);
+                        }
+                        *value = href;
+                    }
+                });
+                for image in images {
+                    let image_path = image.to_string_lossy().to_string();
+                    if let Some(data) = source.resource(&image) {
+                        self.add_resource(&image_path, data);
+                    }
+                }
+
+                let title = ChapterText::parse(&chapter.content).title;
+                let document = xhtml_document(
+                    title.as_deref().unwrap_or_default(),
+                    language.as_deref(),
+                    &body,
+                );
+                self.add_chapter(&path.to_string_lossy(), document);
+                titles.push(title);
+            }
+
+            let toc = source.toc();
+            let toc = if toc.is_empty() {
+                toc_from_titles(&titles)
+            } else {
+                toc
+            };
+            self.toc
+                .extend(toc.into_iter().map(|entry| offset_entry(entry, first)));
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), FormatError> {
+        if self.chapters.is_empty() {
+            return Err(FormatError::Invalid(String::from("Book has no content")));
+        }
+        let identifier = self
+            .metadata
+            .identifier
+            .clone()
+            .unwrap_or_else(|| format!("urn:uuid:{}", glib::uuid_string_random()));
+
+        let mut files = vec![
+            (
+                String::from("META-INF/container.xml"),
+                CONTAINER_XML.as_bytes().to_vec(),
+            ),
+            (
+                format!("{}/content.opf", PACKAGE_DIR),
+                self.package_document(&identifier).into_bytes(),
+            ),
+            (
+                format!("{}/nav.xhtml", PACKAGE_DIR),
+                self.navigation_document().into_bytes(),
+            ),
+            (
+                format!("{}/toc.ncx", PACKAGE_DIR),
+                self.ncx(&identifier).into_bytes(),
+            ),
+        ];
+        for (path, document) in &self.chapters {
+            let mut document = document.clone();
+            if self.stylesheet {
+                let link = format!(
+                    "<link rel=\"stylesheet\" type=\"text/css\" href=\"{}\"/>\n",
+                    relative_href(Path::new(path), Path::new(STYLESHEET))
+                );
+                if let Some(head_end) = document.find("</head>") {
+                    document.insert_str(head_end, &link);
+                }
+            }
+            files.push((format!("{}/{}", PACKAGE_DIR, path), document.into_bytes()));
+        }
+        for (path, data) in &self.resources {
+            files.push((format!("{}/{}", PACKAGE_DIR, path), data.clone()));
+        }
+        write_epub(path, &files)
+    }
+
+    fn title(&self) -> String {
+        self.metadata
+            .title
+            .clone()
+            .unwrap_or_else(|| String::from("Untitled"))
+    }
+
+    fn package_document(&self, identifier: &str) -> String {
+        let metadata = &self.metadata;
+        let mut dc = String::new();
+        dc.push_str(&format!(
+            "    <dc:identifier id=\"book-id\">{}</dc:identifier>\n",
+            escape(identifier)
+        ));
+        dc.push_str(&format!(
+            "    <dc:title>{}</dc:title>\n",
+            escape(&self.title())
+        ));
+        dc.push_str(&format!(
+            "    <dc:language>{}</dc:language>\n",
+            escape(metadata.language.as_deref().unwrap_or("und"))
+        ));
+        for author in &metadata.authors {
+            dc.push_str(&format!(
+                "    <dc:creator>{}</dc:creator>\n",
+                escape(author)
+            ));
+        }
+        for (element, value) in [
+            ("publisher", &metadata.publisher),
+            ("description", &metadata.description),
+            ("date", &metadata.date),
+        ] {
+            if let Some(value) = value {
+                dc.push_str(&format!(
+                    "    <dc:{0}>{1}</dc:{0}>\n",
+                    element,
+                    escape(value)
+                ));
+            }
+        }
+        for subject in &metadata.subjects {
+            dc.push_str(&format!(
+                "    <dc:subject>{}</dc:subject>\n",
+                escape(subject)
+            ));
+        }
+        let modified = glib::DateTime::now_utc()
+            .and_then(|now| now.format("%Y-%m-%dT%H:%M:%SZ"))
+            .map(|now| now.to_string())
+            .unwrap_or_else(|_| String::from("2000-01-01T00:00:00Z"));
+        dc.push_str(&format!(
+            "    <meta property=\"dcterms:modified\">{}</meta>\n",
+            modified
+        ));
+        if self.cover.is_some() {
+            // EPUB 2 reading systems look the cover up this way
+            dc.push_str("    <meta name=\"cover\" content=\"cover-image\"/>\n");
+        }
+
+        let mut manifest = String::from(
+            "    <item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n    \
+             <item id=\"ncx\" href=\"toc.ncx\" media-type=\"application/x-dtbncx+xml\"/>\n",
+        );
+        let mut spine = String::new();
+        for (index, (path, _)) in self.chapters.iter().enumerate() {
+            manifest.push_str(&format!(
+                "    <item id=\"chapter{}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n",
+                index + 1,
+                escape(&encode_href(path))
+            ));
+            spine.push_str(&format!("    <itemref idref=\"chapter{}\"/>\n", index + 1));
+        }
+        for (index, (path, _)) in self.resources.iter().enumerate() {
+            let (id, properties) = if self.cover.as_ref() == Some(path) {
+                (String::from("cover-image"), " properties=\"cover-image\"")
+            } else {
+                (format!("resource{}", index + 1), "")
+            };
+            manifest.push_str(&format!(
+                "    <item id=\"{}\" href=\"{}\" media-type=\"{}\"{}/>\n",
+                id,
+                escape(&encode_href(path)),
+                media_type(path),
+                properties
+            ));
+        }
+
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\">\n  \
+             <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n{}  </metadata>\n  \
+             <manifest>\n{}  </manifest>\n  \
+             <spine toc=\"ncx\">\n{}  </spine>\n\
+             </package>\n",
+            dc, manifest, spine
+        )
+    }
+
+    // href of a TOC entry, relative to the package folder
+    fn entry_href(&self, entry: &TocEntry) -> Option<String> {
+        let (path, _) = self.chapters.get(entry.chapter)?;
+        let mut href = encode_href(path);
+        if let Some(fragment) = &entry.fragment {
+            href.push('#');
+            href.push_str(fragment);
+        }
+        Some(href)
+    }
+
+    fn navigation_document(&self) -> String {
+        fn list(writer: &EpubWriter, entries: &[TocEntry], out: &mut String) {
+            out.push_str("<ol>\n");
+            for entry in entries {
+                let href = match writer.entry_href(entry) {
+                    Some(href) => href,
+                    None => continue,
+                };
+                out.push_str(&format!(
+                    "<li><a href=\"{}\">{}</a>",
+                    escape(&href),
+                    escape(&entry.label)
+                ));
+                if !entry.children.is_empty() {
+                    list(writer, &entry.children, out);
+                }
+                out.push_str("</li>\n");
+            }
+            out.push_str("</ol>\n");
+        }
+
+        let mut body = String::from("<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n");
+        list(self, &self.toc, &mut body);
+        body.push_str("</nav>");
+        xhtml_document(&self.title(), self.metadata.language.as_deref(), &body)
+    }
+
+    fn ncx(&self, identifier: &str) -> String {
+        fn points(writer: &EpubWriter, entries: &[TocEntry], order: &mut usize, out: &mut String) {
+            for entry in entries {
+                let href = match writer.entry_href(entry) {
+                    Some(href) => href,
+                    None => continue,
+                };
+                *order += 1;
+                out.push_str(&format!(
+                    "<navPoint id=\"point{0}\" playOrder=\"{0}\"><navLabel><text>{1}</text></navLabel><content src=\"{2}\"/>\n",
+                    order,
+                    escape(&entry.label),
+                    escape(&href)
+                ));
+                points(writer, &entry.children, order, out);
+                out.push_str("</navPoint>\n");
+            }
+        }
+
+        let mut nav_map = String::new();
+        points(self, &self.toc, &mut 0, &mut nav_map);
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <ncx xmlns=\"http://www.daisy.org/z3986/2005/ncx/\" version=\"2005-1\">\n\
+             <head><meta name=\"dtb:uid\" content=\"{}\"/></head>\n\
+             <docTitle><text>{}</text></docTitle>\n\
+             <navMap>\n{}</navMap>\n\
+             </ncx>\n",
+            escape(identifier),
+            escape(&self.title()),
+            nav_map
+        )
+    }
+}
+
+// Writes an EPUB archive holding `files`, through a temporary file so a
+// failure never leaves a broken book behind.
+pub(crate) fn write_epub(path: &Path, files: &[(String, Vec<u8>)]) -> Result<(), FormatError> {
+    let tmp_path = path.with_extension("tmp");
+    let result = write_archive(File::create(&tmp_path)?, files);
+    match result {
+        Ok(()) => Ok(fs::rename(tmp_path, path)?),
+        Err(e) => {
+            let _ = fs::remove_file(tmp_path);
+            Err(FormatError::Invalid(e.to_string()))
+        }
+    }
+}
+
+fn write_archive<W: Write + Seek>(
+    writer: W,
+    files: &[(String, Vec<u8>)],
+) -> zip::result::ZipResult<()> {
+    let mut zip = ZipWriter::new(writer);
+    // the mimetype has to come first and be stored uncompressed
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(b"application/epub+zip")?;
+
+    for (path, data) in files.iter().filter(|(path, _)| path != "mimetype") {
+        if path.ends_with('/') {
+            zip.add_directory(path.as_str(), deflated)?;
+            continue;
+        }
+        // images are already compressed
+        let options = if media_type(path).starts_with("image/") && !path.ends_with(".svg") {
+            stored
+        } else {
+            deflated
+        };
+        zip.start_file(path.as_str(), options)?;
+        zip.write_all(data)?;
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+// Converts the book at `path` to an EPUB written next to it, without
+// replacing any file. Returns the path of the new book.
+pub fn convert(path: &Path) -> Result<PathBuf, FormatError> {
+    convert_to(path, path.parent().unwrap_or_else(|| Path::new("")))
+}
+
+// Same as `convert`, with the EPUB written to the `dir` folder
+pub fn convert_to(path: &Path, dir: &Path) -> Result<PathBuf, FormatError> {
+    let mut source = formats::open(path)?;
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let target = available_path(dir, &stem);
+    EpubWriter::from_source(source.as_mut()).write(&target)?;
+    Ok(target)
+}
+
+// Writes a new book made of the chapters in `dir` to the `library` folder,
+// named after its title. Returns the path of the new book.
+pub fn create_book(
+    dir: &Path,
+    metadata: BookMetadata,
+    cover: Option<Vec<u8>>,
+    library: &Path,
+) -> Result<PathBuf, FormatError> {
+    let name: String = metadata
+        .title
+        .as_deref()
+        .unwrap_or("Untitled")
+        .chars()
+        .map(|c| if c == '/' || c.is_control() { '_' } else { c })
+        .collect();
+    let target = available_path(library, name.trim());
+    let mut writer = EpubWriter::from_folder(dir, metadata)?;
+    if let Some(cover) = cover {
+        writer.set_cover(cover);
+    }
+    writer.write(&target)?;
+    Ok(target)
+}
+
+// `<stem>.epub` in `dir`, or `<stem> (n).epub` if that's taken
+fn available_path(dir: &Path, stem: &str) -> PathBuf {
+    (0..)
+        .map(|n| match n {
+            0 => dir.join(format!("{}.epub", stem)),
+            n => dir.join(format!("{} ({}).epub", stem, n)),
+        })
+        .find(|target| !target.exists())
+        .unwrap_or_default()
+}
+
+// moves an entry and its children `offset` chapters further
+fn offset_entry(mut entry: TocEntry, offset: usize) -> TocEntry {
+    entry.chapter += offset;
+    entry.children = entry
+        .children
+        .into_iter()
+        .map(|child| offset_entry(child, offset))
+        .collect();
+    entry
+}
+
+// one entry per titled chapter, or per chapter if none have a title
+fn toc_from_titles(titles: &[Option<String>]) -> Vec<TocEntry> {
+    let entry = |chapter: usize, label: String| TocEntry {
+        label,
+        chapter,
+        fragment: None,
+        children: Vec::new(),
+    };
+    let toc: Vec<TocEntry> = titles
+        .iter()
+        .enumerate()
+        .filter_map(|(index, title)| Some(entry(index, title.clone()?)))
+        .collect();
+    if !toc.is_empty() {
+        return toc;
+    }
+    (0..titles.len())
+        .map(|index| entry(index, format!("Chapter {}", index + 1)))
+        .collect()
+}
+
+// ids of the elements of a chapter, the anchors links can point at
+fn ids(content: &str) -> Vec<String> {
+    Tokenizer::new(content)
+        .filter_map(|(_, token)| match token {
+            Token::Start { attrs, .. } => attrs
+                .into_iter()
+                .find(|(key, _)| key == "id")
+                .map(|(_, id)| id),
+            _ => None,
+        })
+        .collect()
+}
+
+fn is_document_path(path: &Path) -> bool {
+    let name = path.to_string_lossy().to_lowercase();
+    [".xhtml", ".html", ".htm"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+// path of `target` as written in a document at `from`
+fn relative_href(from: &Path, target: &Path) -> String {
+    let from_dir: Vec<_> = from
+        .parent()
+        .map(|dir| dir.components().collect())
+        .unwrap_or_default();
+    let target: Vec<_> = target.components().collect();
+    let common = from_dir
+        .iter()
+        .zip(&target)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut parts: Vec<String> = vec![String::from(".."); from_dir.len() - common];
+    parts.extend(
+        target[common..]
+            .iter()
+            .map(|component| component.as_os_str().to_string_lossy().to_string()),
+    );
+    encode_href(&parts.join("/"))
+}
+
+// percent-encodes the characters that can't appear as is in a URL path
+fn encode_href(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            ' ' | '%' | '#' | '?' | '"' | '<' | '>' => out.push_str(&format!("%{:02X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn media_type(path: &str) -> &'static str {
+    let extension = path.rsplit('.').next().unwrap_or_default().to_lowercase();
+    match extension.as_str() {
+        "xhtml" | "html" | "htm" => "application/xhtml+xml",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "css" => "text/css",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+// file extension of an encoded image, recognized by its first bytes
+pub fn image_extension(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("jpg")
+    } else if data.starts_with(b"\x89PNG") {
+        Some("png")
+    } else if data.starts_with(b"GIF8") {
+        Some("gif")
+    } else if data.len() > 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some("webp")
+    } else {
+        None
+    }
+}
@@ -0,0 +1,491 @@
+// Bookx - fb2.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use base64::Engine;
+use tracing::warn;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::formats::{title_from_path, BookMetadata, BookSource, Chapter, FormatError, TocEntry};
+use crate::xhtml::{escape, Token, Tokenizer};
+
+struct Fb2Chapter {
+    title: Option<String>,
+    content: String,
+}
+
+// FictionBook 2 document, each top level section of the main body is a
+// chapter and every other body (usually the notes) one more.
+pub struct Fb2Source {
+    metadata: BookMetadata,
+    cover_id: Option<String>,
+    chapters: Vec<Fb2Chapter>,
+    // images embedded as <binary>, by id
+    binaries: HashMap<String, Vec<u8>>,
+}
+
+impl Fb2Source {
+    pub fn open(path: &Path) -> Result<Self, FormatError> {
+        let data = if path.to_string_lossy().to_lowercase().ends_with(".zip") {
+            read_from_zip(path)?
+        } else {
+            fs::read(path)?
+        };
+        // TODO: books in legacy 8-bit encodings (windows-1251) need transcoding
+        let text = decode(&data);
+
+        let mut source = Fb2Parser::default().parse(&text);
+        if source.metadata.title.is_none() {
+            source.metadata.title = title_from_path(path);
+        }
+        if source.chapters.is_empty() {
+            return Err(FormatError::Invalid(String::from(
+                "FictionBook has no body",
+            )));
+        }
+        Ok(source)
+    }
+
+    fn chapter_path(index: usize) -> PathBuf {
+        PathBuf::from(format!("chapter{}.xhtml", index))
+    }
+}
+
+fn read_from_zip(path: &Path) -> Result<Vec<u8>, FormatError> {
+    let mut archive =
+        zip::ZipArchive::new(File::open(path)?).map_err(|e| FormatError::Invalid(e.to_string()))?;
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .map_err(|e| FormatError::Invalid(e.to_string()))?;
+        if entry.name().to_lowercase().ends_with(".fb2") {
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            return Ok(data);
+        }
+    }
+    Err(FormatError::Invalid(String::from(
+        "Archive doesn't contain a FictionBook",
+    )))
+}
+
+fn decode(data: &[u8]) -> String {
+    if let Some(data) = data.strip_prefix(&[0xFF, 0xFE]) {
+        let units: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        return String::from_utf16_lossy(&units);
+    }
+    let data = data.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(data);
+    String::from_utf8_lossy(data).into_owned()
+}
+
+#[derive(Default)]
+struct Fb2Parser {
+    metadata: BookMetadata,
+    cover_id: Option<String>,
+    chapters: Vec<Fb2Chapter>,
+    binaries: HashMap<String, Vec<u8>>,
+    // ids defined in each chapter, to point links at the right chapter
+    ids: HashMap<String, usize>,
+    // links written as `#id`: chapter, where the `#` is in it and the id
+    links: Vec<(usize, usize, String)>,
+}
+
+impl Fb2Parser {
+    fn parse(mut self, text: &str) -> Fb2Source {
+        let mut stack: Vec<String> = Vec::new();
+        let mut author = Vec::<String>::new();
+        let mut field = String::new();
+        let mut binary: Option<String> = None;
+        let mut body_count = 0;
+        // depth of <section> elements in the current body
+        let mut section_depth = 0usize;
+        let mut in_title = false;
+        let mut chapter_title: Option<String> = None;
+
+        for (_, token) in Tokenizer::new(text) {
+            match &token {
+                Token::Start {
+                    name, self_closing, ..
+                } => {
+                    let name = local_name(name);
+                    let in_description = stack.iter().any(|e| e == "description");
+                    let in_body = stack.iter().any(|e| e == "body");
+
+                    if name == "body" {
+                        body_count += 1;
+                        section_depth = 0;
+                        // a body beside the main one, like the notes
+                        if body_count > 1 || !*self_closing {
+                            let title = token.attr("name").map(capitalize);
+                            self.start_chapter(title);
+                        }
+                    } else if name == "binary" {
+                        binary = token.attr("id").map(str::to_string);
+                        field.clear();
+                    } else if in_description {
+                        // the annotation is made of paragraphs, keep all of them
+                        if !stack.iter().any(|e| e == "annotation") {
+                            field.clear();
+                        }
+                        if name == "image" && stack.iter().any(|e| e == "coverpage") {
+                            self.cover_id = image_href(&token);
+                        }
+                    } else if in_body {
+                        if name == "section" {
+                            section_depth += 1;
+                            // every top level section of the main body starts a chapter
+                            if section_depth == 1 && body_count == 1 {
+                                self.start_chapter(None);
+                                chapter_title = Some(String::new());
+                            }
+                        }
+                        if name == "title" {
+                            in_title = true;
+                        }
+                        self.open_element(name, &token, section_depth, *self_closing);
+                    }
+                    if !*self_closing {
+                        stack.push(name.to_string());
+                    }
+                }
+                Token::End { name } => {
+                    let name = local_name(name);
+                    let pos = match stack.iter().rposition(|e| e == name) {
+                        Some(pos) => pos,
+                        None => continue,
+                    };
+                    stack.truncate(pos);
+                    let in_description = stack.iter().any(|e| e == "description");
+                    let in_body = stack.iter().any(|e| e == "body") || name == "body";
+
+                    if name == "binary" {
+                        if let Some(id) = binary.take() {
+                            let encoded: String =
+                                field.chars().filter(|c| !c.is_whitespace()).collect();
+                            match base64::engine::general_purpose::STANDARD.decode(encoded) {
+                                Ok(data) => {
+                                    self.binaries.insert(id, data);
+                                }
+                                Err(e) => {
+                                    warn!("Unable to decode FictionBook image {:?}: {}", id, e)
+                                }
+                            }
+                        }
+                    } else if in_description {
+                        let value = field.trim().to_string();
+                        let in_title_info = stack.iter().any(|e| e == "title-info");
+                        let in_author = stack.iter().any(|e| e == "author");
+                        match name {
+                            "first-name" | "middle-name" | "last-name"
+                                if in_title_info && !value.is_empty() =>
+                            {
+                                author.push(value)
+                            }
+                            "author" if in_title_info => {
+                                if !author.is_empty() {
+                                    self.metadata.authors.push(author.join(" "));
+                                }
+                                author.clear();
+                            }
+                            "book-title" if in_title_info => self.metadata.title = Some(value),
+                            "lang" if in_title_info => self.metadata.language = Some(value),
+                            "genre" if in_title_info => self.metadata.subjects.push(value),
+                            "annotation" if in_title_info => {
+                                self.metadata.description = Some(value)
+                            }
+                            "publisher" => self.metadata.publisher = Some(value),
+                            "year" | "date" if !in_author => {
+                                self.metadata.date.get_or_insert(value);
+                            }
+                            "isbn" => {
+                                self.metadata.identifier.get_or_insert(value);
+                            }
+                            "id" if stack.iter().any(|e| e == "document-info") => {
+                                self.metadata.identifier.get_or_insert(value);
+                            }
+                            _ => {}
+                        }
+                        if stack.iter().any(|e| e == "annotation") {
+                            field.push('\n');
+                        } else {
+                            field.clear();
+                        }
+                    } else if in_body {
+                        if name == "title" {
+                            in_title = false;
+                            if section_depth == 1 {
+                                if let Some(title) = chapter_title.take() {
+                                    let title =
+                                        title.split_whitespace().collect::<Vec<_>>().join(" ");
+                                    if let Some(chapter) = self.chapters.last_mut() {
+                                        chapter.title = Some(title).filter(|t| !t.is_empty());
+                                    }
+                                }
+                            }
+                        }
+                        self.close_element(name, section_depth);
+                        if name == "section" {
+                            section_depth = section_depth.saturating_sub(1);
+                        }
+                    }
+                }
+                Token::Text(text) => {
+                    let in_body = stack.iter().any(|e| e == "body");
+                    if binary.is_some() || stack.iter().any(|e| e == "description") {
+                        field.push_str(text);
+                    } else if in_body {
+                        if in_title {
+                            if let Some(title) = chapter_title.as_mut() {
+                                title.push(' ');
+                                title.push_str(text);
+                            }
+                        }
+                        self.push(&escape(text));
+                    }
+                }
+            }
+        }
+
+        self.point_links_at_chapters();
+        Fb2Source {
+            metadata: self.metadata,
+            cover_id: self.cover_id,
+            chapters: self.chapters,
+            binaries: self.binaries,
+        }
+    }
+
+    fn start_chapter(&mut self, title: Option<String>) {
+        // content before the first section (like the body title) stays in
+        // the chapter it is in, unless there's nothing else in it
+        if let Some(last) = self.chapters.last() {
+            if last.content.trim().is_empty() {
+                self.chapters.pop();
+            }
+        }
+        self.chapters.push(Fb2Chapter {
+            title,
+            content: String::new(),
+        });
+    }
+
+    fn push(&mut self, s: &str) {
+        if self.chapters.is_empty() {
+            self.chapters.push(Fb2Chapter {
+                title: None,
+                content: String::new(),
+            });
+        }
+        self.chapters.last_mut().unwrap().content.push_str(s);
+    }
+
+    fn open_element(
+        &mut self,
+        name: &str,
+        token: &Token,
+        section_depth: usize,
+        self_closing: bool,
+    ) {
+        let id = token.attr("id").map(str::to_string);
+        if let Some(id) = &id {
+            self.ids
+                .insert(id.clone(), self.chapters.len().saturating_sub(1));
+        }
+        let id_attr = id
+            .map(|id| format!(" id=\"{}\"", escape(&id)))
+            .unwrap_or_default();
+
+        let mut link = None;
+        let html = match name {
+            "section" => format!("<div{}>", id_attr),
+            "title" => format!("<h{}{}>", (section_depth + 1).min(6), id_attr),
+            "subtitle" => format!("<h5{}>", id_attr),
+            "p" | "v" | "text-author" => format!("<p{}>", id_attr),
+            "emphasis" => String::from("<em>"),
+            "strong" => String::from("<strong>"),
+            "strikethrough" => String::from("<del>"),
+            "sub" => String::from("<sub>"),
+            "sup" => String::from("<sup>"),
+            "code" => String::from("<code>"),
+            "epigraph" | "cite" | "annotation" => format!("<blockquote{}>", id_attr),
+            "poem" | "stanza" => format!("<div{}>", id_attr),
+            "empty-line" => String::from("<br/>"),
+            "a" => {
+                let href = token
+                    .attr("l:href")
+                    .or_else(|| token.attr("xlink:href"))
+                    .or_else(|| token.attr("href"))
+                    .unwrap_or_default();
+                link = href.strip_prefix('#').map(str::to_string);
+                let note = if token.attr("type") == Some("note") {
+                    " epub:type=\"noteref\""
+                } else {
+                    ""
+                };
+                format!("<a href=\"{}\"{}{}>", escape(href), note, id_attr)
+            }
+            "image" => match image_href(token) {
+                Some(href) => format!("<img src=\"{}\" alt=\"\"/>", escape(&href)),
+                None => String::new(),
+            },
+            "table" | "tr" | "td" | "th" => format!("<{}>", name),
+            _ => String::new(),
+        };
+        let start = self
+            .chapters
+            .last()
+            .map(|chapter| chapter.content.len())
+            .unwrap_or_default();
+        self.push(&html);
+        if let Some(id) = link {
+            let at = start + "<a href=\"".len();
+            self.links.push((self.chapters.len() - 1, at, id));
+        }
+        if self_closing {
+            self.close_element(name, section_depth);
+        }
+    }
+
+    fn close_element(&mut self, name: &str, section_depth: usize) {
+        let html = match name {
+            "section" | "poem" | "stanza" => String::from("</div>"),
+            "title" => format!("</h{}>", (section_depth + 1).min(6)),
+            "subtitle" => String::from("</h5>"),
+            "p" | "v" | "text-author" => String::from("</p>"),
+            "emphasis" => String::from("</em>"),
+            "strong" => String::from("</strong>"),
+            "strikethrough" => String::from("</del>"),
+            "sub" => String::from("</sub>"),
+            "sup" => String::from("</sup>"),
+            "code" => String::from("</code>"),
+            "epigraph" | "cite" | "annotation" => String::from("</blockquote>"),
+            "a" => String::from("</a>"),
+            "table" | "tr" | "td" | "th" => format!("</{}>", name),
+            _ => String::new(),
+        };
+        self.push(&html);
+    }
+
+    // links are written as `#id`, the target may well be in another chapter
+    fn point_links_at_chapters(&mut self) {
+        // from the last, so the places of the links before it don't move
+        for (chapter, at, id) in self.links.iter().rev() {
+            match self.ids.get(id) {
+                Some(target) if target != chapter => {
+                    let path = Fb2Source::chapter_path(*target);
+                    self.chapters[*chapter]
+                        .content
+                        .insert_str(*at, &path.to_string_lossy());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+fn image_href(token: &Token) -> Option<String> {
+    token
+        .attr("l:href")
+        .or_else(|| token.attr("xlink:href"))
+        .or_else(|| token.attr("href"))
+        .map(|href| href.trim_start_matches('#').to_string())
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+impl BookSource for Fb2Source {
+    fn metadata(&self) -> BookMetadata {
+        self.metadata.clone()
+    }
+
+    fn cover(&mut self) -> Option<Vec<u8>> {
+        self.binaries.get(self.cover_id.as_ref()?).cloned()
+    }
+
+    fn toc(&self) -> Vec<TocEntry> {
+        self.chapters
+            .iter()
+            .enumerate()
+            .filter_map(|(index, chapter)| {
+                chapter.title.as_ref().map(|title| TocEntry {
+                    label: title.clone(),
+                    chapter: index,
+                    fragment: None,
+                    children: Vec::new(),
+                })
+            })
+            .collect()
+    }
+
+    fn chapter_count(&self) -> usize {
+        self.chapters.len()
+    }
+
+    fn chapter(&mut self, index: usize) -> Option<Chapter> {
+        let chapter = self.chapters.get(index)?;
+        Some(Chapter {
+            path: Self::chapter_path(index),
+            content: format!("<html><body>{}</body></html>", chapter.content),
+        })
+    }
+
+    fn resource(&mut self, path: &Path) -> Option<Vec<u8>> {
+        self.binaries.get(path.to_string_lossy().as_ref()).cloned()
+    }
+
+    fn chapter_index(&mut self, path: &Path) -> Option<usize> {
+        (0..self.chapters.len()).find(|&index| Self::chapter_path(index) == path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_point_at_the_chapter_of_their_target() {
+        let source = Fb2Parser::default().parse(
+            r##"<FictionBook><body>
+            <section><p>See <a l:href="#n1">1</a> and <a l:href="#here">here</a>.</p>
+            <p id="here">Here</p></section>
+            <section><p><a l:href="#n1">again</a></p></section>
+            </body><body name="notes"><section id="n1"><p>A note</p></section></body></FictionBook>"##,
+        );
+        assert_eq!(source.chapters.len(), 3);
+        let first = &source.chapters[0].content;
+        assert!(first.contains(r##"<a href="chapter2.xhtml#n1">1</a>"##));
+        assert!(first.contains(r##"<a href="#here">here</a>"##));
+        assert!(source.chapters[1]
+            .content
+            .contains(r##"<a href="chapter2.xhtml#n1">again</a>"##));
+    }
+}
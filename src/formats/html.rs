@@ -0,0 +1,152 @@
+// Bookx - html.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::formats::{title_from_path, BookMetadata, BookSource, Chapter, FormatError, TocEntry};
+use crate::xhtml::{Token, Tokenizer};
+
+// A standalone HTML page read as a single chapter, headings with an `id`
+// make up the table of contents.
+pub struct HtmlSource {
+    dir: PathBuf,
+    file_name: PathBuf,
+    content: String,
+    metadata: BookMetadata,
+    toc: Vec<TocEntry>,
+}
+
+impl HtmlSource {
+    pub fn open(path: &Path) -> Result<Self, FormatError> {
+        let data = fs::read(path)?;
+        let content = String::from_utf8_lossy(&data).into_owned();
+        let (mut metadata, toc) = scan(&content);
+        if metadata.title.is_none() {
+            metadata.title = title_from_path(path);
+        }
+        Ok(Self {
+            dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            file_name: PathBuf::from(path.file_name().unwrap_or_default()),
+            content,
+            metadata,
+            toc,
+        })
+    }
+}
+
+fn scan(content: &str) -> (BookMetadata, Vec<TocEntry>) {
+    let mut metadata = BookMetadata::default();
+    let mut toc = Vec::new();
+    let mut title = None::<String>;
+    let mut heading: Option<(String, String)> = None;
+
+    for (_, token) in Tokenizer::new(content) {
+        match &token {
+            Token::Start { name, .. } => match name.as_str() {
+                "html" => metadata.language = token.attr("lang").map(str::to_string),
+                "title" => title = Some(String::new()),
+                "meta" => {
+                    let content = token.attr("content").map(str::to_string);
+                    match token.attr("name").map(str::to_lowercase).as_deref() {
+                        Some("author") => metadata.authors.extend(content),
+                        Some("description") => metadata.description = content,
+                        Some("keywords") => metadata.subjects.extend(
+                            content
+                                .unwrap_or_default()
+                                .split(',')
+                                .map(|keyword| keyword.trim().to_string())
+                                .filter(|keyword| !keyword.is_empty()),
+                        ),
+                        Some("dc.identifier") => metadata.identifier = content,
+                        _ => {}
+                    }
+                }
+                "h1" | "h2" => {
+                    if let Some(id) = token.attr("id") {
+                        heading = Some((id.to_string(), String::new()));
+                    }
+                }
+                _ => {}
+            },
+            Token::End { name } => match name.as_str() {
+                "title" => {
+                    metadata.title = title
+                        .take()
+                        .map(|title| title.trim().to_string())
+                        .filter(|title| !title.is_empty());
+                }
+                "h1" | "h2" => {
+                    if let Some((id, label)) = heading.take() {
+                        toc.push(TocEntry {
+                            label: label.split_whitespace().collect::<Vec<_>>().join(" "),
+                            chapter: 0,
+                            fragment: Some(id),
+                            children: Vec::new(),
+                        });
+                    }
+                }
+                _ => {}
+            },
+            Token::Text(text) => {
+                if let Some(title) = title.as_mut() {
+                    title.push_str(text);
+                }
+                if let Some((_, label)) = heading.as_mut() {
+                    label.push_str(text);
+                }
+            }
+        }
+    }
+
+    (metadata, toc)
+}
+
+impl BookSource for HtmlSource {
+    fn metadata(&self) -> BookMetadata {
+        self.metadata.clone()
+    }
+
+    fn cover(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn toc(&self) -> Vec<TocEntry> {
+        self.toc.clone()
+    }
+
+    fn chapter_count(&self) -> usize {
+        1
+    }
+
+    fn chapter(&mut self, index: usize) -> Option<Chapter> {
+        if index != 0 {
+            return None;
+        }
+        Some(Chapter {
+            path: self.file_name.clone(),
+            content: self.content.clone(),
+        })
+    }
+
+    fn resource(&mut self, path: &Path) -> Option<Vec<u8>> {
+        fs::read(self.dir.join(path)).ok()
+    }
+
+    fn chapter_index(&mut self, path: &Path) -> Option<usize> {
+        (path == self.file_name).then_some(0)
+    }
+}
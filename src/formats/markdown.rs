@@ -0,0 +1,194 @@
+// Bookx - markdown.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use pulldown_cmark::{html, Options, Parser};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::formats::{title_from_path, BookMetadata, BookSource, Chapter, FormatError, TocEntry};
+
+struct MarkdownChapter {
+    title: Option<String>,
+    source: String,
+}
+
+// Markdown document split into chapters at every top level (`# `) heading,
+// an optional front matter block (`---` delimited `key: value` lines)
+// provides the metadata.
+pub struct MarkdownSource {
+    // directory of the file, images are looked up relative to it
+    dir: PathBuf,
+    file_name: PathBuf,
+    metadata: BookMetadata,
+    chapters: Vec<MarkdownChapter>,
+}
+
+impl MarkdownSource {
+    pub fn open(path: &Path) -> Result<Self, FormatError> {
+        let text = fs::read_to_string(path)?.replace("\r\n", "\n");
+        let (front_matter, body) = split_front_matter(&text);
+
+        let mut metadata = parse_front_matter(front_matter);
+        let chapters = split_chapters(body);
+        if metadata.title.is_none() {
+            metadata.title = chapters
+                .iter()
+                .find_map(|chapter| chapter.title.clone())
+                .or_else(|| title_from_path(path));
+        }
+
+        Ok(Self {
+            dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            file_name: PathBuf::from(path.file_name().unwrap_or_default()),
+            metadata,
+            chapters,
+        })
+    }
+}
+
+fn split_front_matter(text: &str) -> (&str, &str) {
+    if let Some(rest) = text.strip_prefix("---\n") {
+        if let Some(end) = rest.find("\n---\n") {
+            return (&rest[..end], &rest[end + 5..]);
+        }
+    }
+    ("", text)
+}
+
+pub(crate) fn parse_front_matter(front_matter: &str) -> BookMetadata {
+    let mut metadata = BookMetadata::default();
+    for line in front_matter.lines() {
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) => (key.trim().to_lowercase(), value.trim()),
+            None => continue,
+        };
+        let value = value.trim_matches(|c| c == '"' || c == '\'').to_string();
+        if value.is_empty() {
+            continue;
+        }
+        match key.as_str() {
+            "title" => metadata.title = Some(value),
+            "author" | "authors" | "creator" => metadata.authors.extend(
+                value
+                    .trim_matches(|c| c == '[' || c == ']')
+                    .split(',')
+                    .map(|author| author.trim().trim_matches('"').to_string())
+                    .filter(|author| !author.is_empty()),
+            ),
+            "language" | "lang" => metadata.language = Some(value),
+            "publisher" => metadata.publisher = Some(value),
+            "description" | "summary" => metadata.description = Some(value),
+            "date" => metadata.date = Some(value),
+            "identifier" | "id" | "isbn" => metadata.identifier = Some(value),
+            "tags" | "subjects" | "keywords" => metadata.subjects.extend(
+                value
+                    .trim_matches(|c| c == '[' || c == ']')
+                    .split(',')
+                    .map(|subject| subject.trim().to_string())
+                    .filter(|subject| !subject.is_empty()),
+            ),
+            _ => {}
+        }
+    }
+    metadata
+}
+
+fn split_chapters(body: &str) -> Vec<MarkdownChapter> {
+    let mut chapters = vec![MarkdownChapter {
+        title: None,
+        source: String::new(),
+    }];
+    let mut in_code = false;
+
+    for line in body.lines() {
+        if line.trim_start().starts_with("```") || line.trim_start().starts_with("~~~") {
+            in_code = !in_code;
+        }
+        if !in_code {
+            if let Some(title) = line.strip_prefix("# ") {
+                chapters.push(MarkdownChapter {
+                    title: Some(title.trim().trim_end_matches('#').trim().to_string()),
+                    source: String::new(),
+                });
+            }
+        }
+        let chapter = chapters.last_mut().unwrap();
+        chapter.source.push_str(line);
+        chapter.source.push('\n');
+    }
+
+    if chapters.len() > 1 && chapters[0].source.trim().is_empty() {
+        chapters.remove(0);
+    }
+    chapters
+}
+
+pub(crate) fn markdown_to_html(source: &str) -> String {
+    let parser = Parser::new_ext(source, Options::all());
+    let mut out = String::new();
+    html::push_html(&mut out, parser);
+    out
+}
+
+impl BookSource for MarkdownSource {
+    fn metadata(&self) -> BookMetadata {
+        self.metadata.clone()
+    }
+
+    fn cover(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn toc(&self) -> Vec<TocEntry> {
+        self.chapters
+            .iter()
+            .enumerate()
+            .filter_map(|(index, chapter)| {
+                chapter.title.as_ref().map(|title| TocEntry {
+                    label: title.clone(),
+                    chapter: index,
+                    fragment: None,
+                    children: Vec::new(),
+                })
+            })
+            .collect()
+    }
+
+    fn chapter_count(&self) -> usize {
+        self.chapters.len()
+    }
+
+    fn chapter(&mut self, index: usize) -> Option<Chapter> {
+        let chapter = self.chapters.get(index)?;
+        Some(Chapter {
+            path: self.file_name.clone(),
+            content: format!(
+                "<html><body>{}</body></html>",
+                markdown_to_html(&chapter.source)
+            ),
+        })
+    }
+
+    fn resource(&mut self, path: &Path) -> Option<Vec<u8>> {
+        fs::read(self.dir.join(path)).ok()
+    }
+
+    // every chapter is the same file, a link to it goes to the first
+    fn chapter_index(&mut self, path: &Path) -> Option<usize> {
+        (path == self.file_name && !self.chapters.is_empty()).then_some(0)
+    }
+}
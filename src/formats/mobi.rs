@@ -0,0 +1,873 @@
+// Bookx - mobi.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::formats::epub_writer::image_extension;
+use crate::formats::{title_from_path, BookMetadata, BookSource, Chapter, FormatError, TocEntry};
+use crate::xhtml::{to_xhtml_body, xhtml_document, ChapterText, Token, Tokenizer};
+
+// value of unset record indexes in the MOBI header
+const NULL_INDEX: u32 = 0xFFFF_FFFF;
+
+const COMPRESSION_NONE: u16 = 1;
+const COMPRESSION_PALMDOC: u16 = 2;
+const COMPRESSION_HUFFCDIC: u16 = 17480;
+
+const ENCODING_UTF8: u32 = 65001;
+
+// EXTH record types
+const EXTH_AUTHOR: u32 = 100;
+const EXTH_PUBLISHER: u32 = 101;
+const EXTH_DESCRIPTION: u32 = 103;
+const EXTH_ISBN: u32 = 104;
+const EXTH_SUBJECT: u32 = 105;
+const EXTH_DATE: u32 = 106;
+const EXTH_ASIN: u32 = 113;
+const EXTH_KF8_BOUNDARY: u32 = 121;
+const EXTH_COVER_OFFSET: u32 = 201;
+const EXTH_TITLE: u32 = 503;
+const EXTH_LANGUAGE: u32 = 524;
+
+// Mobipocket book (MOBI, AZW or AZW3), both the old MOBI 6 markup and
+// KF8 are read. Books with DRM are refused.
+pub struct MobiSource {
+    db: PalmDb,
+    metadata: BookMetadata,
+    // XHTML body of every part the book was split in
+    parts: Vec<String>,
+    toc: Vec<TocEntry>,
+    // title of every part, from its first heading
+    titles: Vec<Option<String>>,
+    first_resource: usize,
+    // index of the cover among the resources
+    cover: Option<usize>,
+}
+
+// The Palm database every Mobipocket file is wrapped in, a list of records.
+struct PalmDb {
+    data: Vec<u8>,
+    offsets: Vec<usize>,
+}
+
+impl PalmDb {
+    fn parse(data: Vec<u8>) -> Result<Self, FormatError> {
+        let count = be_u16(&data, 76).ok_or_else(invalid)? as usize;
+        let mut offsets = Vec::with_capacity(count);
+        for index in 0..count {
+            let offset = be_u32(&data, 78 + index * 8).ok_or_else(invalid)? as usize;
+            if offset > data.len() || matches!(offsets.last(), Some(&last) if offset < last) {
+                return Err(invalid());
+            }
+            offsets.push(offset);
+        }
+        Ok(Self { data, offsets })
+    }
+
+    fn record(&self, index: usize) -> Option<&[u8]> {
+        let start = *self.offsets.get(index)?;
+        let end = self
+            .offsets
+            .get(index + 1)
+            .copied()
+            .unwrap_or(self.data.len());
+        self.data.get(start..end)
+    }
+
+    fn len(&self) -> usize {
+        self.offsets.len()
+    }
+}
+
+// The parts of a MOBI header that matter here, `record` is the index of the
+// record holding it; KF8 books carry a second header further in the file.
+struct MobiHeader {
+    record: usize,
+    compression: u16,
+    text_record_count: usize,
+    utf8: bool,
+    version: u32,
+    first_resource: usize,
+    extra_flags: u16,
+    fdst: Option<usize>,
+    fragments: Option<usize>,
+    skeletons: Option<usize>,
+    full_name: String,
+    exth: Vec<(u32, Vec<u8>)>,
+}
+
+impl MobiHeader {
+    fn parse(db: &PalmDb, record: usize) -> Result<Self, FormatError> {
+        let data = db.record(record).ok_or_else(invalid)?;
+        if data.get(16..20) != Some(b"MOBI".as_slice()) {
+            return Err(invalid());
+        }
+        if be_u16(data, 12) != Some(0) {
+            return Err(FormatError::Invalid(String::from(
+                "Book is protected by DRM",
+            )));
+        }
+
+        let header_length = be_u32(data, 20).unwrap_or(0) as usize;
+        // fields past the end of an older, shorter header are unset
+        let field = |offset: usize| {
+            be_u32(data, offset)
+                .filter(|_| offset + 4 <= 16 + header_length)
+                .filter(|&value| value != NULL_INDEX)
+                .map(|value| value as usize)
+        };
+        let utf8 = be_u32(data, 28) == Some(ENCODING_UTF8);
+        let full_name = match (field(84), field(88)) {
+            (Some(offset), Some(length)) => data
+                .get(offset..offset + length)
+                .map(|name| decode_text(name, utf8))
+                .unwrap_or_default(),
+            _ => String::new(),
+        };
+        let exth = if field(128).unwrap_or(0) & 0x40 != 0 {
+            parse_exth(data.get(16 + header_length..).unwrap_or_default())
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            record,
+            compression: be_u16(data, 0).unwrap_or(COMPRESSION_NONE),
+            text_record_count: be_u16(data, 8).unwrap_or(0) as usize,
+            utf8,
+            version: be_u32(data, 36).unwrap_or(0),
+            first_resource: field(108).unwrap_or(0),
+            extra_flags: if 16 + header_length >= 0xF4 {
+                be_u16(data, 0xF2).unwrap_or(0)
+            } else {
+                0
+            },
+            // indexes in a KF8 header count from the header's record
+            fdst: field(0xC0).map(|index| index + record),
+            fragments: field(0xF8).map(|index| index + record),
+            skeletons: field(0xFC).map(|index| index + record),
+            full_name,
+            exth,
+        })
+    }
+
+    fn exth_values(&self, kind: u32) -> impl Iterator<Item = &[u8]> {
+        self.exth
+            .iter()
+            .filter(move |(record, _)| *record == kind)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    fn exth_u32(&self, kind: u32) -> Option<usize> {
+        self.exth_values(kind)
+            .next()
+            .and_then(|value| be_u32(value, 0))
+            .filter(|&value| value != NULL_INDEX)
+            .map(|value| value as usize)
+    }
+
+    // all the text records, decompressed and joined
+    fn text(&self, db: &PalmDb) -> Result<Vec<u8>, FormatError> {
+        let mut text = Vec::new();
+        for index in self.record + 1..=self.record + self.text_record_count {
+            let record = db.record(index).ok_or_else(invalid)?;
+            let record = &record[..record.len() - trailing_size(record, self.extra_flags)];
+            match self.compression {
+                COMPRESSION_NONE => text.extend_from_slice(record),
+                COMPRESSION_PALMDOC => text.extend(palmdoc_decompress(record)),
+                COMPRESSION_HUFFCDIC => {
+                    return Err(FormatError::Invalid(String::from(
+                        "Huffman compressed books are not supported",
+                    )))
+                }
+                _ => return Err(FormatError::Unsupported),
+            }
+        }
+        Ok(text)
+    }
+}
+
+impl MobiSource {
+    pub fn open(path: &Path) -> Result<Self, FormatError> {
+        let db = PalmDb::parse(fs::read(path)?)?;
+        let header = MobiHeader::parse(&db, 0)?;
+        // books made for both old and new Kindles have a KF8 part after the
+        // MOBI 6 one, it's the better of the two
+        let kf8 = match header.exth_u32(EXTH_KF8_BOUNDARY) {
+            Some(boundary) if header.version < 8 => {
+                MobiHeader::parse(&db, boundary).ok().map(|mut kf8| {
+                    // images are shared with the MOBI 6 part
+                    kf8.first_resource = header.first_resource;
+                    kf8
+                })
+            }
+            _ => None,
+        };
+        let content = kf8.as_ref().unwrap_or(&header);
+
+        let text = content.text(&db)?;
+        let parts = if content.version >= 8 {
+            kf8_parts(&db, content, &text)
+        } else {
+            mobi6_parts(&db, content, &text)
+        };
+        if parts.is_empty() {
+            return Err(FormatError::Invalid(String::from("Book has no content")));
+        }
+
+        let mut metadata = book_metadata(&header);
+        if metadata.title.is_none() {
+            metadata.title = title_from_path(path);
+        }
+        let titles: Vec<Option<String>> = parts
+            .iter()
+            .map(|part| ChapterText::parse(part).title)
+            .collect();
+        let toc = titles
+            .iter()
+            .enumerate()
+            .filter_map(|(index, title)| {
+                Some(TocEntry {
+                    label: title.clone()?,
+                    chapter: index,
+                    fragment: None,
+                    children: Vec::new(),
+                })
+            })
+            .collect();
+        let cover = header.exth_u32(EXTH_COVER_OFFSET);
+
+        Ok(Self {
+            first_resource: header.first_resource,
+            db,
+            metadata,
+            parts,
+            titles,
+            toc,
+            cover,
+        })
+    }
+
+    fn image(&self, index: usize) -> Option<&[u8]> {
+        self.db
+            .record(self.first_resource + index)
+            .filter(|data| image_extension(data).is_some())
+    }
+}
+
+fn invalid() -> FormatError {
+    FormatError::Invalid(String::from("Not a Mobipocket book"))
+}
+
+fn be_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+// older books are in CP1252, which is Latin-1 apart from 0x80..0xA0
+fn decode_text(data: &[u8], utf8: bool) -> String {
+    const CP1252: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž',
+        '\u{8F}', '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}',
+        'ž', 'Ÿ',
+    ];
+    if utf8 {
+        return String::from_utf8_lossy(data).into_owned();
+    }
+    data.iter()
+        .map(|&byte| match byte {
+            0x80..=0x9F => CP1252[(byte - 0x80) as usize],
+            byte => byte as char,
+        })
+        .collect()
+}
+
+fn parse_exth(data: &[u8]) -> Vec<(u32, Vec<u8>)> {
+    let mut records = Vec::new();
+    if !data.starts_with(b"EXTH") {
+        return records;
+    }
+    let count = be_u32(data, 8).unwrap_or(0);
+    let mut offset = 12;
+    for _ in 0..count {
+        let (kind, length) = match (be_u32(data, offset), be_u32(data, offset + 4)) {
+            (Some(kind), Some(length)) => (kind, length as usize),
+            _ => break,
+        };
+        match data.get(offset + 8..offset + length) {
+            Some(value) if length >= 8 => records.push((kind, value.to_vec())),
+            _ => break,
+        }
+        offset += length;
+    }
+    records
+}
+
+fn book_metadata(header: &MobiHeader) -> BookMetadata {
+    let text = |kind: u32| -> Vec<String> {
+        header
+            .exth_values(kind)
+            .map(|value| decode_text(value, header.utf8).trim().to_string())
+            .filter(|value| !value.is_empty())
+            .collect()
+    };
+    let first = |kind: u32| text(kind).into_iter().next();
+
+    BookMetadata {
+        identifier: first(EXTH_ISBN).or_else(|| first(EXTH_ASIN)),
+        title: first(EXTH_TITLE)
+            .or_else(|| Some(header.full_name.trim().to_string()))
+            .filter(|title| !title.is_empty()),
+        authors: text(EXTH_AUTHOR),
+        language: first(EXTH_LANGUAGE),
+        publisher: first(EXTH_PUBLISHER),
+        // descriptions are often HTML
+        description: first(EXTH_DESCRIPTION).map(|description| {
+            Tokenizer::new(&description)
+                .filter_map(|(_, token)| match token {
+                    Token::Text(text) => Some(text),
+                    _ => None,
+                })
+                .collect::<String>()
+                .trim()
+                .to_string()
+        }),
+        date: first(EXTH_DATE),
+        subjects: text(EXTH_SUBJECT),
+    }
+}
+
+// Size of the extra data appended to a text record, as announced by the
+// flags in the header. Every set bit past the first is an entry ending in
+// its own size; the first bit marks multibyte character overlap.
+fn trailing_size(record: &[u8], flags: u16) -> usize {
+    let mut size = 0;
+    let mut entries = flags >> 1;
+    while entries != 0 {
+        if entries & 1 != 0 {
+            size += backward_varint(&record[..record.len().saturating_sub(size)]);
+        }
+        entries >>= 1;
+    }
+    if flags & 1 != 0 {
+        if let Some(&byte) = record
+            .len()
+            .checked_sub(size + 1)
+            .and_then(|i| record.get(i))
+        {
+            size += (byte & 0x3) as usize + 1;
+        }
+    }
+    size.min(record.len())
+}
+
+// variable width integer read from the end, the first byte has its high bit set
+fn backward_varint(data: &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    for &byte in data.iter().rev() {
+        value |= ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 != 0 || shift >= 28 {
+            break;
+        }
+    }
+    value
+}
+
+// variable width integer read forward, the last byte has its high bit set
+fn forward_varint(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let mut value = 0u32;
+    loop {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value = (value << 7) | (byte & 0x7F) as u32;
+        if byte & 0x80 != 0 {
+            return Some(value);
+        }
+    }
+}
+
+// PalmDOC's LZ77 variant
+fn palmdoc_decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() * 2);
+    let mut i = 0;
+    while i < data.len() {
+        let byte = data[i];
+        i += 1;
+        match byte {
+            // literal run
+            0x01..=0x08 => {
+                let end = (i + byte as usize).min(data.len());
+                out.extend_from_slice(&data[i..end]);
+                i = end;
+            }
+            // back reference: 11 bits of distance, 3 bits of length
+            0x80..=0xBF => {
+                let next = match data.get(i) {
+                    Some(&next) => next,
+                    None => break,
+                };
+                i += 1;
+                let pair = (((byte as usize) << 8) | next as usize) & 0x3FFF;
+                let distance = pair >> 3;
+                let length = (pair & 0x7) + 3;
+                if distance == 0 || distance > out.len() {
+                    continue;
+                }
+                let start = out.len() - distance;
+                for k in 0..length {
+                    out.push(out[start + k]);
+                }
+            }
+            // space followed by a character
+            0xC0..=0xFF => {
+                out.push(b' ');
+                out.push(byte ^ 0x80);
+            }
+            byte => out.push(byte),
+        }
+    }
+    out
+}
+
+fn part_path(index: usize) -> String {
+    format!("part{:04}.xhtml", index + 1)
+}
+
+// path of a resource image, `index` counts from 1 like `recindex` does
+fn image_path(db: &PalmDb, first_resource: usize, index: usize) -> Option<String> {
+    let data = db.record(first_resource + index.checked_sub(1)?)?;
+    Some(format!(
+        "images/image{:05}.{}",
+        index,
+        image_extension(data)?
+    ))
+}
+
+// index of the image at `path`, counting from 0
+fn image_index(path: &Path) -> Option<usize> {
+    let name = path.strip_prefix("images").ok()?.to_str()?;
+    let number = name.strip_prefix("image")?.split('.').next()?;
+    number.parse::<usize>().ok()?.checked_sub(1)
+}
+
+fn find_ignore_case(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle))
+        .map(|position| position + from)
+}
+
+// MOBI 6 text is one long HTML stream. Links point at byte offsets in it
+// (`filepos`) and images at resource numbers (`recindex`); the stream is
+// split into parts at page breaks.
+fn mobi6_parts(db: &PalmDb, header: &MobiHeader, text: &[u8]) -> Vec<String> {
+    // offsets links point at
+    let mut targets = BTreeSet::new();
+    let mut pos = 0;
+    while let Some(found) = find_ignore_case(text, b"filepos=", pos) {
+        pos = found + 8;
+        let digits: String = text[pos..]
+            .iter()
+            .skip_while(|&&byte| byte == b'"' || byte == b'\'')
+            .take_while(|byte| byte.is_ascii_digit())
+            .map(|&byte| byte as char)
+            .collect();
+        if let Ok(target) = digits.parse::<usize>() {
+            targets.insert(target);
+        }
+    }
+
+    // an anchor is inserted at every target, before the tag it may fall in
+    let mut marked = Vec::with_capacity(text.len() + targets.len() * 32);
+    let mut copied = 0;
+    for &target in targets.iter().filter(|&&target| target < text.len()) {
+        let mut at = target;
+        if let Some(open) = text[..at].iter().rposition(|&byte| byte == b'<') {
+            if !text[open..at].contains(&b'>') {
+                at = open;
+            }
+        }
+        let at = at.max(copied);
+        marked.extend_from_slice(&text[copied..at]);
+        marked.extend_from_slice(format!("<a id=\"filepos{}\"></a>", target).as_bytes());
+        copied = at;
+    }
+    marked.extend_from_slice(&text[copied..]);
+
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while let Some(found) = find_ignore_case(&marked, b"<mbp:pagebreak", start + 1) {
+        chunks.push(&marked[start..found]);
+        start = found;
+    }
+    chunks.push(&marked[start..]);
+    let chunks: Vec<String> = chunks
+        .into_iter()
+        .map(|chunk| decode_text(chunk, header.utf8))
+        .filter(|chunk| ChapterText::parse(chunk).char_len > 0 || chunk.contains("<img"))
+        .collect();
+
+    // part every anchor ended up in
+    let mut anchors = HashMap::new();
+    for (index, chunk) in chunks.iter().enumerate() {
+        for (_, token) in Tokenizer::new(chunk) {
+            let target = token
+                .attr("id")
+                .and_then(|id| id.strip_prefix("filepos"))
+                .and_then(|target| target.parse::<usize>().ok());
+            if let Some(target) = target {
+                anchors.insert(target, index);
+            }
+        }
+    }
+
+    chunks
+        .iter()
+        .map(|chunk| {
+            to_xhtml_body(chunk, |name, attrs| {
+                let attr = |key: &str| {
+                    attrs
+                        .iter()
+                        .find(|(k, _)| k == key)
+                        .and_then(|(_, value)| value.parse::<usize>().ok())
+                };
+                let filepos = attr("filepos");
+                let recindex = attr("recindex");
+                if let Some((target, part)) =
+                    filepos.and_then(|target| Some((target, anchors.get(&target)?)))
+                {
+                    attrs.push((
+                        String::from("href"),
+                        format!("{}#filepos{}", part_path(*part), target),
+                    ));
+                }
+                if name == "img" {
+                    attrs.retain(|(key, _)| key != "src");
+                    let path =
+                        recindex.and_then(|index| image_path(db, header.first_resource, index));
+                    attrs.push((String::from("src"), path.unwrap_or_default()));
+                }
+            })
+        })
+        .collect()
+}
+
+// An index (INDX records) entry, its name and the values of every tag.
+struct IndexEntry {
+    name: String,
+    tags: HashMap<u8, Vec<u32>>,
+}
+
+fn read_index(db: &PalmDb, first: usize) -> Option<Vec<IndexEntry>> {
+    let header = db.record(first)?;
+    if !header.starts_with(b"INDX") {
+        return None;
+    }
+    let header_length = be_u32(header, 4)? as usize;
+    let record_count = be_u32(header, 24)? as usize;
+
+    // the TAGX table says which tags an entry may have and how they're encoded
+    let tagx = header.get(header_length..)?;
+    if !tagx.starts_with(b"TAGX") {
+        return None;
+    }
+    let tagx_length = be_u32(tagx, 4)? as usize;
+    let control_bytes = be_u32(tagx, 8)? as usize;
+    let tag_table: Vec<&[u8]> = tagx.get(12..tagx_length)?.chunks_exact(4).collect();
+
+    let mut entries = Vec::new();
+    for index in first + 1..=first + record_count {
+        let record = db.record(index)?;
+        if !record.starts_with(b"INDX") {
+            return None;
+        }
+        let idxt = be_u32(record, 20)? as usize;
+        let count = be_u32(record, 24)? as usize;
+        for entry in 0..count {
+            let start = be_u16(record, idxt + 4 + entry * 2)? as usize;
+            let end = match entry + 1 < count {
+                true => be_u16(record, idxt + 4 + (entry + 1) * 2)? as usize,
+                false => idxt,
+            };
+            entries.push(read_entry(
+                record.get(start..end)?,
+                control_bytes,
+                &tag_table,
+            )?);
+        }
+    }
+    Some(entries)
+}
+
+fn read_entry(data: &[u8], control_bytes: usize, tag_table: &[&[u8]]) -> Option<IndexEntry> {
+    let name_length = *data.first()? as usize;
+    let name = String::from_utf8_lossy(data.get(1..1 + name_length)?).into_owned();
+    let mut control_index = 1 + name_length;
+    let mut pos = control_index + control_bytes;
+
+    // first, how many values (or bytes of values) every tag present has
+    let mut present = Vec::new();
+    for tag in tag_table {
+        let (tag, values_per_entry, mask, end_flag) = (tag[0], tag[1], tag[2], tag[3]);
+        if end_flag == 1 {
+            control_index += 1;
+            continue;
+        }
+        let control = *data.get(control_index)?;
+        let value = control & mask;
+        if value == 0 {
+            continue;
+        }
+        if value == mask && mask.count_ones() > 1 {
+            // too many to fit in the control byte, the count follows
+            let count = forward_varint(data, &mut pos)? as usize;
+            present.push((tag, values_per_entry, None, Some(count)));
+        } else {
+            let count = (value >> mask.trailing_zeros()) as usize;
+            present.push((tag, values_per_entry, Some(count), None));
+        }
+    }
+
+    let mut tags = HashMap::new();
+    for (tag, values_per_entry, count, byte_count) in present {
+        let mut values = Vec::new();
+        match (count, byte_count) {
+            (Some(count), _) => {
+                for _ in 0..count * values_per_entry as usize {
+                    values.push(forward_varint(data, &mut pos)?);
+                }
+            }
+            (None, Some(byte_count)) => {
+                let end = pos + byte_count;
+                while pos < end {
+                    values.push(forward_varint(data, &mut pos)?);
+                }
+            }
+            (None, None) => {}
+        }
+        tags.insert(tag, values);
+    }
+    Some(IndexEntry { name, tags })
+}
+
+// KF8 text is made of flows, the first is the HTML. It's stored as
+// skeletons (the outline of every file) with fragments to insert into
+// them, which are put back together here.
+fn kf8_parts(db: &PalmDb, header: &MobiHeader, text: &[u8]) -> Vec<String> {
+    let html = header
+        .fdst
+        .and_then(|fdst| db.record(fdst))
+        .filter(|fdst| fdst.starts_with(b"FDST") && be_u32(fdst, 8).unwrap_or(0) > 0)
+        .and_then(|fdst| {
+            let start = be_u32(fdst, 12)? as usize;
+            let end = be_u32(fdst, 16)? as usize;
+            text.get(start..end)
+        })
+        .unwrap_or(text);
+
+    let skeletons = header.skeletons.and_then(|index| read_index(db, index));
+    let fragments = header.fragments.and_then(|index| read_index(db, index));
+    let (files, fragment_parts) = match (skeletons, fragments) {
+        (Some(skeletons), Some(fragments)) => assemble(html, &skeletons, &fragments),
+        _ => (vec![html.to_vec()], Vec::new()),
+    };
+
+    files
+        .iter()
+        .map(|file| {
+            to_xhtml_body(&decode_text(file, header.utf8), |_, attrs| {
+                for (key, value) in attrs.iter_mut() {
+                    if key == "src" {
+                        if let Some(path) = embed_path(db, header.first_resource, value) {
+                            *value = path;
+                        }
+                    } else if key == "href" && value.starts_with("kindle:pos:fid:") {
+                        let fragment = value
+                            .get(15..19)
+                            .and_then(base32)
+                            .and_then(|fragment| fragment_parts.get(fragment).copied());
+                        *value = fragment.map(part_path).unwrap_or_default();
+                    }
+                }
+            })
+        })
+        .collect()
+}
+
+// Puts every file back together from its skeleton and fragments, also
+// returns the file each fragment ended up in.
+fn assemble(
+    html: &[u8],
+    skeletons: &[IndexEntry],
+    fragments: &[IndexEntry],
+) -> (Vec<Vec<u8>>, Vec<usize>) {
+    let mut files = Vec::new();
+    let mut fragment_parts = Vec::new();
+    let mut fragments = fragments.iter();
+
+    for skeleton in skeletons {
+        let count = skeleton
+            .tags
+            .get(&1)
+            .and_then(|values| values.first())
+            .copied()
+            .unwrap_or(0);
+        let (start, length) = match skeleton.tags.get(&6).map(Vec::as_slice) {
+            Some([start, length, ..]) => (*start as usize, *length as usize),
+            _ => continue,
+        };
+        let mut file = match html.get(start..start + length) {
+            Some(skeleton) => skeleton.to_vec(),
+            None => continue,
+        };
+        let mut base = start + length;
+
+        for _ in 0..count {
+            let fragment = match fragments.next() {
+                Some(fragment) => fragment,
+                None => break,
+            };
+            let length = match fragment.tags.get(&6).map(Vec::as_slice) {
+                Some([_, length, ..]) => *length as usize,
+                _ => 0,
+            };
+            let content = match html.get(base..base + length) {
+                Some(content) => content,
+                None => break,
+            };
+            // fragments are inserted at a position in the whole flow
+            let insert = fragment
+                .name
+                .parse::<usize>()
+                .ok()
+                .and_then(|position| position.checked_sub(start))
+                .unwrap_or(file.len())
+                .min(file.len());
+            file.splice(insert..insert, content.iter().copied());
+            fragment_parts.push(files.len());
+            base += length;
+        }
+        files.push(file);
+    }
+    (files, fragment_parts)
+}
+
+// Kindle numbers are in base 32, with digits 0-9 and A-V
+fn base32(s: &str) -> Option<usize> {
+    usize::from_str_radix(s, 32).ok()
+}
+
+// `kindle:embed:XXXX?mime=...` references the XXXXth resource
+fn embed_path(db: &PalmDb, first_resource: usize, src: &str) -> Option<String> {
+    let number = src.strip_prefix("kindle:embed:")?.get(..4)?;
+    image_path(db, first_resource, base32(number)?)
+}
+
+impl BookSource for MobiSource {
+    fn metadata(&self) -> BookMetadata {
+        self.metadata.clone()
+    }
+
+    fn cover(&mut self) -> Option<Vec<u8>> {
+        let cover = self.cover.and_then(|index| self.image(index));
+        // without a cover offset, the first image usually is the cover
+        let cover = cover.or_else(|| {
+            (0..self.db.len().saturating_sub(self.first_resource))
+                .find_map(|index| self.image(index))
+        });
+        cover.map(<[u8]>::to_vec)
+    }
+
+    fn toc(&self) -> Vec<TocEntry> {
+        self.toc.clone()
+    }
+
+    fn chapter_count(&self) -> usize {
+        self.parts.len()
+    }
+
+    fn chapter(&mut self, index: usize) -> Option<Chapter> {
+        let body = self.parts.get(index)?;
+        Some(Chapter {
+            path: PathBuf::from(part_path(index)),
+            content: xhtml_document(
+                self.titles[index]
+                    .as_deref()
+                    .or(self.metadata.title.as_deref())
+                    .unwrap_or_default(),
+                self.metadata.language.as_deref(),
+                body,
+            ),
+        })
+    }
+
+    fn resource(&mut self, path: &Path) -> Option<Vec<u8>> {
+        self.image(image_index(path)?).map(<[u8]>::to_vec)
+    }
+
+    fn chapter_index(&mut self, path: &Path) -> Option<usize> {
+        (0..self.parts.len()).find(|&index| Path::new(&part_path(index)) == path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palmdoc_literals_and_spaces() {
+        assert_eq!(palmdoc_decompress(b"plain"), b"plain");
+        // a run of two bytes taken as they are, then a space and `a`
+        assert_eq!(palmdoc_decompress(&[0x02, 0xC3, 0xA9, 0xE1]), b"\xC3\xA9 a");
+    }
+
+    #[test]
+    fn palmdoc_back_references() {
+        // distance 3, length 3
+        assert_eq!(
+            palmdoc_decompress(&[b'a', b'b', b'c', 0x80, 0x18]),
+            b"abcabc"
+        );
+        // a reference overlapping what it writes repeats it
+        assert_eq!(palmdoc_decompress(&[b'a', 0x80, 0x09]), b"aaaaa");
+        // references before the start are skipped, a cut one ends the text
+        assert_eq!(palmdoc_decompress(&[b'a', 0x80, 0x50, b'b', 0x80]), b"ab");
+    }
+
+    #[test]
+    fn trailing_entries_are_measured_from_the_end() {
+        assert_eq!(trailing_size(b"text", 0), 0);
+        // one entry of 3 bytes, its size last
+        assert_eq!(trailing_size(b"textXY\x83", 0b10), 3);
+        // two entries, 2 bytes then 3 bytes
+        assert_eq!(trailing_size(b"textZ\x82XY\x83", 0b110), 5);
+        // multibyte overlap of one byte besides its own, before the entry
+        assert_eq!(trailing_size(b"tex\xC3\xA9\x01Z\x82", 0b11), 4);
+        assert_eq!(trailing_size(b"\x8F", 0b10), 1);
+    }
+}
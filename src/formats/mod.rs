@@ -0,0 +1,321 @@
+// Bookx - mod.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+mod comic;
+mod epub;
+pub mod epub_check;
+pub mod epub_package;
+pub mod epub_writer;
+mod fb2;
+mod html;
+mod markdown;
+mod mobi;
+mod pdf;
+mod plain_text;
+
+pub use self::epub::EpubSource;
+pub use comic::ComicSource;
+pub use fb2::Fb2Source;
+pub use html::HtmlSource;
+pub use markdown::MarkdownSource;
+pub use mobi::MobiSource;
+pub use pdf::PdfSource;
+pub use plain_text::PlainTextSource;
+
+use ::epub::doc::DocError;
+use relm4::gtk::gio::{self, prelude::*};
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default)]
+pub struct BookMetadata {
+    pub identifier: Option<String>,
+    pub title: Option<String>,
+    pub authors: Vec<String>,
+    pub language: Option<String>,
+    pub publisher: Option<String>,
+    pub description: Option<String>,
+    pub date: Option<String>,
+    pub subjects: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TocEntry {
+    pub label: String,
+    // index of the chapter the entry points to
+    pub chapter: usize,
+    pub fragment: Option<String>,
+    pub children: Vec<TocEntry>,
+}
+
+// A chapter as XHTML, relative links and images in it resolve against `path`
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    pub path: PathBuf,
+    pub content: String,
+}
+
+#[derive(Debug)]
+pub enum FormatError {
+    IOError(io::Error),
+    Epub(DocError),
+    Invalid(String),
+    Unsupported,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::IOError(e) => write!(f, "{}", e),
+            FormatError::Epub(e) => write!(f, "{:?}", e),
+            FormatError::Invalid(reason) => write!(f, "{}", reason),
+            FormatError::Unsupported => write!(f, "format is not supported"),
+        }
+    }
+}
+
+impl From<io::Error> for FormatError {
+    fn from(e: io::Error) -> Self {
+        FormatError::IOError(e)
+    }
+}
+
+impl From<DocError> for FormatError {
+    fn from(e: DocError) -> Self {
+        FormatError::Epub(e)
+    }
+}
+
+// Everything the library and the reader need from a book, whatever its
+// format. Content is always handed out as XHTML so the rest of the app
+// only ever deals with one kind of document.
+pub trait BookSource: Send {
+    fn metadata(&self) -> BookMetadata;
+    // raw bytes of the cover image, if the book has one
+    fn cover(&mut self) -> Option<Vec<u8>>;
+    fn toc(&self) -> Vec<TocEntry>;
+    fn chapter_count(&self) -> usize;
+    fn chapter(&mut self, index: usize) -> Option<Chapter>;
+    // image or other file referenced from a chapter
+    fn resource(&mut self, path: &Path) -> Option<Vec<u8>>;
+
+    // index of the chapter at `path`, used to follow links between chapters,
+    // worked out from the path without building the chapters
+    fn chapter_index(&mut self, path: &Path) -> Option<usize>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatKind {
+    // reflowable text, shown by the text reader
+    Text,
+    // a sequence of page images, shown by the image reader
+    Comic,
+    // fixed layout pages, shown by the PDF reader
+    Pdf,
+}
+
+#[derive(Debug)]
+pub struct Format {
+    pub name: &'static str,
+    pub kind: FormatKind,
+    pub content_types: &'static [&'static str],
+    // lowercase, checked against the end of the file name
+    pub extensions: &'static [&'static str],
+    // text that isn't taken for a book in the library folder unless asked
+    // to, the folder may hold notes and saved web pages too
+    pub document: bool,
+    // the file can be written to: edited, checked and given new metadata
+    pub editable: bool,
+    // the book can be converted to an EPUB
+    pub convertible: bool,
+    open: fn(&Path) -> Result<Box<dyn BookSource>, FormatError>,
+}
+
+impl Format {
+    pub fn open(&self, path: &Path) -> Result<Box<dyn BookSource>, FormatError> {
+        (self.open)(path)
+    }
+}
+
+// Registry of the formats Bookx can read, the scanner looks books up
+// by content type and everything else by file name.
+pub static FORMATS: &[Format] = &[
+    Format {
+        name: "EPUB",
+        kind: FormatKind::Text,
+        content_types: &["application/epub+zip"],
+        extensions: &[".epub"],
+        document: false,
+        editable: true,
+        convertible: false,
+        open: |path| Ok(Box::new(EpubSource::open(path)?)),
+    },
+    Format {
+        name: "FictionBook",
+        kind: FormatKind::Text,
+        content_types: &[
+            "application/x-fictionbook+xml",
+            "application/x-fictionbook",
+            "application/x-zip-compressed-fb2",
+        ],
+        extensions: &[".fb2", ".fb2.zip"],
+        document: false,
+        editable: false,
+        convertible: false,
+        open: |path| Ok(Box::new(Fb2Source::open(path)?)),
+    },
+    Format {
+        name: "MOBI",
+        kind: FormatKind::Text,
+        content_types: &[
+            "application/x-mobipocket-ebook",
+            "application/vnd.amazon.mobi8-ebook",
+            "application/vnd.amazon.ebook",
+        ],
+        extensions: &[".mobi", ".azw3", ".azw"],
+        document: false,
+        editable: false,
+        convertible: true,
+        open: |path| Ok(Box::new(MobiSource::open(path)?)),
+    },
+    Format {
+        name: "Markdown",
+        kind: FormatKind::Text,
+        content_types: &["text/markdown", "text/x-markdown"],
+        extensions: &[".md", ".markdown"],
+        document: true,
+        editable: false,
+        convertible: false,
+        open: |path| Ok(Box::new(MarkdownSource::open(path)?)),
+    },
+    Format {
+        name: "HTML",
+        kind: FormatKind::Text,
+        content_types: &["text/html", "application/xhtml+xml"],
+        extensions: &[".html", ".htm", ".xhtml"],
+        document: true,
+        editable: false,
+        convertible: false,
+        open: |path| Ok(Box::new(HtmlSource::open(path)?)),
+    },
+    Format {
+        name: "Plain Text",
+        kind: FormatKind::Text,
+        content_types: &["text/plain"],
+        extensions: &[".txt"],
+        document: true,
+        editable: false,
+        convertible: false,
+        open: |path| Ok(Box::new(PlainTextSource::open(path)?)),
+    },
+    Format {
+        name: "PDF",
+        kind: FormatKind::Pdf,
+        content_types: &["application/pdf"],
+        extensions: &[".pdf"],
+        document: false,
+        editable: false,
+        convertible: false,
+        open: |path| Ok(Box::new(PdfSource::open(path)?)),
+    },
+    Format {
+        name: "Comic Book",
+        kind: FormatKind::Comic,
+        content_types: &[
+            "application/vnd.comicbook+zip",
+            "application/x-cbz",
+            "application/vnd.comicbook-rar",
+            "application/x-cbr",
+            "application/x-cb7",
+        ],
+        extensions: &[".cbz", ".cbr", ".cb7"],
+        document: false,
+        editable: false,
+        convertible: false,
+        open: |path| Ok(Box::new(ComicSource::open(path)?)),
+    },
+];
+
+pub fn for_content_type(content_type: &str) -> Option<&'static Format> {
+    FORMATS
+        .iter()
+        .find(|format| format.content_types.contains(&content_type))
+}
+
+pub fn for_path(path: &Path) -> Option<&'static Format> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    // longest extension first so `.fb2.zip` isn't mistaken for something else
+    FORMATS
+        .iter()
+        .flat_map(|format| format.extensions.iter().map(move |ext| (format, ext)))
+        .filter(|(_, ext)| name.ends_with(*ext))
+        .max_by_key(|(_, ext)| ext.len())
+        .map(|(format, _)| format)
+}
+
+// Format of the file at `path`, by its name and then by what the file
+// manager takes it for, so books without the usual extension open too.
+pub fn detect(path: &Path) -> Option<&'static Format> {
+    for_path(path).or_else(|| {
+        let info = gio::File::for_path(path)
+            .query_info(
+                "standard::content-type",
+                gio::FileQueryInfoFlags::NONE,
+                None::<&gio::Cancellable>,
+            )
+            .ok()?;
+        for_content_type(&info.content_type()?)
+    })
+}
+
+pub fn open(path: &Path) -> Result<Box<dyn BookSource>, FormatError> {
+    match detect(path) {
+        Some(format) => format.open(path),
+        None => Err(FormatError::Unsupported),
+    }
+}
+
+// title to fall back on for formats without metadata
+pub(crate) fn title_from_path(path: &Path) -> Option<String> {
+    let mut stem = Path::new(path.file_stem()?);
+    // `.fb2.zip` is one extension, see `for_path`
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    if name.ends_with(".fb2.zip") {
+        stem = Path::new(stem.file_stem()?);
+    }
+    let stem = stem.to_string_lossy();
+    Some(stem.replace(['_', '-'], " ").trim().to_string()).filter(|title| !title.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_from_file_names() {
+        let title = |name: &str| title_from_path(Path::new(name));
+        assert_eq!(
+            title("/books/war_and-peace.txt"),
+            Some(String::from("war and peace"))
+        );
+        assert_eq!(title("/books/Dr. No.fb2.zip"), Some(String::from("Dr. No")));
+        assert_eq!(title("/books/Dr. No.FB2"), Some(String::from("Dr. No")));
+        assert_eq!(title("/books/notes"), Some(String::from("notes")));
+        assert_eq!(title("/books/_.md"), None);
+    }
+}
@@ -0,0 +1,295 @@
+// Bookx - pdf.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use poppler::ffi;
+use relm4::gtk::glib::{
+    self,
+    translate::{from_glib_full, ToGlibPtr, ToGlibPtrMut},
+};
+use tracing::warn;
+
+use std::ffi::CStr;
+use std::path::{Path, PathBuf};
+
+use crate::formats::{title_from_path, BookMetadata, BookSource, Chapter, FormatError, TocEntry};
+use crate::xhtml::escape;
+
+// width the cover is rendered at, in pixels
+const COVER_WIDTH: f64 = 360.0;
+
+// A PDF rendered with poppler. Poppler documents can't leave the thread
+// they were opened on, so the source only keeps what it read from the
+// document and opens it again when it needs more.
+pub struct PdfSource {
+    path: PathBuf,
+    metadata: BookMetadata,
+    toc: Vec<TocEntry>,
+    page_count: usize,
+    // text of every page, extracted the first time a page is asked for
+    pages: Option<Vec<String>>,
+}
+
+impl PdfSource {
+    pub fn open(path: &Path) -> Result<Self, FormatError> {
+        let document = open_document(path)?;
+        let mut metadata = document_metadata(&document);
+        if metadata.title.is_none() {
+            metadata.title = title_from_path(path);
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            metadata,
+            toc: outline(&document),
+            page_count: document.n_pages().max(0) as usize,
+            pages: None,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // a document for rendering, on the calling thread
+    pub fn document(&self) -> Result<poppler::Document, FormatError> {
+        open_document(&self.path)
+    }
+
+    fn page_text(&mut self, index: usize) -> Option<&str> {
+        if self.pages.is_none() {
+            let document = open_document(&self.path).ok()?;
+            self.pages = Some(
+                (0..document.n_pages())
+                    .map(|index| {
+                        document
+                            .page(index)
+                            .and_then(|page| page.text())
+                            .map(|text| text.to_string())
+                            .unwrap_or_default()
+                    })
+                    .collect(),
+            );
+        }
+        self.pages.as_ref()?.get(index).map(String::as_str)
+    }
+}
+
+fn open_document(path: &Path) -> Result<poppler::Document, FormatError> {
+    let uri = glib::filename_to_uri(path, None).map_err(|e| FormatError::Invalid(e.to_string()))?;
+    poppler::Document::from_file(&uri, None).map_err(|e| FormatError::Invalid(e.to_string()))
+}
+
+// metadata from the document info dictionary
+fn document_metadata(document: &poppler::Document) -> BookMetadata {
+    let text = |value: Option<glib::GString>| {
+        value
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    BookMetadata {
+        identifier: None,
+        title: text(document.title()),
+        authors: text(document.author())
+            .map(|authors| {
+                authors
+                    .split([',', ';', '&'])
+                    .map(|author| author.trim().to_string())
+                    .filter(|author| !author.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        language: None,
+        publisher: text(document.creator()),
+        description: text(document.subject()),
+        // seconds since the epoch, -1 when the document has no date
+        date: Some(document.creation_date() as i64)
+            .filter(|&timestamp| timestamp > 0)
+            .and_then(|timestamp| glib::DateTime::from_unix_utc(timestamp).ok())
+            .and_then(|date| date.format("%Y-%m-%d").ok())
+            .map(|date| date.to_string()),
+        subjects: text(document.keywords())
+            .map(|keywords| {
+                keywords
+                    .split([',', ';'])
+                    .map(|keyword| keyword.trim().to_string())
+                    .filter(|keyword| !keyword.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default(),
+    }
+}
+
+// poppler-rs doesn't bind outline actions, so the outline is read
+// through the C API
+fn outline(document: &poppler::Document) -> Vec<TocEntry> {
+    // null when the document has no outline
+    let iter: Option<poppler::IndexIter> =
+        unsafe { from_glib_full(ffi::poppler_index_iter_new(document.to_glib_none().0)) };
+    match iter {
+        Some(mut iter) => outline_entries(document, &mut iter),
+        None => Vec::new(),
+    }
+}
+
+fn outline_entries(document: &poppler::Document, iter: &mut poppler::IndexIter) -> Vec<TocEntry> {
+    let mut entries = Vec::new();
+    loop {
+        let children = iter
+            .child()
+            .map(|mut child| outline_entries(document, &mut child))
+            .unwrap_or_default();
+        match destination(document, iter) {
+            Some((label, chapter)) => entries.push(TocEntry {
+                label,
+                chapter,
+                fragment: None,
+                children,
+            }),
+            // keep what's below an entry that doesn't link anywhere
+            None => entries.extend(children),
+        }
+        if !iter.next() {
+            break;
+        }
+    }
+    entries
+}
+
+// title and page index of an outline entry, only entries pointing at a
+// page of this document are kept
+fn destination(
+    document: &poppler::Document,
+    iter: &mut poppler::IndexIter,
+) -> Option<(String, usize)> {
+    unsafe {
+        let action = ffi::poppler_index_iter_get_action(iter.to_glib_none_mut().0);
+        if action.is_null() {
+            return None;
+        }
+        let entry = goto_destination(document, &*action);
+        ffi::poppler_action_free(action);
+        entry
+    }
+}
+
+unsafe fn goto_destination(
+    document: &poppler::Document,
+    action: &ffi::PopplerAction,
+) -> Option<(String, usize)> {
+    if action.type_ != ffi::POPPLER_ACTION_GOTO_DEST {
+        return None;
+    }
+    let goto = &action.goto_dest;
+    if goto.title.is_null() || goto.dest.is_null() {
+        return None;
+    }
+    let title = CStr::from_ptr(goto.title)
+        .to_string_lossy()
+        .trim()
+        .to_string();
+
+    let dest = &*goto.dest;
+    let page_num = if dest.type_ == ffi::POPPLER_DEST_NAMED {
+        if dest.named_dest.is_null() {
+            return None;
+        }
+        let named = ffi::poppler_document_find_dest(document.to_glib_none().0, dest.named_dest);
+        if named.is_null() {
+            return None;
+        }
+        let page_num = (*named).page_num;
+        ffi::poppler_dest_free(named);
+        page_num
+    } else {
+        dest.page_num
+    };
+    // page numbers start at 1
+    let page = usize::try_from(page_num - 1).ok()?;
+    Some((title, page))
+}
+
+// Renders the first page to PNG, PDFs have no separate cover image.
+fn render_cover(path: &Path) -> Option<Vec<u8>> {
+    let document = open_document(path).ok()?;
+    let page = document.page(0)?;
+    let (width, height) = page.size();
+    let scale = COVER_WIDTH / width.max(1.0);
+
+    let surface = cairo::ImageSurface::create(
+        cairo::Format::Rgb24,
+        (width * scale).ceil() as i32,
+        (height * scale).ceil() as i32,
+    )
+    .ok()?;
+    {
+        let context = cairo::Context::new(&surface).ok()?;
+        context.set_source_rgb(1.0, 1.0, 1.0);
+        context.paint().ok()?;
+        context.scale(scale, scale);
+        page.render(&context);
+    }
+
+    let mut png = Vec::new();
+    match surface.write_to_png(&mut png) {
+        Ok(()) => Some(png),
+        Err(e) => {
+            warn!("Unable to render cover of {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+impl BookSource for PdfSource {
+    fn metadata(&self) -> BookMetadata {
+        self.metadata.clone()
+    }
+
+    fn cover(&mut self) -> Option<Vec<u8>> {
+        render_cover(&self.path)
+    }
+
+    fn toc(&self) -> Vec<TocEntry> {
+        self.toc.clone()
+    }
+
+    fn chapter_count(&self) -> usize {
+        self.page_count
+    }
+
+    // every page is a chapter, its lines kept as they are laid out
+    fn chapter(&mut self, index: usize) -> Option<Chapter> {
+        let text = self.page_text(index)?;
+        let lines: Vec<String> = text.lines().map(escape).collect();
+        Some(Chapter {
+            path: PathBuf::from(format!("page{}.xhtml", index + 1)),
+            content: format!("<html><body><p>{}</p></body></html>", lines.join("<br/>")),
+        })
+    }
+
+    fn resource(&mut self, _path: &Path) -> Option<Vec<u8>> {
+        None
+    }
+
+    // pages are counted from one in their paths
+    fn chapter_index(&mut self, path: &Path) -> Option<usize> {
+        let page: usize = path
+            .to_str()?
+            .strip_prefix("page")?
+            .strip_suffix(".xhtml")?
+            .parse()
+            .ok()?;
+        (1..=self.page_count).contains(&page).then(|| page - 1)
+    }
+}
@@ -0,0 +1,162 @@
+// Bookx - plain_text.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::formats::{title_from_path, BookMetadata, BookSource, Chapter, FormatError, TocEntry};
+use crate::xhtml::escape;
+
+// a short paragraph starting with one of these begins a new chapter
+const CHAPTER_MARKERS: &[&str] = &["chapter ", "part ", "book ", "prologue", "epilogue"];
+const MAX_HEADING_CHARS: usize = 60;
+
+struct TextChapter {
+    title: Option<String>,
+    paragraphs: Vec<String>,
+}
+
+pub struct PlainTextSource {
+    title: Option<String>,
+    chapters: Vec<TextChapter>,
+}
+
+impl PlainTextSource {
+    pub fn open(path: &Path) -> Result<Self, FormatError> {
+        let data = fs::read(path)?;
+        let text = String::from_utf8_lossy(&data).replace("\r\n", "\n");
+        Ok(Self {
+            title: title_from_path(path),
+            chapters: split_chapters(&text),
+        })
+    }
+}
+
+fn is_chapter_heading(paragraph: &str) -> bool {
+    let lower = paragraph.to_lowercase();
+    paragraph.chars().count() <= MAX_HEADING_CHARS
+        && !paragraph.contains('\n')
+        && CHAPTER_MARKERS
+            .iter()
+            .any(|marker| lower.starts_with(marker))
+}
+
+fn split_chapters(text: &str) -> Vec<TextChapter> {
+    let mut chapters = vec![TextChapter {
+        title: None,
+        paragraphs: Vec::new(),
+    }];
+
+    for paragraph in text.split("\n\n") {
+        let paragraph = paragraph.trim();
+        if paragraph.is_empty() {
+            continue;
+        }
+        if is_chapter_heading(paragraph) {
+            chapters.push(TextChapter {
+                title: Some(paragraph.to_string()),
+                paragraphs: Vec::new(),
+            });
+            continue;
+        }
+        chapters
+            .last_mut()
+            .unwrap()
+            .paragraphs
+            .push(paragraph.to_string());
+    }
+
+    // drop the implicit first chapter when the text starts with a heading
+    if chapters.len() > 1 && chapters[0].paragraphs.is_empty() {
+        chapters.remove(0);
+    }
+    chapters
+}
+
+impl BookSource for PlainTextSource {
+    fn metadata(&self) -> BookMetadata {
+        BookMetadata {
+            title: self.title.clone(),
+            ..BookMetadata::default()
+        }
+    }
+
+    fn cover(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn toc(&self) -> Vec<TocEntry> {
+        self.chapters
+            .iter()
+            .enumerate()
+            .filter_map(|(index, chapter)| {
+                chapter.title.as_ref().map(|title| TocEntry {
+                    label: title.clone(),
+                    chapter: index,
+                    fragment: None,
+                    children: Vec::new(),
+                })
+            })
+            .collect()
+    }
+
+    fn chapter_count(&self) -> usize {
+        self.chapters.len()
+    }
+
+    fn chapter(&mut self, index: usize) -> Option<Chapter> {
+        let chapter = self.chapters.get(index)?;
+        let mut content = String::from("<html><body>");
+        if let Some(title) = &chapter.title {
+            content.push_str(&format!("<h2>{}</h2>", escape(title)));
+        }
+        for paragraph in &chapter.paragraphs {
+            // lines of a paragraph are usually hard wrapped, except for
+            // things like poems which indent their lines
+            let lines: Vec<&str> = paragraph.lines().collect();
+            let joined = if lines.iter().skip(1).any(|line| line.starts_with(' ')) {
+                lines
+                    .iter()
+                    .map(|line| escape(line.trim()))
+                    .collect::<Vec<_>>()
+                    .join("<br/>")
+            } else {
+                escape(&lines.join(" "))
+            };
+            content.push_str(&format!("<p>{}</p>", joined));
+        }
+        content.push_str("</body></html>");
+
+        Some(Chapter {
+            path: PathBuf::from(format!("chapter{}.xhtml", index)),
+            content,
+        })
+    }
+
+    fn resource(&mut self, _path: &Path) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn chapter_index(&mut self, path: &Path) -> Option<usize> {
+        let index: usize = path
+            .to_str()?
+            .strip_prefix("chapter")?
+            .strip_suffix(".xhtml")?
+            .parse()
+            .ok()?;
+        (index < self.chapters.len()).then_some(index)
+    }
+}
@@ -0,0 +1,715 @@
+// Bookx - library_db.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::gettext;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{error, info};
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::storage;
+
+// bump when the layout changes in a way older files can't be read
+const DB_VERSION: u32 = 1;
+
+// What the library knows about books beyond their own metadata: tags and
+// the custom fields the user defined, keyed on the path of the book.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LibraryDb {
+    version: u32,
+    pub fields: Vec<CustomField>,
+    pub books: BTreeMap<String, BookRecord>,
+    // whether tags and custom fields are written to EPUB files too
+    #[serde(default)]
+    pub write_to_books: bool,
+    // every time a book was read, oldest first
+    #[serde(default)]
+    pub sessions: Vec<ReadingSession>,
+    // the books found by the last scan of the library, for searches made
+    // while the window isn't open
+    #[serde(default)]
+    pub catalog: BTreeMap<String, CatalogEntry>,
+    // saved filters, in the order they were made
+    #[serde(default)]
+    pub shelves: Vec<Shelf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FieldKind {
+    Text,
+    Number,
+    Date,
+    Boolean,
+    Enum,
+}
+
+pub const FIELD_KINDS: [FieldKind; 5] = [
+    FieldKind::Text,
+    FieldKind::Number,
+    FieldKind::Date,
+    FieldKind::Boolean,
+    FieldKind::Enum,
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomField {
+    // lookup name, calibre's `#name` without the hash
+    pub name: String,
+    pub label: String,
+    pub kind: FieldKind,
+    // what an enum field can be set to
+    #[serde(default)]
+    pub values: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FieldValue {
+    Text(String),
+    Number(f64),
+    // as `YYYY-MM-DD` so dates sort as text
+    Date(String),
+    Boolean(bool),
+    Enum(String),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BookRecord {
+    pub tags: Vec<String>,
+    // values by field name
+    pub fields: BTreeMap<String, FieldValue>,
+    // metadata brought in from a calibre library, shown in place of the
+    // book's own
+    #[serde(default)]
+    pub imported: Option<ImportedMetadata>,
+    #[serde(default)]
+    pub progress: Option<ReadingProgress>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportedMetadata {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub series: Option<String>,
+    pub series_index: f64,
+    // out of five stars
+    pub rating: Option<f64>,
+    pub cover: Option<PathBuf>,
+}
+
+// A filter saved under a name, shown as a shelf of the library.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Shelf {
+    pub name: String,
+    // as typed, see `Filter`
+    pub filter: String,
+}
+
+// a book the way the library shows it
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub cover: Option<PathBuf>,
+}
+
+// Text is measured in characters, comics and PDFs in pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ProgressUnit {
+    Characters,
+    Pages,
+}
+
+// how far into a book the reader got
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadingProgress {
+    pub position: u64,
+    pub length: u64,
+    pub unit: ProgressUnit,
+    // when the end was first reached, in seconds since the epoch
+    pub finished: Option<i64>,
+}
+
+impl ReadingProgress {
+    // shown under the cover, in whole percents
+    pub fn percent(&self) -> f64 {
+        if self.length == 0 {
+            return 0.0;
+        }
+        (self.position.min(self.length) * 100 / self.length) as f64
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadingSession {
+    pub path: String,
+    // seconds since the epoch
+    pub start: i64,
+    pub end: i64,
+    pub unit: ProgressUnit,
+    // characters or pages read past during the session
+    pub advanced: u64,
+    // where the session ended, zero when the length of the book is unknown
+    pub position: u64,
+    pub length: u64,
+}
+
+impl ReadingSession {
+    // in seconds
+    pub fn duration(&self) -> i64 {
+        (self.end - self.start).max(0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SortKey {
+    FileName,
+    Title,
+    Field(String),
+}
+
+impl LibraryDb {
+    pub fn file_path() -> PathBuf {
+        storage::data_dir().join("library.json")
+    }
+
+    pub fn load() -> Self {
+        let db: Self = storage::load_json(&Self::file_path());
+        if db.version != DB_VERSION {
+            info!("Library database is missing or of another version, starting afresh");
+            return Self {
+                version: DB_VERSION,
+                ..Self::default()
+            };
+        }
+        db
+    }
+
+    pub fn save(&self) {
+        if let Err(e) = storage::save_json(&Self::file_path(), self) {
+            error!("Unable to save the library database: {:?}", e);
+        }
+    }
+
+    pub fn field(&self, name: &str) -> Option<&CustomField> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+
+    // Replaces the field definitions, values of fields that are gone or
+    // changed type are dropped from every book.
+    pub fn set_fields(&mut self, fields: Vec<CustomField>) {
+        self.fields = fields;
+        let fields = &self.fields;
+        for record in self.books.values_mut() {
+            record.fields.retain(|name, value| {
+                fields
+                    .iter()
+                    .find(|field| field.name == *name)
+                    .map(|field| field.kind == value.kind())
+                    .unwrap_or(false)
+            });
+        }
+    }
+
+    // Saves `filter` as the shelf `name`, in place of one of the same name.
+    // Returns where the shelf is.
+    pub fn save_shelf(&mut self, name: &str, filter: &str) -> usize {
+        let shelf = Shelf {
+            name: name.to_string(),
+            filter: filter.to_string(),
+        };
+        match self.shelves.iter().position(|other| other.name == name) {
+            Some(index) => {
+                self.shelves[index] = shelf;
+                index
+            }
+            None => {
+                self.shelves.push(shelf);
+                self.shelves.len() - 1
+            }
+        }
+    }
+
+    pub fn set_record(&mut self, path: &str, record: BookRecord) {
+        if record.tags.is_empty()
+            && record.fields.is_empty()
+            && record.imported.is_none()
+            && record.progress.is_none()
+        {
+            self.books.remove(path);
+        } else {
+            self.books.insert(path.to_string(), record);
+        }
+    }
+
+    // Keeps the session and moves the book's progress to where it ended.
+    // Books count as finished once all but the last percent is read.
+    pub fn record_session(&mut self, session: ReadingSession) {
+        if session.length > 0 {
+            let record = self.books.entry(session.path.clone()).or_default();
+            let finished = record
+                .progress
+                .as_ref()
+                .and_then(|progress| progress.finished)
+                .or_else(|| (session.position * 100 >= session.length * 99).then_some(session.end));
+            record.progress = Some(ReadingProgress {
+                position: session.position,
+                length: session.length,
+                unit: session.unit,
+                finished,
+            });
+        }
+        self.sessions.push(session);
+    }
+
+    // Moves a book's progress to `percent` of its length, which is only
+    // known once it's been read. Returns whether it was.
+    pub fn set_progress(&mut self, path: &str, percent: f64, now: i64) -> bool {
+        let progress = match self
+            .books
+            .get_mut(path)
+            .and_then(|record| record.progress.as_mut())
+        {
+            Some(progress) if progress.length > 0 => progress,
+            _ => return false,
+        };
+        let percent = percent.clamp(0.0, 100.0);
+        progress.position = (progress.length as f64 * percent / 100.0).round() as u64;
+        progress.finished = if percent >= 99.0 {
+            progress.finished.or(Some(now))
+        } else {
+            None
+        };
+        true
+    }
+
+    pub fn add_tag(&mut self, path: &str, tag: &str) {
+        let record = self.books.entry(path.to_string()).or_default();
+        if !record.tags.iter().any(|existing| existing == tag) {
+            record.tags.push(tag.to_string());
+        }
+    }
+
+    // Custom fields of a book the way calibre keeps them in the package
+    // document, as the `#name` lookup name and a JSON description.
+    pub fn calibre_user_metadata(&self, path: &str) -> Vec<(String, String)> {
+        let record = match self.books.get(path) {
+            Some(record) => record,
+            None => return Vec::new(),
+        };
+        self.fields
+            .iter()
+            .filter_map(|field| {
+                let value = record.fields.get(&field.name)?;
+                let (datatype, value) = match value {
+                    FieldValue::Text(text) => ("text", json!(text)),
+                    FieldValue::Number(number) => ("float", json!(number)),
+                    FieldValue::Date(date) => (
+                        "datetime",
+                        json!({
+                            "__class__": "datetime.datetime",
+                            "__value__": format!("{}T00:00:00+00:00", date),
+                        }),
+                    ),
+                    FieldValue::Boolean(set) => ("bool", json!(set)),
+                    FieldValue::Enum(choice) => ("enumeration", json!(choice)),
+                };
+                let display = match field.kind {
+                    FieldKind::Enum => json!({ "enum_values": field.values }),
+                    _ => json!({}),
+                };
+                let lookup = format!("#{}", field.name);
+                let description = json!({
+                    "label": field.name,
+                    "name": field.label,
+                    "datatype": datatype,
+                    "#value#": value,
+                    "#extra#": null,
+                    "is_multiple": {},
+                    "is_custom": true,
+                    "is_editable": true,
+                    "is_category": false,
+                    "is_csp": false,
+                    "kind": "field",
+                    "search_terms": [lookup],
+                    "display": display,
+                });
+                Some((lookup, description.to_string()))
+            })
+            .collect()
+    }
+
+    // Orders books given as `(path, title)`, books without a value for the
+    // field go last.
+    pub fn compare(&self, key: &SortKey, a: (&str, &str), b: (&str, &str)) -> Ordering {
+        let by_title = || a.1.to_lowercase().cmp(&b.1.to_lowercase());
+        match key {
+            SortKey::FileName => crate::components::utils::cmp_like_nautilus(a.0, b.0),
+            SortKey::Title => by_title(),
+            SortKey::Field(name) => {
+                let value = |path: &str| {
+                    self.books
+                        .get(path)
+                        .and_then(|record| record.fields.get(name))
+                };
+                match (value(a.0), value(b.0)) {
+                    (Some(first), Some(second)) => first.compare(second).then_with(by_title),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => by_title(),
+                }
+            }
+        }
+    }
+}
+
+impl FieldKind {
+    pub fn label(&self) -> String {
+        match self {
+            FieldKind::Text => gettext("Text"),
+            FieldKind::Number => gettext("Number"),
+            FieldKind::Date => gettext("Date"),
+            FieldKind::Boolean => gettext("Yes or No"),
+            FieldKind::Enum => gettext("Choice"),
+        }
+    }
+}
+
+impl FieldValue {
+    pub fn kind(&self) -> FieldKind {
+        match self {
+            FieldValue::Text(_) => FieldKind::Text,
+            FieldValue::Number(_) => FieldKind::Number,
+            FieldValue::Date(_) => FieldKind::Date,
+            FieldValue::Boolean(_) => FieldKind::Boolean,
+            FieldValue::Enum(_) => FieldKind::Enum,
+        }
+    }
+
+    // Reads a value of `field` as typed in, booleans take yes and no too.
+    pub fn parse(field: &CustomField, text: &str) -> Option<Self> {
+        let text = text.trim();
+        match field.kind {
+            _ if text.is_empty() => None,
+            FieldKind::Text => Some(FieldValue::Text(text.to_string())),
+            FieldKind::Number => text.parse().ok().map(FieldValue::Number),
+            FieldKind::Date => is_date(text).then(|| FieldValue::Date(text.to_string())),
+            FieldKind::Boolean => match text.to_lowercase().as_str() {
+                "yes" | "true" | "1" => Some(FieldValue::Boolean(true)),
+                "no" | "false" | "0" => Some(FieldValue::Boolean(false)),
+                _ => None,
+            },
+            FieldKind::Enum => field
+                .values
+                .iter()
+                .find(|value| value.to_lowercase() == text.to_lowercase())
+                .map(|value| FieldValue::Enum(value.clone())),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        match self {
+            FieldValue::Text(text) | FieldValue::Date(text) | FieldValue::Enum(text) => {
+                text.clone()
+            }
+            FieldValue::Number(number) if number.fract() == 0.0 => format!("{}", *number as i64),
+            FieldValue::Number(number) => number.to_string(),
+            FieldValue::Boolean(true) => String::from("yes"),
+            FieldValue::Boolean(false) => String::from("no"),
+        }
+    }
+
+    fn compare(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (FieldValue::Number(a), FieldValue::Number(b)) => {
+                a.partial_cmp(b).unwrap_or(Ordering::Equal)
+            }
+            (FieldValue::Boolean(a), FieldValue::Boolean(b)) => a.cmp(b),
+            _ => self
+                .to_text()
+                .to_lowercase()
+                .cmp(&other.to_text().to_lowercase()),
+        }
+    }
+}
+
+// `YYYY-MM-DD`, with a month and day that can exist
+fn is_date(text: &str) -> bool {
+    let parts: Vec<&str> = text.split('-').collect();
+    match parts.as_slice() {
+        [year, month, day] if year.len() == 4 && month.len() == 2 && day.len() == 2 => {
+            match (
+                year.parse::<u32>(),
+                month.parse::<u32>(),
+                day.parse::<u32>(),
+            ) {
+                (Ok(_), Ok(month), Ok(day)) => (1..=12).contains(&month) && (1..=31).contains(&day),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+// A filter typed over the library. Every term has to match:
+// `tag:work` books tagged work, `#priority:high` a field containing or
+// equal to a value, `#due<2024-01-01` and `#pages>100` comparisons, and
+// other words are looked for in titles, tags, and imported authors and series.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    terms: Vec<Term>,
+}
+
+#[derive(Debug, Clone)]
+enum Term {
+    Tag(String),
+    Field(String, Ordering, String),
+    FieldContains(String, String),
+    Word(String),
+}
+
+impl Filter {
+    pub fn parse(text: &str) -> Self {
+        let terms = text
+            .split_whitespace()
+            .map(|term| {
+                if let Some(tag) = term.strip_prefix("tag:") {
+                    return Term::Tag(tag.to_lowercase());
+                }
+                if let Some(rest) = term.strip_prefix('#') {
+                    for (separator, ordering) in [
+                        ('<', Ordering::Less),
+                        ('>', Ordering::Greater),
+                        ('=', Ordering::Equal),
+                    ] {
+                        if let Some((name, value)) = rest.split_once(separator) {
+                            return Term::Field(
+                                name.to_lowercase(),
+                                ordering,
+                                value.to_lowercase(),
+                            );
+                        }
+                    }
+                    if let Some((name, value)) = rest.split_once(':') {
+                        return Term::FieldContains(name.to_lowercase(), value.to_lowercase());
+                    }
+                }
+                Term::Word(term.to_lowercase())
+            })
+            .collect();
+        Self { terms }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn matches(&self, db: &LibraryDb, path: &str, title: &str) -> bool {
+        let record = db.books.get(path);
+        let tags: Vec<String> = record
+            .map(|record| record.tags.iter().map(|tag| tag.to_lowercase()).collect())
+            .unwrap_or_default();
+        // authors and series of books imported from calibre
+        let imported: Vec<String> = record
+            .and_then(|record| record.imported.as_ref())
+            .map(|imported| {
+                imported
+                    .authors
+                    .iter()
+                    .chain(&imported.series)
+                    .map(|text| text.to_lowercase())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let value = |name: &str| {
+            let field = db.field(name)?;
+            record?.fields.get(&field.name).map(|value| (field, value))
+        };
+
+        self.terms.iter().all(|term| match term {
+            Term::Tag(tag) => tags.iter().any(|other| other == tag),
+            Term::Word(word) => {
+                title.to_lowercase().contains(word.as_str())
+                    || tags.iter().any(|tag| tag.contains(word.as_str()))
+                    || imported.iter().any(|text| text.contains(word.as_str()))
+            }
+            Term::FieldContains(name, wanted) => match value(name) {
+                Some((_, FieldValue::Text(text))) => text.to_lowercase().contains(wanted.as_str()),
+                Some((field, value)) => FieldValue::parse(field, wanted).as_ref() == Some(value),
+                None => false,
+            },
+            Term::Field(name, ordering, wanted) => match value(name) {
+                Some((field, value)) => FieldValue::parse(field, wanted)
+                    .map(|wanted| value.compare(&wanted) == *ordering)
+                    .unwrap_or(false),
+                None => false,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, kind: FieldKind) -> CustomField {
+        CustomField {
+            name: name.to_string(),
+            label: name.to_string(),
+            kind,
+            values: vec![String::from("High"), String::from("Low")],
+        }
+    }
+
+    fn library() -> LibraryDb {
+        let fields = BTreeMap::from([
+            (
+                String::from("priority"),
+                FieldValue::Enum(String::from("High")),
+            ),
+            (String::from("pages"), FieldValue::Number(320.0)),
+            (
+                String::from("due"),
+                FieldValue::Date(String::from("2023-06-01")),
+            ),
+            (
+                String::from("notes"),
+                FieldValue::Text(String::from("Second Draft")),
+            ),
+        ]);
+        let record = BookRecord {
+            tags: vec![String::from("Work"), String::from("Rust")],
+            fields,
+            imported: Some(ImportedMetadata {
+                authors: vec![String::from("Ursula K. Le Guin")],
+                series: Some(String::from("Earthsea")),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        LibraryDb {
+            fields: vec![
+                field("priority", FieldKind::Enum),
+                field("pages", FieldKind::Number),
+                field("due", FieldKind::Date),
+                field("notes", FieldKind::Text),
+            ],
+            books: BTreeMap::from([(String::from("/books/a.epub"), record)]),
+            ..Default::default()
+        }
+    }
+
+    fn matches(filter: &str) -> bool {
+        Filter::parse(filter).matches(&library(), "/books/a.epub", "A Wizard of Earthsea")
+    }
+
+    #[test]
+    fn field_values_are_read_by_kind() {
+        let number = field("pages", FieldKind::Number);
+        assert_eq!(
+            FieldValue::parse(&number, " 320 "),
+            Some(FieldValue::Number(320.0))
+        );
+        assert_eq!(FieldValue::parse(&number, "many"), None);
+
+        let date = field("due", FieldKind::Date);
+        assert_eq!(
+            FieldValue::parse(&date, "2024-02-29"),
+            Some(FieldValue::Date(String::from("2024-02-29")))
+        );
+        assert_eq!(FieldValue::parse(&date, "2024-13-01"), None);
+        assert_eq!(FieldValue::parse(&date, "24-01-01"), None);
+
+        let boolean = field("done", FieldKind::Boolean);
+        assert_eq!(
+            FieldValue::parse(&boolean, "Yes"),
+            Some(FieldValue::Boolean(true))
+        );
+        assert_eq!(
+            FieldValue::parse(&boolean, "0"),
+            Some(FieldValue::Boolean(false))
+        );
+        assert_eq!(FieldValue::parse(&boolean, "maybe"), None);
+
+        // choices keep the case they were defined with
+        let choice = field("priority", FieldKind::Enum);
+        assert_eq!(
+            FieldValue::parse(&choice, "high"),
+            Some(FieldValue::Enum(String::from("High")))
+        );
+        assert_eq!(FieldValue::parse(&choice, "medium"), None);
+
+        let text = field("notes", FieldKind::Text);
+        assert_eq!(
+            FieldValue::parse(&text, " a note "),
+            Some(FieldValue::Text(String::from("a note")))
+        );
+        assert_eq!(FieldValue::parse(&text, "  "), None);
+    }
+
+    #[test]
+    fn words_match_titles_tags_and_imported_metadata() {
+        assert!(matches(""));
+        assert!(matches("WIZARD"));
+        assert!(matches("rus"));
+        assert!(matches("guin earthsea"));
+        assert!(!matches("wizard dragon"));
+    }
+
+    #[test]
+    fn tags_match_whole() {
+        assert!(matches("tag:work"));
+        assert!(!matches("tag:wor"));
+    }
+
+    #[test]
+    fn fields_match_values_and_comparisons() {
+        assert!(matches("#priority:high"));
+        assert!(!matches("#priority:low"));
+        assert!(matches("#notes:draft"));
+        assert!(matches("#pages>100"));
+        assert!(!matches("#pages<100"));
+        assert!(matches("#pages=320"));
+        assert!(matches("#due<2024-01-01"));
+        assert!(!matches("#due>2024-01-01"));
+        // values that can't be read never match
+        assert!(!matches("#pages>many"));
+        assert!(!matches("#shelf:top"));
+    }
+
+    #[test]
+    fn shelves_of_the_same_name_are_replaced() {
+        let mut db = LibraryDb::default();
+        assert_eq!(db.save_shelf("Work", "tag:work"), 0);
+        assert_eq!(db.save_shelf("Long", "#pages>500"), 1);
+        assert_eq!(db.save_shelf("Work", "tag:work #priority:high"), 0);
+        assert_eq!(
+            db.shelves,
+            vec![
+                Shelf {
+                    name: String::from("Work"),
+                    filter: String::from("tag:work #priority:high"),
+                },
+                Shelf {
+                    name: String::from("Long"),
+                    filter: String::from("#pages>500"),
+                },
+            ]
+        );
+    }
+}
@@ -16,17 +16,29 @@
 
 #[rustfmt::skip]
 mod config;
+mod annotations;
 mod app;
+mod calibre;
+mod cli;
 mod components;
+mod dbus_api;
+mod formats;
+mod library_db;
+mod search;
+mod search_provider;
 mod setup;
+mod storage;
+mod xhtml;
 
-use gtk::prelude::ApplicationExt;
+use gtk::prelude::{ApplicationExt, Cast, FileExt};
 use relm4::{
     actions::{AccelsPlus, RelmAction, RelmActionGroup},
-    gtk, main_application, RelmApp,
+    gtk::{self, gio},
+    main_application, RelmApp,
 };
 
 use app::App;
+use components::{BackAction, ForwardAction, SearchAction, SplitAction};
 use setup::setup;
 
 use crate::config::APP_ID;
@@ -35,6 +47,20 @@ relm4::new_action_group!(AppActionGroup, "app");
 relm4::new_stateless_action!(QuitAction, AppActionGroup, "quit");
 
 fn main() {
+    // commands run without any window, or GTK set up at all
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args
+        .first()
+        .map(|arg| cli::is_command(arg))
+        .unwrap_or(false)
+    {
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .with_max_level(tracing::Level::WARN)
+            .init();
+        std::process::exit(cli::run(args));
+    }
+
     // Enable logging
     tracing_subscriber::fmt()
         .with_span_events(tracing_subscriber::fmt::format::FmtSpan::FULL)
@@ -45,6 +71,22 @@ fn main() {
 
     let app = main_application();
     app.set_resource_base_path(Some("/com/adhadse/Bookx/"));
+    // books given on the command line or opened from the file manager
+    app.set_application_id(Some(APP_ID));
+    app.set_flags(app.flags() | gio::ApplicationFlags::HANDLES_OPEN);
+    app.connect_open(|app, files, _| {
+        app::open_files(files.iter().filter_map(|file| file.path()).collect());
+        app.activate();
+    });
+    // books are searchable from the GNOME overview, which starts Bookx in
+    // the background when it isn't running
+    app.connect_startup(|app| {
+        search_provider::register(app.upcast_ref());
+        dbus_api::register(app.upcast_ref());
+        // only the first instance gets here, so no comic is open yet
+        formats::ComicSource::remove_unpacked();
+    });
+    app.set_inactivity_timeout(10_000);
 
     let mut actions = RelmActionGroup::<AppActionGroup>::new();
 
@@ -57,10 +99,14 @@ fn main() {
     actions.add_action(quit_action);
 
     app.set_accelerators_for_action::<QuitAction>(&["<Control>q"]);
+    app.set_accelerators_for_action::<SearchAction>(&["<Control>f"]);
+    app.set_accelerators_for_action::<SplitAction>(&["<Control>backslash"]);
+    app.set_accelerators_for_action::<BackAction>(&["<Alt>Left"]);
+    app.set_accelerators_for_action::<ForwardAction>(&["<Alt>Right"]);
 
     app.set_action_group(Some(&actions.into_action_group()));
 
-    let app = RelmApp::from_app(app);
+    let app = RelmApp::from_app(app).with_args(std::env::args().collect());
 
     app.run::<App>(());
 }
@@ -0,0 +1,173 @@
+// Bookx - book_search.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use gettextrs::gettext;
+use tracing::warn;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::formats::{self, BookSource, FormatError, TocEntry};
+use crate::search::{normalize, NormalizedText};
+use crate::xhtml::{ChapterText, OBJECT_REPLACEMENT};
+
+// number of characters shown on each side of a match
+const CONTEXT_CHARS: usize = 40;
+
+#[derive(Debug)]
+pub struct SearchChapter {
+    // index of the chapter in the book
+    pub index: usize,
+    pub title: String,
+    pub text: String,
+    normalized: NormalizedText,
+}
+
+// The plain text of every chapter of a book, extracted once when
+// the book is opened and then searched as many times as needed.
+#[derive(Debug)]
+pub struct BookText {
+    pub chapters: Vec<SearchChapter>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub chapter: usize,
+    pub chapter_title: String,
+    // character offsets of the match in the chapter text
+    pub start: usize,
+    pub end: usize,
+    pub before: String,
+    pub matched: String,
+    pub after: String,
+}
+
+impl SearchResult {
+    // `chars` is the text of the chapter the match (start..end) was found in
+    pub fn new(
+        chapter: usize,
+        chapter_title: &str,
+        chars: &[char],
+        start: usize,
+        end: usize,
+    ) -> Self {
+        let context_start = start.saturating_sub(CONTEXT_CHARS);
+        let context_end = (end + CONTEXT_CHARS).min(chars.len());
+        Self {
+            chapter,
+            chapter_title: chapter_title.to_string(),
+            start,
+            end,
+            before: context(&chars[context_start..start]),
+            matched: context(&chars[start..end]),
+            after: context(&chars[end..context_end]),
+        }
+    }
+}
+
+impl BookText {
+    pub fn extract(book_path: &str) -> Result<Self, FormatError> {
+        let mut source = formats::open(Path::new(book_path))?;
+        Ok(Self::from_source(source.as_mut()))
+    }
+
+    pub fn from_source(source: &mut dyn BookSource) -> Self {
+        let toc_titles = toc_titles(&source.toc());
+        let count = source.chapter_count();
+
+        let mut chapters = Vec::with_capacity(count);
+        for index in 0..count {
+            let content = match source.chapter(index) {
+                Some(chapter) => chapter.content,
+                None => {
+                    warn!("Chapter {} is missing from the book, skipped.", index);
+                    continue;
+                }
+            };
+            let chapter = ChapterText::parse(&content);
+            let title = toc_titles
+                .get(&index)
+                .cloned()
+                .or(chapter.title)
+                .unwrap_or_else(|| gettext("Chapter %s").replace("%s", &(index + 1).to_string()));
+            chapters.push(SearchChapter {
+                index,
+                title,
+                normalized: NormalizedText::new(&chapter.text),
+                text: chapter.text,
+            });
+        }
+
+        Self { chapters }
+    }
+
+    // Case and diacritic insensitive search over the whole book, at most
+    // `limit` results are returned.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchResult> {
+        let needle = normalize(query.trim());
+        let mut results = Vec::new();
+        if needle.is_empty() {
+            return results;
+        }
+
+        for chapter in &self.chapters {
+            let matches = chapter.normalized.find_all(&needle, limit - results.len());
+            if matches.is_empty() {
+                continue;
+            }
+            let chars: Vec<char> = chapter.text.chars().collect();
+            for (start, end) in matches {
+                results.push(SearchResult::new(
+                    chapter.index,
+                    &chapter.title,
+                    &chars,
+                    start,
+                    end,
+                ));
+            }
+            if results.len() >= limit {
+                break;
+            }
+        }
+
+        results
+    }
+}
+
+// context is shown on a single line
+fn context(chars: &[char]) -> String {
+    chars
+        .iter()
+        .map(|&c| match c {
+            '\n' | OBJECT_REPLACEMENT => ' ',
+            c => c,
+        })
+        .collect()
+}
+
+// maps every chapter with a TOC entry to its label, the first entry
+// wins when a chapter has several
+fn toc_titles(toc: &[TocEntry]) -> HashMap<usize, String> {
+    let mut titles = HashMap::new();
+    let mut stack: Vec<&TocEntry> = toc.iter().rev().collect();
+    while let Some(entry) = stack.pop() {
+        titles
+            .entry(entry.chapter)
+            .or_insert_with(|| entry.label.clone());
+        stack.extend(entry.children.iter().rev());
+    }
+    titles
+}
@@ -0,0 +1,407 @@
+// Bookx - library_index.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Instant, UNIX_EPOCH};
+
+use crate::formats;
+use crate::search::{normalize, BookText, NormalizedText, SearchResult};
+use crate::storage;
+
+// bump when the layout of the index changes, older files are rebuilt
+const INDEX_VERSION: u32 = 2;
+// words shorter than this are too common to be worth indexing
+const MIN_TERM_CHARS: usize = 2;
+
+// An inverted index of the text of every book in the library, mapping each
+// (normalized) word to the chapters it appears in. Only the words are kept
+// in memory, the text itself is in a file per book.
+#[derive(Debug, Default)]
+pub struct LibraryIndex {
+    next_id: u32,
+    books: HashMap<u32, IndexedBook>,
+    // term -> (book id, chapter index) of every chapter containing it
+    terms: BTreeMap<String, Vec<(u32, u32)>>,
+}
+
+#[derive(Debug)]
+struct IndexedBook {
+    path: String,
+    title: String,
+    // modification time of the file when it was indexed
+    modified: u64,
+    // chapter titles by chapter index
+    chapters: HashMap<u32, String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LibraryHit {
+    pub path: String,
+    pub book_title: String,
+    pub result: SearchResult,
+}
+
+// What is indexed of a book, saved in its own file so only the books that
+// changed are written. Snippets are cut from the text kept here instead of
+// opening the book again.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoredBook {
+    version: u32,
+    path: String,
+    title: String,
+    modified: u64,
+    chapters: Vec<StoredChapter>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredChapter {
+    index: u32,
+    title: String,
+    // plain text, as the search inside a book sees it
+    text: String,
+}
+
+impl StoredBook {
+    fn extract(path: &str, modified: u64) -> Result<Self, formats::FormatError> {
+        let mut source = formats::open(Path::new(path))?;
+        let title = source.metadata().title.unwrap_or_else(|| path.to_string());
+        let chapters = BookText::from_source(source.as_mut())
+            .chapters
+            .into_iter()
+            .map(|chapter| StoredChapter {
+                index: chapter.index as u32,
+                title: chapter.title,
+                text: chapter.text,
+            })
+            .collect();
+        Ok(Self {
+            version: INDEX_VERSION,
+            path: path.to_string(),
+            title,
+            modified,
+            chapters,
+        })
+    }
+}
+
+impl LibraryIndex {
+    fn folder() -> PathBuf {
+        storage::data_dir().join("search_index")
+    }
+
+    // file of the book at `path`, named after a hash of the path
+    fn book_file(path: &str) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        path.hash(&mut hasher);
+        Self::folder().join(format!("{:016x}.json", hasher.finish()))
+    }
+
+    fn load() -> Self {
+        let mut index = Self::default();
+        // the whole index used to be kept in a single file
+        let _ = fs::remove_file(storage::data_dir().join("search_index.json"));
+
+        let entries = match fs::read_dir(Self::folder()) {
+            Ok(entries) => entries,
+            Err(_) => {
+                info!("Search index is missing, it'll be built");
+                return index;
+            }
+        };
+        for file in entries.flatten().map(|entry| entry.path()) {
+            let stored: StoredBook = storage::load_json(&file);
+            if stored.version == INDEX_VERSION && file == Self::book_file(&stored.path) {
+                index.add_book(&stored);
+                continue;
+            }
+            info!(
+                "Search index of {:?} is outdated, it'll be rebuilt",
+                stored.path
+            );
+            if let Err(e) = fs::remove_file(&file) {
+                error!("Unable to remove {:?}: {:?}", file, e);
+            }
+        }
+        index
+    }
+
+    // books among `paths` which are new or changed since last indexed,
+    // and ids of indexed books that are gone
+    fn stale(&self, paths: &[String]) -> (Vec<(String, u64)>, Vec<u32>) {
+        let indexed: HashMap<&str, (u32, u64)> = self
+            .books
+            .iter()
+            .map(|(id, book)| (book.path.as_str(), (*id, book.modified)))
+            .collect();
+        let current: HashSet<&str> = paths.iter().map(String::as_str).collect();
+
+        let mut to_index = Vec::new();
+        let mut to_remove = Vec::new();
+        for path in paths {
+            let modified = modified_time(path);
+            match indexed.get(path.as_str()) {
+                Some(&(_, indexed_modified)) if indexed_modified == modified => {}
+                Some(&(id, _)) => {
+                    to_remove.push(id);
+                    to_index.push((path.clone(), modified));
+                }
+                None => to_index.push((path.clone(), modified)),
+            }
+        }
+        for (path, (id, _)) in indexed {
+            if !current.contains(path) {
+                to_remove.push(id);
+            }
+        }
+
+        (to_index, to_remove)
+    }
+
+    fn remove_books(&mut self, ids: &[u32]) {
+        if ids.is_empty() {
+            return;
+        }
+        for id in ids {
+            if let Some(book) = self.books.remove(id) {
+                let file = Self::book_file(&book.path);
+                if let Err(e) = fs::remove_file(&file) {
+                    warn!("Unable to remove {:?} from the search index: {:?}", file, e);
+                }
+            }
+        }
+        let ids: HashSet<u32> = ids.iter().copied().collect();
+        self.terms.retain(|_, postings| {
+            postings.retain(|(book, _)| !ids.contains(book));
+            !postings.is_empty()
+        });
+    }
+
+    fn add_book(&mut self, stored: &StoredBook) {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut chapters = HashMap::new();
+        for chapter in &stored.chapters {
+            chapters.insert(chapter.index, chapter.title.clone());
+
+            let normalized = normalize(&chapter.text);
+            let terms: HashSet<&str> = terms(&normalized).collect();
+            for term in terms {
+                self.terms
+                    .entry(term.to_string())
+                    .or_default()
+                    .push((id, chapter.index));
+            }
+        }
+
+        self.books.insert(
+            id,
+            IndexedBook {
+                path: stored.path.clone(),
+                title: stored.title.clone(),
+                modified: stored.modified,
+                chapters,
+            },
+        );
+    }
+
+    // Chapters containing every word of the query, the last word matches
+    // as a prefix so results show up while typing.
+    fn lookup(&self, query: &str) -> Vec<(u32, u32)> {
+        let normalized = normalize(query);
+        let words: Vec<&str> = terms(&normalized).collect();
+        let mut found: Option<HashSet<(u32, u32)>> = None;
+
+        for (i, word) in words.iter().enumerate() {
+            let mut chapters = HashSet::new();
+            if i + 1 == words.len() {
+                for (_, postings) in self
+                    .terms
+                    .range(word.to_string()..)
+                    .take_while(|(term, _)| term.starts_with(word))
+                {
+                    chapters.extend(postings.iter().copied());
+                }
+            } else if let Some(postings) = self.terms.get(*word) {
+                chapters.extend(postings.iter().copied());
+            }
+            found = Some(match found {
+                Some(found) => found.intersection(&chapters).copied().collect(),
+                None => chapters,
+            });
+        }
+
+        let mut found: Vec<(u32, u32)> = found.unwrap_or_default().into_iter().collect();
+        found.sort_by(|a, b| {
+            let title_a = self.books.get(&a.0).map(|book| book.title.as_str());
+            let title_b = self.books.get(&b.0).map(|book| book.title.as_str());
+            title_a.cmp(&title_b).then(a.cmp(b))
+        });
+        found
+    }
+}
+
+// Index shared between the background indexer and the search page.
+#[derive(Default)]
+pub struct SharedIndex {
+    index: RwLock<LibraryIndex>,
+    loaded: AtomicBool,
+    // only one update runs at a time, later ones wait for it
+    updating: Mutex<()>,
+}
+
+impl SharedIndex {
+    pub fn is_updating(&self) -> bool {
+        self.updating.try_lock().is_err()
+    }
+
+    // Brings the index in line with the books at `paths`, meant to be
+    // called from a background thread after every scan of the library.
+    pub fn update(&self, paths: &[String]) {
+        let _updating = self.updating.lock().unwrap();
+        let now = Instant::now();
+
+        if !self.loaded.swap(true, Ordering::SeqCst) {
+            *self.index.write().unwrap() = LibraryIndex::load();
+        }
+
+        let (to_index, to_remove) = self.index.read().unwrap().stale(paths);
+        if to_index.is_empty() && to_remove.is_empty() {
+            return;
+        }
+        self.index.write().unwrap().remove_books(&to_remove);
+
+        for (path, modified) in &to_index {
+            let stored = match StoredBook::extract(path, *modified) {
+                Ok(stored) => stored,
+                Err(e) => {
+                    warn!("Unable to index {:?}: {}", path, e);
+                    continue;
+                }
+            };
+            let file = LibraryIndex::book_file(path);
+            if let Err(e) = storage::save_json(&file, &stored) {
+                error!("Unable to save the search index of {:?}: {:?}", path, e);
+            }
+            self.index.write().unwrap().add_book(&stored);
+        }
+
+        let index = self.index.read().unwrap();
+        info!(
+            "Search index updated in {} ms: {} indexed, {} removed, {} books total",
+            now.elapsed().as_millis(),
+            to_index.len(),
+            to_remove.len(),
+            index.books.len()
+        );
+    }
+
+    // Searches the index and builds a snippet for each hit, this reads the
+    // text of the matching books so it's better run off the main thread.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<LibraryHit> {
+        let (chapters, books) = {
+            let index = self.index.read().unwrap();
+            let chapters = index.lookup(query);
+            let books: HashMap<u32, (String, String, HashMap<u32, String>)> = chapters
+                .iter()
+                .filter_map(|(id, _)| {
+                    index.books.get(id).map(|book| {
+                        (
+                            *id,
+                            (book.path.clone(), book.title.clone(), book.chapters.clone()),
+                        )
+                    })
+                })
+                .collect();
+            (chapters, books)
+        };
+
+        let normalized = normalize(query);
+        let first_word = match terms(&normalized).next() {
+            Some(word) => word.to_string(),
+            None => return Vec::new(),
+        };
+
+        let mut hits = Vec::new();
+        let mut stored: Option<(u32, StoredBook)> = None;
+        for (id, chapter_index) in chapters {
+            if hits.len() >= limit {
+                break;
+            }
+            let (path, title, chapter_titles) = match books.get(&id) {
+                Some(book) => book,
+                None => continue,
+            };
+            if stored.as_ref().map(|(read, _)| *read) != Some(id) {
+                stored = Some((id, storage::load_json(&LibraryIndex::book_file(path))));
+            }
+            let text = match stored.as_ref().and_then(|(_, book)| {
+                book.chapters
+                    .iter()
+                    .find(|chapter| chapter.index == chapter_index)
+            }) {
+                Some(chapter) => &chapter.text,
+                None => continue,
+            };
+            let (start, end) = match NormalizedText::new(text).find_all(&first_word, 1).first() {
+                Some(&range) => range,
+                None => continue,
+            };
+            let chars: Vec<char> = text.chars().collect();
+            let chapter_title = chapter_titles
+                .get(&chapter_index)
+                .map(String::as_str)
+                .unwrap_or_default();
+            hits.push(LibraryHit {
+                path: path.clone(),
+                book_title: title.clone(),
+                result: SearchResult::new(
+                    chapter_index as usize,
+                    chapter_title,
+                    &chars,
+                    start,
+                    end,
+                ),
+            });
+        }
+
+        hits
+    }
+}
+
+fn terms(normalized: &str) -> impl Iterator<Item = &str> {
+    normalized
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| term.chars().count() >= MIN_TERM_CHARS)
+}
+
+fn modified_time(path: &str) -> u64 {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,7 @@
+mod book_search;
+mod library_index;
+mod normalize;
+
+pub use book_search::{BookText, SearchResult};
+pub use library_index::{LibraryHit, SharedIndex};
+pub use normalize::{normalize, NormalizedText};
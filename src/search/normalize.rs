@@ -0,0 +1,121 @@
+// Bookx - normalize.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+// Folds a character for case and diacritic insensitive matching,
+// "É" and "e" both become "e".
+fn fold_char(c: char, out: &mut String) {
+    for d in std::iter::once(c).nfd() {
+        if is_combining_mark(d) {
+            continue;
+        }
+        out.extend(d.to_lowercase());
+    }
+}
+
+pub fn normalize(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        fold_char(c, &mut out);
+    }
+    out
+}
+
+// Folded copy of a text which remembers where each folded character came
+// from, so matches can be reported as character ranges of the original.
+#[derive(Debug)]
+pub struct NormalizedText {
+    pub text: String,
+    // (byte offset in `text`, char offset in the original), one per
+    // original char that produced output
+    positions: Vec<(usize, usize)>,
+    original_len: usize,
+}
+
+impl NormalizedText {
+    pub fn new(original: &str) -> Self {
+        let mut text = String::with_capacity(original.len());
+        let mut positions = Vec::new();
+        let mut original_len = 0;
+        for (i, c) in original.chars().enumerate() {
+            let before = text.len();
+            fold_char(c, &mut text);
+            if text.len() > before {
+                positions.push((before, i));
+            }
+            original_len = i + 1;
+        }
+        Self {
+            text,
+            positions,
+            original_len,
+        }
+    }
+
+    fn original_offset(&self, byte: usize, is_end: bool) -> usize {
+        if byte >= self.text.len() {
+            return self.original_len;
+        }
+        match self.positions.binary_search_by_key(&byte, |&(b, _)| b) {
+            Ok(i) => self.positions[i].1,
+            // inside the expansion of a single original char
+            Err(i) => self.positions[i.saturating_sub(1)].1 + usize::from(is_end),
+        }
+    }
+
+    // Character ranges (start, end) in the original text of every
+    // occurrence of the already normalized `needle`.
+    pub fn find_all(&self, needle: &str, limit: usize) -> Vec<(usize, usize)> {
+        if needle.is_empty() {
+            return Vec::new();
+        }
+        self.text
+            .match_indices(needle)
+            .take(limit)
+            .map(|(start, m)| {
+                (
+                    self.original_offset(start, false),
+                    self.original_offset(start + m.len(), true),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_folds_case_and_diacritics() {
+        assert_eq!(normalize("Élan VITAL"), "elan vital");
+        assert_eq!(normalize("Ångström, naïve café"), "angstrom, naive cafe");
+        assert_eq!(normalize("ΣΟΦΙΑ"), "σοφια");
+        assert_eq!(normalize(""), "");
+    }
+
+    #[test]
+    fn matches_are_ranges_of_the_original() {
+        let text = NormalizedText::new("Café CAFE cafe\u{301}");
+        assert_eq!(
+            text.find_all(&normalize("cafe"), 10),
+            vec![(0, 4), (5, 9), (10, 15)]
+        );
+        assert_eq!(text.find_all("cafe", 1), vec![(0, 4)]);
+        assert!(text.find_all("", 10).is_empty());
+    }
+}
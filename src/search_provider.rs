@@ -0,0 +1,298 @@
+// Bookx - search_provider.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use relm4::gtk::{
+    gio::{self, prelude::*},
+    glib::{self, ToVariant},
+};
+use tracing::{error, warn};
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::app;
+use crate::config::APP_ID;
+use crate::library_db::{CatalogEntry, LibraryDb};
+use crate::search::normalize;
+
+const INTERFACE: &str = "org.gnome.Shell.SearchProvider2";
+const INTERFACE_XML: &str = r#"
+<node>
+  <interface name="org.gnome.Shell.SearchProvider2">
+    <method name="GetInitialResultSet">
+      <arg type="as" name="terms" direction="in"/>
+      <arg type="as" name="results" direction="out"/>
+    </method>
+    <method name="GetSubsearchResultSet">
+      <arg type="as" name="previous_results" direction="in"/>
+      <arg type="as" name="terms" direction="in"/>
+      <arg type="as" name="results" direction="out"/>
+    </method>
+    <method name="GetResultMetas">
+      <arg type="as" name="identifiers" direction="in"/>
+      <arg type="aa{sv}" name="metas" direction="out"/>
+    </method>
+    <method name="ActivateResult">
+      <arg type="s" name="identifier" direction="in"/>
+      <arg type="as" name="terms" direction="in"/>
+      <arg type="u" name="timestamp" direction="in"/>
+    </method>
+    <method name="LaunchSearch">
+      <arg type="as" name="terms" direction="in"/>
+      <arg type="u" name="timestamp" direction="in"/>
+    </method>
+  </interface>
+</node>
+"#;
+// the shell shows only a handful, no need to send more
+const MAX_RESULTS: usize = 20;
+
+// Books of the library for the search in the GNOME overview, results are
+// identified by the path of the book.
+#[derive(Default)]
+struct SearchProvider {
+    // the catalog of the last scan, read again for every new search
+    catalog: BTreeMap<String, CatalogEntry>,
+}
+
+impl SearchProvider {
+    fn initial_results(&mut self, terms: &[String]) -> Vec<String> {
+        self.catalog = LibraryDb::load().catalog;
+        self.results(self.catalog.keys(), terms)
+    }
+
+    // narrows down the results of a search that was typed further
+    fn subsearch_results(&self, previous: &[String], terms: &[String]) -> Vec<String> {
+        self.results(previous.iter(), terms)
+    }
+
+    // Books whose title or authors have every term, the ones where the
+    // title starts with the first term come first.
+    fn results<'a>(
+        &self,
+        paths: impl Iterator<Item = &'a String>,
+        terms: &[String],
+    ) -> Vec<String> {
+        let terms: Vec<String> = terms
+            .iter()
+            .map(|term| normalize(term.trim()))
+            .filter(|term| !term.is_empty())
+            .collect();
+        if terms.is_empty() {
+            return Vec::new();
+        }
+        let mut results: Vec<(bool, String, &String)> = paths
+            .filter_map(|path| {
+                let entry = self.catalog.get(path)?;
+                let title = normalize(&entry.title);
+                let text = format!("{} {}", title, normalize(&entry.authors.join(" ")));
+                terms
+                    .iter()
+                    .all(|term| text.contains(term.as_str()))
+                    .then(|| (!title.starts_with(&terms[0]), title, path))
+            })
+            .collect();
+        results.sort();
+        results
+            .into_iter()
+            .take(MAX_RESULTS)
+            .map(|(_, _, path)| path.clone())
+            .collect()
+    }
+
+    fn result_metas(&self, paths: &[String]) -> Vec<HashMap<String, glib::Variant>> {
+        paths
+            .iter()
+            .filter_map(|path| {
+                let entry = self.catalog.get(path)?;
+                let icon: gio::Icon = match &entry.cover {
+                    Some(cover) if cover.exists() => {
+                        gio::FileIcon::new(&gio::File::for_path(cover)).upcast()
+                    }
+                    _ => gio::ThemedIcon::new(APP_ID).upcast(),
+                };
+                let mut meta = HashMap::new();
+                meta.insert("id".to_string(), path.to_variant());
+                meta.insert("name".to_string(), entry.title.to_variant());
+                if !entry.authors.is_empty() {
+                    meta.insert(
+                        "description".to_string(),
+                        entry.authors.join(", ").to_variant(),
+                    );
+                }
+                if let Some(icon) = icon.serialize() {
+                    meta.insert("icon".to_string(), icon);
+                }
+                Some(meta)
+            })
+            .collect()
+    }
+}
+
+// Exports the search provider on the connection of the application, under
+// `<application object path>/SearchProvider` like the `.ini` file says.
+pub fn register(app: &gio::Application) {
+    let (connection, object_path) = match (app.dbus_connection(), app.dbus_object_path()) {
+        (Some(connection), Some(object_path)) => (connection, object_path),
+        _ => {
+            warn!("Not on the session bus, the search provider isn't available");
+            return;
+        }
+    };
+    let interface = match gio::DBusNodeInfo::for_xml(INTERFACE_XML) {
+        Ok(node) => node.lookup_interface(INTERFACE),
+        Err(e) => {
+            error!("Unable to parse the search provider interface: {}", e);
+            return;
+        }
+    };
+    let interface = match interface {
+        Some(interface) => interface,
+        None => return,
+    };
+
+    let provider = Rc::new(RefCell::new(SearchProvider::default()));
+    let app = app.clone();
+    let registered = connection.register_object(
+        &format!("{}/SearchProvider", object_path),
+        &interface,
+        move |_, _, _, _, method, parameters, invocation| {
+            // keeps a background instance alive while it's answering
+            let _guard = app.hold();
+            match method {
+                "GetInitialResultSet" => {
+                    let results = match parameters.get::<(Vec<String>,)>() {
+                        Some((terms,)) => provider.borrow_mut().initial_results(&terms),
+                        None => Vec::new(),
+                    };
+                    invocation.return_value(Some(&(results,).to_variant()));
+                }
+                "GetSubsearchResultSet" => {
+                    let results = match parameters.get::<(Vec<String>, Vec<String>)>() {
+                        Some((previous, terms)) => {
+                            provider.borrow().subsearch_results(&previous, &terms)
+                        }
+                        None => Vec::new(),
+                    };
+                    invocation.return_value(Some(&(results,).to_variant()));
+                }
+                "GetResultMetas" => {
+                    let metas = match parameters.get::<(Vec<String>,)>() {
+                        Some((paths,)) => provider.borrow().result_metas(&paths),
+                        None => Vec::new(),
+                    };
+                    invocation.return_value(Some(&(metas,).to_variant()));
+                }
+                "ActivateResult" => {
+                    if let Some((path, _, _)) = parameters.get::<(String, Vec<String>, u32)>() {
+                        app::open_files(vec![PathBuf::from(path)]);
+                    }
+                    app.activate();
+                    invocation.return_value(None);
+                }
+                "LaunchSearch" => {
+                    app.activate();
+                    invocation.return_value(None);
+                }
+                _ => invocation.return_dbus_error(
+                    "org.freedesktop.DBus.Error.UnknownMethod",
+                    &format!("No method named {}", method),
+                ),
+            }
+        },
+        // the interface has no properties
+        |_, _, _, _, _| ().to_variant(),
+        |_, _, _, _, _, _| false,
+    );
+    if let Err(e) = registered {
+        error!("Unable to export the search provider: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider() -> SearchProvider {
+        let book = |title: &str, authors: &[&str]| CatalogEntry {
+            title: title.to_string(),
+            authors: authors.iter().map(|author| author.to_string()).collect(),
+            cover: None,
+        };
+        SearchProvider {
+            catalog: BTreeMap::from([
+                (
+                    String::from("/books/dune.epub"),
+                    book("Dune", &["Frank Herbert"]),
+                ),
+                (
+                    String::from("/books/children.epub"),
+                    book("Children of Dune", &["Frank Herbert"]),
+                ),
+                (
+                    String::from("/books/etranger.epub"),
+                    book("L’Étranger", &["Albert Camus"]),
+                ),
+            ]),
+        }
+    }
+
+    fn search(provider: &SearchProvider, terms: &[&str]) -> Vec<String> {
+        let terms: Vec<String> = terms.iter().map(|term| term.to_string()).collect();
+        provider.results(provider.catalog.keys(), &terms)
+    }
+
+    #[test]
+    fn every_term_has_to_match() {
+        let provider = provider();
+        assert_eq!(
+            search(&provider, &["herbert", "children"]),
+            vec![String::from("/books/children.epub")]
+        );
+        assert!(search(&provider, &["herbert", "camus"]).is_empty());
+        assert!(search(&provider, &[]).is_empty());
+        assert!(search(&provider, &[" "]).is_empty());
+    }
+
+    #[test]
+    fn titles_starting_with_the_term_come_first() {
+        assert_eq!(
+            search(&provider(), &["dune"]),
+            vec![
+                String::from("/books/dune.epub"),
+                String::from("/books/children.epub"),
+            ]
+        );
+    }
+
+    #[test]
+    fn case_and_accents_are_ignored() {
+        assert_eq!(
+            search(&provider(), &["ETRANGER"]),
+            vec![String::from("/books/etranger.epub")]
+        );
+    }
+
+    #[test]
+    fn subsearches_stay_within_the_previous_results() {
+        let provider = provider();
+        let previous = vec![String::from("/books/children.epub")];
+        let terms = vec![String::from("dune")];
+        assert_eq!(provider.subsearch_results(&previous, &terms), previous);
+    }
+}
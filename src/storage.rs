@@ -0,0 +1,63 @@
+// Bookx - storage.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use relm4::gtk::glib;
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::{error, info};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::PKGNAME;
+
+// directory holding the library database, search index and the like
+pub fn data_dir() -> PathBuf {
+    glib::user_data_dir().join(PKGNAME)
+}
+
+// Reads a JSON file written by `save_json`, a missing or unreadable file
+// gives the default value so a broken file never prevents startup.
+pub fn load_json<T: DeserializeOwned + Default>(path: &Path) -> T {
+    match fs::read(path) {
+        Ok(data) => match serde_json::from_slice(&data) {
+            Ok(value) => value,
+            Err(e) => {
+                error!("Unable to parse {:?}, starting afresh: {:?}", path, e);
+                T::default()
+            }
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            info!("{:?} doesn't exist yet", path);
+            T::default()
+        }
+        Err(e) => {
+            error!("Unable to read {:?}: {:?}", path, e);
+            T::default()
+        }
+    }
+}
+
+// Writes to a temporary file first so a crash never leaves half a file behind.
+pub fn save_json<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let data = serde_json::to_vec(value)?;
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, data)?;
+    fs::rename(tmp_path, path)
+}
@@ -0,0 +1,1030 @@
+// Bookx - xhtml.rs
+// Copyright (C) 2023  Anurag Dhadse <hello@adhadse.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+// Character used in place of an image in the flattened chapter text, this is
+// the same character GtkTextBuffer uses for paintables and child anchors so
+// offsets stay in sync with what the reader displays.
+pub const OBJECT_REPLACEMENT: char = '\u{FFFC}';
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Start {
+        name: String,
+        attrs: Vec<(String, String)>,
+        self_closing: bool,
+    },
+    End {
+        name: String,
+    },
+    Text(String),
+}
+
+impl Token {
+    pub fn attr(&self, key: &str) -> Option<&str> {
+        match self {
+            Token::Start { attrs, .. } => attrs
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str()),
+            _ => None,
+        }
+    }
+}
+
+// A forgiving tokenizer for the XHTML found inside ebooks, it never fails;
+// malformed markup is passed through as text. Every token comes with the
+// byte offset where it starts in the source.
+pub struct Tokenizer<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Self { src, pos: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn skip_past(&mut self, pat: &str) {
+        match self.rest().find(pat) {
+            Some(i) => self.pos += i + pat.len(),
+            None => self.pos = self.src.len(),
+        }
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = (usize, Token);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.pos >= self.src.len() {
+                return None;
+            }
+            let start = self.pos;
+            let rest = self.rest();
+
+            if !rest.starts_with('<') {
+                let end = rest.find('<').unwrap_or(rest.len());
+                self.pos += end;
+                return Some((start, Token::Text(decode_entities(&rest[..end]))));
+            }
+
+            if rest.starts_with("<!--") {
+                self.skip_past("-->");
+                continue;
+            }
+            if let Some(body) = rest.strip_prefix("<![CDATA[") {
+                let end = body.find("]]>").unwrap_or(body.len());
+                self.skip_past("]]>");
+                return Some((start, Token::Text(body[..end].to_string())));
+            }
+            if rest.starts_with("<?") || rest.starts_with("<!") {
+                self.skip_past(">");
+                continue;
+            }
+
+            let close = match find_tag_end(rest) {
+                Some(close) => close,
+                None => {
+                    // a lone `<`, treat it as text
+                    self.pos += 1;
+                    return Some((start, Token::Text("<".to_string())));
+                }
+            };
+            self.pos += close + 1;
+            let inner = &rest[1..close];
+
+            if let Some(name) = inner.strip_prefix('/') {
+                return Some((
+                    start,
+                    Token::End {
+                        name: name.trim().to_lowercase(),
+                    },
+                ));
+            }
+
+            let (inner, self_closing) = match inner.strip_suffix('/') {
+                Some(inner) => (inner, true),
+                None => (inner, false),
+            };
+            let name_end = inner
+                .find(|c: char| c.is_whitespace())
+                .unwrap_or(inner.len());
+            let name = inner[..name_end].to_lowercase();
+            if name.is_empty() {
+                return Some((start, Token::Text(decode_entities(&rest[..=close]))));
+            }
+            let attrs = parse_attributes(&inner[name_end..]);
+            return Some((
+                start,
+                Token::Start {
+                    name,
+                    attrs,
+                    self_closing,
+                },
+            ));
+        }
+    }
+}
+
+// position of the `>` closing the tag at the beginning of `s`, ignoring any
+// `>` inside quoted attribute values
+fn find_tag_end(s: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in s.char_indices().skip(1) {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return Some(i),
+            (None, '<') => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_attributes(s: &str) -> Vec<(String, String)> {
+    let mut attrs = Vec::new();
+    let mut chars = s.char_indices().peekable();
+
+    while let Some(&(i, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut key_end = i;
+        while let Some(&(j, c)) = chars.peek() {
+            if c.is_whitespace() || c == '=' {
+                break;
+            }
+            key_end = j + c.len_utf8();
+            chars.next();
+        }
+        let key = s[i..key_end].to_lowercase();
+        while let Some(&(_, c)) = chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            chars.next();
+        }
+        if let Some(&(_, '=')) = chars.peek() {
+            chars.next();
+            while let Some(&(_, c)) = chars.peek() {
+                if !c.is_whitespace() {
+                    break;
+                }
+                chars.next();
+            }
+            let value = match chars.peek() {
+                Some(&(j, q)) if q == '"' || q == '\'' => {
+                    chars.next();
+                    let mut end = s.len();
+                    for (k, c) in chars.by_ref() {
+                        if c == q {
+                            end = k;
+                            break;
+                        }
+                    }
+                    &s[j + 1..end]
+                }
+                Some(&(j, _)) => {
+                    let mut end = s.len();
+                    while let Some(&(k, c)) = chars.peek() {
+                        if c.is_whitespace() {
+                            end = k;
+                            break;
+                        }
+                        chars.next();
+                    }
+                    &s[j..end]
+                }
+                None => "",
+            };
+            attrs.push((key, decode_entities(value)));
+        } else if !key.is_empty() {
+            attrs.push((key, String::new()));
+        }
+    }
+
+    attrs
+}
+
+pub fn decode_entities(s: &str) -> String {
+    if !s.contains('&') {
+        return s.to_string();
+    }
+
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest
+            .find(';')
+            .filter(|&semi| semi <= 10)
+            .and_then(|semi| decode_entity(&rest[1..semi]).map(|c| (c, semi)));
+        match decoded {
+            Some((c, semi)) => {
+                out.push(c);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+
+    out
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    if let Some(num) = entity.strip_prefix('#') {
+        let code = match num.strip_prefix('x').or_else(|| num.strip_prefix('X')) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => num.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+    let c = match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{A0}',
+        "shy" => '\u{AD}',
+        "ndash" => '–',
+        "mdash" => '—',
+        "hellip" => '…',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "laquo" => '«',
+        "raquo" => '»',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "deg" => '°',
+        "middot" => '·',
+        "bull" => '•',
+        "times" => '×',
+        _ => return None,
+    };
+    Some(c)
+}
+
+pub fn is_void_element(name: &str) -> bool {
+    matches!(
+        name,
+        "area"
+            | "base"
+            | "br"
+            | "col"
+            | "embed"
+            | "hr"
+            | "img"
+            | "input"
+            | "link"
+            | "meta"
+            | "source"
+            | "track"
+            | "wbr"
+    )
+}
+
+fn is_block_element(name: &str) -> bool {
+    matches!(
+        name,
+        "address"
+            | "article"
+            | "aside"
+            | "blockquote"
+            | "body"
+            | "dd"
+            | "div"
+            | "dl"
+            | "dt"
+            | "figcaption"
+            | "figure"
+            | "footer"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "header"
+            | "hr"
+            | "li"
+            | "nav"
+            | "ol"
+            | "p"
+            | "pre"
+            | "section"
+            | "table"
+            | "tr"
+            | "ul"
+    )
+}
+
+// strip namespace prefixes like `svg:` so `svg:image` is handled as `image`
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Heading(u8),
+    Emphasis,
+    Strong,
+    Code,
+    Preformatted,
+    Quote,
+    Superscript,
+    Subscript,
+}
+
+#[derive(Debug, Clone)]
+pub struct StyleSpan {
+    pub start: usize,
+    pub end: usize,
+    pub style: Style,
+}
+
+#[derive(Debug, Clone)]
+pub struct LinkSpan {
+    pub start: usize,
+    pub end: usize,
+    pub href: String,
+    // value of `epub:type`, if any
+    pub epub_type: Option<String>,
+    // id of the link element itself, used to return to a reference
+    pub id: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImageRef {
+    pub offset: usize,
+    pub src: String,
+    pub alt: Option<String>,
+}
+
+// A chapter flattened to the text shown by the reader. All offsets are in
+// characters (not bytes) as that's what GtkTextIter works with.
+#[derive(Debug, Clone, Default)]
+pub struct ChapterText {
+    pub title: Option<String>,
+    pub text: String,
+    pub char_len: usize,
+    pub styles: Vec<StyleSpan>,
+    pub anchors: HashMap<String, usize>,
+    // extent of the block elements with an id, a note is the whole block
+    pub blocks: HashMap<String, Range<usize>>,
+    pub links: Vec<LinkSpan>,
+    pub images: Vec<ImageRef>,
+}
+
+// notes longer than this are cut short in the popover
+const MAX_NOTE_CHARS: usize = 1500;
+
+struct OpenElement {
+    name: String,
+    start: usize,
+    href: Option<String>,
+    epub_type: Option<String>,
+    id: Option<String>,
+}
+
+impl ChapterText {
+    pub fn parse(src: &str) -> Self {
+        let mut chapter = ChapterText::default();
+        let mut stack: Vec<OpenElement> = Vec::new();
+        // depth of elements whose content is never displayed
+        let mut hidden = 0usize;
+        let mut preformatted = 0usize;
+        let mut in_title = false;
+        let mut title = String::new();
+        let mut first_heading: Option<String> = None;
+        let mut heading_text: Option<String> = None;
+        let mut pending_space = false;
+
+        for (_, token) in Tokenizer::new(src) {
+            match token {
+                Token::Start {
+                    ref name,
+                    self_closing,
+                    ..
+                } => {
+                    let local = local_name(name).to_string();
+                    if let Some(id) = token.attr("id") {
+                        chapter
+                            .anchors
+                            .entry(id.to_string())
+                            .or_insert(chapter.char_len);
+                    }
+                    match local.as_str() {
+                        "head" | "script" | "style" => {
+                            if !self_closing {
+                                hidden += 1;
+                                stack.push(OpenElement {
+                                    name: local,
+                                    start: chapter.char_len,
+                                    href: None,
+                                    epub_type: None,
+                                    id: None,
+                                });
+                            }
+                            continue;
+                        }
+                        "title" if hidden > 0 => {
+                            in_title = !self_closing;
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    if hidden > 0 {
+                        continue;
+                    }
+
+                    if local == "br" {
+                        chapter.push_str("\n");
+                        pending_space = false;
+                        continue;
+                    }
+                    if local == "img" || local == "image" {
+                        let src = token
+                            .attr("src")
+                            .or_else(|| token.attr("xlink:href"))
+                            .or_else(|| token.attr("href"));
+                        if let Some(src) = src {
+                            if pending_space && !chapter.at_line_start() {
+                                chapter.push_str(" ");
+                            }
+                            pending_space = false;
+                            chapter.images.push(ImageRef {
+                                offset: chapter.char_len,
+                                src: src.to_string(),
+                                alt: token.attr("alt").map(str::to_string),
+                            });
+                            chapter.push_char(OBJECT_REPLACEMENT);
+                        }
+                        if self_closing || is_void_element(&local) {
+                            continue;
+                        }
+                    }
+                    if is_block_element(&local) {
+                        chapter.end_line();
+                        pending_space = false;
+                    } else if pending_space && !chapter.at_line_start() {
+                        // keep the space outside of the inline element
+                        chapter.push_str(" ");
+                        pending_space = false;
+                    }
+                    if self_closing || is_void_element(&local) {
+                        continue;
+                    }
+                    if local == "pre" {
+                        preformatted += 1;
+                    }
+                    if matches!(local.as_str(), "h1" | "h2" | "h3" | "h4" | "h5" | "h6")
+                        && first_heading.is_none()
+                    {
+                        heading_text = Some(String::new());
+                    }
+                    stack.push(OpenElement {
+                        href: token.attr("href").map(str::to_string),
+                        epub_type: token.attr("epub:type").map(str::to_string),
+                        id: token.attr("id").map(str::to_string),
+                        name: local,
+                        start: chapter.char_len,
+                    });
+                }
+                Token::End { ref name } => {
+                    let local = local_name(name);
+                    if local == "title" {
+                        in_title = false;
+                        continue;
+                    }
+                    let pos = match stack.iter().rposition(|e| e.name == local) {
+                        Some(pos) => pos,
+                        None => continue,
+                    };
+                    for element in stack.drain(pos..).rev() {
+                        match element.name.as_str() {
+                            "head" | "script" | "style" => {
+                                hidden -= 1;
+                                continue;
+                            }
+                            "pre" => preformatted -= 1,
+                            _ => {}
+                        }
+                        if hidden > 0 {
+                            continue;
+                        }
+                        chapter.close_element(element);
+                    }
+                    if hidden == 0 && is_block_element(local) {
+                        chapter.end_line();
+                        pending_space = false;
+                        if let Some(text) = heading_text.take() {
+                            let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                            if !text.is_empty() {
+                                first_heading = Some(text);
+                            }
+                        }
+                    }
+                }
+                Token::Text(text) => {
+                    if in_title {
+                        title.push_str(&text);
+                        continue;
+                    }
+                    if hidden > 0 {
+                        continue;
+                    }
+                    if let Some(heading) = heading_text.as_mut() {
+                        heading.push_str(&text);
+                    }
+                    if preformatted > 0 {
+                        chapter.push_str(&text);
+                        continue;
+                    }
+                    for (i, word) in text.split_ascii_whitespace().enumerate() {
+                        let leading = i == 0 && text.starts_with(|c: char| c.is_ascii_whitespace());
+                        if (pending_space || leading || i > 0) && !chapter.at_line_start() {
+                            chapter.push_str(" ");
+                        }
+                        chapter.push_str(word);
+                        pending_space = false;
+                    }
+                    if text.ends_with(|c: char| c.is_ascii_whitespace()) {
+                        pending_space = true;
+                    }
+                }
+            }
+        }
+
+        while chapter.text.ends_with('\n') {
+            chapter.text.pop();
+            chapter.char_len -= 1;
+        }
+
+        let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+        chapter.title = first_heading.or(if title.is_empty() { None } else { Some(title) });
+
+        chapter
+    }
+
+    // Whether the link points to a note. EPUB 3 marks them as `noteref`,
+    // in books without that they're told by a short marker like `1`, `[2]`
+    // or `*` that is raised or points to an id that looks like a note's.
+    pub fn is_note_ref(&self, link: &LinkSpan) -> bool {
+        let marked = link
+            .epub_type
+            .iter()
+            .flat_map(|epub_type| epub_type.split_whitespace())
+            .any(|epub_type| epub_type == "noteref");
+        if marked {
+            return true;
+        }
+        let fragment = match link.href.split_once('#') {
+            Some((_, fragment)) if !fragment.is_empty() => fragment.to_lowercase(),
+            _ => return false,
+        };
+        let marker: String = self
+            .text
+            .chars()
+            .skip(link.start)
+            .take(link.end.saturating_sub(link.start))
+            .collect();
+        let marker = marker
+            .trim()
+            .trim_matches(|c| matches!(c, '[' | ']' | '(' | ')'));
+        let short = match marker.chars().count() {
+            0 => false,
+            1 => true,
+            n => {
+                n <= 4
+                    && marker
+                        .chars()
+                        .all(|c| c.is_ascii_digit() || "*†‡§¶".contains(c))
+            }
+        };
+        let raised = self.styles.iter().any(|span| {
+            span.style == Style::Superscript && span.start <= link.start && link.end <= span.end
+        });
+        let note_id = ["fn", "note", "ftn"]
+            .iter()
+            .any(|hint| fragment.contains(hint));
+        short && (raised || note_id)
+    }
+
+    // Text of the note at the anchor, the block that has the id or else
+    // the line it's on, and the next one if it only holds the number.
+    pub fn note(&self, anchor: &str) -> Option<String> {
+        let text: String = match self.blocks.get(anchor) {
+            Some(range) => self
+                .text
+                .chars()
+                .skip(range.start)
+                .take(range.end - range.start)
+                .collect(),
+            None => {
+                let start = *self.anchors.get(anchor)?;
+                let mut note = String::new();
+                for line in self
+                    .text
+                    .chars()
+                    .skip(start)
+                    .take(MAX_NOTE_CHARS * 2)
+                    .collect::<String>()
+                    .lines()
+                {
+                    if !note.is_empty() {
+                        note.push('\n');
+                    }
+                    note.push_str(line.trim());
+                    if note.chars().filter(|c| c.is_alphabetic()).count() >= 3 {
+                        break;
+                    }
+                }
+                note
+            }
+        };
+        let text = text.replace(OBJECT_REPLACEMENT, "");
+        let text = text.trim();
+        if text.is_empty() {
+            return None;
+        }
+        if text.chars().count() > MAX_NOTE_CHARS {
+            let cut: String = text.chars().take(MAX_NOTE_CHARS).collect();
+            return Some(format!("{}…", cut.trim_end()));
+        }
+        Some(text.to_string())
+    }
+
+    fn push_str(&mut self, s: &str) {
+        self.text.push_str(s);
+        self.char_len += s.chars().count();
+    }
+
+    fn push_char(&mut self, c: char) {
+        self.text.push(c);
+        self.char_len += 1;
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.text.is_empty() || self.text.ends_with('\n')
+    }
+
+    fn end_line(&mut self) {
+        if !self.at_line_start() {
+            self.push_str("\n");
+        }
+    }
+
+    fn close_element(&mut self, element: OpenElement) {
+        let (start, end) = (element.start, self.char_len);
+        if let Some(id) = &element.id {
+            if is_block_element(&element.name) && element.name != "body" {
+                self.blocks.insert(id.clone(), start..end);
+            }
+        }
+        if element.name == "a" {
+            if let Some(href) = element.href {
+                self.links.push(LinkSpan {
+                    start,
+                    end,
+                    href,
+                    epub_type: element.epub_type,
+                    id: element.id,
+                });
+            }
+            return;
+        }
+        let style = match element.name.as_str() {
+            "h1" => Style::Heading(1),
+            "h2" => Style::Heading(2),
+            "h3" => Style::Heading(3),
+            "h4" => Style::Heading(4),
+            "h5" => Style::Heading(5),
+            "h6" => Style::Heading(6),
+            "em" | "i" | "cite" | "dfn" => Style::Emphasis,
+            "strong" | "b" => Style::Strong,
+            "code" | "kbd" | "samp" | "tt" => Style::Code,
+            "pre" => Style::Preformatted,
+            "blockquote" => Style::Quote,
+            "sup" => Style::Superscript,
+            "sub" => Style::Subscript,
+            _ => return,
+        };
+        if end > start {
+            self.styles.push(StyleSpan { start, end, style });
+        }
+    }
+}
+
+// Resolves an `href` found in the document at `base` (a path inside the
+// archive) to the path of the target and its fragment, if any. External
+// links resolve to `None`.
+pub fn resolve_href(base: &Path, href: &str) -> Option<(PathBuf, Option<String>)> {
+    if href.contains("://") || href.starts_with("mailto:") {
+        return None;
+    }
+    let (path, fragment) = match href.split_once('#') {
+        Some((path, fragment)) => (path, Some(fragment.to_string())),
+        None => (href, None),
+    };
+    if path.is_empty() {
+        return Some((base.to_path_buf(), fragment));
+    }
+
+    let mut resolved = PathBuf::new();
+    if let Some(parent) = base.parent() {
+        resolved.push(parent);
+    }
+    for component in Path::new(&percent_decode(path)).components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(part) => resolved.push(part),
+            Component::RootDir => resolved = PathBuf::new(),
+            _ => {}
+        }
+    }
+
+    Some((resolved, fragment))
+}
+
+fn percent_decode(s: &str) -> String {
+    if !s.contains('%') {
+        return s.to_string();
+    }
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() && s.is_char_boundary(i + 3) {
+            if let Ok(b) = u8::from_str_radix(&s[i + 1..i + 3], 16) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// escapes text for use in XHTML content and attribute values
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// attributes kept when re-serializing, anything else (event handlers,
+// presentational leftovers, vendor extensions) is dropped
+const ALLOWED_ATTRIBUTES: &[&str] = &[
+    "alt", "class", "colspan", "dir", "height", "href", "id", "lang", "rowspan", "src", "start",
+    "style", "title", "type", "width",
+];
+
+// obsolete elements and what they become, with a style keeping their look
+fn replacement_element(name: &str) -> Option<(&'static str, Option<&'static str>)> {
+    Some(match name {
+        "font" | "big" => ("span", None),
+        "center" => ("div", Some("text-align: center")),
+        "strike" => ("s", None),
+        "tt" => ("code", None),
+        _ => return None,
+    })
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_lowercase())
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+// Re-serializes forgiving HTML as well-formed XHTML body content. The head,
+// scripts and prefixed elements (like `mbp:pagebreak`) are dropped, tags are
+// balanced and text is escaped. `rewrite` gets the attributes of every
+// element before they are filtered, to fix up links and image sources.
+pub fn to_xhtml_body(
+    html: &str,
+    mut rewrite: impl FnMut(&str, &mut Vec<(String, String)>),
+) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut names: Vec<String> = Vec::new();
+    // elements whose content is skipped altogether
+    let mut skipped: Vec<String> = Vec::new();
+
+    for (_, token) in Tokenizer::new(html) {
+        match token {
+            Token::Start {
+                name,
+                mut attrs,
+                self_closing,
+            } => {
+                if !skipped.is_empty() {
+                    if !self_closing && !is_void_element(&name) && name == skipped[0] {
+                        skipped.push(name);
+                    }
+                    continue;
+                }
+                if matches!(name.as_str(), "head" | "script" | "style" | "title") {
+                    if !self_closing {
+                        skipped.push(name);
+                    }
+                    continue;
+                }
+                // SVG wrappers are dropped, the images in them kept
+                if matches!(name.as_str(), "html" | "body" | "svg") || !is_valid_name(&name) {
+                    continue;
+                }
+                let name = if name == "image" {
+                    let href = attrs
+                        .iter()
+                        .find(|(key, _)| key == "xlink:href" || key == "href")
+                        .map(|(_, href)| href.clone());
+                    attrs.retain(|(key, _)| key != "href");
+                    attrs.extend(href.map(|href| (String::from("src"), href)));
+                    String::from("img")
+                } else {
+                    name
+                };
+
+                rewrite(&name, &mut attrs);
+                let (name, style) = match replacement_element(&name) {
+                    Some((replacement, style)) => (replacement.to_string(), style),
+                    None => (name, None),
+                };
+                let align = attrs
+                    .iter()
+                    .find(|(key, _)| key == "align")
+                    .map(|(_, align)| format!("text-align: {}", align));
+                for extra in align.into_iter().chain(style.map(str::to_string)) {
+                    match attrs.iter_mut().find(|(key, _)| key == "style") {
+                        Some((_, style)) => *style = format!("{}; {}", extra, style),
+                        None => attrs.push((String::from("style"), extra)),
+                    }
+                }
+
+                out.push('<');
+                out.push_str(&name);
+                let mut written: Vec<&str> = Vec::new();
+                for (key, value) in &attrs {
+                    if !ALLOWED_ATTRIBUTES.contains(&key.as_str())
+                        || written.contains(&key.as_str())
+                    {
+                        continue;
+                    }
+                    written.push(key);
+                    out.push_str(&format!(" {}=\"{}\"", key, escape(&xml_text(value))));
+                }
+                if self_closing || is_void_element(&name) {
+                    out.push_str("/>");
+                } else {
+                    out.push('>');
+                    names.push(name);
+                }
+            }
+            Token::End { name } => {
+                if !skipped.is_empty() {
+                    if name == skipped[0] {
+                        skipped.pop();
+                    }
+                    continue;
+                }
+                let name = replacement_element(&name)
+                    .map(|(replacement, _)| replacement.to_string())
+                    .unwrap_or(name);
+                // closing an element closes whatever was left open in it
+                if let Some(index) = names.iter().rposition(|open| *open == name) {
+                    for open in names.drain(index..).rev() {
+                        out.push_str(&format!("</{}>", open));
+                    }
+                }
+            }
+            Token::Text(text) => {
+                if skipped.is_empty() {
+                    out.push_str(&escape(&xml_text(&text)));
+                }
+            }
+        }
+    }
+    for name in names.into_iter().rev() {
+        out.push_str(&format!("</{}>", name));
+    }
+    out
+}
+
+// drops the control characters XML doesn't allow
+fn xml_text(s: &str) -> String {
+    s.chars()
+        .filter(|&c| c >= ' ' || matches!(c, '\t' | '\n' | '\r'))
+        .collect()
+}
+
+// A complete XHTML document around `body`, as EPUB content documents
+// need to be.
+pub fn xhtml_document(title: &str, language: Option<&str>, body: &str) -> String {
+    let lang = language
+        .map(|lang| format!(" xml:lang=\"{0}\" lang=\"{0}\"", escape(lang)))
+        .unwrap_or_default();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE html>\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\"{}>\n\
+         <head>\n<meta charset=\"UTF-8\"/>\n<title>{}</title>\n</head>\n\
+         <body>\n{}\n</body>\n</html>\n",
+        lang,
+        escape(title),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolved(base: &str, href: &str) -> Option<(String, Option<String>)> {
+        resolve_href(Path::new(base), href)
+            .map(|(path, fragment)| (path.display().to_string(), fragment))
+    }
+
+    #[test]
+    fn hrefs_resolve_against_the_document() {
+        assert_eq!(
+            resolved("OEBPS/text/ch1.xhtml", "ch2.xhtml#s1"),
+            Some((
+                String::from("OEBPS/text/ch2.xhtml"),
+                Some(String::from("s1"))
+            ))
+        );
+        assert_eq!(
+            resolved("OEBPS/text/ch1.xhtml", "../images/a%20b.png"),
+            Some((String::from("OEBPS/images/a b.png"), None))
+        );
+        assert_eq!(
+            resolved("OEBPS/text/ch1.xhtml", "./../../cover.xhtml"),
+            Some((String::from("cover.xhtml"), None))
+        );
+        assert_eq!(
+            resolved("OEBPS/text/ch1.xhtml", "/OEBPS/notes.xhtml"),
+            Some((String::from("OEBPS/notes.xhtml"), None))
+        );
+    }
+
+    #[test]
+    fn fragments_alone_stay_in_the_document() {
+        assert_eq!(
+            resolved("OEBPS/ch1.xhtml", "#note-1"),
+            Some((
+                String::from("OEBPS/ch1.xhtml"),
+                Some(String::from("note-1"))
+            ))
+        );
+    }
+
+    #[test]
+    fn external_links_dont_resolve() {
+        assert_eq!(resolved("ch1.xhtml", "https://example.com/a.html"), None);
+        assert_eq!(resolved("ch1.xhtml", "mailto:hello@example.com"), None);
+    }
+}